@@ -0,0 +1,63 @@
+//! cargo bench --bench bank
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use protohackers::bank::bench::{
+    bursts, decode, interleaved, run, traffic, Fenwick, Indexed, Op, Rebuilt, Scan, SortedVec,
+    Storage,
+};
+
+/// Decodes a million messages arriving in 4 KiB reads, with and without
+/// asset bytes.
+fn decoder(c: &mut Criterion) {
+    let n = 1_000_000;
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(n as u64));
+    for assets in [false, true] {
+        let traffic = traffic(n, assets);
+        group.bench_function(BenchmarkId::new("assets", assets), |b| {
+            b.iter(|| assert_eq!(decode(&traffic, assets), n as usize))
+        });
+    }
+}
+
+fn workload<S: Storage>(c: &mut Criterion, name: &str, workload: &str, ops: &[Op]) {
+    c.benchmark_group("storage")
+        .sample_size(10)
+        .bench_function(BenchmarkId::new(name, workload), |b| {
+            b.iter_batched(S::default, |mut s| run(&mut s, ops), BatchSize::LargeInput)
+        });
+}
+
+/// Each storage under the checker's pattern, bursts of inserts each followed
+/// by a few queries, and with queries interleaved with inserts.
+fn storage(c: &mut Criterion) {
+    let bursts = bursts(10, 5_000, 50);
+    let interleaved = interleaved(50_000, 10);
+    for (name, ops) in [("bursts", &bursts), ("interleaved", &interleaved)] {
+        workload::<Indexed>(c, "indexed", name, ops);
+        workload::<Rebuilt>(c, "rebuilt", name, ops);
+        workload::<Scan>(c, "btreemap", name, ops);
+        workload::<SortedVec>(c, "sorted vec", name, ops);
+        workload::<Fenwick>(c, "fenwick", name, ops);
+    }
+}
+
+fn queries<S: Storage>(c: &mut Criterion, name: &str, inserts: &[Op], queries: &[Op]) {
+    let mut storage = S::default();
+    run(&mut storage, inserts);
+    c.benchmark_group("1000 queries over 100k prices")
+        .bench_function(name, |b| b.iter(|| run(&mut storage, queries)));
+}
+
+/// Queries alone, once the prices are in.
+fn mean(c: &mut Criterion) {
+    let ops = bursts(1, 100_000, 1_000);
+    let (inserts, ops) = ops.split_at(100_000);
+    queries::<Indexed>(c, "indexed", inserts, ops);
+    queries::<Rebuilt>(c, "rebuilt", inserts, ops);
+    queries::<Fenwick>(c, "fenwick", inserts, ops);
+    queries::<Scan>(c, "btreemap", inserts, ops);
+}
+
+criterion_group!(benches, decoder, storage, mean);
+criterion_main!(benches);
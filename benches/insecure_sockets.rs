@@ -0,0 +1,27 @@
+//! cargo bench --bench insecure_sockets
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protohackers::insecure_sockets::bench::{specs, Encoder};
+
+/// Encodes 1 MiB with each spec, 4 KiB at a time, looking each byte up in the
+/// cipher's tables and applying each op in turn.
+fn cipher(c: &mut Criterion) {
+    let mut data = vec![0x55; 1 << 20];
+    for (name, spec) in specs() {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for (method, tables) in [("tables", true), ("ops", false)] {
+            let mut encoder = Encoder::new(&spec, tables);
+            group.bench_function(BenchmarkId::from_parameter(method), |b| {
+                b.iter(|| {
+                    for chunk in data.chunks_mut(4096) {
+                        encoder.encode(chunk);
+                    }
+                })
+            });
+        }
+    }
+}
+
+criterion_group!(benches, cipher);
+criterion_main!(benches);
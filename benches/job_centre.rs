@@ -0,0 +1,58 @@
+//! cargo bench --bench job_centre
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protohackers::job_centre::bench::{decode, many_connections, start, traffic, ManyQueues};
+
+/// Decodes and parses 100k requests arriving in 4 KiB reads.
+fn decoder(c: &mut Criterion) {
+    let n = 100_000;
+    let traffic = traffic(n);
+    c.benchmark_group("decode")
+        .throughput(Throughput::Elements(n as u64))
+        .bench_function("requests", |b| b.iter(|| assert_eq!(decode(&traffic), n)));
+}
+
+/// A get across queues of 10 jobs each, finding the best job with the index of
+/// non-empty queues and by checking each named queue: naming all of 10k full
+/// queues, 10 of them, and 10k queues of which 10 are full.
+fn many_queues(c: &mut Criterion) {
+    for (name, full, named) in [
+        ("10k queues, all named", 10_000, 10_000),
+        ("10k queues, 10 named", 10_000, 10),
+        ("10k named, 10 full", 10, 10_000),
+    ] {
+        let mut group = c.benchmark_group(name);
+        for (method, scan) in [("index", false), ("scan", true)] {
+            let mut queues = ManyQueues::new(full, named);
+            group.bench_function(BenchmarkId::from_parameter(method), |b| {
+                b.iter(|| queues.get(scan))
+            });
+        }
+    }
+}
+
+/// Producers putting jobs and workers getting and deleting them, 2 and 16 of
+/// each, over loopback connections to one server, with and without a journal.
+fn shared(c: &mut Criterion) {
+    let jobs = 1000;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let path = std::env::temp_dir().join(format!("job-centre-bench-{}", std::process::id()));
+    for journal in [false, true] {
+        let addr = rt.block_on(start(journal.then_some(path.as_path())));
+        let mut group = c.benchmark_group(match journal {
+            true => "producers and workers, journalled",
+            false => "producers and workers",
+        });
+        group.sample_size(10);
+        for clients in [2, 16] {
+            group.throughput(Throughput::Elements((clients * jobs * 3) as u64));
+            group.bench_function(BenchmarkId::from_parameter(clients), |b| {
+                b.iter(|| rt.block_on(many_connections(addr, clients, clients, jobs)))
+            });
+        }
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+criterion_group!(benches, decoder, many_queues, shared);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+//! cargo bench --bench line_reversal
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protohackers::line_reversal::bench::{lines, transfer};
+
+/// Goodput: 32 KiB of lines reversed through the server, at 0%, 10% and 25%
+/// loss, with windows of 1, 8 and 32 KiB. Time is paused, so what's measured
+/// is how long the network takes, its delay and the waits to resend, not the
+/// CPU. Each iteration loses different datagrams.
+fn goodput(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let lines = lines(32 << 10);
+    let mut seed = 0;
+    for loss in [0, 10, 25] {
+        let mut group = c.benchmark_group(format!("{loss}% loss"));
+        group
+            .sample_size(10)
+            .throughput(Throughput::Bytes(lines.len() as u64));
+        for window in [1 << 10, 8 << 10, 32 << 10] {
+            let id = BenchmarkId::new("window", format!("{} KiB", window >> 10));
+            group.bench_function(id, |b| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let mut total = Duration::ZERO;
+                        for _ in 0..iters {
+                            seed += 1;
+                            total += transfer(&lines, window, loss, seed).await;
+                        }
+                        total
+                    })
+                })
+            });
+        }
+    }
+}
+
+criterion_group! {
+    name = benches;
+    // With nothing lost, every transfer takes exactly as long, which the
+    // plots can't draw.
+    config = Criterion::default().without_plots();
+    targets = goodput
+}
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+//! cargo bench --bench prime_time
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protohackers::prime_time::bench::{frame, traffic};
+
+/// Frames 100k requests arriving in 4 KiB reads, by lines and by values.
+fn framing(c: &mut Criterion) {
+    let n = 100_000;
+    let traffic = traffic(n);
+    let mut group = c.benchmark_group("framing");
+    group.throughput(Throughput::Elements(n as u64));
+    for (name, values) in [("lines", false), ("values", true)] {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| assert_eq!(frame(&traffic, values), n))
+        });
+    }
+}
+
+criterion_group!(benches, framing);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! cargo bench --bench smoke
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protohackers::smoke::bench::{echo, start};
+
+/// Echoes 64 MiB over loopback for each buffer size and number of
+/// connections, with one read then one write at a time, and with reads and
+/// vectored writes split.
+fn throughput(c: &mut Criterion) {
+    let total = 64 << 20;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    for (name, split) in [("sequential", false), ("split", true)] {
+        let mut group = c.benchmark_group(name);
+        group
+            .sample_size(10)
+            .throughput(Throughput::Bytes(total as u64));
+        for buffer in [4 << 10, 16 << 10, 64 << 10, 256 << 10] {
+            let addr = rt.block_on(start(buffer, split));
+            for connections in [1, 4, 16] {
+                let id = BenchmarkId::new(format!("{} KiB", buffer >> 10), connections);
+                group.bench_function(id, |b| {
+                    b.iter(|| rt.block_on(echo(addr, connections, total)))
+                });
+            }
+        }
+    }
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
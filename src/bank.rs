@@ -1,39 +1,215 @@
 use std::{
-    collections::BTreeMap,
-    sync::atomic::{AtomicU32, Ordering},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
-use anyhow::Result;
-use futures::StreamExt;
+use anyhow::{bail, Context, Result};
 use tokio::{
-    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpListener,
+    sync::watch,
+    task::JoinSet,
 };
 use tokio_util::{
-    bytes::Buf,
-    codec::{Decoder, FramedRead},
+    bytes::{Buf, BytesMut},
+    codec::Decoder,
 };
 
 use crate::config::ADDR;
 
+use self::{
+    prices::{Prices, Rounding},
+    snapshot::Snapshot,
+    stats::Stats,
+};
+
+pub mod bench;
+pub mod fuzz;
+mod prices;
+mod snapshot;
+mod stats;
+
+/// Set to `overwrite` (the default), `ignore` or `reject` to choose what an
+/// insert for a timestamp that already has a price does.
+const DUPLICATES_VAR: &str = "BANK_DUPLICATES";
+/// Set to `reply` (the default), `reply-close` or `close` to choose what a
+/// protocol error does.
+const INVALID_VAR: &str = "BANK_INVALID";
+/// Set to limit how many prices a session may store.
+const MAX_PRICES_VAR: &str = "BANK_MAX_PRICES";
+/// Set to `close` (the default) or `reject` to choose what an insert beyond
+/// the limit does.
+const FULL_VAR: &str = "BANK_FULL";
+/// Set to `truncate` (the default) or `floor` to choose how a mean is rounded.
+const ROUNDING_VAR: &str = "BANK_ROUNDING";
+/// Set to accept the non-standard `C`, `N` and `X` messages: the count, lowest
+/// and highest price in a range.
+const EXTENSIONS_VAR: &str = "BANK_EXTENSIONS";
+/// Set to have an asset byte after each message's type, choosing one of 256
+/// independent sets of prices in the session.
+const ASSETS_VAR: &str = "BANK_ASSETS";
+/// Set to a file to save live sessions' prices there on Ctrl-C, and restore
+/// them on startup.
+const SNAPSHOT_VAR: &str = "BANK_SNAPSHOT";
+
+/// What to do with an insert for a timestamp that already has a price, which
+/// the spec leaves undefined.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Duplicates {
+    #[default]
+    Overwrite,
+    /// Keep the first price.
+    Ignore,
+    /// Treat it as a protocol error, like an invalid message.
+    Reject,
+}
+
+/// What to do on a protocol error: an invalid message type, a message that
+/// can't be decoded, or a rejected duplicate.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum OnInvalid {
+    /// Reply "undefined behavior" and carry on.
+    #[default]
+    Reply,
+    /// Reply "undefined behavior" and close the connection.
+    ReplyAndClose,
+    /// Close the connection without a word.
+    Close,
+}
+
+/// What to do with an insert for a new timestamp once a session has stored as
+/// many prices as it may.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum OnFull {
+    /// Close the connection.
+    #[default]
+    Close,
+    /// Treat it as a protocol error, and don't store it.
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Config {
+    duplicates: Duplicates,
+    on_invalid: OnInvalid,
+    /// No limit if `None`.
+    max_prices: Option<usize>,
+    on_full: OnFull,
+    rounding: Rounding,
+    extensions: bool,
+    assets: bool,
+}
+
+impl Config {
+    fn from_env() -> Result<Config> {
+        let mut config = Config::default();
+        if let Ok(value) = std::env::var(DUPLICATES_VAR) {
+            config.duplicates = match value.as_str() {
+                "overwrite" => Duplicates::Overwrite,
+                "ignore" => Duplicates::Ignore,
+                "reject" => Duplicates::Reject,
+                _ => bail!("{DUPLICATES_VAR} must be overwrite, ignore or reject"),
+            };
+        }
+        if let Ok(value) = std::env::var(INVALID_VAR) {
+            config.on_invalid = match value.as_str() {
+                "reply" => OnInvalid::Reply,
+                "reply-close" => OnInvalid::ReplyAndClose,
+                "close" => OnInvalid::Close,
+                _ => bail!("{INVALID_VAR} must be reply, reply-close or close"),
+            };
+        }
+        if let Ok(value) = std::env::var(MAX_PRICES_VAR) {
+            let max = value
+                .parse()
+                .with_context(|| format!("{MAX_PRICES_VAR} must be a number of prices"))?;
+            config.max_prices = Some(max);
+        }
+        if let Ok(value) = std::env::var(FULL_VAR) {
+            config.on_full = match value.as_str() {
+                "close" => OnFull::Close,
+                "reject" => OnFull::Reject,
+                _ => bail!("{FULL_VAR} must be close or reject"),
+            };
+        }
+        if let Ok(value) = std::env::var(ROUNDING_VAR) {
+            config.rounding = match value.as_str() {
+                "truncate" => Rounding::Truncate,
+                "floor" => Rounding::Floor,
+                _ => bail!("{ROUNDING_VAR} must be truncate or floor"),
+            };
+        }
+        config.extensions = std::env::var_os(EXTENSIONS_VAR).is_some();
+        config.assets = std::env::var_os(ASSETS_VAR).is_some();
+        Ok(config)
+    }
+}
+
 struct Session {
     id: u32,
-    prices: BTreeMap<i32, i32>,
+    config: Config,
+    /// The prices of the asset the last message was for; always asset 0
+    /// unless assets are enabled.
+    prices: Prices,
+    asset: u8,
+    /// Every other asset's prices.
+    assets: HashMap<u8, Prices>,
+    stats: Stats,
 }
 
 impl Session {
-    fn new() -> Session {
+    fn new(config: Config) -> Session {
         static ID: AtomicU32 = AtomicU32::new(0);
         let id = ID.fetch_add(1, Ordering::Relaxed);
-        let prices = BTreeMap::new();
-        Session { id, prices }
+        Session {
+            id,
+            config,
+            prices: Prices::default(),
+            asset: 0,
+            assets: HashMap::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Makes `asset`'s prices the current ones.
+    fn select(&mut self, asset: u8) {
+        if asset == self.asset {
+            return;
+        }
+        let prices = self.assets.remove(&asset).unwrap_or_default();
+        let previous = std::mem::replace(&mut self.prices, prices);
+        self.assets.insert(self.asset, previous);
+        self.asset = asset;
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum Message {
-    Insert { timestamp: i32, price: i32 },
-    Query { start: i32, end: i32 },
+    Insert {
+        timestamp: i32,
+        price: i32,
+    },
+    Query {
+        start: i32,
+        end: i32,
+    },
+    /// Extensions, each answered with a single number like a query.
+    Count {
+        start: i32,
+        end: i32,
+    },
+    Min {
+        start: i32,
+        end: i32,
+    },
+    Max {
+        start: i32,
+        end: i32,
+    },
     Invalid,
 }
 
@@ -41,65 +217,203 @@ pub async fn run() -> Result<()> {
     let listener = TcpListener::bind(ADDR).await.unwrap();
     println!("Listening on {ADDR}...");
 
+    let config = Config::from_env()?;
+    let snapshot = match std::env::var_os(SNAPSHOT_VAR) {
+        Some(path) => Some(Arc::new(Snapshot::load(path)?)),
+        None => None,
+    };
+    serve(listener, config, snapshot).await
+}
+
+/// Serves until Ctrl-C, if there's a snapshot to save then.
+async fn serve(
+    listener: TcpListener,
+    config: Config,
+    snapshot: Option<Arc<Snapshot>>,
+) -> Result<()> {
+    let (shutdown, _) = watch::channel(());
+    let mut sessions = JoinSet::new();
     loop {
-        let (mut socket, addr) = listener.accept().await?;
+        let (mut socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = tokio::signal::ctrl_c(), if snapshot.is_some() => break,
+        };
         println!("Connected to {addr}");
-        tokio::spawn(async move {
-            let mut session = Session::new();
+        while sessions.try_join_next().is_some() {}
+        let snapshot = snapshot.clone();
+        let mut shutdown = shutdown.subscribe();
+        sessions.spawn(async move {
+            let mut session = Session::new(config);
+            let restored = snapshot.as_ref().and_then(|s| s.take(session.id));
+            if let Some(prices) = restored {
+                println!("id={}, restored {} prices", session.id, prices.len());
+                session.prices = prices;
+            }
             let (reader, writer) = socket.split();
-            session.start(reader, writer).await
+            tokio::select! {
+                result = session.start(reader, writer) => {
+                    if let Err(e) = result {
+                        println!("id={}, {e:?}", session.id);
+                    }
+                }
+                _ = shutdown.changed() => {
+                    // Only asset 0 is kept, the only one unless assets are
+                    // enabled.
+                    session.select(0);
+                    let prices = std::mem::take(&mut session.prices);
+                    snapshot.unwrap().put(session.id, prices);
+                }
+            }
         });
     }
+
+    // Only reachable with a snapshot to save.
+    drop(shutdown);
+    while sessions.join_next().await.is_some() {}
+    snapshot.unwrap().save()?;
+    println!("Saved snapshot");
+    Ok(())
 }
 
 impl Session {
-    async fn start<R, W>(&mut self, reader: R, mut writer: W) -> Result<()>
+    async fn start<R, W>(&mut self, reader: R, writer: W) -> Result<()>
     where
         R: AsyncRead + Unpin,
         W: AsyncWrite + Unpin,
     {
-        let mut messages = FramedRead::new(reader, MessageDecoder);
-        while let Some(message) = messages.next().await {
-            println!("id={}, {message:?}", self.id);
-            match message {
-                Ok(Message::Query { start, end }) => {
-                    let mean = self.get_mean(start, end).await;
-                    println!("id={}, mean={mean}", self.id);
-                    writer.write_i32(mean).await?
-                }
-                Ok(Message::Insert { timestamp, price }) => {
-                    self.prices.insert(timestamp, price);
-                }
-                Ok(Message::Invalid) => {
-                    writer.write(b"undefined behavior").await?;
+        let result = self.serve(reader, writer).await;
+        println!("id={}, closed: {}", self.id, self.stats);
+        result
+    }
+
+    /// Runs the session, handling every complete message in the buffer after
+    /// each read, and sending their responses together in one write.
+    async fn serve<R, W>(&mut self, mut reader: R, mut writer: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut decoder = MessageDecoder {
+            assets: self.config.assets,
+        };
+        let mut buf = BytesMut::with_capacity(8 * 1024);
+        let mut responses = vec![];
+        loop {
+            let eof = reader.read_buf(&mut buf).await? == 0;
+            let mut open = true;
+            while open {
+                match decoder.decode(&mut buf).transpose() {
+                    Some(message) => {
+                        let message = message.map(|(asset, message)| {
+                            self.select(asset);
+                            message
+                        });
+                        open = self.handle(message, &mut responses).await
+                    }
+                    None => break,
                 }
-                Err(e) => {
-                    println!("Message error: {e:?}");
-                    writer.write(b"undefined behavior").await?;
+            }
+            if eof && open {
+                // Complains about any partial message left over.
+                if let Some(Err(e)) = decoder.decode_eof(&mut buf).transpose() {
+                    self.handle(Err(e), &mut responses).await;
                 }
-            };
+            }
+            debug_assert!(
+                !open || buf.len() < decoder.len(),
+                "complete message left unread"
+            );
+            writer.write_all(&responses).await?;
+            responses.clear();
+            if eof || !open {
+                return Ok(());
+            }
         }
-        Ok(())
     }
 
-    async fn get_mean(&mut self, start: i32, end: i32) -> i32 {
-        if start > end {
-            return 0;
+    /// Handles one message, adding any response to `responses`. Returns whether
+    /// to carry on with the session.
+    async fn handle(&mut self, message: Result<Message>, responses: &mut Vec<u8>) -> bool {
+        println!("id={}, {message:?}", self.id);
+        match message {
+            Ok(Message::Query { start, end }) => {
+                let started = Instant::now();
+                let mean = self.prices.mean(start, end, self.config.rounding);
+                self.stats.query(started.elapsed());
+                println!("id={}, mean={mean}", self.id);
+                responses.extend_from_slice(&mean.to_be_bytes());
+                true
+            }
+            Ok(Message::Insert { timestamp, price }) => {
+                self.stats.inserts += 1;
+                let new = !self.prices.contains(timestamp);
+                match self.config.duplicates {
+                    _ if new && self.is_full() => match self.config.on_full {
+                        OnFull::Close => {
+                            println!("id={}, too many prices", self.id);
+                            false
+                        }
+                        OnFull::Reject => self.protocol_error(responses),
+                    },
+                    _ if new => {
+                        self.prices.insert(timestamp, price);
+                        true
+                    }
+                    Duplicates::Overwrite => {
+                        self.prices.insert(timestamp, price);
+                        true
+                    }
+                    Duplicates::Ignore => true,
+                    Duplicates::Reject => self.protocol_error(responses),
+                }
+            }
+            Ok(Message::Count { .. } | Message::Min { .. } | Message::Max { .. })
+                if !self.config.extensions =>
+            {
+                self.protocol_error(responses)
+            }
+            Ok(Message::Count { start, end }) => {
+                let count = self.prices.count(start, end);
+                let count = i32::try_from(count).unwrap_or(i32::MAX);
+                responses.extend_from_slice(&count.to_be_bytes());
+                true
+            }
+            Ok(Message::Min { start, end }) => {
+                let min = self.prices.min(start, end).unwrap_or(0);
+                responses.extend_from_slice(&min.to_be_bytes());
+                true
+            }
+            Ok(Message::Max { start, end }) => {
+                let max = self.prices.max(start, end).unwrap_or(0);
+                responses.extend_from_slice(&max.to_be_bytes());
+                true
+            }
+            Ok(Message::Invalid) => self.protocol_error(responses),
+            Err(e) => {
+                println!("Message error: {e:?}");
+                self.protocol_error(responses)
+            }
         }
-        let mut count = 0;
-        let total = self
-            .prices
-            .range(start..=end)
-            .map(|(_, p)| {
-                count += 1;
-                *p as i64
-            })
-            .sum::<i64>();
-        if count == 0 {
-            0
-        } else {
-            (total / count) as i32
+    }
+
+    fn is_full(&self) -> bool {
+        self.config.max_prices.is_some_and(|max| self.len() >= max)
+    }
+
+    /// How many prices the session has stored, over every asset.
+    fn len(&self) -> usize {
+        let others: usize = self.assets.values().map(Prices::len).sum();
+        self.prices.len() + others
+    }
+
+    /// Responds to a protocol error as configured, returning whether to carry
+    /// on with the session.
+    fn protocol_error(&mut self, responses: &mut Vec<u8>) -> bool {
+        self.stats.errors += 1;
+        if self.config.on_invalid != OnInvalid::Close {
+            responses.extend_from_slice(b"undefined behavior");
         }
+        self.config.on_invalid == OnInvalid::Reply
     }
 }
 
@@ -110,43 +424,120 @@ fn to_num(bytes: &[u8]) -> i32 {
     i32::from_be_bytes(b)
 }
 
-struct MessageDecoder;
+/// Decodes messages along with the asset each is for, which is 0 unless
+/// `assets` is set.
+struct MessageDecoder {
+    /// Whether each message has an asset byte after its type.
+    assets: bool,
+}
+
+impl MessageDecoder {
+    /// The length of a message.
+    fn len(&self) -> usize {
+        9 + self.assets as usize
+    }
+}
 
 impl Decoder for MessageDecoder {
-    type Item = Message;
+    type Item = (u8, Message);
     type Error = anyhow::Error;
 
     fn decode(
         &mut self,
-        src: &mut tokio_util::bytes::BytesMut,
+        src: &mut BytesMut,
     ) -> std::prelude::v1::Result<Option<Self::Item>, Self::Error> {
-        if src.len() < 9 {
+        if src.len() < self.len() {
             // Not enough data
             return Ok(None);
         }
-        let msg_type = src[0];
-        let num1 = to_num(&src[1..5]);
-        let num2 = to_num(&src[5..9]);
-        src.advance(9);
-        match msg_type {
+        let msg_type = src.get_u8();
+        let asset = if self.assets { src.get_u8() } else { 0 };
+        let num1 = to_num(&src[0..4]);
+        let num2 = to_num(&src[4..8]);
+        src.advance(8);
+        let message = match msg_type {
             b'I' => {
                 let timestamp = num1;
                 let price = num2;
-                Ok(Some(Message::Insert { timestamp, price }))
+                Message::Insert { timestamp, price }
             }
             b'Q' => {
                 let start = num1;
                 let end = num2;
-                Ok(Some(Message::Query { start, end }))
+                Message::Query { start, end }
             }
-            _ => Ok(Some(Message::Invalid)),
+            b'C' => Message::Count {
+                start: num1,
+                end: num2,
+            },
+            b'N' => Message::Min {
+                start: num1,
+                end: num2,
+            },
+            b'X' => Message::Max {
+                start: num1,
+                end: num2,
+            },
+            _ => Message::Invalid,
+        };
+        Ok(Some((asset, message)))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(message) = self.decode(src)? {
+            return Ok(Some(message));
         }
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let len = src.len();
+        src.clear();
+        bail!("stream ended {len} bytes into a message")
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Session;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use proptest::prelude::*;
+    use tokio::io::AsyncWrite;
+    use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+    use crate::{
+        clients::bank::Client,
+        testutil::{
+            alloc::allocations, decode_chunks, duplex, split_at, transcript::replay_dir, TestServer,
+        },
+    };
+
+    use super::{serve, Config, Duplicates, Message, MessageDecoder, OnFull, OnInvalid, Session};
+
+    /// Records each write separately.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.0.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
 
     #[tokio::test]
     async fn transactions() {
@@ -165,7 +556,7 @@ mod test {
         let writer = tokio_test::io::Builder::new()
             .write(&[0x00, 0x00, 0x00, 0x65]) // 101
             .build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
 
@@ -176,7 +567,7 @@ mod test {
             .read(&[0x51, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x40, 0x00])
             .build();
         let writer = tokio_test::io::Builder::new().write(&[0, 0, 0, 0]).build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
 
@@ -195,7 +586,7 @@ mod test {
             .read(&[0x51, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x30, 0x00])
             .build();
         let writer = tokio_test::io::Builder::new().write(&[0, 0, 0, 0]).build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
 
@@ -219,22 +610,25 @@ mod test {
             .write(b"undefined behavior")
             .write(&[0x00, 0x00, 0x00, 0x65])
             .build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
 
     #[tokio::test]
     async fn truncated_message() {
-        let reader = tokio_test::io::Builder::new()
+        for leftover in 1..9 {
+            let mut messages = vec![];
             // I 12345 101
-            .read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65])
-            .read(&[0x49, 0x00, 0x00, 0xa0, 0x00])
-            .build();
-        let writer = tokio_test::io::Builder::new()
-            .write(b"undefined behavior")
-            .build();
-        let mut session = Session::new();
-        let _ = session.start(reader, writer).await;
+            messages.extend_from_slice(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]);
+            // Q 12345 12345, cut short by the end of the stream
+            messages.extend_from_slice(
+                &[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39][..leftover],
+            );
+            let mut writer = Writes::default();
+            let mut session = Session::new(Config::default());
+            session.start(&messages[..], &mut writer).await.unwrap();
+            assert_eq!(writer.0, [b"undefined behavior"], "{leftover} bytes");
+        }
     }
 
     #[tokio::test]
@@ -255,7 +649,7 @@ mod test {
         let writer = tokio_test::io::Builder::new()
             .write(&[0x00, 0x00, 0x00, 0x65])
             .build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
 
@@ -272,7 +666,328 @@ mod test {
             .write(&[0x7a, 0x30, 0x84, 0x80])
             .write(&[0x7a, 0x30, 0x84, 0x80])
             .build();
-        let mut session = Session::new();
+        let mut session = Session::new(Config::default());
         let _ = session.start(reader, writer).await;
     }
+
+    #[tokio::test]
+    async fn duplicate_timestamps() {
+        // (policy, response to the duplicate, mean)
+        let cases: [(_, &[u8], _); 3] = [
+            (Duplicates::Overwrite, b"", [0x00, 0x00, 0x00, 0x66]),
+            (Duplicates::Ignore, b"", [0x00, 0x00, 0x00, 0x65]),
+            (
+                Duplicates::Reject,
+                b"undefined behavior",
+                [0x00, 0x00, 0x00, 0x65],
+            ),
+        ];
+        for (duplicates, response, mean) in cases {
+            let reader = tokio_test::io::Builder::new()
+                // I 12345 101
+                .read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65])
+                // I 12345 102
+                .read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x66])
+                // Q 12345 12345
+                .read(&[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39])
+                .build();
+            let mut writer = tokio_test::io::Builder::new();
+            if !response.is_empty() {
+                writer.write(response);
+            }
+            let writer = writer.write(&mean).build();
+            let mut session = Session::new(Config {
+                duplicates,
+                ..Config::default()
+            });
+            session.start(reader, writer).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_policies() {
+        let cases: [(_, &[&[u8]]); 3] = [
+            (
+                OnInvalid::Reply,
+                &[b"undefined behavior", &[0x00, 0x00, 0x00, 0x65]],
+            ),
+            (OnInvalid::ReplyAndClose, &[b"undefined behavior"]),
+            (OnInvalid::Close, &[]),
+        ];
+        for (on_invalid, responses) in cases {
+            let mut reader = tokio_test::io::Builder::new();
+            // I 12345 101
+            reader.read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]);
+            // P 40960 5 (invalid)
+            reader.read(&[0x50, 0x00, 0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x05]);
+            if on_invalid == OnInvalid::Reply {
+                // Q 12345 12345, never read once the session is closed
+                reader.read(&[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39]);
+            }
+            let mut writer = tokio_test::io::Builder::new();
+            for response in responses {
+                writer.write(response);
+            }
+            let mut session = Session::new(Config {
+                on_invalid,
+                ..Config::default()
+            });
+            session.start(reader.build(), writer.build()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn batched_responses() {
+        let mut messages = vec![];
+        // I 12345 101
+        messages.extend_from_slice(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]);
+        for _ in 0..3 {
+            // Q 12345 12345
+            messages.extend_from_slice(&[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39]);
+        }
+        // P 40960 5 (invalid), and the start of another query
+        messages.extend_from_slice(&[0x50, 0x00, 0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x05, 0x51]);
+        let reader = tokio_test::io::Builder::new()
+            .read(&messages)
+            .read(&[0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39])
+            .build();
+        let mut writer = Writes::default();
+        let mut session = Session::new(Config::default());
+        session.start(reader, &mut writer).await.unwrap();
+
+        let mut first = [0, 0, 0, 0x65].repeat(3);
+        first.extend_from_slice(b"undefined behavior");
+        assert_eq!(writer.0, [first, vec![0, 0, 0, 0x65]]);
+        let stats = &session.stats;
+        assert_eq!((stats.inserts, stats.queries, stats.errors), (1, 4, 1));
+    }
+
+    #[tokio::test]
+    async fn max_prices() {
+        let cases: [(_, &[&[u8]]); 2] = [
+            (OnFull::Close, &[]),
+            (
+                OnFull::Reject,
+                &[b"undefined behavior", &[0x00, 0x00, 0x00, 0x66]],
+            ),
+        ];
+        for (on_full, responses) in cases {
+            let mut reader = tokio_test::io::Builder::new();
+            // I 12345 101
+            reader.read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]);
+            // I 12345 102, which replaces a price rather than adding one
+            reader.read(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x66]);
+            // I 12346 200
+            reader.read(&[0x49, 0x00, 0x00, 0x30, 0x3a, 0x00, 0x00, 0x00, 0xc8]);
+            if on_full == OnFull::Reject {
+                // Q 12345 12346
+                reader.read(&[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x3a]);
+            }
+            let mut writer = tokio_test::io::Builder::new();
+            for response in responses {
+                writer.write(response);
+            }
+            let mut session = Session::new(Config {
+                max_prices: Some(1),
+                on_full,
+                ..Config::default()
+            });
+            session.start(reader.build(), writer.build()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn extensions() {
+        let messages: [&[u8]; 5] = [
+            // I 12345 101
+            &[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65],
+            // I 12346 -3
+            &[0x49, 0x00, 0x00, 0x30, 0x3a, 0xff, 0xff, 0xff, 0xfd],
+            // C 12345 12346
+            &[0x43, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x3a],
+            // N 12345 12346
+            &[0x4e, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x3a],
+            // X 12345 12346
+            &[0x58, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x3a],
+        ];
+        let cases: [(_, &[&[u8]]); 2] = [
+            (
+                true,
+                &[&[0, 0, 0, 2], &[0xff, 0xff, 0xff, 0xfd], &[0, 0, 0, 0x65]],
+            ),
+            (false, &[b"undefined behavior" as &[u8]; 3]),
+        ];
+        for (extensions, responses) in cases {
+            let mut reader = tokio_test::io::Builder::new();
+            for message in messages {
+                reader.read(message);
+            }
+            let mut writer = tokio_test::io::Builder::new();
+            for response in responses {
+                writer.write(response);
+            }
+            let mut session = Session::new(Config {
+                extensions,
+                ..Config::default()
+            });
+            session.start(reader.build(), writer.build()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn assets() {
+        let reader = tokio_test::io::Builder::new()
+            // I asset 1, 12345 101
+            .read(&[0x49, 0x01, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65])
+            // I asset 2, 12345 200
+            .read(&[0x49, 0x02, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0xc8])
+            // Q asset 1, 12345 12345
+            .read(&[0x51, 0x01, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39])
+            // Q asset 2, 12345 12345
+            .read(&[0x51, 0x02, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39])
+            // Q asset 0, 12345 12345
+            .read(&[0x51, 0x00, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39])
+            // I asset 3, 12346 5, over the limit across assets
+            .read(&[0x49, 0x03, 0x00, 0x00, 0x30, 0x3a, 0x00, 0x00, 0x00, 0x05])
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&[0x00, 0x00, 0x00, 0x65])
+            .write(&[0x00, 0x00, 0x00, 0xc8])
+            .write(&[0x00, 0x00, 0x00, 0x00])
+            .write(b"undefined behavior")
+            .build();
+        let mut session = Session::new(Config {
+            assets: true,
+            max_prices: Some(2),
+            on_full: OnFull::Reject,
+            ..Config::default()
+        });
+        session.start(reader, writer).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplex_client() {
+        let (reader, writer) = duplex(|reader, writer| async move {
+            Session::new(Config::default()).start(reader, writer).await
+        });
+        let mut client = Client::new(reader, writer);
+        client.insert(1, 10).await.unwrap();
+        client.insert(2, 20).await.unwrap();
+        assert_eq!(client.query(0, 5).await.unwrap(), 15);
+        assert_eq!(client.query(3, 5).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let message = |kind: u8, a: i32, b: i32| {
+            let mut message = vec![kind];
+            message.extend(a.to_be_bytes());
+            message.extend(b.to_be_bytes());
+            message
+        };
+        let server = TestServer::start(|listener| serve(listener, Config::default(), None)).await;
+        // Each connection has its own prices.
+        let mut clients = [server.connect().await, server.connect().await];
+        for (i, client) in clients.iter_mut().enumerate() {
+            let price = 100 * (i as i32 + 1);
+            client.send(&message(b'I', 1, price)).await;
+            client.send(&message(b'I', 2, price + 2)).await;
+            client.send(&message(b'Q', 0, 10)).await;
+        }
+        for (i, client) in clients.iter_mut().enumerate() {
+            let mean = 100 * (i as i32 + 1) + 1;
+            assert_eq!(client.recv_exact(4).await, mean.to_be_bytes());
+        }
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_isolated() {
+        let server = TestServer::start(|listener| serve(listener, Config::default(), None)).await;
+        let addr = server.addr.to_string();
+        // Sessions running at once, with the same timestamps, only ever see
+        // their own prices.
+        let sessions = (1..=8).map(|n| {
+            let addr = addr.clone();
+            async move {
+                let mut client = Client::connect(&addr).await.unwrap();
+                for t in 0..100 {
+                    client.insert(t, 10 * n + t % 2).await.unwrap();
+                    if t % 10 == 9 {
+                        assert_eq!(client.query(0, t).await.unwrap(), 10 * n);
+                    }
+                }
+            }
+        });
+        futures::future::join_all(sessions).await;
+        // Nor are they handed on to later sessions.
+        let mut client = Client::connect(&addr).await.unwrap();
+        assert_eq!(client.query(0, 100).await.unwrap(), 0);
+        server.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn decode_allocations() {
+        let mut traffic = vec![];
+        for i in 0..1000i32 {
+            traffic.push(if i % 2 == 0 { b'I' } else { b'Q' });
+            traffic.extend(i.to_be_bytes());
+            traffic.extend(i.to_be_bytes());
+        }
+        let mut decoder = MessageDecoder { assets: false };
+        let mut buf = BytesMut::with_capacity(traffic.len());
+        buf.extend_from_slice(&traffic);
+        let decoded = allocations(|| while decoder.decode(&mut buf).unwrap().is_some() {});
+        assert_eq!(decoded, 0);
+    }
+
+    proptest! {
+        #[test]
+        fn decode_round_trip(
+            assets in any::<bool>(),
+            messages in prop::collection::vec(
+                (prop::sample::select(&b"IQCNXZ"[..]), any::<u8>(), any::<i32>(), any::<i32>()),
+                0..16,
+            ),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let mut stream = vec![];
+            let mut expected = vec![];
+            for &(kind, asset, a, b) in &messages {
+                stream.push(kind);
+                if assets {
+                    stream.push(asset);
+                }
+                stream.extend(a.to_be_bytes());
+                stream.extend(b.to_be_bytes());
+                let message = match kind {
+                    b'I' => Message::Insert { timestamp: a, price: b },
+                    b'Q' => Message::Query { start: a, end: b },
+                    b'C' => Message::Count { start: a, end: b },
+                    b'N' => Message::Min { start: a, end: b },
+                    b'X' => Message::Max { start: a, end: b },
+                    _ => Message::Invalid,
+                };
+                expected.push((if assets { asset } else { 0 }, message));
+            }
+            let decoder = MessageDecoder { assets };
+            let decoded = decode_chunks(decoder, &split_at(&stream, &cuts)).unwrap();
+            prop_assert_eq!(decoded, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let server = TestServer::start(|listener| serve(listener, Config::default(), None)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("bank", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn transcripts() {
+        replay_dir("testdata/bank", |reader, writer| async move {
+            Session::new(Config::default()).start(reader, writer).await
+        })
+        .await;
+    }
 }
@@ -0,0 +1,318 @@
+//! The entry points for the `bank` benchmarks in `benches/`: the decoder, the
+//! storage choices for a session's prices, and the workloads to weigh them up
+//! with.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::fuzz::decode_all;
+
+use super::{
+    prices::{Prices, Rounding},
+    MessageDecoder,
+};
+
+/// `n` messages, mostly inserts with a query every tenth, with an asset byte
+/// after each type if `assets`.
+pub fn traffic(n: i32, assets: bool) -> Vec<u8> {
+    let mut traffic = vec![];
+    for i in 0..n {
+        traffic.push(if i % 10 == 9 { b'Q' } else { b'I' });
+        if assets {
+            traffic.push(i as u8 % 4);
+        }
+        traffic.extend(i.to_be_bytes());
+        traffic.extend((i * 7).to_be_bytes());
+    }
+    traffic
+}
+
+/// Decodes `traffic` arriving in 4 KiB reads, returning how many messages
+/// there were.
+pub fn decode(traffic: &[u8], assets: bool) -> usize {
+    let mut decoded = 0;
+    decode_all(MessageDecoder { assets }, traffic, 4096, |_| decoded += 1);
+    decoded
+}
+
+/// What a session does to its prices.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Insert { timestamp: i32, price: i32 },
+    Query { start: i32, end: i32 },
+}
+
+/// Somewhere to keep a session's prices.
+pub trait Storage: Default {
+    fn insert(&mut self, timestamp: i32, price: i32);
+    fn mean(&mut self, start: i32, end: i32) -> i32;
+}
+
+/// Runs `ops` against `storage`, returning the answers to the queries.
+pub fn run<S: Storage>(storage: &mut S, ops: &[Op]) -> Vec<i32> {
+    let mut means = vec![];
+    for &op in ops {
+        match op {
+            Op::Insert { timestamp, price } => storage.insert(timestamp, price),
+            Op::Query { start, end } => means.push(storage.mean(start, end)),
+        }
+    }
+    means
+}
+
+/// A deterministic stream of pseudo-random numbers.
+fn numbers(mut state: u64) -> impl Iterator<Item = i32> {
+    std::iter::from_fn(move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        Some((state >> 32) as i32)
+    })
+}
+
+fn ops(seed: u64, queries: impl IntoIterator<Item = bool>) -> Vec<Op> {
+    let mut numbers = numbers(seed);
+    let mut next = move || numbers.next().unwrap();
+    queries
+        .into_iter()
+        .map(|query| match query {
+            true => {
+                let (a, b) = (next(), next());
+                Op::Query {
+                    start: a.min(b),
+                    end: a.max(b),
+                }
+            }
+            false => Op::Insert {
+                timestamp: next(),
+                price: next(),
+            },
+        })
+        .collect()
+}
+
+/// The checker's pattern: `rounds` bursts of `inserts` inserts, each
+/// followed by `queries` queries.
+pub fn bursts(rounds: usize, inserts: usize, queries: usize) -> Vec<Op> {
+    let round = (0..inserts)
+        .map(|_| false)
+        .chain((0..queries).map(|_| true));
+    ops(11, std::iter::repeat_n(round, rounds).flatten())
+}
+
+/// `n` operations, every `every`th of them a query.
+pub fn interleaved(n: usize, every: usize) -> Vec<Op> {
+    ops(11, (1..=n).map(|i| i % every == 0))
+}
+
+/// What the server uses: a map, and a treap of running totals.
+#[derive(Default)]
+pub struct Indexed(Prices);
+
+impl Storage for Indexed {
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        self.0.insert(timestamp, price);
+    }
+
+    fn mean(&mut self, start: i32, end: i32) -> i32 {
+        self.0.mean(start, end, Rounding::Truncate)
+    }
+}
+
+/// What `Prices` replaced: a map, summed by scanning the range.
+#[derive(Default)]
+pub struct Scan(BTreeMap<i32, i32>);
+
+impl Storage for Scan {
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        self.0.insert(timestamp, price);
+    }
+
+    fn mean(&mut self, start: i32, end: i32) -> i32 {
+        if start > end {
+            return 0;
+        }
+        let (total, count) = self
+            .0
+            .range(start..=end)
+            .fold((0i128, 0i128), |(total, count), (_, &p)| {
+                (total + p as i128, count + 1)
+            });
+        if count == 0 {
+            0
+        } else {
+            (total / count) as i32
+        }
+    }
+}
+
+/// Prices sorted by timestamp, summed by scanning the range.
+#[derive(Default)]
+pub struct SortedVec(Vec<(i32, i32)>);
+
+impl Storage for SortedVec {
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        match self.0.binary_search_by_key(&timestamp, |&(t, _)| t) {
+            Ok(i) => self.0[i].1 = price,
+            Err(i) => self.0.insert(i, (timestamp, price)),
+        }
+    }
+
+    fn mean(&mut self, start: i32, end: i32) -> i32 {
+        if start > end {
+            return 0;
+        }
+        let from = self.0.partition_point(|&(t, _)| t < start);
+        let to = self.0.partition_point(|&(t, _)| t <= end);
+        if from == to {
+            return 0;
+        }
+        let total: i128 = self.0[from..to].iter().map(|&(_, p)| p as i128).sum();
+        (total / (to - from) as i128) as i32
+    }
+}
+
+/// What the server used before the treap: a map, and prefix sums over it
+/// rebuilt by the first query after an insert. Cheap for bursts, but a
+/// query after every few inserts rebuilds every time.
+#[derive(Default)]
+pub struct Rebuilt {
+    map: BTreeMap<i32, i32>,
+    timestamps: Vec<i32>,
+    /// `sums[i]` is the total of the first `i` prices in timestamp order.
+    sums: Vec<i128>,
+    stale: bool,
+}
+
+impl Storage for Rebuilt {
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        self.map.insert(timestamp, price);
+        self.stale = true;
+    }
+
+    fn mean(&mut self, start: i32, end: i32) -> i32 {
+        if start > end {
+            return 0;
+        }
+        if self.stale {
+            self.timestamps.clear();
+            self.sums.clear();
+            self.sums.push(0);
+            let mut total = 0;
+            for (&timestamp, &price) in &self.map {
+                total += price as i128;
+                self.timestamps.push(timestamp);
+                self.sums.push(total);
+            }
+            self.stale = false;
+        }
+        let from = self.timestamps.partition_point(|&t| t < start);
+        let to = self.timestamps.partition_point(|&t| t <= end);
+        if from == to {
+            return 0;
+        }
+        ((self.sums[to] - self.sums[from]) / (to - from) as i128) as i32
+    }
+}
+
+/// A Fenwick tree over every possible timestamp, with only the entries in
+/// use stored, in a hash map. Timestamps can't be compressed up front, since
+/// they arrive one at a time, but the tree has a fixed 2^32 slots, so
+/// inserting a price and summing before a timestamp each touch at most 32
+/// entries, whatever order they come in. The map of prices is only for
+/// telling an overwrite from a new price.
+#[derive(Default)]
+pub struct Fenwick {
+    prices: HashMap<i32, i32>,
+    /// `tree[i]` holds the count and total of the `i & i.wrapping_neg()`
+    /// slots up to slot `i`, where timestamp `t` is in slot
+    /// `t - i32::MIN + 1`.
+    tree: HashMap<u64, (i64, i128)>,
+}
+
+impl Fenwick {
+    fn slot(timestamp: i32) -> u64 {
+        (timestamp as i64 - i32::MIN as i64 + 1) as u64
+    }
+
+    fn add(&mut self, timestamp: i32, count: i64, total: i128) {
+        let mut i = Fenwick::slot(timestamp);
+        while i <= 1 << 32 {
+            let entry = self.tree.entry(i).or_default();
+            entry.0 += count;
+            entry.1 += total;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The count and total of the prices in the first `i` slots.
+    fn prefix(&self, mut i: u64) -> (i64, i128) {
+        let (mut count, mut total) = (0, 0);
+        while i > 0 {
+            if let Some(&(c, t)) = self.tree.get(&i) {
+                count += c;
+                total += t;
+            }
+            i &= i - 1;
+        }
+        (count, total)
+    }
+}
+
+impl Storage for Fenwick {
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        match self.prices.insert(timestamp, price) {
+            Some(old) => self.add(timestamp, 0, price as i128 - old as i128),
+            None => self.add(timestamp, 1, price as i128),
+        }
+    }
+
+    fn mean(&mut self, start: i32, end: i32) -> i32 {
+        if start > end {
+            return 0;
+        }
+        let (count, total) = self.prefix(Fenwick::slot(end));
+        let (skipped, skipped_total) = self.prefix(Fenwick::slot(start) - 1);
+        if count == skipped {
+            return 0;
+        }
+        ((total - skipped_total) / (count - skipped) as i128) as i32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        bursts, decode, interleaved, run, traffic, Fenwick, Indexed, Op, Rebuilt, Scan, SortedVec,
+        Storage,
+    };
+
+    fn means<S: Storage>(ops: &[super::Op]) -> Vec<i32> {
+        run(&mut S::default(), ops)
+    }
+
+    #[test]
+    fn storages_agree() {
+        let (min, max) = (i32::MIN, i32::MAX);
+        let edges = [(min, 1), (max, 5), (0, 9), (min, 3)]
+            .map(|(timestamp, price)| Op::Insert { timestamp, price })
+            .into_iter()
+            .chain(
+                [(min, max), (min, min), (max, max), (1, max)]
+                    .map(|(start, end)| Op::Query { start, end }),
+            )
+            .collect();
+        for ops in [bursts(3, 200, 20), interleaved(2000, 3), edges] {
+            let expected = means::<Scan>(&ops);
+            assert!(expected.iter().any(|&mean| mean != 0));
+            assert_eq!(means::<Indexed>(&ops), expected);
+            assert_eq!(means::<Rebuilt>(&ops), expected);
+            assert_eq!(means::<SortedVec>(&ops), expected);
+            assert_eq!(means::<Fenwick>(&ops), expected);
+        }
+    }
+
+    #[test]
+    fn decodes_traffic() {
+        for assets in [false, true] {
+            assert_eq!(decode(&traffic(1000, assets), assets), 1000);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! The entry point for the `bank` fuzz target in `fuzz/`.
+
+use crate::fuzz::Chunks;
+
+use super::{Config, Duplicates, OnInvalid, Session};
+
+/// Runs a session over `data`, panicking if anything misbehaves.
+///
+/// The first byte picks the setup: its low nibble how many bytes each read
+/// gets, from 1 to 16, and its high bits the duplicate and protocol error
+/// policies. The rest is the stream.
+pub fn session(data: &[u8]) {
+    let Some((&setup, stream)) = data.split_first() else {
+        return;
+    };
+    let size = (setup & 0xf) as usize + 1;
+    let duplicates = match (setup >> 4) & 0b11 {
+        0 => Duplicates::Overwrite,
+        1 => Duplicates::Ignore,
+        _ => Duplicates::Reject,
+    };
+    let on_invalid = match setup >> 6 {
+        0 => OnInvalid::Reply,
+        1 => OnInvalid::ReplyAndClose,
+        _ => OnInvalid::Close,
+    };
+
+    let reader = Chunks::new(stream, size);
+    let mut output = vec![];
+    let mut session = Session::new(Config {
+        duplicates,
+        on_invalid,
+        ..Config::default()
+    });
+    tokio_test::block_on(session.start(reader, &mut output)).unwrap();
+
+    // No message, even a truncated last one, gets more than "undefined
+    // behavior" back.
+    let most = stream.len().div_ceil(9) * b"undefined behavior".len();
+    assert!(output.len() <= most, "{} bytes out", output.len());
+}
+
+#[cfg(test)]
+mod test {
+    use super::session;
+
+    #[test]
+    fn seeds() {
+        let mut stream = vec![];
+        // I 12345 101, Q 12345 12345, I 12345 102 (a duplicate), P (invalid)
+        stream.extend_from_slice(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]);
+        stream.extend_from_slice(&[0x51, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x30, 0x39]);
+        stream.extend_from_slice(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x66]);
+        stream.extend_from_slice(&[0x50, 0x00, 0x00, 0x00]);
+        session(&[]);
+        for setup in 0..=255 {
+            let mut data = vec![setup];
+            data.extend_from_slice(&stream);
+            session(&data);
+        }
+    }
+}
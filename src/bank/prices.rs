@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+
+/// How a mean that isn't a whole number is rounded, which matters for negative
+/// means: -3.5 truncates to -3, but floors to -4.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) enum Rounding {
+    /// Toward zero.
+    #[default]
+    Truncate,
+    /// Toward negative infinity.
+    Floor,
+}
+
+/// A session's prices by timestamp, indexed so that inserting a price and
+/// finding a range's mean both cost O(log n), however the two are mixed.
+///
+/// The map answers lookups and scans; the index keeps running totals (see
+/// `Sums`). The `bank` benchmark measures bursts of inserts and queries, and
+/// the two interleaved, against the alternatives.
+#[derive(Default)]
+pub(super) struct Prices {
+    map: BTreeMap<i32, i32>,
+    sums: Sums,
+}
+
+impl Prices {
+    pub(super) fn insert(&mut self, timestamp: i32, price: i32) {
+        match self.map.insert(timestamp, price) {
+            Some(old) => self.sums.replace(timestamp, old, price),
+            None => self.sums.insert(timestamp, price),
+        }
+    }
+
+    pub(super) fn contains(&self, timestamp: i32) -> bool {
+        self.map.contains_key(&timestamp)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Every price, in timestamp order.
+    pub(super) fn iter(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.map
+            .iter()
+            .map(|(&timestamp, &price)| (timestamp, price))
+    }
+
+    /// The mean price in `start..=end`, or 0 if there are none.
+    pub(super) fn mean(&self, start: i32, end: i32, rounding: Rounding) -> i32 {
+        let (count, total) = self.sums.range(start, end);
+        if count == 0 {
+            return 0;
+        }
+        let count = count as i128;
+        // A mean of i32s is always within i32's range.
+        match rounding {
+            Rounding::Truncate => (total / count) as i32,
+            Rounding::Floor => total.div_euclid(count) as i32,
+        }
+    }
+
+    /// How many prices there are in `start..=end`.
+    pub(super) fn count(&self, start: i32, end: i32) -> usize {
+        self.sums.range(start, end).0
+    }
+
+    /// The lowest price in `start..=end`, if there are any. Unindexed, so this
+    /// scans the range.
+    pub(super) fn min(&self, start: i32, end: i32) -> Option<i32> {
+        self.range(start, end).min()
+    }
+
+    /// The highest price in `start..=end`, if there are any. Unindexed, so
+    /// this scans the range.
+    pub(super) fn max(&self, start: i32, end: i32) -> Option<i32> {
+        self.range(start, end).max()
+    }
+
+    fn range(&self, start: i32, end: i32) -> impl Iterator<Item = i32> + '_ {
+        // BTreeMap::range panics on a backwards range.
+        let range = (start <= end).then(|| self.map.range(start..=end));
+        range.into_iter().flatten().map(|(_, &price)| price)
+    }
+}
+
+/// The prices again, in a treap: a binary search tree by timestamp in which
+/// each node also has a random priority, and sits above every node of lower
+/// priority. That keeps the tree's depth logarithmic whatever order the
+/// timestamps arrive in, without any rebalancing.
+///
+/// Each node also holds the count and total of the prices in its subtree. An
+/// insert updates them on its way down, and the count and total of the
+/// prices before a timestamp come from one walk down the tree, so a range
+/// costs two walks.
+///
+/// Nodes live in a Vec and refer to each other by index. Prices are never
+/// removed, so nothing is ever freed.
+struct Sums {
+    nodes: Vec<Node>,
+    root: u32,
+    /// The state of the generator for priorities.
+    seed: u64,
+}
+
+/// No node: an empty subtree.
+const NIL: u32 = u32::MAX;
+
+struct Node {
+    timestamp: i32,
+    price: i32,
+    priority: u64,
+    left: u32,
+    right: u32,
+    /// How many prices there are in the subtree.
+    count: u32,
+    /// Their total. An i64 could overflow with every price at `i32::MIN`.
+    total: i128,
+}
+
+impl Default for Sums {
+    fn default() -> Self {
+        Sums {
+            nodes: vec![],
+            root: NIL,
+            seed: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+}
+
+impl Sums {
+    /// Adds a price for a timestamp that doesn't have one yet.
+    fn insert(&mut self, timestamp: i32, price: i32) {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let node = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            timestamp,
+            price,
+            priority: self.seed,
+            left: NIL,
+            right: NIL,
+            count: 1,
+            total: price as i128,
+        });
+        self.root = self.insert_below(self.root, node);
+    }
+
+    /// Inserts `node` into the subtree at `at`, returning the subtree's new
+    /// root.
+    fn insert_below(&mut self, at: u32, node: u32) -> u32 {
+        if at == NIL {
+            return node;
+        }
+        let timestamp = self.node(node).timestamp;
+        if self.node(node).priority > self.node(at).priority {
+            let (left, right) = self.split(at, timestamp);
+            let new = self.node_mut(node);
+            new.left = left;
+            new.right = right;
+            self.update(node);
+            return node;
+        }
+        if timestamp < self.node(at).timestamp {
+            let left = self.insert_below(self.node(at).left, node);
+            self.node_mut(at).left = left;
+        } else {
+            let right = self.insert_below(self.node(at).right, node);
+            self.node_mut(at).right = right;
+        }
+        self.update(at);
+        at
+    }
+
+    /// Splits the subtree at `at` into the nodes before `timestamp` and those
+    /// after it, returning the roots of the two.
+    fn split(&mut self, at: u32, timestamp: i32) -> (u32, u32) {
+        if at == NIL {
+            return (NIL, NIL);
+        }
+        let split = if self.node(at).timestamp < timestamp {
+            let (left, right) = self.split(self.node(at).right, timestamp);
+            self.node_mut(at).right = left;
+            (at, right)
+        } else {
+            let (left, right) = self.split(self.node(at).left, timestamp);
+            self.node_mut(at).left = right;
+            (left, at)
+        };
+        self.update(at);
+        split
+    }
+
+    /// Recomputes a node's count and total from its children's.
+    fn update(&mut self, at: u32) {
+        let (left, right) = (self.node(at).left, self.node(at).right);
+        let count = self.count(left) + self.count(right) + 1;
+        let total = self.total(left) + self.total(right);
+        let node = self.node_mut(at);
+        node.count = count;
+        node.total = total + node.price as i128;
+    }
+
+    /// Changes an existing timestamp's price from `old` to `price`.
+    fn replace(&mut self, timestamp: i32, old: i32, price: i32) {
+        let delta = price as i128 - old as i128;
+        let mut at = self.root;
+        loop {
+            let node = self.node_mut(at);
+            node.total += delta;
+            if timestamp == node.timestamp {
+                node.price = price;
+                return;
+            }
+            at = match timestamp < node.timestamp {
+                true => node.left,
+                false => node.right,
+            };
+        }
+    }
+
+    /// The count and total of the prices in `start..=end`.
+    fn range(&self, start: i32, end: i32) -> (usize, i128) {
+        if start > end {
+            return (0, 0);
+        }
+        let (count, total) = self.before(end as i64 + 1);
+        let (skipped, skipped_total) = self.before(start as i64);
+        ((count - skipped) as usize, total - skipped_total)
+    }
+
+    /// The count and total of the prices before `timestamp`, which is an i64
+    /// so that it can be past `i32::MAX`.
+    fn before(&self, timestamp: i64) -> (u32, i128) {
+        let (mut count, mut total) = (0, 0);
+        let mut at = self.root;
+        while at != NIL {
+            let node = self.node(at);
+            if (node.timestamp as i64) < timestamp {
+                count += self.count(node.left) + 1;
+                total += self.total(node.left) + node.price as i128;
+                at = node.right;
+            } else {
+                at = node.left;
+            }
+        }
+        (count, total)
+    }
+
+    fn count(&self, at: u32) -> u32 {
+        match at {
+            NIL => 0,
+            at => self.node(at).count,
+        }
+    }
+
+    fn total(&self, at: u32) -> i128 {
+        match at {
+            NIL => 0,
+            at => self.node(at).total,
+        }
+    }
+
+    fn node(&self, at: u32) -> &Node {
+        &self.nodes[at as usize]
+    }
+
+    fn node_mut(&mut self, at: u32) -> &mut Node {
+        &mut self.nodes[at as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{Prices, Rounding, Sums, NIL};
+    use crate::testutil::alloc::allocations;
+
+    /// What `Prices` replaced: a scan of the range.
+    fn scan_mean(prices: &BTreeMap<i32, i32>, start: i32, end: i32) -> i32 {
+        if start > end {
+            return 0;
+        }
+        let (total, count) = prices
+            .range(start..=end)
+            .fold((0i64, 0i64), |(total, count), (_, &p)| {
+                (total + p as i64, count + 1)
+            });
+        if count == 0 {
+            0
+        } else {
+            (total / count) as i32
+        }
+    }
+
+    /// A deterministic stream of pseudo-random numbers.
+    fn numbers(mut state: u64) -> impl Iterator<Item = i32> {
+        std::iter::from_fn(move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            Some((state >> 32) as i32)
+        })
+    }
+
+    #[test]
+    fn matches_scan() {
+        let mut prices = Prices::default();
+        let mut reference = BTreeMap::new();
+        let mut numbers = numbers(1).map(|n| n % 1000);
+        for _ in 0..100 {
+            for _ in 0..20 {
+                let (timestamp, price) = (numbers.next().unwrap(), numbers.next().unwrap());
+                prices.insert(timestamp, price);
+                reference.insert(timestamp, price);
+            }
+            for _ in 0..20 {
+                let (start, end) = (numbers.next().unwrap(), numbers.next().unwrap());
+                assert_eq!(
+                    prices.mean(start, end, Rounding::Truncate),
+                    scan_mean(&reference, start, end),
+                    "{start}..={end}"
+                );
+            }
+        }
+    }
+
+    fn depth(sums: &Sums, at: u32) -> usize {
+        match at {
+            NIL => 0,
+            at => 1 + depth(sums, sums.node(at).left).max(depth(sums, sums.node(at).right)),
+        }
+    }
+
+    #[test]
+    fn balanced() {
+        // In order is the worst case for a plain search tree.
+        let n = 100_000;
+        let mut prices = Prices::default();
+        for t in 0..n {
+            prices.insert(t, 1);
+        }
+        let depth = depth(&prices.sums, prices.sums.root);
+        assert!(depth < 60, "depth {depth} for {n} prices in order");
+        assert_eq!(prices.count(10, n), n as usize - 10);
+    }
+
+    #[test]
+    fn edges() {
+        let mut prices = Prices::default();
+        assert_eq!(prices.mean(i32::MIN, i32::MAX, Rounding::Truncate), 0);
+        prices.insert(i32::MIN, 10);
+        prices.insert(i32::MAX, 20);
+        prices.insert(0, 30);
+        assert_eq!(prices.mean(i32::MIN, i32::MAX, Rounding::Truncate), 20);
+        assert_eq!(prices.mean(i32::MIN, i32::MIN, Rounding::Truncate), 10);
+        assert_eq!(prices.mean(1, i32::MAX, Rounding::Truncate), 20);
+        assert_eq!(prices.mean(1, -1, Rounding::Truncate), 0);
+        // Overwriting a price updates the index.
+        prices.insert(0, 60);
+        assert_eq!(prices.mean(0, 0, Rounding::Truncate), 60);
+    }
+
+    #[test]
+    fn rounding() {
+        // (prices, truncated mean, floored mean)
+        let cases: [(&[i32], _, _); 5] = [
+            (&[-3, -4], -3, -4),
+            (&[3, 4], 3, 3),
+            (&[i32::MIN, i32::MAX], 0, -1),
+            (&[i32::MIN, i32::MIN, -1], -1431655765, -1431655766),
+            (&[i32::MIN; 3], i32::MIN, i32::MIN),
+        ];
+        for (values, truncated, floored) in cases {
+            let mut prices = Prices::default();
+            for (timestamp, &price) in values.iter().enumerate() {
+                prices.insert(timestamp as i32, price);
+            }
+            let (start, end) = (i32::MIN, i32::MAX);
+            assert_eq!(
+                prices.mean(start, end, Rounding::Truncate),
+                truncated,
+                "{values:?}"
+            );
+            assert_eq!(
+                prices.mean(start, end, Rounding::Floor),
+                floored,
+                "{values:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn count_min_max() {
+        let mut prices = Prices::default();
+        assert_eq!(prices.count(i32::MIN, i32::MAX), 0);
+        assert_eq!(prices.min(i32::MIN, i32::MAX), None);
+        for (timestamp, price) in [(1, 30), (2, -10), (3, 20), (5, 40)] {
+            prices.insert(timestamp, price);
+        }
+        assert_eq!(prices.count(i32::MIN, i32::MAX), 4);
+        assert_eq!(prices.count(2, 4), 2);
+        assert_eq!(prices.count(4, 2), 0);
+        assert_eq!(prices.min(1, 3), Some(-10));
+        assert_eq!(prices.max(1, 3), Some(30));
+        assert_eq!(prices.min(3, 5), Some(20));
+        assert_eq!(prices.max(4, 4), None);
+        assert_eq!(prices.max(5, 1), None);
+    }
+
+    #[test]
+    fn allocations_per_request() {
+        let n = 10_000;
+        let mut prices = Prices::default();
+        let new = allocations(|| (0..n).for_each(|t| prices.insert(t, t)));
+        // A map node holds up to 11 prices.
+        assert!(new < n as u64 / 5, "{new} allocations for {n} new prices");
+        let overwrites = allocations(|| (0..n).for_each(|t| prices.insert(t, -t)));
+        assert_eq!(overwrites, 0);
+        prices.mean(0, 0, Rounding::Truncate);
+        let queries = allocations(|| {
+            for t in 0..n {
+                prices.mean(t, t + 100, Rounding::Truncate);
+                prices.count(t, t + 100);
+                prices.min(t, t + 100);
+            }
+        });
+        assert_eq!(queries, 0);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+
+use super::prices::Prices;
+
+/// A file holding the prices of the sessions that were live at shutdown, one
+/// `<session id> <timestamp> <price>` line per price.
+///
+/// A session restored from it is the one given the same id after restart, so
+/// the first connection gets what the first connection had.
+pub(super) struct Snapshot {
+    path: PathBuf,
+    sessions: Mutex<HashMap<u32, Prices>>,
+}
+
+impl Snapshot {
+    /// Loads the snapshot at `path`, if there is one.
+    pub(super) fn load(path: impl AsRef<Path>) -> Result<Snapshot> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("{}", path.display())),
+        };
+        let mut sessions = HashMap::<_, Prices>::new();
+        for (n, line) in contents.lines().enumerate() {
+            let numbers: Vec<_> = line.split(' ').collect();
+            let [id, timestamp, price] = numbers[..] else {
+                anyhow::bail!("{}:{}: expected 3 numbers", path.display(), n + 1);
+            };
+            let number = || format!("{}:{}: bad number", path.display(), n + 1);
+            let id = id.parse().with_context(number)?;
+            let timestamp = timestamp.parse().with_context(number)?;
+            let price = price.parse().with_context(number)?;
+            sessions.entry(id).or_default().insert(timestamp, price);
+        }
+        let sessions = Mutex::new(sessions);
+        Ok(Snapshot { path, sessions })
+    }
+
+    /// Takes the prices saved for session `id`.
+    pub(super) fn take(&self, id: u32) -> Option<Prices> {
+        self.sessions.lock().unwrap().remove(&id)
+    }
+
+    /// Keeps a live session's prices to be saved.
+    pub(super) fn put(&self, id: u32, prices: Prices) {
+        self.sessions.lock().unwrap().insert(id, prices);
+    }
+
+    /// Writes out every session put since loading, along with any that were
+    /// loaded but never taken.
+    pub(super) fn save(&self) -> Result<()> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut ids: Vec<_> = sessions.keys().collect();
+        ids.sort();
+        let mut contents = String::new();
+        for id in ids {
+            for (timestamp, price) in sessions[id].iter() {
+                writeln!(contents, "{id} {timestamp} {price}").unwrap();
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Prices, Snapshot};
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("bank-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let snapshot = Snapshot::load(&path).unwrap();
+        assert!(snapshot.take(0).is_none());
+        let mut prices = Prices::default();
+        prices.insert(-5, 10);
+        prices.insert(i32::MAX, i32::MIN);
+        snapshot.put(3, prices);
+        snapshot.put(0, Prices::default());
+        snapshot.save().unwrap();
+
+        let snapshot = Snapshot::load(&path).unwrap();
+        let prices: Vec<_> = snapshot.take(3).unwrap().iter().collect();
+        assert_eq!(prices, [(-5, 10), (i32::MAX, i32::MIN)]);
+        // A session without prices leaves nothing to restore.
+        assert!(snapshot.take(0).is_none());
+        assert!(snapshot.take(3).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
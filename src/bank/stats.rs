@@ -0,0 +1,152 @@
+use std::{fmt, time::Duration};
+
+/// What a session has done, logged when it closes.
+#[derive(Default)]
+pub(super) struct Stats {
+    pub(super) inserts: usize,
+    pub(super) queries: usize,
+    /// Protocol errors, including rejected inserts.
+    pub(super) errors: usize,
+    /// How many queries took how long (see `bucket`). Empty until the first
+    /// query, so a session that never queries doesn't pay for it.
+    latencies: Vec<u32>,
+    /// The longest query, exactly.
+    max: Duration,
+}
+
+/// Each power of two of nanoseconds is split into this many buckets, so a
+/// latency is known to within an eighth.
+const SUB_BUCKETS: u64 = 8;
+/// Latencies from 2^40ns, about 18 minutes, all land in the last bucket.
+const MAX_LATENCY: u64 = (1 << 40) - 1;
+const BUCKETS: usize = bucket(MAX_LATENCY) + 1;
+
+/// The bucket for a latency of `ns`: below 16ns each has its own, and above
+/// that each power of two is split into `SUB_BUCKETS` equal parts.
+const fn bucket(ns: u64) -> usize {
+    if ns < 2 * SUB_BUCKETS {
+        return ns as usize;
+    }
+    let exp = 63 - ns.leading_zeros() as u64;
+    let sub = (ns >> (exp - 3)) - SUB_BUCKETS;
+    ((exp - 2) * SUB_BUCKETS + sub) as usize
+}
+
+/// The longest latency in `bucket`.
+fn bucket_max(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < 2 * SUB_BUCKETS {
+        return bucket;
+    }
+    let (exp, sub) = (bucket / SUB_BUCKETS + 2, bucket % SUB_BUCKETS);
+    ((SUB_BUCKETS + sub + 1) << (exp - 3)) - 1
+}
+
+impl Stats {
+    pub(super) fn query(&mut self, latency: Duration) {
+        self.queries += 1;
+        if self.latencies.is_empty() {
+            self.latencies = vec![0; BUCKETS];
+        }
+        let ns = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.latencies[bucket(ns.min(MAX_LATENCY))] += 1;
+        self.max = self.max.max(latency);
+    }
+
+    /// The latency that `p` percent of queries were at most, or zero if there
+    /// were none. It's the top of the bucket the query at that rank is in, so
+    /// up to an eighth more than the query took, but never more than `max`.
+    fn percentile(&self, p: usize) -> Duration {
+        // Nearest rank
+        let rank = (self.queries * p).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.latencies.iter().enumerate() {
+            seen += count as usize;
+            if seen >= rank {
+                // The last bucket has no top.
+                return match bucket == BUCKETS - 1 {
+                    true => self.max,
+                    false => Duration::from_nanos(bucket_max(bucket)).min(self.max),
+                };
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "inserts={} queries={} errors={} query p50={:?} p90={:?} p99={:?} max={:?}",
+            self.inserts,
+            self.queries,
+            self.errors,
+            self.percentile(50),
+            self.percentile(90),
+            self.percentile(99),
+            self.max,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{bucket, bucket_max, Stats, BUCKETS, MAX_LATENCY};
+
+    #[test]
+    fn display() {
+        let mut stats = Stats::default();
+        assert_eq!(
+            stats.to_string(),
+            "inserts=0 queries=0 errors=0 query p50=0ns p90=0ns p99=0ns max=0ns"
+        );
+        stats.inserts = 3;
+        stats.errors = 1;
+        for ns in (1..=10).rev() {
+            stats.query(Duration::from_nanos(ns));
+        }
+        // Short latencies are exact.
+        assert_eq!(
+            stats.to_string(),
+            "inserts=3 queries=10 errors=1 query p50=5ns p90=9ns p99=10ns max=10ns"
+        );
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut stats = Stats::default();
+        for ms in (1..=100).rev() {
+            stats.query(Duration::from_millis(ms));
+        }
+        for p in [1, 50, 90, 99] {
+            let exact = Duration::from_millis(p as u64);
+            let estimate = stats.percentile(p);
+            assert!(
+                exact <= estimate && estimate <= exact * 9 / 8,
+                "p{p} = {estimate:?}"
+            );
+        }
+        assert_eq!(stats.percentile(100), Duration::from_millis(100));
+        // However long, a query counts.
+        stats.query(Duration::from_secs(1 << 20));
+        assert_eq!(stats.latencies.len(), BUCKETS);
+        assert_eq!(stats.percentile(100), Duration::from_secs(1 << 20));
+    }
+
+    #[test]
+    fn buckets() {
+        // Every latency is in a bucket whose top is at most an eighth more.
+        for ns in (0..4096).chain([123_456_789, MAX_LATENCY - 1, MAX_LATENCY]) {
+            let max = bucket_max(bucket(ns));
+            assert!(ns <= max && max <= ns * 9 / 8, "{ns}ns up to {max}ns");
+        }
+        // The buckets follow each other with no gaps.
+        for i in 1..BUCKETS {
+            assert_eq!(bucket(bucket_max(i - 1) + 1), i);
+        }
+        assert_eq!(bucket_max(BUCKETS - 1), MAX_LATENCY);
+    }
+}
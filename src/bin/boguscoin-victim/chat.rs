@@ -0,0 +1,117 @@
+//! A stand-in for the Budget Chat server behind the proxy. It plays the whole
+//! room for the one client that connects: the welcome prompt, the name
+//! handshake, and then relaying lines to and from the other users. It fails
+//! with errors rather than panics, so that each case can be reported.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+};
+
+/// How long to wait for the other end before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const WELCOME: &str = "Welcome to budgetchat! What shall I call you?";
+
+/// What the server tells a user who joins about the users already there.
+pub fn room(others: &[&str]) -> String {
+    format!("* The room contains: {}", others.join(", "))
+}
+
+/// A line at a time over TCP, giving up on the other end after `TIMEOUT`.
+pub struct LineConn {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl LineConn {
+    pub fn new(stream: TcpStream) -> LineConn {
+        let (reader, writer) = stream.into_split();
+        LineConn {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    pub async fn send(&mut self, line: &str) -> Result<()> {
+        Ok(self
+            .writer
+            .write_all(format!("{line}\n").as_bytes())
+            .await?)
+    }
+
+    pub async fn recv(&mut self) -> Result<String> {
+        match tokio::time::timeout(TIMEOUT, self.lines.next_line()).await {
+            Ok(line) => line?.context("closed"),
+            Err(_) => bail!("nothing within {TIMEOUT:?}"),
+        }
+    }
+}
+
+/// The server's end of one client's session.
+pub struct ChatUpstream(LineConn);
+
+impl ChatUpstream {
+    /// Waits for a client to connect to `listener`.
+    pub async fn accept(listener: &TcpListener) -> Result<ChatUpstream> {
+        let (stream, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+            .await
+            .context("nobody connected")??;
+        Ok(ChatUpstream(LineConn::new(stream)))
+    }
+
+    /// Asks the client for its name.
+    pub async fn welcome(&mut self) -> Result<()> {
+        self.0.send(WELCOME).await
+    }
+
+    /// Takes the client's name and tells it `others` are in the room,
+    /// returning the name.
+    pub async fn join(&mut self, others: &[&str]) -> Result<String> {
+        let name = self.0.recv().await?;
+        self.0.send(&room(others)).await?;
+        Ok(name)
+    }
+
+    /// Relays `line` to the client as a message from `from`.
+    pub async fn say(&mut self, from: &str, line: &str) -> Result<()> {
+        self.0.send(&format!("[{from}] {line}")).await
+    }
+
+    /// The next message the client sends to the room.
+    pub async fn recv(&mut self) -> Result<String> {
+        self.0.recv().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{room, ChatUpstream, LineConn, WELCOME};
+
+    #[tokio::test]
+    async fn session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = LineConn::new(TcpStream::connect(addr).await.unwrap());
+        let mut server = ChatUpstream::accept(&listener).await.unwrap();
+
+        server.welcome().await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), WELCOME);
+        client.send("alice").await.unwrap();
+        assert_eq!(server.join(&["bob", "carol"]).await.unwrap(), "alice");
+        assert_eq!(client.recv().await.unwrap(), room(&["bob", "carol"]));
+
+        client.send("hi all").await.unwrap();
+        assert_eq!(server.recv().await.unwrap(), "hi all");
+        server.say("bob", "hi alice").await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), "[bob] hi alice");
+    }
+}
@@ -0,0 +1,209 @@
+//! A victim for testing a Mob in the Middle proxy, with the chat server
+//! behind the proxy played by a fixture, so that both ends of every message
+//! can be compared.
+//!
+//! Usage: boguscoin-victim [--proxy addr] [--upstream addr]
+//!
+//! Start the proxy with `upstream` (127.0.0.1:16963 by default) as its chat
+//! server first. Then this listens there, connects to the proxy, and joins as
+//! a chat client. It sends lines with valid and nearly valid Boguscoin
+//! addresses in various places, in both directions, and checks that exactly
+//! the valid ones come out as Tony's.
+
+use std::env;
+
+use anyhow::{bail, Context, Result};
+use tokio::net::{TcpListener, TcpStream};
+
+use self::chat::{room, ChatUpstream, LineConn, WELCOME};
+
+mod chat;
+
+const USAGE: &str = "usage: boguscoin-victim [--proxy addr] [--upstream addr]";
+
+const TONY: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
+
+/// An address-like word of `len` characters starting with `first`.
+fn address(first: char, len: usize) -> String {
+    let chars = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let rest = (1..len).map(|i| chars[(i * 7) % chars.len()] as char);
+    std::iter::once(first).chain(rest).collect()
+}
+
+/// Lines to send, by what's in them.
+fn cases() -> Vec<(&'static str, String)> {
+    let short = address('7', 26);
+    let long = address('7', 35);
+    vec![
+        (
+            "at the end",
+            format!("Please pay the ticket price to {short}"),
+        ),
+        ("at the start", format!("{long} is my address")),
+        ("alone", long.clone()),
+        ("twice", format!("either {short} or {long} works")),
+        ("already Tony's", format!("pay {TONY} please")),
+        ("25 characters", format!("pay {} please", address('7', 25))),
+        ("36 characters", format!("pay {} please", address('7', 36))),
+        (
+            "not starting with 7",
+            format!("pay {} please", address('8', 30)),
+        ),
+        ("a dash in it", format!("pay {}-x please", address('7', 29))),
+        ("after a letter", format!("pay x{short} please")),
+        ("before a full stop", format!("pay {short}.")),
+        (
+            "in a product ID",
+            format!("product ID {short}-{long}, not an address"),
+        ),
+        ("double spaces", format!("pay  {short}  please")),
+    ]
+}
+
+fn is_address(word: &str) -> bool {
+    (26..=35).contains(&word.len())
+        && word.starts_with('7')
+        && word.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// What a correct proxy makes of `line`: each address, alone between spaces
+/// or the ends of the line, replaced by Tony's.
+fn rewritten(line: &str) -> String {
+    line.split(' ')
+        .map(|word| if is_address(word) { TONY } else { word })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Checks that `got` is `sent` as a proxy should pass it on.
+fn check(sent: &str, got: Result<String>) -> Result<()> {
+    let got = got?;
+    let expected = rewritten(sent);
+    if got != expected {
+        bail!("sent {sent:?}, expected {expected:?}, got {got:?}");
+    }
+    Ok(())
+}
+
+/// Sends `line` from the victim to the room.
+async fn from_victim(client: &mut LineConn, server: &mut ChatUpstream, line: &str) -> Result<()> {
+    client.send(line).await?;
+    check(line, server.recv().await)
+}
+
+/// Sends `line` from bob to the victim.
+async fn to_victim(server: &mut ChatUpstream, client: &mut LineConn, line: &str) -> Result<()> {
+    server.say("bob", line).await?;
+    check(&format!("[bob] {line}"), client.recv().await)
+}
+
+/// Joins the chat through the proxy at `proxy`, playing the chat server on
+/// `upstream`, and returns how each case went. Fails if the proxy can't be
+/// joined at all.
+async fn run(proxy: &str, upstream: &TcpListener) -> Result<Vec<Result<()>>> {
+    let mut client = LineConn::new(
+        TcpStream::connect(proxy)
+            .await
+            .with_context(|| format!("connecting to {proxy}"))?,
+    );
+    let mut server = ChatUpstream::accept(upstream)
+        .await
+        .context("the proxy didn't connect")?;
+
+    server.welcome().await?;
+    check(WELCOME, client.recv().await)?;
+    client.send("victim").await?;
+    check("victim", server.join(&["bob"]).await)?;
+    check(&room(&["bob"]), client.recv().await)?;
+
+    let mut outcomes = vec![];
+    for (_, line) in cases() {
+        let sent = from_victim(&mut client, &mut server, &line)
+            .await
+            .context("from the victim");
+        let received = to_victim(&mut server, &mut client, &line)
+            .await
+            .context("to the victim");
+        outcomes.push(sent.and(received));
+    }
+    Ok(outcomes)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let mut proxy = "127.0.0.1:10000".to_string();
+    let mut upstream = "127.0.0.1:16963".to_string();
+    while let Some(flag) = args.next() {
+        let value = args.next().context(USAGE)?;
+        match flag.as_str() {
+            "--proxy" => proxy = value,
+            "--upstream" => upstream = value,
+            _ => bail!(USAGE),
+        }
+    }
+
+    let listener = TcpListener::bind(&upstream)
+        .await
+        .with_context(|| format!("listening on {upstream}"))?;
+    let outcomes = run(&proxy, &listener)
+        .await
+        .with_context(|| format!("with the proxy's chat server on {upstream}"))?;
+
+    let mut passed = 0;
+    for ((name, _), outcome) in cases().iter().zip(&outcomes) {
+        match outcome {
+            Ok(()) => {
+                println!("PASS {name}");
+                passed += 1;
+            }
+            Err(e) => println!("FAIL {name}, {e:#}"),
+        }
+    }
+    println!("{passed}/{} passed", outcomes.len());
+    if passed < outcomes.len() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{cases, rewritten, run};
+
+    /// A proxy that rewrites nothing: the handshake goes through, and so does
+    /// every case with no address in it, but none of the others.
+    #[tokio::test]
+    async fn pass_through() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut client, _) = proxy.accept().await.unwrap();
+            let mut server = TcpStream::connect(upstream_addr).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+        });
+
+        let outcomes = run(&proxy_addr, &upstream).await.unwrap();
+        let cases = cases();
+        assert_eq!(outcomes.len(), cases.len());
+        for ((name, line), outcome) in cases.iter().zip(outcomes) {
+            match outcome {
+                Ok(()) => assert_eq!(&rewritten(line), line, "{name}"),
+                Err(e) => {
+                    assert_ne!(&rewritten(line), line, "{name}: {e:#}");
+                    assert!(
+                        format!("{e:#}").starts_with("from the victim: sent"),
+                        "{e:#}"
+                    );
+                }
+            }
+        }
+        // Both kinds are covered.
+        assert!(cases.iter().any(|(_, line)| &rewritten(line) == line));
+        assert!(cases.iter().any(|(_, line)| &rewritten(line) != line));
+    }
+}
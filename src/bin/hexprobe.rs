@@ -0,0 +1,158 @@
+//! A probe for poking at a binary protocol by hand: connects to a server,
+//! sends the given bytes, and hex-dumps everything it gets back.
+//!
+//! Usage: hexprobe [--hex] [--idle ms] <addr> [data]...
+//!
+//! Each `data` argument is sent as one write, escaped like capture files, so
+//! `\n`, `\r`, `\t`, `\\` and `\xNN`. With `--hex` they're hex digits
+//! instead, with any spaces ignored. Without any, each line of stdin is one
+//! write, without its newline, and lines that don't parse are skipped.
+//!
+//! Both directions are dumped, with the time since connecting and the
+//! offset in the stream. Once everything is sent, it waits for the server to
+//! close the connection, or to send nothing for `ms` (2000 by default).
+
+use std::{
+    env,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use protohackers::record::unescape;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+const USAGE: &str = "usage: hexprobe [--hex] [--idle ms] <addr> [data]...";
+
+fn parse(data: &str, hex: bool) -> Result<Vec<u8>> {
+    if !hex {
+        return unescape(data).map_err(anyhow::Error::msg);
+    }
+    let digits: Vec<u8> = data.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        bail!("odd number of hex digits in {data:?}");
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)?;
+            u8::from_str_radix(pair, 16).with_context(|| format!("bad hex {pair:?}"))
+        })
+        .collect()
+}
+
+/// `data` as lines of 16 bytes, each with its offset, its bytes in hex, and
+/// its printable ASCII, under a heading `arrow` with the time since `start`.
+fn dump(start: Instant, arrow: char, offset: usize, data: &[u8]) -> String {
+    let mut text = format!(
+        "{:>9.3}s {arrow} {} bytes\n",
+        start.elapsed().as_secs_f64(),
+        data.len()
+    );
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(text, "  {:08x}  ", offset + i * 16).unwrap();
+        for j in 0..16 {
+            match line.get(j) {
+                Some(byte) => write!(text, "{byte:02x} ").unwrap(),
+                None => text.push_str("   "),
+            }
+            if j == 7 {
+                text.push(' ');
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| match b {
+                b' '..=b'~' => b as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(text, " |{ascii}|").unwrap();
+    }
+    text
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args().skip(1).peekable();
+    let mut hex = false;
+    let mut idle = Duration::from_millis(2000);
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--hex" => hex = true,
+            "--idle" => {
+                let ms = args.next().context(USAGE)?;
+                idle = Duration::from_millis(ms.parse().context("bad --idle")?);
+            }
+            _ => bail!(USAGE),
+        }
+    }
+    let addr = args.next().context(USAGE)?;
+    let writes = args
+        .map(|data| parse(&data, hex))
+        .collect::<Result<Vec<_>>>()?;
+
+    let stream = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("connecting to {addr}"))?;
+    // Each write should be its own segment, as it would be from a client.
+    stream.set_nodelay(true)?;
+    let start = Instant::now();
+    let (mut reader, mut writer) = stream.into_split();
+
+    let sent = Arc::new(AtomicBool::new(false));
+    let receive = tokio::spawn({
+        let sent = sent.clone();
+        async move {
+            let mut buf = vec![0; 4096];
+            let mut offset = 0;
+            loop {
+                match tokio::time::timeout(idle, reader.read(&mut buf)).await {
+                    Err(_) if sent.load(Ordering::Relaxed) => {
+                        println!("{:>9.3}s idle, stopping", start.elapsed().as_secs_f64());
+                        return Ok(());
+                    }
+                    Err(_) => {}
+                    Ok(Ok(0)) => {
+                        println!("{:>9.3}s < closed", start.elapsed().as_secs_f64());
+                        return Ok(());
+                    }
+                    Ok(Ok(len)) => {
+                        print!("{}", dump(start, '<', offset, &buf[..len]));
+                        offset += len;
+                    }
+                    Ok(Err(e)) => return Err(e.into()),
+                }
+            }
+        }
+    });
+
+    let mut offset = 0;
+    let mut send = |data: Vec<u8>| {
+        print!("{}", dump(start, '>', offset, &data));
+        offset += data.len();
+        data
+    };
+    if writes.is_empty() {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await? {
+            match parse(&line, hex) {
+                Ok(data) => writer.write_all(&send(data)).await?,
+                Err(e) => eprintln!("not sent: {e:#}"),
+            }
+        }
+    } else {
+        for data in writes {
+            writer.write_all(&send(data)).await?;
+        }
+    }
+    sent.store(true, Ordering::Relaxed);
+    receive.await?
+}
@@ -0,0 +1,164 @@
+//! Load test for a running Job Centre server.
+//!
+//! Usage: job-centre-load [addr] [producers] [workers] [jobs per producer]
+
+use std::{
+    collections::HashSet,
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+const QUEUES: usize = 10;
+
+struct Connection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    async fn open(addr: &str) -> Result<Connection> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        let lines = BufReader::new(reader).lines();
+        Ok(Connection { lines, writer })
+    }
+
+    /// Sends a request and returns the response along with the round trip.
+    async fn request(&mut self, request: &Value) -> Result<(Value, Duration)> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        let start = Instant::now();
+        self.writer.write_all(&line).await?;
+        let Some(response) = self.lines.next_line().await? else {
+            bail!("server closed the connection");
+        };
+        Ok((serde_json::from_str(&response)?, start.elapsed()))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let addr = args.next().unwrap_or("127.0.0.1:10000".to_string());
+    let producers: usize = args.next().map_or(Ok(8), |a| a.parse())?;
+    let workers: usize = args.next().map_or(Ok(8), |a| a.parse())?;
+    let jobs: usize = args.next().map_or(Ok(1000), |a| a.parse())?;
+
+    let start = Instant::now();
+    let done = Arc::new(AtomicBool::new(false));
+    // Jobs currently held by some worker; a second holder is a bug.
+    let held = Arc::new(Mutex::new(HashSet::new()));
+
+    let mut puts = vec![];
+    for p in 0..producers {
+        let mut conn = Connection::open(&addr).await?;
+        puts.push(tokio::spawn(async move {
+            let mut latencies = vec![];
+            for i in 0..jobs {
+                let request = json!({
+                    "request": "put",
+                    "queue": format!("load{}", (p + i) % QUEUES),
+                    "job": { "producer": p, "n": i },
+                    "pri": (p * jobs + i) % 100,
+                });
+                let (response, latency) = conn.request(&request).await?;
+                if response["status"] != "ok" {
+                    bail!("put failed: {response}");
+                }
+                latencies.push(latency);
+            }
+            Ok::<_, anyhow::Error>(latencies)
+        }));
+    }
+
+    let mut gets = vec![];
+    for _ in 0..workers {
+        let mut conn = Connection::open(&addr).await?;
+        let (done, held) = (done.clone(), held.clone());
+        gets.push(tokio::spawn(async move {
+            let queues: Vec<_> = (0..QUEUES).map(|q| format!("load{q}")).collect();
+            let get = json!({ "request": "get", "queues": queues });
+            let mut latencies = vec![];
+            loop {
+                // Producers finishing before the get was sent means an empty
+                // reply really is the end.
+                let finished = done.load(Ordering::Acquire);
+                let (response, latency) = conn.request(&get).await?;
+                if response["status"] == "no-job" {
+                    if finished {
+                        break;
+                    }
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+                latencies.push(latency);
+                let id = response["id"].as_u64().unwrap_or_default();
+                if !held.lock().unwrap().insert(id) {
+                    bail!("job {id} delivered to two workers at once");
+                }
+                let (response, _) = conn
+                    .request(&json!({ "request": "delete", "id": id }))
+                    .await?;
+                if response["status"] != "ok" {
+                    bail!("delete {id} failed: {response}");
+                }
+                held.lock().unwrap().remove(&id);
+            }
+            Ok::<_, anyhow::Error>(latencies)
+        }));
+    }
+
+    let mut put_latencies = vec![];
+    for task in puts {
+        put_latencies.extend(task.await??);
+    }
+    done.store(true, Ordering::Release);
+    let mut get_latencies = vec![];
+    for task in gets {
+        get_latencies.extend(task.await??);
+    }
+
+    let elapsed = start.elapsed();
+    println!("{producers} producers, {workers} workers, {elapsed:?}");
+    report("put", &mut put_latencies);
+    report("get", &mut get_latencies);
+    if put_latencies.len() != get_latencies.len() {
+        bail!(
+            "{} jobs put but {} delivered",
+            put_latencies.len(),
+            get_latencies.len()
+        );
+    }
+    Ok(())
+}
+
+fn report(name: &str, latencies: &mut [Duration]) {
+    latencies.sort();
+    let pct = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "{name}: n={} p50={:?} p90={:?} p99={:?} max={:?}",
+        latencies.len(),
+        pct(50),
+        pct(90),
+        pct(99),
+        latencies.last().copied().unwrap_or_default()
+    );
+}
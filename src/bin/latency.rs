@@ -0,0 +1,261 @@
+//! Latency harness for a running server: connections sending a weighted mix
+//! of request types, with percentiles reported for each type, to check that
+//! small requests stay fast while bulk ones are in flight.
+//!
+//! Usage:
+//!   latency <smoke|prime|bank|jobs|vcs> [--addr addr] [--connections n]
+//!     [--duration secs] [--mix type=weight,...]
+//!
+//! The request types, and the default mix, are:
+//!
+//! - smoke: `small=9,bulk=1`, echoing 100 bytes or 1 MiB
+//! - prime: `small=9,big=1`, checking a 7 digit number or a 600 digit one
+//! - bank: `query=9,bulk=1`, one insert and a query, or 10000 inserts and a
+//!   query
+//! - jobs: `put=1,take=1`, a put, or a get and delete of what it got
+//! - vcs: `list=3,get=3,put=3,bulk=1`, with puts of 1 KiB or 1 MiB. Every
+//!   put is kept, so a long run fills the server's memory
+//!
+//! Each connection picks the type of each request in turn, and sends it once
+//! the last is answered.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use protohackers::clients::{bank, job_centre, prime_time, vcs};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+const USAGE: &str = "usage: latency <smoke|prime|bank|jobs|vcs> [--addr addr] [--connections n] \
+    [--duration secs] [--mix type=weight,...]";
+
+struct Options {
+    problem: String,
+    addr: String,
+    connections: usize,
+    duration: Duration,
+    /// Each request type, with how often it's picked relative to the others.
+    mix: Vec<(String, u64)>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let Some(problem) = args.next() else {
+            bail!(USAGE);
+        };
+        let mix = match problem.as_str() {
+            "smoke" => "small=9,bulk=1",
+            "prime" => "small=9,big=1",
+            "bank" => "query=9,bulk=1",
+            "jobs" => "put=1,take=1",
+            "vcs" => "list=3,get=3,put=3,bulk=1",
+            _ => bail!(USAGE),
+        };
+        let mut options = Options {
+            problem,
+            addr: "127.0.0.1:10000".to_string(),
+            connections: 10,
+            duration: Duration::from_secs(10),
+            mix: parse_mix(mix)?,
+        };
+        while let Some(flag) = args.next() {
+            let value = args.next().context(USAGE)?;
+            let number = || value.parse::<u64>().with_context(|| format!("bad {flag}"));
+            match flag.as_str() {
+                "--addr" => options.addr = value,
+                "--connections" => options.connections = number()? as usize,
+                "--duration" => options.duration = Duration::from_secs(number()?),
+                "--mix" => options.mix = parse_mix(&value)?,
+                _ => bail!(USAGE),
+            }
+        }
+        let defaults = parse_mix(mix)?;
+        for (kind, _) in &options.mix {
+            ensure!(
+                defaults.iter().any(|(k, _)| k == kind),
+                "{} has no {kind:?} requests, only {mix}",
+                options.problem
+            );
+        }
+        ensure!(
+            options.mix.iter().any(|&(_, weight)| weight > 0),
+            "every weight is 0"
+        );
+        Ok(options)
+    }
+
+    /// The request type to send `n`th: each in proportion to its weight,
+    /// spread out rather than in runs.
+    fn pick(&self, n: u64) -> &str {
+        let total: u64 = self.mix.iter().map(|(_, weight)| weight).sum();
+        // Knuth's multiplicative hash, to scatter consecutive requests
+        let mut slot = n.wrapping_mul(2654435761) % total;
+        for (kind, weight) in &self.mix {
+            if slot < *weight {
+                return kind;
+            }
+            slot -= weight;
+        }
+        unreachable!()
+    }
+}
+
+fn parse_mix(mix: &str) -> Result<Vec<(String, u64)>> {
+    mix.split(',')
+        .map(|entry| {
+            let (kind, weight) = entry.split_once('=').context("expected type=weight")?;
+            let weight = weight
+                .parse()
+                .with_context(|| format!("bad weight {weight}"))?;
+            Ok((kind.to_string(), weight))
+        })
+        .collect()
+}
+
+enum Connection {
+    Smoke(TcpStream),
+    Prime(prime_time::Client<OwnedReadHalf, OwnedWriteHalf>),
+    Bank(bank::Client<OwnedReadHalf, OwnedWriteHalf>),
+    Jobs(job_centre::Client<OwnedReadHalf, OwnedWriteHalf>),
+    Vcs(vcs::Client<OwnedReadHalf, OwnedWriteHalf>),
+}
+
+impl Connection {
+    async fn open(options: &Options) -> Result<Connection> {
+        let addr = &options.addr;
+        Ok(match options.problem.as_str() {
+            "smoke" => Connection::Smoke(TcpStream::connect(addr).await?),
+            "prime" => Connection::Prime(prime_time::Client::connect(addr).await?),
+            "bank" => Connection::Bank(bank::Client::connect(addr).await?),
+            "jobs" => Connection::Jobs(job_centre::Client::connect(addr).await?),
+            "vcs" => {
+                let mut client = vcs::Client::connect(addr).await?;
+                client.put("/latency/small", &text(1 << 10)).await?;
+                Connection::Vcs(client)
+            }
+            _ => bail!(USAGE),
+        })
+    }
+
+    /// Sends the `i`th request, of type `kind`, and waits for its answer.
+    async fn request(&mut self, kind: &str, i: u64) -> Result<()> {
+        match (self, kind) {
+            (Connection::Smoke(stream), _) => {
+                let payload = text(if kind == "bulk" { 1 << 20 } else { 100 });
+                let (mut reader, mut writer) = stream.split();
+                let mut echo = vec![0; payload.len()];
+                let (sent, received) =
+                    tokio::join!(writer.write_all(&payload), reader.read_exact(&mut echo));
+                sent?;
+                received?;
+                ensure!(echo == payload, "echo differs");
+            }
+            (Connection::Prime(client), "small") => {
+                client.is_prime("1000003").await?;
+            }
+            (Connection::Prime(client), _) => {
+                client.is_prime(&format!("{}7", "1".repeat(599))).await?;
+            }
+            (Connection::Bank(client), _) => {
+                let inserts = if kind == "bulk" { 10_000 } else { 1 };
+                let base = (i as i32).wrapping_mul(10_000);
+                for n in 0..inserts {
+                    client.insert(base.wrapping_add(n), n).await?;
+                }
+                client.query(base, base.wrapping_add(inserts)).await?;
+            }
+            (Connection::Jobs(client), "put") => {
+                client.put("latency", &json!({ "n": i }), i % 100).await?;
+            }
+            (Connection::Jobs(client), _) => {
+                if let Some(job) = client.get(&["latency"], false).await? {
+                    client.delete(job.id).await?;
+                }
+            }
+            (Connection::Vcs(client), "list") => {
+                client.list("/latency").await?;
+            }
+            (Connection::Vcs(client), "get") => {
+                client.get("/latency/small", None).await?;
+            }
+            (Connection::Vcs(client), _) => {
+                let len = if kind == "bulk" { 1 << 20 } else { 1 << 10 };
+                client.put(&format!("/latency/{kind}"), &text(len)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `len` bytes of text, in lines, which every problem accepts.
+fn text(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| if i % 64 == 63 { b'\n' } else { b'a' })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+    let deadline = Instant::now() + options.duration;
+
+    let mut connections = vec![];
+    for _ in 0..options.connections {
+        connections.push(Connection::open(&options).await?);
+    }
+    let tasks = connections.into_iter().enumerate().map(|(c, mut conn)| {
+        let options = &options;
+        async move {
+            let mut latencies: Vec<(String, Duration)> = vec![];
+            // Each connection starts at a different point in the mix.
+            for i in (c as u64 * 7919).. {
+                if Instant::now() >= deadline {
+                    break;
+                }
+                let kind = options.pick(i);
+                let sent = Instant::now();
+                conn.request(kind, i).await?;
+                latencies.push((kind.to_string(), sent.elapsed()));
+            }
+            anyhow::Ok(latencies)
+        }
+    });
+    let mut by_kind: BTreeMap<String, Vec<Duration>> = BTreeMap::new();
+    for result in futures::future::join_all(tasks).await {
+        for (kind, latency) in result? {
+            by_kind.entry(kind).or_default().push(latency);
+        }
+    }
+
+    println!(
+        "{} connections, {:?}:",
+        options.connections, options.duration
+    );
+    for (kind, latencies) in &mut by_kind {
+        report(kind, latencies);
+    }
+    Ok(())
+}
+
+fn report(kind: &str, latencies: &mut [Duration]) {
+    latencies.sort();
+    let pct = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+    println!(
+        "{kind:>8}: n={} p50={:?} p95={:?} p99={:?} max={:?}",
+        latencies.len(),
+        pct(50),
+        pct(95),
+        pct(99),
+        latencies[latencies.len() - 1]
+    );
+}
@@ -0,0 +1,204 @@
+//! Load generator for a running server, roughly like the checker's stress
+//! phases: many connections, each sending requests at a set rate.
+//!
+//! Usage:
+//!   loadgen <smoke|prime|bank|jobs> [--addr addr] [--connections n]
+//!     [--rate requests/s per connection] [--size bytes] [--duration secs]
+//!
+//! `--size` is the echoed payload for smoke, the digits in each number for
+//! prime, and the inserts before each query for bank. A rate of 0, the
+//! default, sends each request as soon as the last is answered.
+
+use std::{
+    env,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use protohackers::clients::{bank, job_centre, prime_time};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::MissedTickBehavior,
+};
+
+const USAGE: &str = "usage: loadgen <smoke|prime|bank|jobs> [--addr addr] [--connections n] \
+    [--rate requests/s] [--size bytes] [--duration secs]";
+
+struct Options {
+    problem: String,
+    addr: String,
+    connections: usize,
+    rate: u32,
+    size: usize,
+    duration: Duration,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let Some(problem) = args.next() else {
+            bail!(USAGE);
+        };
+        let mut options = Options {
+            problem,
+            addr: "127.0.0.1:10000".to_string(),
+            connections: 10,
+            rate: 0,
+            size: 64,
+            duration: Duration::from_secs(10),
+        };
+        while let Some(flag) = args.next() {
+            let value = args.next().context(USAGE)?;
+            let number = || value.parse::<u64>().with_context(|| format!("bad {flag}"));
+            match flag.as_str() {
+                "--addr" => options.addr = value,
+                "--connections" => options.connections = number()? as usize,
+                "--rate" => options.rate = number()? as u32,
+                "--size" => options.size = number()? as usize,
+                "--duration" => options.duration = Duration::from_secs(number()?),
+                _ => bail!(USAGE),
+            }
+        }
+        Ok(options)
+    }
+}
+
+enum Connection {
+    Smoke(TcpStream, Vec<u8>),
+    Prime(prime_time::Client<TcpReader, TcpWriter>, String),
+    Bank(bank::Client<TcpReader, TcpWriter>, usize),
+    Jobs(job_centre::Client<TcpReader, TcpWriter>),
+}
+
+type TcpReader = tokio::net::tcp::OwnedReadHalf;
+type TcpWriter = tokio::net::tcp::OwnedWriteHalf;
+
+impl Connection {
+    async fn open(options: &Options) -> Result<Connection> {
+        let addr = &options.addr;
+        Ok(match options.problem.as_str() {
+            "smoke" => Connection::Smoke(TcpStream::connect(addr).await?, vec![b'x'; options.size]),
+            "prime" => {
+                // Odd, so it isn't ruled out before the check
+                let number = format!("{}7", "1".repeat(options.size.max(1) - 1));
+                Connection::Prime(prime_time::Client::connect(addr).await?, number)
+            }
+            "bank" => Connection::Bank(bank::Client::connect(addr).await?, options.size),
+            "jobs" => Connection::Jobs(job_centre::Client::connect(addr).await?),
+            _ => bail!(USAGE),
+        })
+    }
+
+    /// Sends the `i`th request and waits for its answer, returning the bytes
+    /// sent.
+    async fn request(&mut self, i: u64) -> Result<usize> {
+        match self {
+            Connection::Smoke(stream, payload) => {
+                stream.write_all(payload).await?;
+                let mut echo = vec![0; payload.len()];
+                stream.read_exact(&mut echo).await?;
+                if echo != *payload {
+                    bail!("echo differs");
+                }
+                Ok(payload.len())
+            }
+            Connection::Prime(client, number) => {
+                client.is_prime(number).await?;
+                Ok(number.len() + 32)
+            }
+            Connection::Bank(client, inserts) => {
+                let base = (i * *inserts as u64) as i32;
+                for n in 0..*inserts as i32 {
+                    client.insert(base.wrapping_add(n), n).await?;
+                }
+                client
+                    .query(base, base.wrapping_add(*inserts as i32))
+                    .await?;
+                Ok(9 * (*inserts + 1))
+            }
+            Connection::Jobs(client) => {
+                let queue = format!("load{}", i % 10);
+                client.put(&queue, &json!({ "n": i }), i % 100).await?;
+                if let Some(job) = client.get(&[&queue], false).await? {
+                    client.delete(job.id).await?;
+                }
+                Ok(0)
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+    let bytes = AtomicU64::new(0);
+    let deadline = Instant::now() + options.duration;
+
+    let mut connections = vec![];
+    for _ in 0..options.connections {
+        connections.push(Connection::open(&options).await?);
+    }
+    let start = Instant::now();
+    let tasks = connections.into_iter().map(|mut conn| {
+        let (options, bytes) = (&options, &bytes);
+        async move {
+            let mut latencies = vec![];
+            let mut ticks = match options.rate {
+                0 => None,
+                rate => {
+                    let mut ticks = tokio::time::interval(Duration::from_secs(1) / rate);
+                    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    Some(ticks)
+                }
+            };
+            for i in 0.. {
+                if let Some(ticks) = &mut ticks {
+                    ticks.tick().await;
+                }
+                if Instant::now() >= deadline {
+                    break;
+                }
+                let sent = Instant::now();
+                let n = conn.request(i).await?;
+                latencies.push(sent.elapsed());
+                bytes.fetch_add(n as u64, Ordering::Relaxed);
+            }
+            Ok::<_, anyhow::Error>(latencies)
+        }
+    });
+    let mut latencies = vec![];
+    for result in futures::future::join_all(tasks).await {
+        latencies.extend(result?);
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let bytes = bytes.load(Ordering::Relaxed) as f64;
+    println!(
+        "{} connections, {elapsed:.1}s: {:.0} requests/s, {:.2} MB/s sent",
+        options.connections,
+        latencies.len() as f64 / elapsed,
+        bytes / elapsed / 1e6
+    );
+    report(&mut latencies);
+    Ok(())
+}
+
+fn report(latencies: &mut [Duration]) {
+    latencies.sort();
+    let pct = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "latency: n={} p50={:?} p90={:?} p99={:?} max={:?}",
+        latencies.len(),
+        pct(50),
+        pct(90),
+        pct(99),
+        latencies.last().copied().unwrap_or_default()
+    );
+}
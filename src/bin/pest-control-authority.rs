@@ -0,0 +1,93 @@
+//! A fake Authority Server for trying out a Pest Control server locally.
+//!
+//! Usage: pest-control-authority [--addr addr] [--delay ms] [--bogus-ids]
+//!   [--script fault,...] <site:species=min-max,...>...
+//!
+//! For example `pest-control-authority 1:dog=2-4,cat=0-1 2:rat=0-0` serves two
+//! sites. Point the server at it with `PEST_CONTROL_AUTHORITY`.
+//!
+//! `--script` sets what to do to each reply to a policy request in turn,
+//! from any site: `ok` to send it as it is, `checksum` to garble its
+//! checksum, `delay=ms` to hold it back, or `close` to hang up without acting
+//! on the request. For example `ok,checksum` sends the first reply and
+//! garbles the second. The rest are sent as they are.
+
+use std::{env, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use protohackers::pest_control::fake_authority::{FakeAuthority, Fault};
+
+const USAGE: &str = "usage: pest-control-authority [--addr addr] [--delay ms] [--bogus-ids] \
+    [--script ok|checksum|delay=ms|close,...] <site:species=min-max,...>...";
+
+/// `(species, min, max)`
+type Target<'a> = (&'a str, u32, u32);
+
+/// Parses `site:species=min-max,...`.
+fn parse_site(arg: &str) -> Option<(u32, Vec<Target<'_>>)> {
+    let (site, targets) = arg.split_once(':')?;
+    let targets = targets
+        .split(',')
+        .map(|target| {
+            let (species, range) = target.split_once('=')?;
+            let (min, max) = range.split_once('-')?;
+            Some((species, min.parse().ok()?, max.parse().ok()?))
+        })
+        .collect::<Option<_>>()?;
+    Some((site.parse().ok()?, targets))
+}
+
+/// Parses `fault,...`.
+fn parse_script(arg: &str) -> Option<Vec<Fault>> {
+    arg.split(',')
+        .map(|fault| match fault {
+            "ok" => Some(Fault::None),
+            "checksum" => Some(Fault::BadChecksum),
+            "close" => Some(Fault::Close),
+            _ => {
+                let ms = fault.strip_prefix("delay=")?.parse().ok()?;
+                Some(Fault::Delay(Duration::from_millis(ms)))
+            }
+        })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut addr = "127.0.0.1:20547";
+    let mut delay = Duration::ZERO;
+    let mut bogus_ids = false;
+    let mut script = vec![];
+    let mut sites = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().context(USAGE)?,
+            "--delay" => {
+                let ms = args.next().context(USAGE)?.parse().context(USAGE)?;
+                delay = Duration::from_millis(ms);
+            }
+            "--bogus-ids" => bogus_ids = true,
+            "--script" => {
+                let arg = args.next().context(USAGE)?;
+                script = parse_script(arg).with_context(|| format!("bad script {arg:?}"))?;
+            }
+            site => sites.push(parse_site(site).with_context(|| format!("bad site {site:?}"))?),
+        }
+    }
+    if sites.is_empty() {
+        bail!(USAGE);
+    }
+
+    let authority = FakeAuthority::bind(addr).await?;
+    for (site, targets) in &sites {
+        authority.set_targets(*site, targets);
+    }
+    authority.set_delay(delay);
+    authority.set_bogus_policy_ids(bogus_ids);
+    authority.script(&script);
+    println!("Authority listening on {}...", authority.addr);
+    std::future::pending::<()>().await;
+    Ok(())
+}
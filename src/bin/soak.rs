@@ -0,0 +1,251 @@
+//! Soak test for running servers: hours of mixed traffic, with connections
+//! opened and closed all the time, while the servers' memory, file
+//! descriptors and threads are sampled, to catch leaks that only show in a
+//! long deployment.
+//!
+//! Usage:
+//!   soak [--duration secs] [--interval secs] [--workers n]
+//!     <smoke|prime|bank|jobs> <addr> <pid> ...
+//!
+//! Each server is given by its problem, address and process id, on Linux,
+//! where `/proc` has the samples. Open descriptors stand in for tokio's task
+//! count, which isn't visible from outside: each connection's task holds one.
+//!
+//! A server fails if a measure grew through the whole run: the peak of each
+//! quarter of the samples above the peak of the quarter before. A store that
+//! grows by design, like VCS keeping every revision, would always fail, so it
+//! isn't offered.
+
+use std::{
+    env, fs,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use protohackers::clients::{bank, job_centre, prime_time};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const USAGE: &str = "usage: soak [--duration secs] [--interval secs] [--workers n] \
+    <smoke|prime|bank|jobs> <addr> <pid> ...";
+
+/// The fewest samples to judge growth from.
+const MIN_SAMPLES: usize = 8;
+
+struct Target {
+    problem: String,
+    addr: String,
+    pid: u32,
+}
+
+struct Options {
+    targets: Vec<Target>,
+    duration: Duration,
+    interval: Duration,
+    workers: usize,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Options> {
+        let mut options = Options {
+            targets: vec![],
+            duration: Duration::from_secs(3600),
+            interval: Duration::from_secs(60),
+            workers: 4,
+        };
+        let mut args = args.peekable();
+        while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+            let value = args.next().context(USAGE)?;
+            let number = || value.parse::<u64>().with_context(|| format!("bad {flag}"));
+            match flag.as_str() {
+                "--duration" => options.duration = Duration::from_secs(number()?),
+                "--interval" => options.interval = Duration::from_secs(number()?),
+                "--workers" => options.workers = number()? as usize,
+                _ => bail!(USAGE),
+            }
+        }
+        let args: Vec<String> = args.collect();
+        if args.is_empty() || !args.len().is_multiple_of(3) {
+            bail!(USAGE);
+        }
+        for target in args.chunks(3) {
+            let [problem, addr, pid] = target else {
+                unreachable!()
+            };
+            if !["smoke", "prime", "bank", "jobs"].contains(&problem.as_str()) {
+                bail!(USAGE);
+            }
+            options.targets.push(Target {
+                problem: problem.clone(),
+                addr: addr.clone(),
+                pid: pid.parse().context("bad pid")?,
+            });
+        }
+        Ok(options)
+    }
+}
+
+/// One reading of a server process.
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    rss_kb: u64,
+    fds: u64,
+    threads: u64,
+}
+
+impl Sample {
+    fn take(pid: u32) -> Result<Sample> {
+        let status = fs::read_to_string(format!("/proc/{pid}/status"))
+            .with_context(|| format!("process {pid} is gone"))?;
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next()?.parse().ok())
+                .with_context(|| format!("no {name} in /proc/{pid}/status"))
+        };
+        Ok(Sample {
+            rss_kb: field("VmRSS:")?,
+            threads: field("Threads:")?,
+            fds: fs::read_dir(format!("/proc/{pid}/fd"))?.count() as u64,
+        })
+    }
+}
+
+/// Whether `values` grew through the whole run: each quarter's peak above
+/// the last.
+fn grew(values: &[u64]) -> bool {
+    if values.len() < MIN_SAMPLES {
+        return false;
+    }
+    let len = values.len();
+    let peaks: Vec<u64> = (0..4)
+        .map(|q| *values[q * len / 4..(q + 1) * len / 4].iter().max().unwrap())
+        .collect();
+    peaks.windows(2).all(|pair| pair[1] > pair[0])
+}
+
+/// Keeps opening sessions to `target` until `deadline`, each making a few
+/// requests before closing. Returns how many sessions it finished.
+async fn traffic(target: &Target, worker: u64, deadline: Instant) -> Result<u64> {
+    let addr = &target.addr;
+    let mut sessions = 0;
+    while Instant::now() < deadline {
+        let requests = 1 + (sessions * 7 + worker) % 50;
+        match target.problem.as_str() {
+            "smoke" => {
+                let mut stream = TcpStream::connect(addr).await?;
+                let payload = vec![b'x'; 1 << (requests % 16)];
+                stream.write_all(&payload).await?;
+                stream.shutdown().await?;
+                let mut echo = vec![];
+                stream.read_to_end(&mut echo).await?;
+                if echo != payload {
+                    bail!("echo differs");
+                }
+            }
+            "prime" => {
+                let mut client = prime_time::Client::connect(addr).await?;
+                for n in 0..requests {
+                    client.is_prime(&(n * 1_000_003).to_string()).await?;
+                }
+            }
+            "bank" => {
+                let mut client = bank::Client::connect(addr).await?;
+                for n in 0..requests as i32 * 20 {
+                    client.insert(n, n).await?;
+                }
+                client.query(0, i32::MAX).await?;
+            }
+            "jobs" => {
+                let mut client = job_centre::Client::connect(addr).await?;
+                let queue = format!("soak{worker}");
+                for n in 0..requests {
+                    client.put(&queue, &json!({ "n": n }), n).await?;
+                }
+                // Leaves one job in progress, for disconnecting to abort,
+                // and gets and deletes the rest.
+                client.get(&[&queue], false).await?;
+                for _ in 1..requests {
+                    if let Some(job) = client.get(&[&queue], false).await? {
+                        client.delete(job.id).await?;
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        sessions += 1;
+    }
+    Ok(sessions)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+    let deadline = Instant::now() + options.duration;
+
+    let mut tasks = vec![];
+    for target in &options.targets {
+        for worker in 0..options.workers as u64 {
+            tasks.push(traffic(target, worker, deadline));
+        }
+    }
+    let sampling = async {
+        let mut samples = vec![vec![]; options.targets.len()];
+        let mut ticks = tokio::time::interval(options.interval);
+        while Instant::now() < deadline {
+            ticks.tick().await;
+            for (target, samples) in options.targets.iter().zip(&mut samples) {
+                let sample = Sample::take(target.pid)?;
+                println!(
+                    "{} pid {}: rss={}kB fds={} threads={}",
+                    target.problem, target.pid, sample.rss_kb, sample.fds, sample.threads
+                );
+                samples.push(sample);
+            }
+        }
+        anyhow::Ok(samples)
+    };
+    let (sessions, samples) = tokio::join!(futures::future::try_join_all(tasks), sampling);
+    let sessions: u64 = sessions?.iter().sum();
+    let samples = samples?;
+    println!("{sessions} sessions finished");
+
+    let mut failed = false;
+    for (target, samples) in options.targets.iter().zip(&samples) {
+        if samples.len() < MIN_SAMPLES {
+            println!(
+                "{}: only {} samples, too few to judge",
+                target.problem,
+                samples.len()
+            );
+            continue;
+        }
+        let measures = [
+            ("rss", samples.iter().map(|s| s.rss_kb).collect::<Vec<_>>()),
+            ("fds", samples.iter().map(|s| s.fds).collect()),
+            ("threads", samples.iter().map(|s| s.threads).collect()),
+        ];
+        let mut leaked = false;
+        for (name, values) in measures {
+            if grew(&values) {
+                println!(
+                    "FAIL {}: {name} grew through the run: {values:?}",
+                    target.problem
+                );
+                leaked = true;
+            }
+        }
+        failed |= leaked;
+        if !leaked {
+            println!("PASS {}", target.problem);
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
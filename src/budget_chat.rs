@@ -0,0 +1,278 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::mpsc::{self, error::TrySendError},
+};
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+
+use crate::config::ADDR;
+
+const WELCOME: &str = "Welcome to budgetchat! What shall I call you?";
+/// The longest line accepted, in bytes. The spec asks for messages of at
+/// least 1000 characters.
+const MAX_LINE: usize = 1 << 16;
+/// The longest name accepted. The spec asks for at least 16 characters.
+const MAX_NAME: usize = 32;
+/// How many messages may wait for a member before they're dropped from the
+/// room, so that one client that stops reading can't hold everyone's
+/// messages in memory.
+const MAX_QUEUED: usize = 1024;
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+    serve(listener).await
+}
+
+async fn serve(listener: TcpListener) -> Result<()> {
+    let room = Arc::new(Room::default());
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        let room = room.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer, &room).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn process<R, W>(reader: R, writer: W, room: &Room) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE));
+    let mut writer = FramedWrite::new(writer, LinesCodec::new());
+    writer.send(WELCOME).await?;
+    let Some(name) = lines.next().await.transpose()? else {
+        return Ok(());
+    };
+    let (outbox, mut inbox) = mpsc::channel(MAX_QUEUED);
+    let (id, present) = match room.join(&name, outbox) {
+        Ok(joined) => joined,
+        Err(e) => return Ok(writer.send(format!("* {e}")).await?),
+    };
+    writer.send(present).await?;
+
+    // Leave the room however the session ends.
+    let result = async {
+        loop {
+            tokio::select! {
+                line = lines.next() => match line {
+                    Some(line) => room.say(id, &line?),
+                    None => return Ok(()),
+                },
+                message = inbox.recv() => match message {
+                    Some(message) => writer.send(&*message).await?,
+                    None => bail!("{name} fell too far behind"),
+                },
+            }
+        }
+    }
+    .await;
+    room.leave(id);
+    result
+}
+
+/// Everyone who has joined, by when they joined, and how to reach them.
+#[derive(Default)]
+struct Room(Mutex<Members>);
+
+#[derive(Default)]
+struct Members {
+    next_id: u64,
+    members: BTreeMap<u64, Member>,
+}
+
+struct Member {
+    name: String,
+    outbox: mpsc::Sender<Arc<str>>,
+}
+
+impl Room {
+    /// Adds `name` to the room, telling everyone else, and returns its id and
+    /// the message listing who else is there. Fails if the name is invalid or
+    /// already taken.
+    fn join(
+        &self,
+        name: &str,
+        outbox: mpsc::Sender<Arc<str>>,
+    ) -> Result<(u64, String), &'static str> {
+        if name.is_empty() || name.len() > MAX_NAME {
+            return Err("names must be 1 to 32 characters");
+        }
+        if !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err("names must be letters and digits only");
+        }
+        let mut room = self.0.lock().unwrap();
+        if room.members.values().any(|member| member.name == name) {
+            return Err("that name is taken");
+        }
+        let present: Vec<_> = room.members.values().map(|m| m.name.as_str()).collect();
+        let present = format!("* The room contains: {}", present.join(", "));
+        room.broadcast(None, &format!("* {name} has entered the room"));
+        let id = room.next_id;
+        room.next_id += 1;
+        let name = name.to_string();
+        room.members.insert(id, Member { name, outbox });
+        Ok((id, present))
+    }
+
+    /// Relays a line from member `id` to everyone else.
+    fn say(&self, id: u64, line: &str) {
+        let mut room = self.0.lock().unwrap();
+        if let Some(member) = room.members.get(&id) {
+            let message = format!("[{}] {line}", member.name);
+            room.broadcast(Some(id), &message);
+        }
+    }
+
+    /// Removes member `id`, telling everyone else, unless it's gone already.
+    fn leave(&self, id: u64) {
+        let mut room = self.0.lock().unwrap();
+        if let Some(member) = room.members.remove(&id) {
+            room.broadcast(None, &format!("* {} has left the room", member.name));
+        }
+    }
+}
+
+impl Members {
+    /// Sends `message` to every member but `from`. Anyone whose queue is
+    /// full is dropped from the room, which everyone left is told about in
+    /// turn.
+    fn broadcast(&mut self, from: Option<u64>, message: &str) {
+        let mut messages = vec![(from, Arc::<str>::from(message))];
+        while let Some((from, message)) = messages.pop() {
+            let mut dropped = vec![];
+            for (&id, member) in &self.members {
+                if Some(id) == from {
+                    continue;
+                }
+                match member.outbox.try_send(message.clone()) {
+                    Ok(()) => {}
+                    // Its session has ended, and will leave by itself.
+                    Err(TrySendError::Closed(_)) => {}
+                    Err(TrySendError::Full(_)) => dropped.push(id),
+                }
+            }
+            for id in dropped {
+                let member = self.members.remove(&id).expect("just seen");
+                let left = format!("* {} has left the room", member.name);
+                messages.push((None, left.into()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use crate::testutil::{TestClient, TestServer};
+
+    use super::{serve, Room, MAX_QUEUED, WELCOME};
+
+    async fn join(server: &TestServer, name: &str) -> (TestClient, String) {
+        let mut client = server.connect().await;
+        assert_eq!(client.recv_line().await, WELCOME);
+        client.send_line(name).await;
+        let present = client.recv_line().await;
+        (client, present)
+    }
+
+    #[tokio::test]
+    async fn spec_example() {
+        let server = TestServer::start(serve).await;
+        let (mut bob, present) = join(&server, "bob").await;
+        assert_eq!(present, "* The room contains: ");
+        let (mut charlie, present) = join(&server, "charlie").await;
+        assert_eq!(present, "* The room contains: bob");
+        assert_eq!(bob.recv_line().await, "* charlie has entered the room");
+        let (mut dave, present) = join(&server, "dave").await;
+        assert_eq!(present, "* The room contains: bob, charlie");
+        assert_eq!(bob.recv_line().await, "* dave has entered the room");
+        assert_eq!(charlie.recv_line().await, "* dave has entered the room");
+
+        bob.send_line("hi dave").await;
+        assert_eq!(charlie.recv_line().await, "[bob] hi dave");
+        assert_eq!(dave.recv_line().await, "[bob] hi dave");
+        // Nobody hears themselves, so the next thing bob hears is dave.
+        dave.send_line("hello").await;
+        assert_eq!(bob.recv_line().await, "[dave] hello");
+        assert_eq!(charlie.recv_line().await, "[dave] hello");
+
+        charlie.close().await;
+        assert_eq!(bob.recv_line().await, "* charlie has left the room");
+        assert_eq!(dave.recv_line().await, "* charlie has left the room");
+        let long = "x".repeat(1000);
+        dave.send_line(&long).await;
+        assert_eq!(bob.recv_line().await, format!("[dave] {long}"));
+    }
+
+    #[tokio::test]
+    async fn bad_names() {
+        let server = TestServer::start(serve).await;
+        let (mut alice, _) = join(&server, "alice").await;
+        for (name, error) in [
+            ("", "* names must be 1 to 32 characters"),
+            (&"a".repeat(33), "* names must be 1 to 32 characters"),
+            ("bob smith", "* names must be letters and digits only"),
+            ("bob!", "* names must be letters and digits only"),
+            ("alice", "* that name is taken"),
+        ] {
+            let mut client = server.connect().await;
+            assert_eq!(client.recv_line().await, WELCOME);
+            client.send_line(name).await;
+            assert_eq!(client.recv_line().await, error, "{name:?}");
+            assert!(client.recv_to_end().await.is_empty());
+        }
+        // Nobody else was told about any of them.
+        let (_, present) = join(&server, "Bob2").await;
+        assert_eq!(present, "* The room contains: alice");
+        assert_eq!(alice.recv_line().await, "* Bob2 has entered the room");
+    }
+
+    #[tokio::test]
+    async fn leaving_before_joining() {
+        let server = TestServer::start(serve).await;
+        let (mut alice, _) = join(&server, "alice").await;
+        let mut client = server.connect().await;
+        assert_eq!(client.recv_line().await, WELCOME);
+        client.close().await;
+        assert!(client.recv_to_end().await.is_empty());
+        let (_, present) = join(&server, "bob").await;
+        assert_eq!(present, "* The room contains: alice");
+        assert_eq!(alice.recv_line().await, "* bob has entered the room");
+    }
+
+    #[test]
+    fn slow_members_dropped() {
+        let room = Room::default();
+        let (fast, mut fast_inbox) = mpsc::channel(2 * MAX_QUEUED);
+        let (slow, _slow_inbox) = mpsc::channel(MAX_QUEUED);
+        let (alice, _) = room.join("alice", fast).unwrap();
+        room.join("bob", slow).unwrap();
+        assert_eq!(
+            &*fast_inbox.try_recv().unwrap(),
+            "* bob has entered the room"
+        );
+        for i in 0..=MAX_QUEUED {
+            room.say(alice, &i.to_string());
+        }
+        // bob never read anything, so bob's gone, and alice is told.
+        let (carol, _) = mpsc::channel(1);
+        let (_, present) = room.join("carol", carol).unwrap();
+        assert_eq!(present, "* The room contains: alice");
+        assert_eq!(&*fast_inbox.try_recv().unwrap(), "* bob has left the room");
+    }
+}
@@ -0,0 +1,393 @@
+//! A conformance suite to run against a server before the real checker: the
+//! example exchanges from each problem's spec, plus edge cases. Each scenario
+//! makes its own connections, and passes or fails on its own.
+
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::clients::{bank, job_centre, prime_time, unusual_database, vcs};
+
+/// How long a scenario may take before it fails.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Scenario = (&'static str, fn(String) -> BoxFuture<'static, Result<()>>);
+
+/// The problems with a suite, for usage messages.
+pub const PROBLEMS: &str = "smoke|prime|bank|jobs|kv|vcs|pest";
+
+/// Runs every scenario for `problem` against the server at `addr`, printing
+/// each result. Whether they all passed.
+pub async fn run(problem: &str, addr: &str) -> Result<bool> {
+    let scenarios: &[Scenario] = match problem {
+        "smoke" => &[
+            ("echo", |addr| Box::pin(smoke_echo(addr, 5))),
+            ("echo 1 MiB", |addr| Box::pin(smoke_echo(addr, 1 << 20))),
+            ("five clients at once", |addr| Box::pin(smoke_clients(addr))),
+        ],
+        "prime" => &[
+            ("spec example", |addr| Box::pin(prime_example(addr))),
+            ("big and non-integer numbers", |addr| {
+                Box::pin(prime_numbers(addr))
+            }),
+            ("pipelined requests", |addr| Box::pin(prime_pipelined(addr))),
+            ("malformed request", |addr| Box::pin(prime_malformed(addr))),
+        ],
+        "bank" => &[
+            ("spec example", |addr| Box::pin(bank_example(addr))),
+            ("empty and backwards queries", |addr| {
+                Box::pin(bank_empty(addr))
+            }),
+            ("sessions are separate", |addr| {
+                Box::pin(bank_sessions(addr))
+            }),
+        ],
+        "jobs" => &[
+            ("put, get, delete", |addr| Box::pin(jobs_example(addr))),
+            ("disconnect aborts", |addr| Box::pin(jobs_disconnect(addr))),
+            ("waiting get", |addr| Box::pin(jobs_wait(addr))),
+            ("invalid request", |addr| Box::pin(jobs_invalid(addr))),
+        ],
+        "kv" => &[
+            ("insert and retrieve", |addr| Box::pin(kv_example(addr))),
+            ("empty keys and equals signs", |addr| {
+                Box::pin(kv_keys(addr))
+            }),
+            ("version", |addr| Box::pin(kv_version(addr))),
+        ],
+        "vcs" => &[
+            ("put, get, list", |addr| Box::pin(vcs_example(addr))),
+            ("illegal names", |addr| Box::pin(vcs_illegal(addr))),
+        ],
+        "pest" => &[
+            ("hello", |addr| Box::pin(pest_hello(addr))),
+            ("bad hello", |addr| Box::pin(pest_bad_hello(addr))),
+        ],
+        _ => bail!("no checks for {problem:?}, expected one of {PROBLEMS}"),
+    };
+    let mut passed = 0;
+    for (name, scenario) in scenarios {
+        match tokio::time::timeout(TIMEOUT, scenario(addr.to_string())).await {
+            Ok(Ok(())) => {
+                println!("PASS {name}");
+                passed += 1;
+            }
+            Ok(Err(e)) => println!("FAIL {name}: {e:#}"),
+            Err(_) => println!("FAIL {name}: timed out after {TIMEOUT:?}"),
+        }
+    }
+    println!("{passed}/{} passed", scenarios.len());
+    Ok(passed == scenarios.len())
+}
+
+async fn smoke_echo(addr: String, len: usize) -> Result<()> {
+    let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    let mut stream = TcpStream::connect(addr).await?;
+    let (mut reader, mut writer) = stream.split();
+    let send = async {
+        writer.write_all(&data).await?;
+        writer.shutdown().await
+    };
+    let mut echo = vec![];
+    let (sent, received) = tokio::join!(send, reader.read_to_end(&mut echo));
+    sent?;
+    received.context("the server should close after our EOF")?;
+    ensure!(echo == data, "echoed {} bytes, differing", echo.len());
+    Ok(())
+}
+
+async fn smoke_clients(addr: String) -> Result<()> {
+    let clients = (0..5).map(|_| smoke_echo(addr.clone(), 100_000));
+    futures::future::try_join_all(clients).await?;
+    Ok(())
+}
+
+async fn prime_example(addr: String) -> Result<()> {
+    let mut client = prime_time::Client::connect(&addr).await?;
+    ensure!(!client.is_prime("123").await?, "123 is not prime");
+    ensure!(client.is_prime("7").await?, "7 is prime");
+    Ok(())
+}
+
+async fn prime_numbers(addr: String) -> Result<()> {
+    let mut client = prime_time::Client::connect(&addr).await?;
+    for (number, prime) in [
+        ("2", true),
+        ("1", false),
+        ("0", false),
+        ("-7", false),
+        ("7.0", true),
+        ("7.5", false),
+        ("618970019642690137449562111", true),
+        ("618970019642690137449562113", false),
+    ] {
+        let answer = client.is_prime(number).await?;
+        ensure!(answer == prime, "{number}: expected {prime}, got {answer}");
+    }
+    Ok(())
+}
+
+async fn prime_pipelined(addr: String) -> Result<()> {
+    let stream = TcpStream::connect(addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    let requests: String = (0..100)
+        .map(|n| format!("{{\"method\":\"isPrime\",\"number\":{n}}}\n"))
+        .collect();
+    writer.write_all(requests.as_bytes()).await?;
+    let mut lines = BufReader::new(reader).lines();
+    for n in 0..100u32 {
+        let line = lines.next_line().await?.context("closed early")?;
+        let response: Value = serde_json::from_str(&line)?;
+        let prime = n >= 2 && (2..n).all(|d| n % d != 0);
+        ensure!(response["prime"] == prime, "{n}: got {line}");
+    }
+    Ok(())
+}
+
+async fn prime_malformed(addr: String) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(b"{\"method\":\"isPrime\",\"number\":\"7\"}\n")
+        .await?;
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .await
+        .context("the server should close after a malformed response")?;
+    let response = String::from_utf8_lossy(&response);
+    let well_formed = serde_json::from_str::<Value>(response.trim_end())
+        .is_ok_and(|r| r["method"] == "isPrime" && r["prime"].is_boolean());
+    ensure!(!well_formed, "got a well-formed response: {response:?}");
+    Ok(())
+}
+
+async fn bank_example(addr: String) -> Result<()> {
+    let mut client = bank::Client::connect(&addr).await?;
+    for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+        client.insert(timestamp, price).await?;
+    }
+    let mean = client.query(12288, 16384).await?;
+    ensure!(mean == 101, "expected a mean of 101, got {mean}");
+    Ok(())
+}
+
+async fn bank_empty(addr: String) -> Result<()> {
+    let mut client = bank::Client::connect(&addr).await?;
+    ensure!(client.query(0, 100).await? == 0, "empty session");
+    client.insert(50, 10).await?;
+    ensure!(client.query(100, 0).await? == 0, "min after max");
+    ensure!(client.query(60, 100).await? == 0, "no prices in range");
+    ensure!(client.query(50, 50).await? == 10, "single timestamp");
+    Ok(())
+}
+
+async fn bank_sessions(addr: String) -> Result<()> {
+    let mut first = bank::Client::connect(&addr).await?;
+    let mut second = bank::Client::connect(&addr).await?;
+    first.insert(1, 100).await?;
+    second.insert(1, 200).await?;
+    ensure!(first.query(0, 10).await? == 100, "first session");
+    ensure!(second.query(0, 10).await? == 200, "second session");
+    Ok(())
+}
+
+/// A queue name unlikely to be used by anything else on the server.
+fn queue(name: &str) -> String {
+    format!("check-{name}-{}", std::process::id())
+}
+
+async fn jobs_example(addr: String) -> Result<()> {
+    let queue1 = queue("example1");
+    let queue2 = queue("example2");
+    let mut client = job_centre::Client::connect(&addr).await?;
+    let low = client.put(&queue1, &json!({"title": "low"}), 10).await?;
+    let high = client.put(&queue2, &json!({"title": "high"}), 20).await?;
+    let job = client.get(&[&queue1, &queue2], false).await?;
+    ensure!(
+        job.as_ref().map(|j| j.id) == Some(high),
+        "expected {high}, got {job:?}"
+    );
+    ensure!(client.delete(high).await?, "delete {high}");
+    ensure!(!client.delete(high).await?, "delete {high} twice");
+    let job = client.get(&[&queue1], false).await?;
+    ensure!(
+        job.as_ref().map(|j| j.id) == Some(low),
+        "expected {low}, got {job:?}"
+    );
+    ensure!(client.abort(low).await?, "abort {low}");
+    ensure!(client.delete(low).await?, "delete {low}");
+    ensure!(
+        client.get(&[&queue1], false).await?.is_none(),
+        "queue is empty"
+    );
+    Ok(())
+}
+
+async fn jobs_disconnect(addr: String) -> Result<()> {
+    let queue = queue("disconnect");
+    let mut worker = job_centre::Client::connect(&addr).await?;
+    let id = worker.put(&queue, &json!({}), 1).await?;
+    ensure!(worker.get(&[&queue], false).await?.is_some(), "got the job");
+    drop(worker);
+
+    let mut other = job_centre::Client::connect(&addr).await?;
+    let job = other.get(&[&queue], true).await?;
+    ensure!(
+        job.as_ref().map(|j| j.id) == Some(id),
+        "expected {id}, got {job:?}"
+    );
+    other.delete(id).await?;
+    Ok(())
+}
+
+async fn jobs_wait(addr: String) -> Result<()> {
+    let queue = queue("wait");
+    let mut worker = job_centre::Client::connect(&addr).await?;
+    let get = {
+        let queue = queue.clone();
+        tokio::spawn(async move { worker.get(&[&queue], true).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut producer = job_centre::Client::connect(&addr).await?;
+    let id = producer.put(&queue, &json!({}), 1).await?;
+    let job = get.await??;
+    ensure!(
+        job.as_ref().map(|j| j.id) == Some(id),
+        "expected {id}, got {job:?}"
+    );
+    producer.delete(id).await?;
+    Ok(())
+}
+
+async fn jobs_invalid(addr: String) -> Result<()> {
+    let mut client = job_centre::Client::connect(&addr).await?;
+    let err = client.delete(u64::MAX).await;
+    ensure!(matches!(err, Ok(false)), "deleting a missing job: {err:?}");
+    let mut stream = TcpStream::connect(&addr).await?;
+    stream.write_all(b"not json\n").await?;
+    let mut line = String::new();
+    BufReader::new(&mut stream).read_line(&mut line).await?;
+    let response: Value = serde_json::from_str(&line)?;
+    ensure!(response["status"] == "error", "got {line:?}");
+    Ok(())
+}
+
+/// A key unlikely to be used by anything else on the server.
+fn kv_key(name: &str) -> String {
+    format!("check-{name}-{}", std::process::id())
+}
+
+async fn kv_example(addr: String) -> Result<()> {
+    let key = kv_key("example");
+    let mut client = unusual_database::Client::connect(&addr).await?;
+    for value in ["one", "two"] {
+        client.set(&key, value).await?;
+        let got = client.get(&key).await?;
+        ensure!(
+            got.as_deref() == Some(value),
+            "expected {value}, got {got:?}"
+        );
+    }
+    Ok(())
+}
+
+async fn kv_keys(addr: String) -> Result<()> {
+    let key = kv_key("equals");
+    let mut client = unusual_database::Client::connect(&addr).await?;
+    client.set(&key, "=bar=baz=").await?;
+    let got = client.get(&key).await?;
+    ensure!(
+        got.as_deref() == Some("=bar=baz="),
+        "everything after the first '=' is the value, got {got:?}"
+    );
+    client.set("", &key).await?;
+    let got = client.get("").await?;
+    ensure!(got == Some(key), "empty key: got {got:?}");
+    Ok(())
+}
+
+async fn kv_version(addr: String) -> Result<()> {
+    let mut client = unusual_database::Client::connect(&addr).await?;
+    let version = client.get("version").await?.context("no version")?;
+    ensure!(!version.is_empty(), "empty version");
+    client.set("version", "check").await?;
+    let got = client.get("version").await?;
+    ensure!(
+        got.as_ref() == Some(&version),
+        "version changed from {version:?} to {got:?}"
+    );
+    Ok(())
+}
+
+async fn vcs_example(addr: String) -> Result<()> {
+    let path = format!("/check/{}/file.txt", std::process::id());
+    let mut client = vcs::Client::connect(&addr).await?;
+    let first = client.put(&path, b"one\n").await?;
+    let second = client.put(&path, b"two\n").await?;
+    ensure!(second == first + 1, "revisions {first} then {second}");
+    ensure!(
+        client.put(&path, b"two\n").await? == second,
+        "an unchanged PUT"
+    );
+    ensure!(
+        client.get(&path, None).await? == b"two\n",
+        "latest revision"
+    );
+    ensure!(
+        client.get(&path, Some(first)).await? == b"one\n",
+        "first revision"
+    );
+    let dir = format!("/check/{}", std::process::id());
+    let entries = client.list(&dir).await?;
+    ensure!(
+        entries == [format!("file.txt r{second}")],
+        "got {entries:?}"
+    );
+    Ok(())
+}
+
+async fn vcs_illegal(addr: String) -> Result<()> {
+    let mut client = vcs::Client::connect(&addr).await?;
+    for path in ["no-slash", "/a//b", "/bad$name"] {
+        let err = client.put(path, b"x").await;
+        ensure!(err.is_err(), "PUT {path:?} was accepted");
+    }
+    let err = client.put("/check/binary", b"\x00\x01").await;
+    ensure!(err.is_err(), "binary data was accepted");
+    Ok(())
+}
+
+/// Hello { protocol: "pestcontrol", version: 1 }, from the spec.
+const PEST_HELLO: &[u8] = b"\x50\x00\x00\x00\x19\x00\x00\x00\x0bpestcontrol\x00\x00\x00\x01\xce";
+
+async fn pest_hello(addr: String) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(PEST_HELLO).await?;
+    let mut hello = vec![0; PEST_HELLO.len()];
+    stream.read_exact(&mut hello).await?;
+    ensure!(hello == PEST_HELLO, "got {hello:02x?}");
+    Ok(())
+}
+
+async fn pest_bad_hello(addr: String) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    // An Ok message instead of a Hello
+    stream.write_all(b"\x52\x00\x00\x00\x06\xa8").await?;
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .await
+        .context("the server should close after an error")?;
+    let error = response.get(PEST_HELLO.len()).copied();
+    ensure!(
+        error == Some(0x51),
+        "expected Hello then Error, got {response:02x?}"
+    );
+    Ok(())
+}
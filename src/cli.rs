@@ -0,0 +1,124 @@
+//! `protohackers client`: the command line clients for each problem, behind
+//! one binary, sharing the options for where and how to connect. Each is a
+//! thin front end over the typed clients in [`crate::clients`].
+
+mod bank;
+mod chat;
+mod jobs;
+mod kv;
+mod lrcp;
+mod prime;
+mod speed_cameras;
+mod speed_dispatcher;
+mod vcs;
+
+use std::{future::Future, time::Duration};
+
+use anyhow::{bail, Context, Result};
+
+/// The problems with a client, for usage messages.
+pub const PROBLEMS: &str = "prime|bank|chat|speed-cameras|speed-dispatcher|jobs|kv|lrcp|vcs";
+
+/// The options every client takes, before the problem.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Options {
+    addr: String,
+    /// How long to wait for each connection, and for the Unusual Database,
+    /// for each attempt at a retrieve.
+    timeout: Option<Duration>,
+}
+
+impl Options {
+    /// Runs `connect`, failing if it takes longer than the timeout.
+    async fn connect<T>(&self, connect: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(timeout) = self.timeout else {
+            return connect.await;
+        };
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(connected) => connected,
+            Err(_) => bail!("couldn't connect to {} within {timeout:?}", self.addr),
+        }
+    }
+}
+
+fn usage() -> String {
+    format!(
+        "usage: protohackers client [--addr addr] [--timeout ms] <{PROBLEMS}> [args]...\n\
+        --timeout only bounds connecting, and each attempt at a kv get, not \
+        reading or writing on a connection"
+    )
+}
+
+/// Splits `args` into the options, the problem and its arguments.
+fn parse(args: &[String]) -> Result<(Options, &str, Vec<&str>)> {
+    let mut args = args.iter().map(String::as_str).peekable();
+    let mut options = Options {
+        addr: "127.0.0.1:10000".to_string(),
+        timeout: None,
+    };
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        let value = args.next().with_context(usage)?;
+        match flag {
+            "--addr" => options.addr = value.to_string(),
+            "--timeout" => {
+                let ms = value.parse().context("bad --timeout")?;
+                options.timeout = Some(Duration::from_millis(ms));
+            }
+            _ => bail!(usage()),
+        }
+    }
+    let problem = args.next().with_context(usage)?;
+    Ok((options, problem, args.collect()))
+}
+
+/// Runs the client for the problem named in `args`, the arguments after
+/// `client`.
+pub async fn run(args: &[String]) -> Result<()> {
+    let (options, problem, args) = parse(args)?;
+    match problem {
+        "prime" => prime::run(&options, &args).await,
+        "bank" => bank::run(&options, &args).await,
+        "chat" => chat::run(&options, &args).await,
+        "speed-cameras" => speed_cameras::run(&options, &args).await,
+        "speed-dispatcher" => speed_dispatcher::run(&options, &args).await,
+        "jobs" => jobs::run(&options, &args).await,
+        "kv" => kv::run(&options, &args).await,
+        "lrcp" => lrcp::run(&options, &args).await,
+        "vcs" => vcs::run(&options, &args).await,
+        _ => bail!(usage()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_before_the_problem() {
+        let args = split("--timeout 500 --addr 10.0.0.1:9000 kv get --addr");
+        let (options, problem, rest) = parse(&args).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                addr: "10.0.0.1:9000".to_string(),
+                timeout: Some(Duration::from_millis(500)),
+            }
+        );
+        assert_eq!(problem, "kv");
+        assert_eq!(rest, ["get", "--addr"]);
+
+        let args = split("lrcp");
+        let (options, problem, rest) = parse(&args).unwrap();
+        assert_eq!(options.addr, "127.0.0.1:10000");
+        assert_eq!(options.timeout, None);
+        assert_eq!((problem, rest.len()), ("lrcp", 0));
+
+        for bad in ["", "--addr", "--port 1 kv", "--timeout soon kv"] {
+            assert!(parse(&split(bad)).is_err(), "{bad:?}");
+        }
+    }
+}
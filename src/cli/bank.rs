@@ -0,0 +1,56 @@
+//! Means to an End: `client bank [insert <timestamp> <price> | query <min> <max>]...`
+//!
+//! Runs the commands in order on one session, since a session only sees its
+//! own prices. Each query prints the mean the server answers with. Without
+//! any commands, they are read from stdin, one a line.
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+};
+
+use super::Options;
+use crate::clients::bank::Client;
+
+const USAGE: &str =
+    "usage: protohackers client [options] bank [insert <timestamp> <price> | query <min> <max>]...";
+
+async fn command(
+    client: &mut Client<OwnedReadHalf, OwnedWriteHalf>,
+    command: &[&str],
+) -> Result<()> {
+    let number = |arg: &str| {
+        arg.parse::<i32>()
+            .with_context(|| format!("{arg:?} isn't an i32"))
+    };
+    match *command {
+        ["insert", timestamp, price] => client.insert(number(timestamp)?, number(price)?).await,
+        ["query", min, max] => {
+            println!("{}", client.query(number(min)?, number(max)?).await?);
+            Ok(())
+        }
+        _ => bail!("bad command {:?}: {USAGE}", command.join(" ")),
+    }
+}
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    if !args.len().is_multiple_of(3) {
+        bail!(USAGE);
+    }
+    let mut client = options.connect(Client::connect(&options.addr)).await?;
+    if args.is_empty() {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await? {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.is_empty() {
+                command(&mut client, &words).await?;
+            }
+        }
+        return Ok(());
+    }
+    for words in args.chunks(3) {
+        command(&mut client, words).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! Budget Chat: `client chat <name>`
+//!
+//! Prints the server's welcome, answers it with `name`, and then sends each
+//! line typed as a message while printing everything the server sends, until
+//! either side closes.
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::Options;
+
+const USAGE: &str = "usage: protohackers client [options] chat <name>";
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    let [name] = args else {
+        bail!(USAGE);
+    };
+    let addr = &options.addr;
+    let stream = options
+        .connect(async {
+            TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("connecting to {addr}"))
+        })
+        .await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut incoming = BufReader::new(reader).lines();
+    let welcome = incoming
+        .next_line()
+        .await?
+        .context("closed before welcome")?;
+    println!("{welcome}");
+    writer.write_all(format!("{name}\n").as_bytes()).await?;
+
+    let mut typed = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        tokio::select! {
+            line = incoming.next_line() => match line? {
+                Some(line) => println!("{line}"),
+                None => {
+                    println!("* server closed the connection");
+                    return Ok(());
+                }
+            },
+            line = typed.next_line() => match line? {
+                Some(line) => writer.write_all(format!("{line}\n").as_bytes()).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
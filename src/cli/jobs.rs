@@ -0,0 +1,137 @@
+//! Job Centre: `client jobs put|get|delete|abort ...`
+//!
+//! - `put <queue> <pri> [job]`
+//! - `get [--wait] [--delete] <queue>...`
+//! - `get [--wait] [--loop] <queue>... -- <command>...`
+//! - `delete <id>`
+//! - `abort <id>`
+//!
+//! `put` reads the job's JSON from stdin when it isn't given, and prints the
+//! new job's id. `get` prints the job it got as JSON. The server puts a job
+//! back in its queue when the connection that got it closes, so `get` alone
+//! only peeks at it, unless `--delete` takes it for good.
+//!
+//! With a command, `get` runs it with the job on stdin, and `JOB_ID` and
+//! `JOB_QUEUE` set, then deletes the job if the command succeeds, or aborts it
+//! and stops if not. `--loop` keeps getting jobs until one fails, so that a
+//! few of these make a pool of workers.
+//!
+//! The server only lets the connection working on a job abort it, so `abort`
+//! from here is refused unless that's changed.
+
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+use super::Options;
+use crate::clients::job_centre::{Client, Job};
+
+const USAGE: &str = "usage: protohackers client [options] jobs put <queue> <pri> [job] \
+    | get [--wait] [--delete] [--loop] <queue>... [-- <command>...] | delete <id> | abort <id>";
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    let mut client = options.connect(Client::connect(&options.addr)).await?;
+    match *args {
+        ["put", queue, pri, ref job @ ..] if job.len() <= 1 => {
+            let pri = pri.parse().context("bad priority")?;
+            let job = match job {
+                [job] => job.to_string(),
+                _ => {
+                    let mut job = String::new();
+                    tokio::io::stdin().read_to_string(&mut job).await?;
+                    job
+                }
+            };
+            let job: Value = serde_json::from_str(&job).context("the job isn't JSON")?;
+            println!("{}", client.put(queue, &job, pri).await?);
+        }
+        ["get", ref rest @ ..] => get(&mut client, rest).await?,
+        ["delete", id] => {
+            let id = id.parse().context("bad id")?;
+            if !client.delete(id).await? {
+                bail!("no job {id}");
+            }
+        }
+        ["abort", id] => {
+            let id = id.parse().context("bad id")?;
+            if !client.abort(id).await? {
+                bail!("no job {id}");
+            }
+        }
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
+
+async fn get<R, W>(client: &mut Client<R, W>, args: &[&str]) -> Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let (args, command) = match args.iter().position(|&arg| arg == "--") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[][..]),
+    };
+    let flag = |name| args.contains(&name);
+    let (wait, delete, repeat) = (flag("--wait"), flag("--delete"), flag("--loop"));
+    let queues: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if queues.is_empty() || (repeat && command.is_empty()) {
+        bail!(USAGE);
+    }
+
+    loop {
+        let Some(job) = client.get(&queues, wait).await? else {
+            if repeat {
+                return Ok(());
+            }
+            bail!("no job in {}", queues.join(", "));
+        };
+        if command.is_empty() {
+            println!("{}", serde_json::to_string(&job_json(&job))?);
+            if delete {
+                client.delete(job.id).await?;
+            }
+            return Ok(());
+        }
+        if work(command, &job).await? {
+            client.delete(job.id).await?;
+        } else {
+            // Stop rather than get the same job straight back, over and over.
+            client.abort(job.id).await?;
+            bail!("job {} failed, and was aborted", job.id);
+        }
+        if !repeat {
+            return Ok(());
+        }
+    }
+}
+
+/// The job as the server sent it.
+fn job_json(job: &Job) -> Value {
+    serde_json::json!({"id": job.id, "queue": job.queue, "pri": job.pri, "job": job.job})
+}
+
+/// Runs `command` on `job`, returning whether it succeeded.
+async fn work(command: &[&str], job: &Job) -> Result<bool> {
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .env("JOB_ID", job.id.to_string())
+        .env("JOB_QUEUE", &job.queue)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running {}", command[0]))?;
+    let mut stdin = child.stdin.take().unwrap();
+    // A command that doesn't read its input shouldn't fail the job.
+    let _ = stdin.write_all(job.job.to_string().as_bytes()).await;
+    drop(stdin);
+    Ok(child.wait().await?.success())
+}
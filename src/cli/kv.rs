@@ -0,0 +1,28 @@
+//! Unusual Database Program: `client kv set <key> <value> | get <key>`
+//!
+//! `get` prints the value, and fails if the server doesn't answer within
+//! each of a few timeouts, `--timeout` each if it's given. `get version` asks
+//! for the server's version.
+
+use anyhow::{bail, Result};
+
+use super::Options;
+use crate::clients::unusual_database::Client;
+
+const USAGE: &str = "usage: protohackers client [options] kv set <key> <value> | get <key>";
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    let mut client = Client::connect(&options.addr).await?;
+    if let Some(timeout) = options.timeout {
+        client.set_timeout(timeout);
+    }
+    match *args {
+        ["set", key, value] => client.set(key, value).await?,
+        ["get", key] => match client.get(key).await? {
+            Some(value) => println!("{value}"),
+            None => bail!("no answer for {key:?}"),
+        },
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Line Reversal: `client lrcp`, netcat over LRCP.
+//!
+//! Opens an LRCP session and sends stdin over it, printing whatever comes
+//! back. At the end of stdin it waits for everything to be acknowledged, and
+//! for replies to stop coming, then closes the session. It exits when the
+//! server closes it, too.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::Options;
+use crate::clients::lrcp::Session;
+
+const USAGE: &str = "usage: protohackers client [options] lrcp";
+
+/// How long to wait for more replies once all of stdin is acknowledged: long
+/// enough for a server to resend one that was lost.
+const LINGER: Duration = Duration::from_secs(5);
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    if !args.is_empty() {
+        bail!(USAGE);
+    }
+    let mut session = options.connect(Session::connect(&options.addr)).await?;
+    eprintln!("session {}", session.id());
+
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut buf = vec![0; 4096];
+    loop {
+        tokio::select! {
+            data = session.recv() => match data? {
+                Some(data) => {
+                    stdout.write_all(&data).await?;
+                    stdout.flush().await?;
+                }
+                None => {
+                    eprintln!("server closed the session");
+                    return Ok(());
+                }
+            },
+            len = stdin.read(&mut buf) => match len? {
+                0 => break,
+                len => session.write(&buf[..len]).await?,
+            },
+        }
+    }
+
+    session.flush().await?;
+    while let Ok(data) = tokio::time::timeout(LINGER, session.recv()).await {
+        let Some(data) = data? else {
+            return Ok(());
+        };
+        stdout.write_all(&data).await?;
+        stdout.flush().await?;
+    }
+    session.close().await
+}
@@ -0,0 +1,147 @@
+//! Prime Time: `client prime [file] | --malformed`
+//!
+//! Reads numbers from `file`, or stdin, one a line, and sends each as an
+//! isPrime request without waiting for the answers before, printing
+//! `<number> true` or `<number> false` as they come back. Numbers are sent as
+//! they're written, so they can be any JSON number.
+//!
+//! With `--malformed`, it instead sends each of a set of malformed requests
+//! on its own connection, and checks that the server answers with a malformed
+//! response and closes the connection.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use super::Options;
+
+const USAGE: &str = "usage: protohackers client [options] prime [file] | --malformed";
+
+/// Requests a conforming server must answer with a malformed response.
+const MALFORMED: &[(&str, &str)] = &[
+    ("not JSON", "isPrime 7"),
+    ("not an object", "[\"isPrime\",7]"),
+    ("no method", "{\"number\":7}"),
+    ("wrong method", "{\"method\":\"isEven\",\"number\":7}"),
+    ("no number", "{\"method\":\"isPrime\"}"),
+    (
+        "number as a string",
+        "{\"method\":\"isPrime\",\"number\":\"7\"}",
+    ),
+    (
+        "number as a bool",
+        "{\"method\":\"isPrime\",\"number\":true}",
+    ),
+];
+
+/// How long to wait for the server to answer a malformed request and close.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn well_formed(response: &str) -> Option<bool> {
+    let response: Value = serde_json::from_str(response).ok()?;
+    if response["method"] != "isPrime" {
+        return None;
+    }
+    response["prime"].as_bool()
+}
+
+async fn connect(options: &Options) -> Result<TcpStream> {
+    let addr = &options.addr;
+    options
+        .connect(async {
+            TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("connecting to {addr}"))
+        })
+        .await
+}
+
+async fn stream(options: &Options, input: impl AsyncRead + Unpin + Send + 'static) -> Result<()> {
+    let (reader, mut writer) = connect(options).await?.into_split();
+    let (numbers, mut sent) = mpsc::unbounded_channel();
+    let send = tokio::spawn(async move {
+        let mut lines = BufReader::new(input).lines();
+        while let Some(line) = lines.next_line().await? {
+            let number = line.trim().to_string();
+            if number.is_empty() {
+                continue;
+            }
+            let request = format!("{{\"method\":\"isPrime\",\"number\":{number}}}\n");
+            writer.write_all(request.as_bytes()).await?;
+            // The receiving end only goes if the server misbehaves.
+            if numbers.send(number).is_err() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    let mut responses = BufReader::new(reader).lines();
+    // Ends once the input has, and every request has been answered.
+    while let Some(number) = sent.recv().await {
+        let Some(response) = responses.next_line().await? else {
+            bail!("server closed the connection before answering {number}");
+        };
+        match well_formed(&response) {
+            Some(prime) => println!("{number} {prime}"),
+            None => bail!("malformed response to {number}: {response:?}"),
+        }
+    }
+    send.await?
+}
+
+/// Sends each of `MALFORMED`, returning whether the server rejected them
+/// all.
+async fn malformed(options: &Options) -> Result<bool> {
+    let mut passed = 0;
+    for (name, request) in MALFORMED {
+        let mut connection = connect(options).await?;
+        connection
+            .write_all(format!("{request}\n").as_bytes())
+            .await?;
+        let mut response = vec![];
+        match tokio::time::timeout(TIMEOUT, connection.read_to_end(&mut response)).await {
+            Err(_) => println!("FAIL {name}: still open after {TIMEOUT:?}"),
+            Ok(Err(e)) => println!("FAIL {name}: {e}"),
+            Ok(Ok(_)) => {
+                let response = String::from_utf8_lossy(&response);
+                match response.lines().next().map(well_formed) {
+                    None => println!("FAIL {name}: closed without answering"),
+                    Some(Some(_)) => println!("FAIL {name}: got {:?}", response.trim_end()),
+                    Some(None) => {
+                        println!("PASS {name}");
+                        passed += 1;
+                    }
+                }
+            }
+        }
+    }
+    println!("{passed}/{} passed", MALFORMED.len());
+    Ok(passed == MALFORMED.len())
+}
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    match *args {
+        [] => stream(options, tokio::io::stdin()).await,
+        ["--malformed"] => {
+            if !malformed(options).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        [path] if !path.starts_with("--") => {
+            let file = File::open(path)
+                .await
+                .with_context(|| format!("opening {path}"))?;
+            stream(options, file).await
+        }
+        _ => bail!(USAGE),
+    }
+}
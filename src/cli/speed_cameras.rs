@@ -0,0 +1,192 @@
+//! Speed Daemon: `client speed-cameras <scenario file>`, a camera simulator
+//! connecting as every camera in a scenario, and reporting the plates of
+//! scripted cars as they'd pass each one.
+//!
+//! Each line of a scenario is one of:
+//!
+//! - `road <road> limit <mph> cameras <mile>,<mile>,...`
+//! - `car <plate> road <road> at <timestamp> mile <mile> speed <mph>`, a car
+//!   at `mile` at `timestamp`, driving up the road at a steady speed
+//! - `# comment`, or blank
+//!
+//! A car passes each camera from its mile onwards. Give a plate several cars
+//! to script a trip over several roads or days.
+//!
+//! Once every plate is sent, this prints the tickets expected from a server
+//! comparing each observation with the car's last on the road, in the
+//! format `client speed-dispatcher --record` writes, so the two can be diffed once
+//! sorted. A server checking other pairs may pick different ones.
+
+use std::{collections::HashMap, fs};
+
+use anyhow::{bail, Context, Result};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use super::Options;
+use crate::clients::speed_daemon::{Client, Ticket};
+
+const USAGE: &str = "usage: protohackers client [options] speed-cameras <scenario file>";
+
+/// Seconds in a day, for the one ticket per car per day.
+const DAY: u32 = 86400;
+
+struct Road {
+    road: u16,
+    limit: u16,
+    cameras: Vec<u16>,
+}
+
+struct Car {
+    plate: String,
+    road: u16,
+    at: u32,
+    mile: u16,
+    speed: f64,
+}
+
+fn parse(text: &str) -> Result<(Vec<Road>, Vec<Car>)> {
+    let mut roads = vec![];
+    let mut cars = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = || format!("line {}: {line:?}", i + 1);
+        match words[..] {
+            [] => {}
+            [first, ..] if first.starts_with('#') => {}
+            ["road", road, "limit", limit, "cameras", cameras] => roads.push(Road {
+                road: road.parse().with_context(error)?,
+                limit: limit.parse().with_context(error)?,
+                cameras: cameras
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .with_context(error)?,
+            }),
+            ["car", plate, "road", road, "at", at, "mile", mile, "speed", speed] => {
+                cars.push(Car {
+                    plate: plate.to_string(),
+                    road: road.parse().with_context(error)?,
+                    at: at.parse().with_context(error)?,
+                    mile: mile.parse().with_context(error)?,
+                    speed: speed.parse().with_context(error)?,
+                })
+            }
+            _ => bail!("{}: expected `road ...`, `car ...` or `#`", error()),
+        }
+    }
+    Ok((roads, cars))
+}
+
+/// An observation: `plate` at `mile` on `road` at `timestamp`.
+struct Seen {
+    timestamp: u32,
+    road: u16,
+    mile: u16,
+    plate: String,
+}
+
+/// When each car passes each camera ahead of it.
+fn observations(roads: &[Road], cars: &[Car]) -> Result<Vec<Seen>> {
+    let mut seen = vec![];
+    for car in cars {
+        let Some(road) = roads.iter().find(|road| road.road == car.road) else {
+            bail!(
+                "car {} is on road {}, which has no cameras",
+                car.plate,
+                car.road
+            );
+        };
+        if car.speed <= 0.0 {
+            bail!("car {} isn't moving", car.plate);
+        }
+        for &mile in road.cameras.iter().filter(|&&mile| mile >= car.mile) {
+            let hours = f64::from(mile - car.mile) / car.speed;
+            seen.push(Seen {
+                timestamp: car.at + (hours * 3600.0).round() as u32,
+                road: road.road,
+                mile,
+                plate: car.plate.clone(),
+            });
+        }
+    }
+    seen.sort_by_key(|seen| seen.timestamp);
+    Ok(seen)
+}
+
+/// The tickets for `seen`, in time order: for a car going 0.5 mph or more
+/// over the limit between one camera and the next, unless it's already had a
+/// ticket on one of the days between.
+fn expected_tickets(roads: &[Road], seen: &[Seen]) -> Vec<Ticket> {
+    let mut last: HashMap<(&str, u16), &Seen> = HashMap::new();
+    let mut ticketed: HashMap<&str, Vec<u32>> = HashMap::new();
+    let mut tickets = vec![];
+    for now in seen {
+        let key = (now.plate.as_str(), now.road);
+        let Some(before) = last.insert(key, now) else {
+            continue;
+        };
+        let limit = roads
+            .iter()
+            .find(|road| road.road == now.road)
+            .unwrap()
+            .limit;
+        let miles = f64::from(now.mile.abs_diff(before.mile));
+        let hours = f64::from(now.timestamp - before.timestamp) / 3600.0;
+        let speed = miles / hours;
+        if hours == 0.0 || speed < f64::from(limit) + 0.5 {
+            continue;
+        }
+        let days = before.timestamp / DAY..=now.timestamp / DAY;
+        let ticketed = ticketed.entry(key.0).or_default();
+        if days.clone().any(|day| ticketed.contains(&day)) {
+            continue;
+        }
+        ticketed.extend(days);
+        let (first, second) = match before.mile < now.mile {
+            true => (before, now),
+            false => (now, before),
+        };
+        tickets.push(Ticket {
+            plate: now.plate.clone(),
+            road: now.road,
+            mile1: first.mile,
+            timestamp1: first.timestamp,
+            mile2: second.mile,
+            timestamp2: second.timestamp,
+            speed: (speed * 100.0).round() as u16,
+        });
+    }
+    tickets
+}
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    let [path] = args else {
+        bail!(USAGE);
+    };
+    let text = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+    let (roads, cars) = parse(&text)?;
+    let seen = observations(&roads, &cars)?;
+
+    let mut cameras: HashMap<(u16, u16), Client<OwnedReadHalf, OwnedWriteHalf>> = HashMap::new();
+    for road in &roads {
+        for &mile in &road.cameras {
+            let mut client = options.connect(Client::connect(&options.addr)).await?;
+            client.camera(road.road, mile, road.limit).await?;
+            cameras.insert((road.road, mile), client);
+        }
+    }
+    eprintln!(
+        "{} cameras on {} roads, sending {} plates",
+        cameras.len(),
+        roads.len(),
+        seen.len()
+    );
+    for seen in &seen {
+        let camera = cameras.get_mut(&(seen.road, seen.mile)).unwrap();
+        camera.plate(&seen.plate, seen.timestamp).await?;
+    }
+    for ticket in expected_tickets(&roads, &seen) {
+        println!("{ticket}");
+    }
+    Ok(())
+}
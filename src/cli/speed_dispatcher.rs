@@ -0,0 +1,59 @@
+//! Speed Daemon: `client speed-dispatcher [--heartbeat deciseconds]
+//! [--record file] <road>...`, a ticket dispatcher printing each ticket sent
+//! for its roads as it arrives.
+//!
+//! With `--record`, tickets are also written to `file`, one a line in the
+//! format `client speed-cameras` prints its expected tickets in, for diffing the
+//! two once sorted.
+
+use std::{fs::File, io::Write};
+
+use anyhow::{bail, Context, Result};
+
+use super::Options;
+use crate::clients::speed_daemon::{Client, Message};
+
+const USAGE: &str = "usage: protohackers client [options] speed-dispatcher \
+    [--heartbeat deciseconds] [--record file] <road>...";
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    let mut args = args.iter().copied().peekable();
+    let mut heartbeat = 0;
+    let mut record = None;
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        let value = args.next().context(USAGE)?;
+        match flag {
+            "--heartbeat" => heartbeat = value.parse().context("bad --heartbeat")?,
+            "--record" => {
+                record = Some(File::create(value).with_context(|| format!("creating {value}"))?)
+            }
+            _ => bail!(USAGE),
+        }
+    }
+    let roads = args
+        .map(|road| road.parse().with_context(|| format!("bad road {road}")))
+        .collect::<Result<Vec<u16>>>()?;
+    if roads.is_empty() {
+        bail!(USAGE);
+    }
+
+    let mut client = options.connect(Client::connect(&options.addr)).await?;
+    client.dispatcher(&roads).await?;
+    if heartbeat > 0 {
+        client.want_heartbeat(heartbeat).await?;
+    }
+    while let Some(message) = client.recv().await? {
+        match message {
+            Message::Ticket(ticket) => {
+                println!("{ticket}");
+                if let Some(file) = &mut record {
+                    writeln!(file, "{ticket}")?;
+                }
+            }
+            Message::Heartbeat => {}
+            Message::Error(e) => bail!("server error: {e}"),
+        }
+    }
+    eprintln!("server closed the connection");
+    Ok(())
+}
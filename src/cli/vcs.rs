@@ -0,0 +1,67 @@
+//! Voracious Code Storage: `client vcs put <path> [file] | get <path>
+//! [revision] | list [dir]`
+//!
+//! `put` reads stdin when no file is given, and prints the new revision.
+//! `get` writes to stdout.
+
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+};
+
+use super::Options;
+use crate::clients::vcs::Client;
+
+const USAGE: &str =
+    "usage: protohackers client [options] vcs put <path> [file] | get <path> [revision] | list [dir]";
+
+pub(super) async fn run(options: &Options, args: &[&str]) -> Result<()> {
+    match *args {
+        ["put", path] => {
+            let mut data = vec![];
+            tokio::io::stdin().read_to_end(&mut data).await?;
+            put(options, path, &data).await
+        }
+        ["put", path, file] => {
+            let data = fs::read(file).with_context(|| format!("reading {file}"))?;
+            put(options, path, &data).await
+        }
+        ["get", path] => get(options, path, None).await,
+        ["get", path, revision] => {
+            let revision = revision.strip_prefix('r').unwrap_or(revision);
+            let revision = revision.parse().context("bad revision")?;
+            get(options, path, Some(revision)).await
+        }
+        ["list"] => list(options, "/").await,
+        ["list", dir] => list(options, dir).await,
+        _ => bail!(USAGE),
+    }
+}
+
+async fn connect(options: &Options) -> Result<Client<OwnedReadHalf, OwnedWriteHalf>> {
+    options.connect(Client::connect(&options.addr)).await
+}
+
+async fn put(options: &Options, path: &str, data: &[u8]) -> Result<()> {
+    let revision = connect(options).await?.put(path, data).await?;
+    println!("r{revision}");
+    Ok(())
+}
+
+async fn get(options: &Options, path: &str, revision: Option<usize>) -> Result<()> {
+    let data = connect(options).await?.get(path, revision).await?;
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&data).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+async fn list(options: &Options, dir: &str) -> Result<()> {
+    for entry in connect(options).await?.list(dir).await? {
+        println!("{entry}");
+    }
+    Ok(())
+}
@@ -0,0 +1,12 @@
+//! Typed async clients for the problems' protocols, for tools and tests.
+//! Each is generic over its reader and writer, with `connect` for TCP, but for
+//! LRCP's and the Unusual Database's, which have their own UDP sockets.
+
+pub mod bank;
+pub mod insecure_sockets;
+pub mod job_centre;
+pub mod lrcp;
+pub mod prime_time;
+pub mod speed_daemon;
+pub mod unusual_database;
+pub use crate::vcs::client as vcs;
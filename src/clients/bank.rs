@@ -0,0 +1,77 @@
+//! A client for Means to an End.
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+pub struct Client<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        // Inserts are small and unanswered, so without this a query behind
+        // them can wait on a delayed ACK.
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Client::new(reader, writer))
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Client { reader, writer }
+    }
+
+    /// Stores a price. The server doesn't answer inserts.
+    pub async fn insert(&mut self, timestamp: i32, price: i32) -> Result<()> {
+        self.send(b'I', timestamp, price).await
+    }
+
+    /// The mean price between `min` and `max` inclusive, 0 if there are none.
+    pub async fn query(&mut self, min: i32, max: i32) -> Result<i32> {
+        self.send(b'Q', min, max).await?;
+        Ok(self.reader.read_i32().await?)
+    }
+
+    async fn send(&mut self, kind: u8, a: i32, b: i32) -> Result<()> {
+        let mut message = [0; 9];
+        message[0] = kind;
+        message[1..5].copy_from_slice(&a.to_be_bytes());
+        message[5..].copy_from_slice(&b.to_be_bytes());
+        Ok(self.writer.write_all(&message).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Client;
+
+    #[tokio::test]
+    async fn insert_query() {
+        let reader = tokio_test::io::Builder::new()
+            .read(&101i32.to_be_bytes())
+            .read(&(-3i32).to_be_bytes())
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"I\x00\x00\x30\x39\x00\x00\x00\x65")
+            .write(b"Q\x00\x00\x03\xe8\x00\x01\x86\xa0")
+            .write(b"Q\xff\xff\xff\xff\x00\x00\x00\x00")
+            .build();
+        let mut client = Client::new(reader, writer);
+        client.insert(12345, 101).await.unwrap();
+        assert_eq!(client.query(1000, 100000).await.unwrap(), 101);
+        assert_eq!(client.query(-1, 0).await.unwrap(), -3);
+    }
+}
@@ -0,0 +1,156 @@
+//! A client for Insecure Sockets Layer. It enciphers by applying each op of
+//! its spec in turn, straight from the spec's description, and not with the
+//! server's tables, so testing one against the other checks both.
+
+use anyhow::{ensure, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    ReverseBits,
+    Xor(u8),
+    XorPos,
+    Add(u8),
+    AddPos,
+}
+
+/// The bytes of the spec for `ops`, ending with its 00.
+pub fn spec(ops: &[Op]) -> Vec<u8> {
+    let mut spec = vec![];
+    for op in ops {
+        match *op {
+            Op::ReverseBits => spec.push(0x01),
+            Op::Xor(n) => spec.extend([0x02, n]),
+            Op::XorPos => spec.push(0x03),
+            Op::Add(n) => spec.extend([0x04, n]),
+            Op::AddPos => spec.push(0x05),
+        }
+    }
+    spec.push(0x00);
+    spec
+}
+
+pub struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+    ops: Vec<Op>,
+    /// How many bytes have been sent and received since the spec, as
+    /// positions modulo 256.
+    sent: u8,
+    received: u8,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str, ops: Vec<Op>) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Client::new(reader, writer, ops).await
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Starts a session by sending the spec for `ops`.
+    pub async fn new(reader: R, mut writer: W, ops: Vec<Op>) -> Result<Self> {
+        writer.write_all(&spec(&ops)).await?;
+        Ok(Client {
+            reader: BufReader::new(reader),
+            writer,
+            ops,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    /// Sends a request, like "10x toy car,15x dog on a string", without
+    /// waiting for the answer. Requests may be pipelined.
+    pub async fn send(&mut self, toys: &str) -> Result<()> {
+        let mut request = format!("{toys}\n").into_bytes();
+        for b in &mut request {
+            *b = self.encode(*b);
+        }
+        Ok(self.writer.write_all(&request).await?)
+    }
+
+    /// The answer to the oldest request not yet answered: the toy there are
+    /// most copies of.
+    pub async fn recv(&mut self) -> Result<String> {
+        let mut response = vec![];
+        loop {
+            let b = self.reader.read_u8().await?;
+            let b = self.decode(b);
+            if b == b'\n' {
+                break;
+            }
+            response.push(b);
+            ensure!(response.len() <= 5000, "response too long");
+        }
+        Ok(String::from_utf8(response)?)
+    }
+
+    /// Sends a request and waits for its answer.
+    pub async fn most_copies(&mut self, toys: &str) -> Result<String> {
+        self.send(toys).await?;
+        self.recv().await
+    }
+
+    fn encode(&mut self, mut b: u8) -> u8 {
+        let pos = self.sent;
+        for op in &self.ops {
+            b = match *op {
+                Op::ReverseBits => b.reverse_bits(),
+                Op::Xor(n) => b ^ n,
+                Op::XorPos => b ^ pos,
+                Op::Add(n) => b.wrapping_add(n),
+                Op::AddPos => b.wrapping_add(pos),
+            };
+        }
+        self.sent = pos.wrapping_add(1);
+        b
+    }
+
+    fn decode(&mut self, mut b: u8) -> u8 {
+        let pos = self.received;
+        for op in self.ops.iter().rev() {
+            b = match *op {
+                Op::ReverseBits => b.reverse_bits(),
+                Op::Xor(n) => b ^ n,
+                Op::XorPos => b ^ pos,
+                Op::Add(n) => b.wrapping_sub(n),
+                Op::AddPos => b.wrapping_sub(pos),
+            };
+        }
+        self.received = pos.wrapping_add(1);
+        b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Client, Op};
+
+    #[tokio::test]
+    async fn spec_example() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"\x72\x20\xba\xd8\x78\x70\xee")
+            .read(b"\xf2\xd0\x26\xc8\xa4\xd8\x7e")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"\x02\x7b\x05\x01\x00")
+            .write(b"\xf2\x20\xba\x44\x18\x84\xba\xaa\xd0\x26\x44\xa4\xa8\x7e")
+            .write(b"\x6a\x48\xd6\x58\x34\x44\xd6\x7a\x98\x4e\x0c\xcc\x94\x31")
+            .build();
+        let ops = vec![Op::Xor(123), Op::AddPos, Op::ReverseBits];
+        let mut client = Client::new(reader, writer, ops).await.unwrap();
+        assert_eq!(client.most_copies("4x dog,5x car").await.unwrap(), "5x car");
+        assert_eq!(client.most_copies("3x rat,2x cat").await.unwrap(), "3x rat");
+    }
+}
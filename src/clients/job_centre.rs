@@ -0,0 +1,148 @@
+//! A client for Job Centre.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+pub struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+/// A job handed out by `get`, now in progress for this client.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub queue: String,
+    pub pri: u64,
+    pub job: Value,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    status: String,
+    error: Option<String>,
+    #[serde(flatten)]
+    rest: Value,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Client::new(reader, writer))
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    /// Adds a job to `queue`, returning its id.
+    pub async fn put(&mut self, queue: &str, job: &Value, pri: u64) -> Result<u64> {
+        let request = json!({"request": "put", "queue": queue, "job": job, "pri": pri});
+        let Some(response) = self.request(&request).await? else {
+            bail!("unexpected no-job response");
+        };
+        response["id"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("bad put response: {response}"))
+    }
+
+    /// The highest priority job in `queues`, if any, or with `wait` the first
+    /// one to arrive.
+    pub async fn get(&mut self, queues: &[&str], wait: bool) -> Result<Option<Job>> {
+        let request = json!({"request": "get", "queues": queues, "wait": wait});
+        match self.request(&request).await? {
+            Some(response) => Ok(Some(serde_json::from_value(response)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the job existed to be deleted.
+    pub async fn delete(&mut self, id: u64) -> Result<bool> {
+        let request = json!({"request": "delete", "id": id});
+        Ok(self.request(&request).await?.is_some())
+    }
+
+    /// Puts a job this client is working on back in its queue. Whether it was
+    /// ours to abort.
+    pub async fn abort(&mut self, id: u64) -> Result<bool> {
+        let request = json!({"request": "abort", "id": id});
+        Ok(self.request(&request).await?.is_some())
+    }
+
+    /// The rest of an `ok` response, `None` for `no-job`, and an error for
+    /// `error`.
+    async fn request(&mut self, request: &Value) -> Result<Option<Value>> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            bail!("server closed the connection");
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        match response.status.as_str() {
+            "ok" => Ok(Some(response.rest)),
+            "no-job" => Ok(None),
+            "error" => bail!("{}", response.error.unwrap_or_default()),
+            status => bail!("unknown status {status:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{Client, Job};
+
+    #[tokio::test]
+    async fn requests() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"status\":\"ok\",\"id\":7}\n")
+            .read(b"{\"status\":\"ok\",\"id\":7,\"job\":{\"n\":1},\"pri\":3,\"queue\":\"q\"}\n")
+            .read(b"{\"status\":\"ok\"}\n")
+            .read(b"{\"status\":\"no-job\"}\n")
+            .read(b"{\"status\":\"no-job\"}\n")
+            .read(b"{\"status\":\"error\",\"error\":\"missing `id`\"}\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"job\":{\"n\":1},\"pri\":3,\"queue\":\"q\",\"request\":\"put\"}\n")
+            .write(b"{\"queues\":[\"q\"],\"request\":\"get\",\"wait\":false}\n")
+            .write(b"{\"id\":7,\"request\":\"delete\"}\n")
+            .write(b"{\"id\":7,\"request\":\"abort\"}\n")
+            .write(b"{\"queues\":[\"q\"],\"request\":\"get\",\"wait\":true}\n")
+            .write(b"{\"id\":7,\"request\":\"delete\"}\n")
+            .build();
+        let mut client = Client::new(reader, writer);
+        assert_eq!(client.put("q", &json!({"n": 1}), 3).await.unwrap(), 7);
+        let job = Job {
+            id: 7,
+            queue: "q".to_string(),
+            pri: 3,
+            job: json!({"n": 1}),
+        };
+        assert_eq!(client.get(&["q"], false).await.unwrap(), Some(job));
+        assert!(client.delete(7).await.unwrap());
+        assert!(!client.abort(7).await.unwrap());
+        assert_eq!(client.get(&["q"], true).await.unwrap(), None);
+        let err = client.delete(7).await.unwrap_err();
+        assert_eq!(err.to_string(), "missing `id`");
+    }
+}
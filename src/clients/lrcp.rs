@@ -0,0 +1,333 @@
+//! The client end of an LRCP session, the reliable byte stream over UDP that
+//! Line Reversal runs on.
+//!
+//! Messages are `/`-separated fields between an opening and closing `/`,
+//! with `/` and `\` in data escaped by a `\`. The client connects, sends its
+//! data at positions in the stream and resends whatever isn't acknowledged,
+//! and acknowledges the peer's data in turn.
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::{net::UdpSocket, time::Instant};
+
+pub use crate::line_reversal::message::MAX_LEN;
+use crate::{
+    datagram::Datagram,
+    line_reversal::message::{data_messages, parse, Message, MAX_NUMBER},
+};
+
+/// How long to wait for an acknowledgement before sending again.
+pub const RETRANSMIT: Duration = Duration::from_secs(3);
+
+/// How long to go on sending without hearing from the peer before giving up.
+pub const EXPIRY: Duration = Duration::from_secs(60);
+
+pub struct Session<D = UdpSocket> {
+    socket: D,
+    peer: SocketAddr,
+    id: u32,
+    /// Everything written, from the start of the stream.
+    sent: Vec<u8>,
+    /// How much of `sent` the peer has acknowledged.
+    acked: usize,
+    /// How much of the peer's stream has been received.
+    received: usize,
+    /// Received data that `recv` hasn't returned yet.
+    unread: Vec<u8>,
+    /// Whether the peer has closed the session.
+    closed: bool,
+    /// When the peer was last heard from.
+    heard: Instant,
+    /// When unacknowledged data is next sent again.
+    retransmit: Instant,
+}
+
+impl Session {
+    /// Opens a session with the server at `addr`, with an id made from the
+    /// time so that separate runs don't share one.
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let peer = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .with_context(|| format!("no address for {addr}"))?;
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        // Connected, so that datagrams from anywhere else are dropped.
+        socket.connect(peer).await?;
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .subsec_nanos();
+        let id = (nanos ^ std::process::id()) % MAX_NUMBER as u32;
+        Session::open(socket, peer, id).await
+    }
+}
+
+impl<D: Datagram> Session<D> {
+    /// Opens session `id` with the server at `peer`, over `socket`.
+    pub(crate) async fn open(socket: D, peer: SocketAddr, id: u32) -> Result<Self> {
+        let mut session = Session {
+            socket,
+            peer,
+            id,
+            sent: vec![],
+            acked: 0,
+            received: 0,
+            unread: vec![],
+            closed: false,
+            heard: Instant::now(),
+            retransmit: Instant::now(),
+        };
+
+        let connect = format!("/connect/{id}/");
+        let mut buf = [0; MAX_LEN];
+        let deadline = Instant::now() + EXPIRY;
+        while Instant::now() < deadline {
+            session.send(connect.as_bytes()).await?;
+            let wait = Instant::now() + RETRANSMIT;
+            while let Ok(len) = tokio::time::timeout_at(wait, session.recv_datagram(&mut buf)).await
+            {
+                match parse(&buf[..len?]) {
+                    Some(Message::Ack(session_id, 0)) if session_id == id => {
+                        session.heard = Instant::now();
+                        return Ok(session);
+                    }
+                    Some(Message::Close(session_id)) if session_id == id => {
+                        bail!("the server closed session {id} on connecting")
+                    }
+                    _ => {}
+                }
+            }
+        }
+        bail!("no answer to connecting in {EXPIRY:?}")
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Whether the peer has acknowledged everything written.
+    pub fn flushed(&self) -> bool {
+        self.acked == self.sent.len()
+    }
+
+    /// Sends `data` after everything written so far. It's sent again until
+    /// it's acknowledged, as long as `recv` or `flush` is being called.
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.flushed() {
+            self.retransmit = Instant::now() + RETRANSMIT;
+        }
+        let pos = self.sent.len();
+        self.sent.extend(data);
+        self.send_data(pos).await
+    }
+
+    /// The next data from the peer, or `None` once it's closed the session.
+    /// Meanwhile, acknowledges the peer's data and resends ours. Cancelling
+    /// it loses no data.
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if !self.unread.is_empty() {
+                return Ok(Some(std::mem::take(&mut self.unread)));
+            }
+            if self.closed {
+                return Ok(None);
+            }
+            self.step().await?;
+        }
+    }
+
+    /// Waits until the peer has acknowledged everything written, keeping
+    /// what it sends meanwhile for `recv`.
+    pub async fn flush(&mut self) -> Result<()> {
+        while !self.flushed() {
+            if self.closed {
+                bail!(
+                    "the server closed the session with {} bytes unacknowledged",
+                    self.sent.len() - self.acked
+                );
+            }
+            self.step().await?;
+        }
+        Ok(())
+    }
+
+    /// Handles the next message from the peer, or resends what it hasn't
+    /// acknowledged if it's been too long.
+    async fn step(&mut self) -> Result<()> {
+        if !self.flushed() && self.heard.elapsed() > EXPIRY {
+            bail!("no answer from the server in {EXPIRY:?}");
+        }
+        let mut buf = [0; MAX_LEN];
+        let len = if self.flushed() {
+            self.recv_datagram(&mut buf).await?
+        } else {
+            match tokio::time::timeout_at(self.retransmit, self.recv_datagram(&mut buf)).await {
+                Ok(len) => len?,
+                Err(_) => {
+                    self.retransmit = Instant::now() + RETRANSMIT;
+                    return self.send_data(self.acked).await;
+                }
+            }
+        };
+        let message = match parse(&buf[..len]) {
+            Some(message) if self.is_ours(&message) => message,
+            _ => return Ok(()),
+        };
+        self.heard = Instant::now();
+        match message {
+            Message::Data(_, pos, data) => {
+                let pos = pos as usize;
+                if pos > self.received {
+                    // Missed some: acknowledge what we have so it's resent.
+                    return self.ack().await;
+                }
+                // Any of `data` before `received` is a resend.
+                let new = data.get(self.received - pos..).unwrap_or_default();
+                self.received += new.len();
+                self.unread.extend(new);
+                self.ack().await?;
+            }
+            Message::Ack(_, len) => {
+                let len = len as usize;
+                if len <= self.acked {
+                    return Ok(());
+                }
+                if len > self.sent.len() {
+                    self.close().await?;
+                    bail!("the server acknowledged {len} bytes of {}", self.sent.len());
+                }
+                self.acked = len;
+                self.retransmit = Instant::now() + RETRANSMIT;
+                if !self.flushed() {
+                    self.send_data(len).await?;
+                }
+            }
+            Message::Close(_) => {
+                self.closed = true;
+                self.send(format!("/close/{}/", self.id).as_bytes()).await?;
+            }
+            Message::Connect(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Closes the session, waiting a little for the server to close its end.
+    /// Anything unacknowledged is lost, so `flush` first.
+    pub async fn close(&mut self) -> Result<()> {
+        let close = format!("/close/{}/", self.id);
+        let mut buf = [0; MAX_LEN];
+        for _ in 0..3 {
+            self.send(close.as_bytes()).await?;
+            let wait = Instant::now() + RETRANSMIT;
+            while let Ok(len) = tokio::time::timeout_at(wait, self.recv_datagram(&mut buf)).await {
+                if let Some(Message::Close(id)) = parse(&buf[..len?]) {
+                    if id == self.id {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_ours(&self, message: &Message) -> bool {
+        match *message {
+            Message::Connect(id)
+            | Message::Data(id, ..)
+            | Message::Ack(id, _)
+            | Message::Close(id) => id == self.id,
+        }
+    }
+
+    async fn ack(&mut self) -> Result<()> {
+        let ack = format!("/ack/{}/{}/", self.id, self.received);
+        self.send(ack.as_bytes()).await
+    }
+
+    /// Sends everything written from `pos` on.
+    async fn send_data(&mut self, pos: usize) -> Result<()> {
+        if self.sent.len() >= MAX_NUMBER as usize {
+            bail!("LRCP streams are at most {MAX_NUMBER} bytes");
+        }
+        for message in data_messages(self.id, pos, &self.sent[pos..]) {
+            self.send(&message).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, message: &[u8]) -> Result<()> {
+        self.socket
+            .send_to(message, self.peer)
+            .await
+            .context("sending to the server")?;
+        Ok(())
+    }
+
+    /// Receives the next datagram from the server, skipping any from
+    /// elsewhere.
+    async fn recv_datagram(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (len, from) = self.socket.recv_from(buf).await?;
+            if from == self.peer {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::UdpSocket;
+
+    use super::{parse, Message, Session, MAX_LEN};
+
+    async fn recv(server: &UdpSocket) -> Message {
+        let mut buf = [0; MAX_LEN];
+        let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+        server.connect(peer).await.unwrap();
+        parse(&buf[..len]).unwrap()
+    }
+
+    async fn send(server: &UdpSocket, message: String) {
+        server.send(message.as_bytes()).await.unwrap();
+    }
+
+    /// Plays the server's part: acks the connect, then `hello\n`, sending
+    /// `olleh\n` back in two messages, the second a resend overlapping the
+    /// first, then closes once the client does.
+    #[tokio::test]
+    async fn session() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let peer = tokio::spawn(async move {
+            let Message::Connect(id) = recv(&server).await else {
+                panic!("expected a connect");
+            };
+            send(&server, format!("/ack/{id}/0/")).await;
+            let hello = Message::Data(id, 0, b"hello\n".to_vec());
+            assert_eq!(recv(&server).await, hello);
+            send(&server, format!("/ack/{id}/6/")).await;
+            send(&server, format!("/data/{id}/0/oll/")).await;
+            assert_eq!(recv(&server).await, Message::Ack(id, 3));
+            send(&server, format!("/data/{id}/1/lleh\n/")).await;
+            assert_eq!(recv(&server).await, Message::Ack(id, 6));
+            assert_eq!(recv(&server).await, Message::Close(id));
+            send(&server, format!("/close/{id}/")).await;
+        });
+
+        let mut session = Session::connect(&addr).await.unwrap();
+        session.write(b"hello\n").await.unwrap();
+        let mut received = vec![];
+        while received.len() < 6 {
+            received.extend(session.recv().await.unwrap().unwrap());
+        }
+        assert_eq!(received, b"olleh\n");
+        session.flush().await.unwrap();
+        session.close().await.unwrap();
+        peer.await.unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! A client for Prime Time.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+pub struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+#[derive(Deserialize)]
+struct Response<P> {
+    method: String,
+    prime: P,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Client::new(reader, writer))
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    /// Whether `number` is prime. It's sent as it is, so it can be any JSON
+    /// number, however big. A malformed response is an error, and the server
+    /// closes the connection after one.
+    pub async fn is_prime(&mut self, number: &str) -> Result<bool> {
+        self.request(number).await
+    }
+
+    /// Checks several numbers in one request, which the server only accepts
+    /// with `PRIME_TIME_BATCH` set.
+    pub async fn batch(&mut self, numbers: &[&str]) -> Result<Vec<bool>> {
+        self.request(&format!("[{}]", numbers.join(","))).await
+    }
+
+    async fn request<P: for<'de> Deserialize<'de>>(&mut self, number: &str) -> Result<P> {
+        let request = format!("{{\"method\":\"isPrime\",\"number\":{number}}}\n");
+        self.writer.write_all(request.as_bytes()).await?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            bail!("server closed the connection");
+        }
+        let line = line.trim_end_matches('\n');
+        let response: Response<P> =
+            serde_json::from_str(line).map_err(|_| anyhow!("malformed response: {line:?}"))?;
+        if response.method != "isPrime" {
+            bail!("malformed response: {line:?}");
+        }
+        Ok(response.prime)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Client;
+
+    #[tokio::test]
+    async fn is_prime() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .read(b"{\"method\":\"isPrime\",\"prime\":[false,true]}\n")
+            .read(b"malformed\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"method\":\"isPrime\",\"number\":618970019642690137449562111}\n")
+            .write(b"{\"method\":\"isPrime\",\"number\":[4,5]}\n")
+            .write(b"{\"method\":\"isPrime\",\"number\":\"7\"}\n")
+            .build();
+        let mut client = Client::new(reader, writer);
+        assert!(client
+            .is_prime("618970019642690137449562111")
+            .await
+            .unwrap());
+        assert_eq!(client.batch(&["4", "5"]).await.unwrap(), [false, true]);
+        let err = client.is_prime("\"7\"").await.unwrap_err();
+        assert_eq!(err.to_string(), "malformed response: \"malformed\"");
+    }
+}
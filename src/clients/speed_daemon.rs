@@ -0,0 +1,206 @@
+//! A client for Speed Daemon, as either a camera or a ticket dispatcher.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// A ticket, with `speed` in hundredths of a mile per hour.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ticket {
+    pub plate: String,
+    pub road: u16,
+    pub mile1: u16,
+    pub timestamp1: u32,
+    pub mile2: u16,
+    pub timestamp2: u32,
+    pub speed: u16,
+}
+
+/// One line of space-separated fields, in the order they're sent, for
+/// diffing one list of tickets against another.
+impl fmt::Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.plate,
+            self.road,
+            self.mile1,
+            self.timestamp1,
+            self.mile2,
+            self.timestamp2,
+            self.speed
+        )
+    }
+}
+
+/// What the server sends a client.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Ticket(Ticket),
+    Heartbeat,
+    Error(String),
+}
+
+pub struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        // Plates are small and unanswered, so they'd otherwise be held back
+        // waiting for an ACK.
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Client::new(reader, writer))
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    /// Identifies as a camera at `mile` on `road`, which has a speed limit of
+    /// `limit` miles per hour.
+    pub async fn camera(&mut self, road: u16, mile: u16, limit: u16) -> Result<()> {
+        let mut message = vec![0x80];
+        for n in [road, mile, limit] {
+            message.extend(n.to_be_bytes());
+        }
+        self.send(&message).await
+    }
+
+    /// Identifies as a dispatcher for `roads`.
+    pub async fn dispatcher(&mut self, roads: &[u16]) -> Result<()> {
+        let Ok(len) = u8::try_from(roads.len()) else {
+            bail!("at most 255 roads, not {}", roads.len());
+        };
+        let mut message = vec![0x81, len];
+        for road in roads {
+            message.extend(road.to_be_bytes());
+        }
+        self.send(&message).await
+    }
+
+    /// Reports `plate` passing the camera at `timestamp`.
+    pub async fn plate(&mut self, plate: &str, timestamp: u32) -> Result<()> {
+        let mut message = vec![0x20];
+        push_str(&mut message, plate)?;
+        message.extend(timestamp.to_be_bytes());
+        self.send(&message).await
+    }
+
+    /// Asks for a heartbeat every `interval` tenths of a second, or none if 0.
+    pub async fn want_heartbeat(&mut self, interval: u32) -> Result<()> {
+        let mut message = vec![0x40];
+        message.extend(interval.to_be_bytes());
+        self.send(&message).await
+    }
+
+    /// The next message from the server, or `None` once it's closed the
+    /// connection.
+    pub async fn recv(&mut self) -> Result<Option<Message>> {
+        let kind = match self.reader.read_u8().await {
+            Ok(kind) => kind,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let message = match kind {
+            0x10 => Message::Error(self.read_str().await?),
+            0x21 => Message::Ticket(Ticket {
+                plate: self.read_str().await?,
+                road: self.reader.read_u16().await?,
+                mile1: self.reader.read_u16().await?,
+                timestamp1: self.reader.read_u32().await?,
+                mile2: self.reader.read_u16().await?,
+                timestamp2: self.reader.read_u32().await?,
+                speed: self.reader.read_u16().await?,
+            }),
+            0x41 => Message::Heartbeat,
+            kind => bail!("unexpected message type {kind:#04x}"),
+        };
+        Ok(Some(message))
+    }
+
+    async fn read_str(&mut self) -> Result<String> {
+        let len = self.reader.read_u8().await?;
+        let mut bytes = vec![0; len as usize];
+        self.reader.read_exact(&mut bytes).await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    async fn send(&mut self, message: &[u8]) -> Result<()> {
+        Ok(self.writer.write_all(message).await?)
+    }
+}
+
+fn push_str(message: &mut Vec<u8>, s: &str) -> Result<()> {
+    let Ok(len) = u8::try_from(s.len()) else {
+        bail!("strings are at most 255 bytes, not {}", s.len());
+    };
+    message.push(len);
+    message.extend(s.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Client, Message, Ticket};
+
+    #[tokio::test]
+    async fn camera_and_dispatcher() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"\x21\x04UN1X\x00\x42\x00\x64\x00\x01\xe2\x40\x00\x6e\x00\x01\xe3\xa8\x27\x10")
+            .read(b"\x41")
+            .read(b"\x10\x03bad")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"\x80\x00\x42\x00\x64\x00\x3c")
+            .write(b"\x20\x04UN1X\x00\x00\x03\xe8")
+            .write(b"\x81\x02\x00\x42\x01\x70")
+            .write(b"\x40\x00\x00\x00\x19")
+            .build();
+        let mut client = Client::new(reader, writer);
+        client.camera(66, 100, 60).await.unwrap();
+        client.plate("UN1X", 1000).await.unwrap();
+        client.dispatcher(&[66, 368]).await.unwrap();
+        client.want_heartbeat(25).await.unwrap();
+        let ticket = Ticket {
+            plate: "UN1X".to_string(),
+            road: 66,
+            mile1: 100,
+            timestamp1: 123456,
+            mile2: 110,
+            timestamp2: 123816,
+            speed: 10000,
+        };
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Some(Message::Ticket(ticket.clone()))
+        );
+        assert_eq!(client.recv().await.unwrap(), Some(Message::Heartbeat));
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Some(Message::Error("bad".to_string()))
+        );
+        assert_eq!(client.recv().await.unwrap(), None);
+        assert_eq!(ticket.to_string(), "UN1X 66 100 123456 110 123816 10000");
+    }
+}
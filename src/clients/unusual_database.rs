@@ -0,0 +1,135 @@
+//! A client for Unusual Database Program, over UDP.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::{net::UdpSocket, time::Instant};
+
+/// Requests and responses must be shorter than this.
+pub const MAX_LEN: usize = 1000;
+
+/// How long a retrieve waits for an answer before asking again, by default.
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many times a retrieve is sent before giving up, since either it or
+/// the answer may be lost.
+const ATTEMPTS: u32 = 3;
+
+pub struct Client {
+    socket: UdpSocket,
+    timeout: Duration,
+}
+
+impl Client {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        // Connected, so that datagrams from anywhere else are dropped.
+        socket.connect(addr).await?;
+        Ok(Client {
+            socket,
+            timeout: TIMEOUT,
+        })
+    }
+
+    /// Sets how long each attempt at a retrieve waits for an answer.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Inserts `value` at `key`, which can't contain `=` since the first one
+    /// splits the two. The server doesn't answer inserts, so a lost one goes
+    /// unnoticed.
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if key.contains('=') {
+            bail!("keys can't contain '=': {key:?}");
+        }
+        self.send(&format!("{key}={value}")).await
+    }
+
+    /// The value at `key`, or `None` if the server didn't answer after a few
+    /// attempts, as it may not for a missing key.
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>> {
+        if key.contains('=') {
+            bail!("keys can't contain '=', that'd be an insert: {key:?}");
+        }
+        let mut buf = [0; MAX_LEN];
+        for _ in 0..ATTEMPTS {
+            self.send(key).await?;
+            let deadline = Instant::now() + self.timeout;
+            // Skip answers to other keys, or to an earlier attempt at another.
+            while let Ok(len) = tokio::time::timeout_at(deadline, self.socket.recv(&mut buf)).await
+            {
+                let response = String::from_utf8_lossy(&buf[..len?]);
+                if let Some(value) = response
+                    .strip_prefix(key)
+                    .and_then(|rest| rest.strip_prefix('='))
+                {
+                    return Ok(Some(value.to_string()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn send(&mut self, request: &str) -> Result<()> {
+        if request.len() >= MAX_LEN {
+            bail!(
+                "requests must be under {MAX_LEN} bytes, not {}",
+                request.len()
+            );
+        }
+        self.socket.send(request.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use tokio::net::UdpSocket;
+
+    use super::Client;
+
+    /// A server that answers retrieves of keys it has, and drops the first
+    /// of each so that the client has to ask again.
+    async fn server(socket: UdpSocket) {
+        let mut data = HashMap::new();
+        let mut asked = HashMap::new();
+        let mut buf = [0; 1000];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let request = String::from_utf8(buf[..len].to_vec()).unwrap();
+            if let Some((key, value)) = request.split_once('=') {
+                data.insert(key.to_string(), value.to_string());
+                continue;
+            }
+            let asked = asked.entry(request.clone()).or_insert(0);
+            *asked += 1;
+            if let (Some(value), 2..) = (data.get(&request), *asked) {
+                let response = format!("{request}={value}");
+                socket.send_to(response.as_bytes(), peer).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn set_get() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(server(socket));
+
+        let mut client = Client::connect(&addr).await.unwrap();
+        client.set_timeout(Duration::from_millis(50));
+        client.set("foo", "bar=baz").await.unwrap();
+        client.set("", "empty").await.unwrap();
+        assert_eq!(client.get("foo").await.unwrap().as_deref(), Some("bar=baz"));
+        assert_eq!(client.get("").await.unwrap().as_deref(), Some("empty"));
+        assert_eq!(client.get("missing").await.unwrap(), None);
+
+        assert!(client.set("a=b", "c").await.is_err());
+        assert!(client.get("a=b").await.is_err());
+        assert!(client.set("big", &"x".repeat(995)).await.is_ok());
+        assert!(client.set("big", &"x".repeat(996)).await.is_err());
+    }
+}
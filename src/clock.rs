@@ -0,0 +1,35 @@
+//! The time, for logic driven by timers: heartbeats, retransmits and expiry.
+//! Servers with timers take a `Clock` rather than calling tokio's directly,
+//! so that tests can pass one that's paused and step through minutes of
+//! timeouts deterministically, in no time.
+
+use std::{future::Future, time::Duration};
+
+use tokio::time::{interval_at, Instant, Interval};
+
+pub(crate) trait Clock {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send;
+
+    /// Ticks every `period`, the first one `period` from now.
+    fn interval(&self, period: Duration) -> Interval;
+}
+
+/// Tokio's timers, in real time.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Tokio;
+
+impl Clock for Tokio {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep(duration)
+    }
+
+    fn interval(&self, period: Duration) -> Interval {
+        interval_at(Instant::now() + period, period)
+    }
+}
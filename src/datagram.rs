@@ -0,0 +1,29 @@
+//! Sending and receiving datagrams, so that a UDP server can run over a real
+//! socket or, in tests and benchmarks, over a simulated network that delays,
+//! loses, duplicates and reorders them.
+
+use std::{future::Future, io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+pub(crate) mod network;
+
+/// Public, but in a private module, so that the LRCP client can be generic
+/// over it without the crate exporting it.
+pub trait Datagram {
+    /// Receives one datagram into `buf`, returning its length and sender. Any
+    /// of it beyond `buf` is lost.
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>>;
+
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> impl Future<Output = io::Result<usize>>;
+}
+
+impl Datagram for UdpSocket {
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf).await
+    }
+
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr).await
+    }
+}
@@ -0,0 +1,243 @@
+//! A simulated network of `Datagram` endpoints, for testing and benchmarking
+//! UDP servers against delay, loss, duplication and reordering without real
+//! sockets. Faults are drawn from a seeded generator, so a failure can be
+//! reproduced.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+
+use crate::datagram::Datagram;
+
+/// The chance of each fault, in percent, for every datagram sent.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Faults {
+    pub(crate) loss: u64,
+    pub(crate) duplicate: u64,
+    /// Held back and delivered after the next datagram sent on the network.
+    pub(crate) reorder: u64,
+    /// How long every datagram takes to arrive.
+    pub(crate) delay: Duration,
+}
+
+type Packet = (Vec<u8>, SocketAddr);
+
+/// A packet, and when it arrives.
+type InFlight = (Instant, Packet);
+
+#[derive(Clone)]
+pub(crate) struct Network(Arc<Mutex<Inner>>);
+
+struct Inner {
+    faults: Faults,
+    state: u64,
+    endpoints: HashMap<SocketAddr, UnboundedSender<InFlight>>,
+    /// A datagram being reordered, and where it's going.
+    held: Option<(SocketAddr, InFlight)>,
+}
+
+impl Network {
+    pub(crate) fn new(faults: Faults, seed: u64) -> Network {
+        Network(Arc::new(Mutex::new(Inner {
+            faults,
+            // Never zero, which xorshift can't leave
+            state: seed.wrapping_mul(0x9e3779b97f4a7c15) | 1,
+            endpoints: HashMap::new(),
+            held: None,
+        })))
+    }
+
+    /// An endpoint at `addr`, which datagrams sent there reach.
+    pub(crate) fn endpoint(&self, addr: &str) -> Endpoint {
+        let addr = addr.parse().unwrap();
+        let (sender, inbox) = mpsc::unbounded_channel();
+        self.0.lock().unwrap().endpoints.insert(addr, sender);
+        Endpoint {
+            addr,
+            network: self.clone(),
+            inbox: tokio::sync::Mutex::new(Inbox { inbox, next: None }),
+        }
+    }
+}
+
+impl Inner {
+    /// Whether a fault with a `percent` chance happens.
+    fn roll(&mut self, percent: u64) -> bool {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state % 100 < percent
+    }
+
+    /// Delivers `packet` to `to`, unless nothing is there, as with UDP.
+    fn deliver(&self, to: SocketAddr, packet: InFlight) {
+        if let Some(endpoint) = self.endpoints.get(&to) {
+            let _ = endpoint.send(packet);
+        }
+    }
+
+    fn send(&mut self, to: SocketAddr, packet: Packet) {
+        if self.roll(self.faults.loss) {
+            return;
+        }
+        let packet = (Instant::now() + self.faults.delay, packet);
+        let copies = 1 + self.roll(self.faults.duplicate) as usize;
+        for _ in 0..copies {
+            if self.held.is_none() && self.roll(self.faults.reorder) {
+                self.held = Some((to, packet.clone()));
+                continue;
+            }
+            self.deliver(to, packet.clone());
+            if let Some((to, packet)) = self.held.take() {
+                self.deliver(to, packet);
+            }
+        }
+    }
+}
+
+pub(crate) struct Endpoint {
+    pub(crate) addr: SocketAddr,
+    network: Network,
+    inbox: tokio::sync::Mutex<Inbox>,
+}
+
+struct Inbox {
+    inbox: UnboundedReceiver<InFlight>,
+    /// The next packet, kept here while it's on its way, so that a cancelled
+    /// `recv_from` doesn't lose it.
+    next: Option<InFlight>,
+}
+
+impl Datagram for Endpoint {
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = self.inbox.lock().await;
+        if inbox.next.is_none() {
+            inbox.next = inbox.inbox.recv().await;
+        }
+        let Some((arrives, _)) = inbox.next else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        if arrives > Instant::now() {
+            tokio::time::sleep_until(arrives).await;
+        }
+        let (_, (data, from)) = inbox.next.take().unwrap();
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok((n, from))
+    }
+
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let packet = (buf.to_vec(), self.addr);
+        self.network.0.lock().unwrap().send(addr, packet);
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Endpoint, Faults, Network};
+    use crate::{
+        clock::Clock,
+        datagram::Datagram,
+        testutil::clock::{assert_on_time, Paused},
+    };
+
+    /// Sends 0..n from `a` to `b`, returning what arrived, in order.
+    async fn send(a: &Endpoint, b: &Endpoint, n: u8) -> Vec<u8> {
+        for i in 0..n {
+            a.send_to(&[i], b.addr).await.unwrap();
+        }
+        let mut received = vec![];
+        let mut buf = [0; 1];
+        while let Ok(Ok((1, from))) =
+            tokio::time::timeout(Duration::from_millis(10), b.recv_from(&mut buf)).await
+        {
+            assert_eq!(from, a.addr);
+            received.push(buf[0]);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn faults() {
+        let all: Vec<u8> = (0..100).collect();
+        let knobs = |loss, duplicate, reorder| Faults {
+            loss,
+            duplicate,
+            reorder,
+            ..Faults::default()
+        };
+        let endpoints = |faults| {
+            let network = Network::new(faults, 1);
+            (
+                network.endpoint("10.0.0.1:1"),
+                network.endpoint("10.0.0.2:2"),
+            )
+        };
+
+        let (a, b) = endpoints(Faults::default());
+        assert_eq!(send(&a, &b, 100).await, all);
+        let (a, b) = endpoints(knobs(100, 0, 0));
+        assert!(send(&a, &b, 100).await.is_empty());
+
+        let (a, b) = endpoints(knobs(25, 0, 0));
+        let received = send(&a, &b, 100).await;
+        assert!((50..95).contains(&received.len()), "{received:?}");
+        assert!(received.is_sorted());
+
+        let (a, b) = endpoints(knobs(0, 100, 0));
+        let doubled: Vec<u8> = all.iter().flat_map(|&i| [i, i]).collect();
+        assert_eq!(send(&a, &b, 100).await, doubled);
+
+        let (a, b) = endpoints(knobs(0, 0, 50));
+        let mut received = send(&a, &b, 100).await;
+        // Only the last can be held back for good, with nothing after it.
+        assert!(received.len() >= 99);
+        assert!(!received.is_sorted());
+        received.sort();
+        received.dedup();
+        assert!(received.len() >= 99);
+    }
+
+    /// Datagrams arrive a delay after they're sent, in order, and waiting
+    /// for one and giving up doesn't lose it.
+    #[tokio::test]
+    async fn delay() {
+        let clock = Paused::start();
+        let delay = Duration::from_millis(50);
+        let network = Network::new(
+            Faults {
+                delay,
+                ..Faults::default()
+            },
+            1,
+        );
+        let (a, b) = (
+            network.endpoint("10.0.0.1:1"),
+            network.endpoint("10.0.0.2:2"),
+        );
+        let start = clock.now();
+        for i in 0..3 {
+            a.send_to(&[i], b.addr).await.unwrap();
+        }
+        let mut buf = [0; 1];
+        let early = tokio::time::timeout(delay / 2, b.recv_from(&mut buf)).await;
+        assert!(early.is_err());
+        for i in 0..3 {
+            assert_eq!(b.recv_from(&mut buf).await.unwrap(), (1, a.addr));
+            assert_eq!(buf[0], i);
+            assert_on_time(clock.now(), start + delay);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Shared by each problem's `fuzz` module, the entry points for the targets in
+//! `fuzz/`.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+/// Splits fuzz input into how many bytes each read gets, from 1 to 16, taken
+/// from the low nibble of the first byte, the first byte, and the stream.
+pub(crate) fn setup(data: &[u8]) -> Option<(usize, u8, &[u8])> {
+    let (&setup, stream) = data.split_first()?;
+    Some(((setup & 0xf) as usize + 1, setup, stream))
+}
+
+/// Feeds `stream` to `decoder` `size` bytes at a time, as reads from a
+/// connection would, then ends it. Calls `check` on each item, stopping at
+/// the first error, as a connection would.
+pub(crate) fn decode_all<D: Decoder>(
+    mut decoder: D,
+    stream: &[u8],
+    size: usize,
+    mut check: impl FnMut(D::Item),
+) {
+    let mut buf = BytesMut::new();
+    for chunk in stream.chunks(size) {
+        buf.extend_from_slice(chunk);
+        loop {
+            match decoder.decode(&mut buf) {
+                Ok(Some(item)) => check(item),
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+    while let Ok(Some(item)) = decoder.decode_eof(&mut buf) {
+        check(item);
+    }
+}
+
+/// A stream that hands out at most `size` bytes per read. Unlike a mock, it
+/// doesn't mind a session closing before the end.
+pub(crate) struct Chunks<'a> {
+    data: &'a [u8],
+    size: usize,
+}
+
+impl Chunks<'_> {
+    pub(crate) fn new(data: &[u8], size: usize) -> Chunks<'_> {
+        Chunks { data, size }
+    }
+}
+
+impl AsyncRead for Chunks<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let n = self.data.len().min(self.size).min(buf.remaining());
+        let (chunk, rest) = self.data.split_at(n);
+        buf.put_slice(chunk);
+        self.data = rest;
+        Poll::Ready(Ok(()))
+    }
+}
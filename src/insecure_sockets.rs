@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context, Result};
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, FramedRead},
+};
+
+use crate::config::ADDR;
+
+use self::cipher::{CipherStream, SpecDecoder};
+
+pub mod bench;
+mod cipher;
+pub mod fuzz;
+
+/// The longest request accepted, in bytes, which is as long as the spec
+/// promises they get.
+const MAX_LINE: usize = 5000;
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+    serve(listener).await
+}
+
+async fn serve(listener: TcpListener) -> Result<()> {
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn process<R, W>(reader: R, mut writer: W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut frames = FramedRead::new(reader, SpecDecoder);
+    let Some(cipher) = frames.next().await.transpose()? else {
+        return Ok(());
+    };
+    // The spec says to disconnect a client whose cipher changes nothing.
+    ensure!(!cipher.is_noop(), "no-op cipher");
+    let cipher = Arc::new(cipher);
+    // Whatever followed the spec in the same read is still in the buffer.
+    let mut requests =
+        frames.map_decoder(|_| RequestDecoder::new(CipherStream::decoding(cipher.clone())));
+    let mut responses = CipherStream::encoding(cipher);
+    while let Some(request) = requests.next().await {
+        let request = request?;
+        let mut response = most_copies(&request)?.as_bytes().to_vec();
+        response.push(b'\n');
+        responses.apply(&mut response);
+        writer.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+/// The toy there are most copies of, from a request like
+/// "10x toy car,15x dog on a string,4x inflatable motorcycle".
+fn most_copies(request: &str) -> Result<&str> {
+    let mut most: Option<(u64, &str)> = None;
+    for toy in request.split(',') {
+        let count = toy
+            .split_once("x ")
+            .and_then(|(count, _)| count.parse().ok())
+            .with_context(|| format!("bad toy {toy:?}"))?;
+        if most.is_none_or(|(most, _)| count > most) {
+            most = Some((count, toy));
+        }
+    }
+    Ok(most.expect("split always yields something").1)
+}
+
+/// Deciphers what arrives and splits it into lines.
+struct RequestDecoder {
+    stream: CipherStream,
+    /// How much of the buffer has been deciphered already.
+    deciphered: usize,
+}
+
+impl RequestDecoder {
+    fn new(stream: CipherStream) -> RequestDecoder {
+        RequestDecoder {
+            stream,
+            deciphered: 0,
+        }
+    }
+}
+
+impl Decoder for RequestDecoder {
+    type Item = String;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.stream.apply(&mut src[self.deciphered..]);
+        self.deciphered = src.len();
+        let Some(n) = src.iter().position(|&b| b == b'\n') else {
+            if src.len() > MAX_LINE {
+                bail!("request longer than {MAX_LINE} bytes");
+            }
+            return Ok(None);
+        };
+        let line = src.split_to(n + 1);
+        self.deciphered -= n + 1;
+        Ok(Some(String::from_utf8(line[..n].to_vec())?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        clients::insecure_sockets::{Client, Op},
+        testutil::{duplex, TestServer},
+    };
+
+    use super::{
+        cipher::{Cipher, CipherStream},
+        most_copies, process, serve, MAX_LINE,
+    };
+
+    #[test]
+    fn most() {
+        assert_eq!(
+            most_copies("10x toy car,15x dog on a string,4x inflatable motorcycle").unwrap(),
+            "15x dog on a string"
+        );
+        assert_eq!(most_copies("1x a").unwrap(), "1x a");
+        assert!(most_copies("").is_err());
+        assert!(most_copies("3x a,b").is_err());
+        assert!(most_copies("-1x a").is_err());
+    }
+
+    #[tokio::test]
+    async fn spec_example() {
+        let server = TestServer::start(serve).await;
+        let mut client = server.connect().await;
+        // xor(123),addpos,reversebits
+        client.send(b"\x02\x7b\x05\x01\x00").await;
+        // 4x dog,5x car
+        client
+            .send(b"\xf2\x20\xba\x44\x18\x84\xba\xaa\xd0\x26\x44\xa4\xa8\x7e")
+            .await;
+        // 5x car
+        assert_eq!(client.recv_exact(7).await, b"\x72\x20\xba\xd8\x78\x70\xee");
+        // 3x rat,2x cat
+        client
+            .send(b"\x6a\x48\xd6\x58\x34\x44\xd6\x7a\x98\x4e\x0c\xcc\x94\x31")
+            .await;
+        // 3x rat
+        assert_eq!(client.recv_exact(7).await, b"\xf2\xd0\x26\xc8\xa4\xd8\x7e");
+    }
+
+    /// Checks the server's tables against the client's op-by-op cipher, for
+    /// specs with and without positions, up to the longest allowed.
+    #[tokio::test]
+    async fn clients() {
+        use Op::*;
+        let server = TestServer::start(serve).await;
+        let addr = server.addr.to_string();
+        let long = (0..80)
+            .map(|i| match i % 5 {
+                0 => Xor(i),
+                1 => AddPos,
+                2 => ReverseBits,
+                3 => XorPos,
+                _ => Add(i),
+            })
+            .collect();
+        for ops in [
+            vec![Xor(123), AddPos, ReverseBits],
+            vec![XorPos],
+            vec![Add(200), AddPos, AddPos, ReverseBits, Xor(0xff)],
+            long,
+        ] {
+            let mut client = Client::connect(&addr, ops).await.unwrap();
+            // Enough to go round the positions a few times.
+            for i in 0..100 {
+                client.send(&format!("{i}x dog,50x cat")).await.unwrap();
+            }
+            for i in 0..100 {
+                let expected = match i >= 50 {
+                    true => format!("{i}x dog"),
+                    false => "50x cat".to_string(),
+                };
+                assert_eq!(client.recv().await.unwrap(), expected);
+            }
+        }
+    }
+
+    /// The checker sends 5000 requests on one connection, and wants them all
+    /// answered within a few seconds.
+    #[tokio::test]
+    async fn many_requests() {
+        let n = 5000;
+        let spec = b"\x02\x7b\x05\x01\x03\x04\x20\x00";
+        let (cipher, _) = Cipher::parse(spec).unwrap().unwrap();
+        let cipher = Arc::new(cipher);
+        let server = TestServer::start(serve).await;
+        let (mut reader, mut writer) = TcpStream::connect(server.addr).await.unwrap().into_split();
+
+        let mut encoder = CipherStream::encoding(cipher.clone());
+        let sender = tokio::spawn(async move {
+            writer.write_all(spec).await.unwrap();
+            for i in 0..n {
+                let mut request = format!("{i}x dog on a string,{}x toy car\n", n - i).into_bytes();
+                encoder.apply(&mut request);
+                writer.write_all(&request).await.unwrap();
+            }
+        });
+        let expected: String = (0..n)
+            .map(|i| match i >= n - i {
+                true => format!("{i}x dog on a string\n"),
+                false => format!("{}x toy car\n", n - i),
+            })
+            .collect();
+        let mut received = vec![0; expected.len()];
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            reader.read_exact(&mut received),
+        )
+        .await
+        .expect("answered within 5s")
+        .unwrap();
+        CipherStream::decoding(cipher).apply(&mut received);
+        assert_eq!(String::from_utf8(received).unwrap(), expected);
+        sender.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_in_one_write_with_the_spec() {
+        let (mut reader, mut writer) = duplex(process);
+        // add(1): each byte goes up by one.
+        let mut sent = b"\x04\x01\x00".to_vec();
+        sent.extend(b"1x a,2x b\n3x c\n".iter().map(|b| b + 1));
+        writer.write_all(&sent).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut received = vec![];
+        reader.read_to_end(&mut received).await.unwrap();
+        let received: Vec<u8> = received.iter().map(|b| b - 1).collect();
+        assert_eq!(received, b"2x b\n3x c\n");
+    }
+
+    #[tokio::test]
+    async fn truncated_spec() {
+        let reader = tokio_test::io::Builder::new().read(b"\x02\x7b\x05").build();
+        let writer = tokio_test::io::Builder::new().build();
+        let error = process(reader, writer).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "closed before the end of the cipher spec"
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnects() {
+        let long = format!("{}x a\n", "1".repeat(MAX_LINE));
+        for sent in [
+            // A cipher that changes nothing
+            &b"\x02\x05\x02\x05\x00"[..],
+            b"\x00",
+            // An unknown op
+            b"\x07\x00",
+            // Too many ops
+            &[0x02; 200],
+            // Not a request
+            b"\x02\x01\x00x\x0b",
+            // Too long
+            &[b"\x02\x00\x01\x00".as_slice(), long.as_bytes()].concat(),
+        ] {
+            let (mut reader, mut writer) = duplex(process);
+            writer.write_all(sent).await.unwrap();
+            let mut received = vec![];
+            reader.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"", "{sent:x?}");
+        }
+    }
+}
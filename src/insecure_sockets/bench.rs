@@ -0,0 +1,74 @@
+//! The entry points for the `insecure_sockets` benchmarks in `benches/`.
+
+use std::sync::Arc;
+
+use super::cipher::{Cipher, CipherStream};
+
+/// Specs to compare, by name: the spec's example, a single op, and 80 ops.
+pub fn specs() -> Vec<(&'static str, Vec<u8>)> {
+    let mut long: Vec<u8> = (0..16)
+        .flat_map(|i| [0x02, i, 0x05, 0x01, 0x03, 0x04, i])
+        .collect();
+    long.push(0x00);
+    vec![
+        (
+            "xor(123),addpos,reversebits",
+            b"\x02\x7b\x05\x01\x00".to_vec(),
+        ),
+        ("reversebits", b"\x01\x00".to_vec()),
+        ("80 ops", long),
+    ]
+}
+
+/// Encodes a stream with a spec, either with the cipher's tables or by
+/// applying each op to each byte in turn, as before there were tables.
+pub struct Encoder(Method);
+
+enum Method {
+    Tables(CipherStream),
+    Ops { cipher: Cipher, pos: u8 },
+}
+
+impl Encoder {
+    pub fn new(spec: &[u8], tables: bool) -> Encoder {
+        let (cipher, _) = Cipher::parse(spec).unwrap().expect("a whole spec");
+        Encoder(if tables {
+            Method::Tables(CipherStream::encoding(Arc::new(cipher)))
+        } else {
+            Method::Ops { cipher, pos: 0 }
+        })
+    }
+
+    /// Encodes `data` in place, as the next bytes of the stream.
+    pub fn encode(&mut self, data: &mut [u8]) {
+        match &mut self.0 {
+            Method::Tables(stream) => stream.apply(data),
+            Method::Ops { cipher, pos } => {
+                for b in data {
+                    *b = cipher.encode_byte(*b, *pos);
+                    *pos = pos.wrapping_add(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{specs, Encoder};
+
+    #[test]
+    fn tables_match_ops() {
+        for (name, spec) in specs() {
+            let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+            let mut tables = Encoder::new(&spec, true);
+            let mut ops = Encoder::new(&spec, false);
+            for chunk in data.chunks(300) {
+                let (mut a, mut b) = (chunk.to_vec(), chunk.to_vec());
+                tables.encode(&mut a);
+                ops.encode(&mut b);
+                assert_eq!(a, b, "{name}");
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! The obfuscation layer: the cipher spec each client sends first, and the
+//! cipher it describes, applied to each direction of the stream.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::Decoder,
+};
+
+/// The most ops a spec may have. The spec sets no limit, but a cipher
+/// needs no more than a handful, and this stops a client sending ops
+/// forever.
+pub(crate) const MAX_OPS: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    ReverseBits,
+    Xor(u8),
+    XorPos,
+    Add(u8),
+    AddPos,
+}
+
+/// The ops of a spec, applied in order to encode.
+///
+/// The ops only see a byte and its position modulo 256, so rather than
+/// applying each op to each byte, the cipher works out every combination up
+/// front: two 64 KiB tables, one each way, indexed by position then byte.
+/// Each op is reversible, so decoding is just the encoding table inverted.
+/// Building them costs as much as applying the spec to 64 KiB, and after that
+/// each byte is one lookup however long the spec is. The `insecure_sockets`
+/// bench compares the two.
+pub(crate) struct Cipher {
+    ops: Vec<Op>,
+    encode: Box<[[u8; 256]]>,
+    decode: Box<[[u8; 256]]>,
+}
+
+impl Cipher {
+    pub(crate) fn new(ops: Vec<Op>) -> Cipher {
+        let mut cipher = Cipher {
+            ops,
+            encode: vec![[0; 256]; 256].into_boxed_slice(),
+            decode: vec![[0; 256]; 256].into_boxed_slice(),
+        };
+        for pos in 0..=255 {
+            for b in 0..=255 {
+                let encoded = cipher.encode_byte(b, pos);
+                cipher.encode[pos as usize][b as usize] = encoded;
+                cipher.decode[pos as usize][encoded as usize] = b;
+            }
+        }
+        cipher
+    }
+
+    /// Parses the spec at the start of `src`, returning the cipher and how
+    /// many bytes it took, or None if its end hasn't arrived yet. It's an
+    /// error as soon as there's an unknown op, or more than `MAX_OPS`.
+    pub(crate) fn parse(src: &[u8]) -> Result<Option<(Cipher, usize)>> {
+        let mut ops = vec![];
+        let mut bytes = src.iter().copied();
+        loop {
+            let Some(op) = bytes.next() else {
+                return Ok(None);
+            };
+            let op = match op {
+                0x00 => break,
+                0x01 => Op::ReverseBits,
+                0x02 => match bytes.next() {
+                    Some(n) => Op::Xor(n),
+                    None => return Ok(None),
+                },
+                0x03 => Op::XorPos,
+                0x04 => match bytes.next() {
+                    Some(n) => Op::Add(n),
+                    None => return Ok(None),
+                },
+                0x05 => Op::AddPos,
+                op => bail!("unknown cipher op {op:#04x}"),
+            };
+            if ops.len() == MAX_OPS {
+                bail!("cipher spec longer than {MAX_OPS} ops");
+            }
+            ops.push(op);
+        }
+        Ok(Some((Cipher::new(ops), src.len() - bytes.len())))
+    }
+
+    /// Whether the cipher leaves every byte as it is, wherever it is in the
+    /// stream.
+    pub(crate) fn is_noop(&self) -> bool {
+        self.encode.iter().all(|row| {
+            row.iter()
+                .enumerate()
+                .all(|(b, &encoded)| encoded == b as u8)
+        })
+    }
+
+    /// Applies each op to `b` in turn, at `pos` in the stream.
+    pub(super) fn encode_byte(&self, mut b: u8, pos: u8) -> u8 {
+        for op in &self.ops {
+            b = match *op {
+                Op::ReverseBits => b.reverse_bits(),
+                Op::Xor(n) => b ^ n,
+                Op::XorPos => b ^ pos,
+                Op::Add(n) => b.wrapping_add(n),
+                Op::AddPos => b.wrapping_add(pos),
+            };
+        }
+        b
+    }
+}
+
+/// One direction of a connection: the cipher, and how far along the stream
+/// it has got.
+pub(crate) struct CipherStream {
+    cipher: Arc<Cipher>,
+    decode: bool,
+    /// Only the position modulo 256 matters, since the ops use it as a byte.
+    pos: u8,
+}
+
+impl CipherStream {
+    pub(crate) fn encoding(cipher: Arc<Cipher>) -> CipherStream {
+        CipherStream {
+            cipher,
+            decode: false,
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn decoding(cipher: Arc<Cipher>) -> CipherStream {
+        CipherStream {
+            cipher,
+            decode: true,
+            pos: 0,
+        }
+    }
+
+    /// Applies the cipher in place to `buf`, as the next bytes of the stream.
+    pub(crate) fn apply(&mut self, buf: &mut [u8]) {
+        let table = if self.decode {
+            &self.cipher.decode
+        } else {
+            &self.cipher.encode
+        };
+        for b in buf {
+            *b = table[self.pos as usize][*b as usize];
+            self.pos = self.pos.wrapping_add(1);
+        }
+    }
+}
+
+/// Takes the cipher spec off the front of a connection. Whatever follows it
+/// is left in the buffer, already enciphered.
+pub(crate) struct SpecDecoder;
+
+impl Decoder for SpecDecoder {
+    type Item = Cipher;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some((cipher, len)) = Cipher::parse(src)? else {
+            return Ok(None);
+        };
+        src.advance(len);
+        Ok(Some(cipher))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            None if !src.is_empty() => bail!("closed before the end of the cipher spec"),
+            cipher => Ok(cipher),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use proptest::prelude::*;
+
+    use crate::testutil::{decode_chunks, split_at};
+
+    use super::{Cipher, CipherStream, Op, SpecDecoder, MAX_OPS};
+
+    /// The bytes of a spec for `ops`.
+    fn spec(ops: &[Op]) -> Vec<u8> {
+        let mut spec = vec![];
+        for op in ops {
+            match *op {
+                Op::ReverseBits => spec.push(0x01),
+                Op::Xor(n) => spec.extend([0x02, n]),
+                Op::XorPos => spec.push(0x03),
+                Op::Add(n) => spec.extend([0x04, n]),
+                Op::AddPos => spec.push(0x05),
+            }
+        }
+        spec.push(0x00);
+        spec
+    }
+
+    fn encode(ops: Vec<Op>, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        CipherStream::encoding(Arc::new(Cipher::new(ops))).apply(&mut data);
+        data
+    }
+
+    #[test]
+    fn spec_examples() {
+        use Op::*;
+        assert_eq!(
+            encode(vec![Xor(1), ReverseBits], b"hello"),
+            [0x96, 0x26, 0xb6, 0xb6, 0x76]
+        );
+        assert_eq!(
+            encode(vec![AddPos, AddPos], b"hello"),
+            [0x68, 0x67, 0x70, 0x72, 0x77]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let (cipher, len) = Cipher::parse(b"\x02\x00\x05\x01\x00rest").unwrap().unwrap();
+        assert_eq!(cipher.ops, [Op::Xor(0), Op::AddPos, Op::ReverseBits]);
+        assert_eq!(len, 5);
+        // An operand of 0 doesn't end the spec.
+        assert!(Cipher::parse(b"\x04\x00").unwrap().is_none());
+        assert!(Cipher::parse(b"\x02").unwrap().is_none());
+        assert!(Cipher::parse(b"\x06\x00").is_err());
+    }
+
+    #[test]
+    fn too_long() {
+        let spec = spec(&[Op::Xor(1); MAX_OPS]);
+        assert!(Cipher::parse(&spec).unwrap().is_some());
+        // One more is an error before the end of the spec arrives.
+        let ops = vec![0x01; MAX_OPS + 1];
+        assert_eq!(
+            Cipher::parse(&ops).err().unwrap().to_string(),
+            "cipher spec longer than 80 ops"
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        for spec in [&b"\x01"[..], b"\x02", b"\x02\x00", b"\x05\x04"] {
+            let error = decode_chunks(SpecDecoder, &[spec]).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "closed before the end of the cipher spec",
+                "{spec:x?}"
+            );
+        }
+        // Closing without sending anything isn't an error.
+        assert!(decode_chunks(SpecDecoder, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn noop() {
+        use Op::*;
+        for ops in [
+            vec![],
+            vec![Xor(0)],
+            vec![Xor(0xab), Xor(0xab)],
+            vec![ReverseBits, ReverseBits],
+            vec![Xor(0xa0), Xor(0x0b), Xor(0xab)],
+            vec![Add(1), XorPos, XorPos, Add(255), Xor(3), Xor(3)],
+        ] {
+            assert!(Cipher::new(ops.clone()).is_noop(), "{ops:?}");
+        }
+        for ops in [vec![XorPos], vec![Add(1)], vec![AddPos, Xor(1)]] {
+            assert!(!Cipher::new(ops.clone()).is_noop(), "{ops:?}");
+        }
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            Just(Op::ReverseBits),
+            any::<u8>().prop_map(Op::Xor),
+            Just(Op::XorPos),
+            any::<u8>().prop_map(Op::Add),
+            Just(Op::AddPos),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(
+            ops in prop::collection::vec(op(), 0..8),
+            data in prop::collection::vec(any::<u8>(), 0..512),
+            offset in 0..1024usize,
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let cipher = Arc::new(Cipher::new(ops));
+            let mut encoder = CipherStream::encoding(cipher.clone());
+            let mut decoder = CipherStream::decoding(cipher);
+            // Both ends are `offset` bytes into the stream already.
+            encoder.apply(&mut vec![0; offset]);
+            decoder.apply(&mut vec![0; offset]);
+
+            let mut encoded = data.clone();
+            encoder.apply(&mut encoded);
+            let mut decoded = vec![];
+            for chunk in split_at(&encoded, &cuts) {
+                let mut chunk = chunk.to_vec();
+                decoder.apply(&mut chunk);
+                decoded.extend(chunk);
+            }
+            prop_assert_eq!(decoded, data);
+        }
+
+        #[test]
+        fn split_spec(
+            ops in prop::collection::vec(op(), 0..8),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let spec = spec(&ops);
+            let decoded = decode_chunks(SpecDecoder, &split_at(&spec, &cuts)).unwrap();
+            let decoded: Vec<_> = decoded.into_iter().map(|cipher| cipher.ops).collect();
+            prop_assert_eq!(decoded, [ops]);
+        }
+    }
+}
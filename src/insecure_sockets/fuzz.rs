@@ -0,0 +1,99 @@
+//! The entry point for the `insecure_sockets` fuzz target in `fuzz/`.
+
+use std::sync::Arc;
+
+use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+use crate::fuzz::{setup, Chunks};
+
+use super::{
+    cipher::{CipherStream, SpecDecoder, MAX_OPS},
+    most_copies, process, RequestDecoder, MAX_LINE,
+};
+
+/// Runs a session over `data`, taken as a cipher spec and then enciphered
+/// requests, panicking if anything misbehaves. The first byte sets the read
+/// size.
+pub fn session(data: &[u8]) {
+    let Some((size, _, stream)) = setup(data) else {
+        return;
+    };
+    decode(stream, size);
+
+    let mut output = vec![];
+    // An error only ends the session.
+    let _ = tokio_test::block_on(process(Chunks::new(stream, size), &mut output));
+    // Each response is one toy from a request line, and its newline.
+    assert!(output.len() <= stream.len(), "{} bytes out", output.len());
+}
+
+/// Decodes the spec and then the requests in `stream`, `size` bytes at a time
+/// as `process` would, checking that neither decoder holds on to more than
+/// its limit.
+fn decode(stream: &[u8], size: usize) {
+    let mut buf = BytesMut::new();
+    let mut chunks = stream.chunks(size);
+    let cipher = loop {
+        let Some(chunk) = chunks.next() else {
+            return;
+        };
+        buf.extend_from_slice(chunk);
+        match SpecDecoder.decode(&mut buf) {
+            Ok(Some(cipher)) => break cipher,
+            // Two bytes for each op, and the first of one more.
+            Ok(None) => assert!(buf.len() <= 2 * MAX_OPS + 1, "{} bytes", buf.len()),
+            Err(_) => return,
+        }
+    };
+    if cipher.is_noop() {
+        return;
+    }
+    let mut requests = RequestDecoder::new(CipherStream::decoding(Arc::new(cipher)));
+    loop {
+        match requests.decode(&mut buf) {
+            Ok(Some(request)) => {
+                if most_copies(&request).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {
+                assert!(buf.len() <= MAX_LINE, "{} bytes", buf.len());
+                let Some(chunk) = chunks.next() else {
+                    break;
+                };
+                buf.extend_from_slice(chunk);
+            }
+            Err(_) => return,
+        }
+    }
+    let _ = requests.decode_eof(&mut buf);
+}
+
+#[cfg(test)]
+mod test {
+    use super::session;
+
+    #[test]
+    fn seeds() {
+        let streams: [&[u8]; 4] = [
+            // The spec's example: xor(123),addpos,reversebits, then
+            // "4x dog,5x car\n" and "3x rat,2x cat\n".
+            b"\x02\x7b\x05\x01\x00\
+                \xf2\x20\xba\x44\x18\x84\xba\xaa\xd0\x26\x44\xa4\xa8\x7e\
+                \x6a\x48\xd6\x58\x34\x44\xd6\x7a\x98\x4e\x0c\xcc\x94\x31",
+            // add(1), then a request that isn't one
+            b"\x04\x01\x00\x79\x0b",
+            // A no-op cipher
+            b"\x02\xa0\x02\xa0\x00\x31\x79\x21\x62\x0b",
+            &[0x01; 200],
+        ];
+        session(&[]);
+        for stream in streams {
+            for setup in 0..16 {
+                let mut data = vec![setup];
+                data.extend_from_slice(stream);
+                session(&data);
+            }
+        }
+    }
+}
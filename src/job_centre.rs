@@ -0,0 +1,1080 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, FramedRead},
+};
+
+use crate::config::ADDR;
+
+use self::{journal::Journal, queue::Queues, request::Request};
+
+pub mod bench;
+pub mod fuzz;
+mod journal;
+mod queue;
+mod request;
+
+/// Set to a file path to journal jobs there and replay them on startup.
+const JOURNAL_VAR: &str = "JOB_CENTRE_JOURNAL";
+/// Set to accept the non-standard `stats` request.
+const STATS_VAR: &str = "JOB_CENTRE_STATS";
+
+/// The longest request line accepted, in bytes.
+const MAX_LINE: usize = 1 << 20;
+/// The most requests a client may send while it waits in a `get`, before the
+/// connection is closed. They're read as they arrive, to notice a disconnect.
+const MAX_PENDING: usize = 64;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum Response {
+    #[serde(rename = "ok")]
+    Put {
+        id: u64,
+    },
+    #[serde(rename = "ok")]
+    Job {
+        id: u64,
+        job: Arc<Value>,
+        pri: u64,
+        queue: String,
+    },
+    #[serde(rename = "ok")]
+    Stats {
+        queues: BTreeMap<String, QueueStats>,
+    },
+    Ok,
+    NoJob,
+    Error {
+        error: String,
+    },
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct QueueStats {
+    waiting: usize,
+    in_progress: usize,
+    waiters: usize,
+}
+
+#[derive(Debug)]
+struct Job {
+    id: u64,
+    queue: String,
+    pri: u64,
+    job: Arc<Value>,
+}
+
+impl From<&Job> for Response {
+    fn from(job: &Job) -> Self {
+        Response::Job {
+            id: job.id,
+            job: job.job.clone(),
+            pri: job.pri,
+            queue: job.queue.clone(),
+        }
+    }
+}
+
+/// A client blocked in `get` with `wait: true`.
+struct Waiter {
+    client: u32,
+    queues: HashSet<String>,
+    tx: oneshot::Sender<Response>,
+}
+
+enum Reply {
+    Ready(Response),
+    Wait(oneshot::Receiver<Response>),
+}
+
+/// Where a live job currently is.
+enum Location {
+    Waiting { queue: String },
+    InProgress { client: u32, job: Job },
+}
+
+/// Jobs shared by all connections. Each job is either waiting in its queue or
+/// in progress with exactly one client.
+///
+/// All connections share one `State`, owned by a task that runs their
+/// requests one at a time (see `Shared`). A get may span any number of queues
+/// and has to register as a waiter atomically with finding them empty, so
+/// sharding by queue would mean coordinating many shards per request for
+/// little gain. Instead the task only does the bookkeeping, which is a few
+/// hash and heap operations: parsing, serializing and socket IO happen on the
+/// connection's own task, journal writes on the journal's own thread, and job
+/// bodies are reference-counted so handing one out doesn't copy it. The
+/// `job_centre` bench measures producers and workers sharing a server.
+#[derive(Default)]
+struct State {
+    /// Ids come from a single counter across all queues, so an id alone is
+    /// enough to find a job.
+    next_id: u64,
+    queues: Queues,
+    jobs: HashMap<u64, Location>,
+    /// Jobs in progress, by client.
+    working: HashMap<u32, HashSet<u64>>,
+    /// Blocked clients, longest waiting first.
+    waiters: VecDeque<Waiter>,
+    journal: Option<Journal>,
+    stats_enabled: bool,
+}
+
+impl State {
+    fn with_journal(path: impl AsRef<Path>) -> Result<State> {
+        let (journal, replay) = Journal::open(path)?;
+        let mut state = State {
+            next_id: replay.next_id,
+            journal: Some(journal),
+            ..State::default()
+        };
+        println!("Restored {} jobs from the journal", replay.jobs.len());
+        for job in replay.jobs {
+            state.enqueue(job);
+        }
+        Ok(state)
+    }
+
+    fn handle(&mut self, client: u32, request: Request) -> Response {
+        match request {
+            Request::Put { queue, job, pri } => Response::Put {
+                id: self.put(queue, Value::Object(job), pri),
+            },
+            Request::Get { queues, .. } => match self.get(client, &queues) {
+                Some(job) => job.into(),
+                None => Response::NoJob,
+            },
+            Request::Delete { id } => match self.delete(id) {
+                true => Response::Ok,
+                false => Response::NoJob,
+            },
+            Request::Abort { id } => self.abort(client, id),
+            Request::Stats if self.stats_enabled => Response::Stats {
+                queues: self.stats(),
+            },
+            Request::Stats => Response::Error {
+                error: r#"unknown request type "stats""#.to_string(),
+            },
+        }
+    }
+
+    fn stats(&self) -> BTreeMap<String, QueueStats> {
+        let mut stats = BTreeMap::<String, QueueStats>::new();
+        for location in self.jobs.values() {
+            match location {
+                Location::Waiting { queue } => stats.entry(queue.clone()).or_default().waiting += 1,
+                Location::InProgress { job, .. } => {
+                    stats.entry(job.queue.clone()).or_default().in_progress += 1
+                }
+            }
+        }
+        for waiter in self.waiters.iter().filter(|w| !w.tx.is_closed()) {
+            for queue in &waiter.queues {
+                stats.entry(queue.clone()).or_default().waiters += 1;
+            }
+        }
+        stats
+    }
+
+    fn put(&mut self, queue: String, job: Value, pri: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let job = Job {
+            id,
+            queue,
+            pri,
+            job: Arc::new(job),
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.put(&job);
+        }
+        self.enqueue(job);
+        id
+    }
+
+    /// Hands the job to the longest-waiting client interested in its queue,
+    /// or queues it if there is none.
+    fn enqueue(&mut self, job: Job) {
+        while let Some(idx) = self
+            .waiters
+            .iter()
+            .position(|w| w.queues.contains(&job.queue))
+        {
+            let waiter = self.waiters.remove(idx).unwrap();
+            // A failed send means the waiter has gone away; try the next one.
+            if waiter.tx.send((&job).into()).is_ok() {
+                self.start(waiter.client, job);
+                return;
+            }
+        }
+        let queue = job.queue.clone();
+        self.jobs.insert(job.id, Location::Waiting { queue });
+        self.queues.push(job);
+    }
+
+    /// Takes the highest-priority job across `queues` and assigns it to
+    /// `client`.
+    fn get(&mut self, client: u32, queues: &HashSet<String>) -> Option<&Job> {
+        let job = self.queues.pop(queues)?;
+        let id = job.id;
+        self.start(client, job);
+        match self.jobs.get(&id) {
+            Some(Location::InProgress { job, .. }) => Some(job),
+            _ => None,
+        }
+    }
+
+    fn start(&mut self, client: u32, job: Job) {
+        self.working.entry(client).or_default().insert(job.id);
+        self.jobs
+            .insert(job.id, Location::InProgress { client, job });
+    }
+
+    fn get_or_wait(&mut self, client: u32, queues: HashSet<String>) -> Reply {
+        if let Some(job) = self.get(client, &queues) {
+            return Reply::Ready(job.into());
+        }
+        let (tx, rx) = oneshot::channel();
+        self.waiters.retain(|w| !w.tx.is_closed());
+        self.waiters.push_back(Waiter { client, queues, tx });
+        Reply::Wait(rx)
+    }
+
+    fn delete(&mut self, id: u64) -> bool {
+        let location = self.jobs.remove(&id);
+        if let (Some(_), Some(journal)) = (&location, &mut self.journal) {
+            journal.delete(id);
+        }
+        match location {
+            Some(Location::Waiting { queue }) => {
+                self.queues.delete(&queue, id);
+                true
+            }
+            Some(Location::InProgress { client, .. }) => {
+                self.working.get_mut(&client).unwrap().remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn abort(&mut self, client: u32, id: u64) -> Response {
+        match self.jobs.remove(&id) {
+            Some(Location::InProgress { client: owner, job }) if owner == client => {
+                self.working.get_mut(&client).unwrap().remove(&id);
+                if let Some(journal) = &mut self.journal {
+                    journal.abort(id);
+                }
+                self.enqueue(job);
+                Response::Ok
+            }
+            Some(location) => {
+                self.jobs.insert(id, location);
+                Response::Error {
+                    error: format!("job {id} is not being worked on by this client"),
+                }
+            }
+            None => Response::NoJob,
+        }
+    }
+
+    /// Returns every job the client was working on to its queue.
+    fn disconnect(&mut self, client: u32) {
+        for id in self.working.remove(&client).unwrap_or_default() {
+            if let Some(Location::InProgress { job, .. }) = self.jobs.remove(&id) {
+                if let Some(journal) = &mut self.journal {
+                    journal.abort(id);
+                }
+                self.enqueue(job);
+            }
+        }
+    }
+}
+
+/// Something for the state task to do.
+type Call = Box<dyn FnOnce(&mut State) + Send>;
+
+/// A handle on the task that owns the `State`. Each connection has at most
+/// one call in flight, so the channel is bounded by the number of
+/// connections.
+#[derive(Clone)]
+struct Shared(mpsc::UnboundedSender<Call>);
+
+impl Shared {
+    /// Spawns the task that owns `state`. It ends once every handle is gone.
+    fn spawn(mut state: State) -> Shared {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Call>();
+        tokio::spawn(async move {
+            while let Some(call) = rx.recv().await {
+                call(&mut state);
+            }
+        });
+        Shared(tx)
+    }
+
+    /// Runs `f` against the state, after any calls already queued.
+    async fn call<T>(&self, f: impl FnOnce(&mut State) -> T + Send + 'static) -> T
+    where
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let call: Call = Box::new(move |state| {
+            let _ = tx.send(f(state));
+        });
+        // Both fail only if an earlier call panicked and took the task down.
+        if self.0.send(call).is_err() {
+            panic!("the state task is gone");
+        }
+        rx.await.expect("the state task is gone")
+    }
+}
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+
+    let mut state = match std::env::var_os(JOURNAL_VAR) {
+        Some(path) => State::with_journal(path)?,
+        None => State::default(),
+    };
+    state.stats_enabled = std::env::var_os(STATS_VAR).is_some();
+    serve(listener, Shared::spawn(state)).await
+}
+
+async fn serve(listener: TcpListener, state: Shared) -> Result<()> {
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        let state = state.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer, &state).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn process<R, W>(reader: R, writer: W, state: &Shared) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    static ID: AtomicU32 = AtomicU32::new(0);
+    let client = ID.fetch_add(1, Ordering::Relaxed);
+
+    let result = session(client, reader, writer, state).await;
+    // However the session ended, its jobs go back for other workers.
+    state.call(move |state| state.disconnect(client)).await;
+    result
+}
+
+async fn session<R, W>(client: u32, reader: R, mut writer: W, state: &Shared) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut requests = FramedRead::new(reader, RequestDecoder::default());
+    // Requests that arrived while the client was blocked in a `get`.
+    let mut pending = VecDeque::new();
+    loop {
+        let request = match pending.pop_front() {
+            Some(request) => request,
+            None => match requests.next().await {
+                Some(request) => request?,
+                None => break,
+            },
+        };
+        let response = match request {
+            Ok(Request::Get { queues, wait: true }) => {
+                let reply = state
+                    .call(move |state| state.get_or_wait(client, queues))
+                    .await;
+                match reply {
+                    Reply::Ready(response) => response,
+                    Reply::Wait(rx) => {
+                        tokio::select! {
+                            response = rx => response?,
+                            // Disconnected while waiting
+                            result = read_until_eof(&mut requests, &mut pending) => {
+                                result?;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(request) => state.call(move |state| state.handle(client, request)).await,
+            Err(error) => Response::Error { error },
+        };
+        let mut res = serde_json::to_vec(&response)?;
+        res.push(b'\n');
+        writer.write_all(&res).await?;
+    }
+    Ok(())
+}
+
+/// Buffers requests into `pending` until the client disconnects, or sends
+/// more than `MAX_PENDING`.
+async fn read_until_eof<R>(
+    requests: &mut FramedRead<R, RequestDecoder>,
+    pending: &mut VecDeque<Result<Request, String>>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    while let Some(request) = requests.next().await {
+        if pending.len() == MAX_PENDING {
+            bail!("more than {MAX_PENDING} requests while waiting");
+        }
+        pending.push_back(request?);
+    }
+    Ok(())
+}
+
+/// Splits the stream on newlines and parses each line as a request. A line
+/// that isn't a valid request is yielded as an error item rather than a
+/// decoder error, so the connection survives it.
+///
+/// A line longer than `max_line` is an error item too, as soon as it's known
+/// to be too long. The rest of it is dropped as it arrives, up to and
+/// including its newline, so it's never buffered whole.
+struct RequestDecoder {
+    max_line: usize,
+    /// How far into the buffer is known to hold no newline, so that each read
+    /// only scans what's new.
+    scanned: usize,
+    /// Whether the buffer is the rest of a line that was too long.
+    discarding: bool,
+}
+
+impl Default for RequestDecoder {
+    fn default() -> Self {
+        RequestDecoder {
+            max_line: MAX_LINE,
+            scanned: 0,
+            discarding: false,
+        }
+    }
+}
+
+impl RequestDecoder {
+    fn too_long(&self) -> Result<Request, String> {
+        Err(format!("request longer than {} bytes", self.max_line))
+    }
+}
+
+impl Decoder for RequestDecoder {
+    type Item = Result<Request, String>;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let Some(i) = src[self.scanned..].iter().position(|&b| b == b'\n') else {
+                if self.discarding {
+                    src.clear();
+                    self.scanned = 0;
+                } else if src.len() > self.max_line {
+                    src.clear();
+                    self.scanned = 0;
+                    self.discarding = true;
+                    return Ok(Some(self.too_long()));
+                } else {
+                    // Not enough data
+                    self.scanned = src.len();
+                }
+                return Ok(None);
+            };
+            let n = self.scanned + i;
+            self.scanned = 0;
+            let line = src.split_to(n + 1);
+            if std::mem::take(&mut self.discarding) {
+                continue;
+            }
+            if n > self.max_line {
+                return Ok(Some(self.too_long()));
+            }
+            return Ok(Some(Request::parse(&line[..n])));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc};
+
+    use serde_json::Value;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+    use crate::testutil::{duplex, transcript::replay_dir, DuplexReader, DuplexWriter, TestServer};
+
+    use super::{process, serve, Location, RequestDecoder, Shared, State, MAX_LINE, MAX_PENDING};
+
+    /// A client connected to `process` over an in-memory pipe.
+    struct Client {
+        lines: Lines<BufReader<DuplexReader>>,
+        writer: DuplexWriter,
+    }
+
+    impl Client {
+        fn connect(state: &Shared) -> Client {
+            let state = state.clone();
+            let (reader, writer) =
+                duplex(|reader, writer| async move { process(reader, writer, &state).await });
+            let lines = BufReader::new(reader).lines();
+            Client { lines, writer }
+        }
+
+        async fn send(&mut self, request: &str) {
+            self.writer.write_all(request.as_bytes()).await.unwrap();
+            self.writer.write_all(b"\n").await.unwrap();
+        }
+
+        async fn recv(&mut self) -> String {
+            self.lines.next_line().await.unwrap().unwrap()
+        }
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    async fn until_waiters(state: &Shared, n: usize) {
+        while state.call(|state| state.waiters.len()).await != n {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_requests() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"not json\n")
+            .read(b"{\"request\": \"jump\"}\n")
+            .read(b"{\"request\": \"delete\"}\n")
+            .read(b"\xff\xfe\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"status\":\"error\",\"error\":\"invalid JSON: expected ident at line 1 column 2\"}\n")
+            .write(b"{\"status\":\"error\",\"error\":\"unknown request type \\\"jump\\\"\"}\n")
+            .write(b"{\"status\":\"error\",\"error\":\"missing `id`\"}\n")
+            .write(b"{\"status\":\"error\",\"error\":\"invalid JSON: expected value at line 1 column 1\"}\n")
+            .build();
+        process(reader, writer, &Shared::spawn(State::default()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn split_request() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"request\": ")
+            .read(b"\"jump\"}\nnot")
+            .read(b" json\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"status\":\"error\",\"error\":\"unknown request type \\\"jump\\\"\"}\n")
+            .write(b"{\"status\":\"error\",\"error\":\"invalid JSON: expected ident at line 1 column 2\"}\n")
+            .build();
+        process(reader, writer, &Shared::spawn(State::default()))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut decoder = RequestDecoder {
+            max_line: 32,
+            ..RequestDecoder::default()
+        };
+        let mut buf = BytesMut::new();
+        let mut decoded = vec![];
+        let delete = r#"{"request":"delete","id":123456}"#;
+        assert_eq!(delete.len(), 32);
+        let long = format!(r#"{{"request":"delete","id":{}}}"#, "1".repeat(100));
+        let stream = format!("{delete}\n{long}\n{delete}\n{long}\n");
+        // A byte at a time, and all at once.
+        for chunk in stream.as_bytes().chunks(1).chain([stream.as_bytes()]) {
+            buf.extend_from_slice(chunk);
+            while let Some(request) = decoder.decode(&mut buf).unwrap() {
+                decoded.push(request.is_ok());
+            }
+            // Never more than a line's worth buffered.
+            assert!(buf.len() <= 33, "{} bytes buffered", buf.len());
+        }
+        assert_eq!(decoded, [true, false, true, false].repeat(2));
+    }
+
+    #[tokio::test]
+    async fn long_request() {
+        // The rest of the line is dropped, and the next line is read.
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"request\":\"jump\"")
+            .read(&[b' '; MAX_LINE])
+            .read(b"}\n{\"request\":\"delete\",\"id\":0}\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"status\":\"error\",\"error\":\"request longer than 1048576 bytes\"}\n")
+            .write(b"{\"status\":\"no-job\"}\n")
+            .build();
+        process(reader, writer, &Shared::spawn(State::default()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_get_delete() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"request\":\"put\",\"queue\":\"q1\",\"job\":{\"title\":\"a\"},\"pri\":10}\n")
+            .read(b"{\"request\":\"put\",\"queue\":\"q2\",\"job\":{\"title\":\"b\"},\"pri\":20}\n")
+            .read(b"{\"request\":\"put\",\"queue\":\"q1\",\"job\":{\"title\":\"c\"},\"pri\":15}\n")
+            .read(b"{\"request\":\"get\",\"queues\":[\"q1\",\"q2\"]}\n")
+            .read(b"{\"request\":\"get\",\"queues\":[\"q1\"]}\n")
+            .read(b"{\"request\":\"delete\",\"id\":0}\n")
+            .read(b"{\"request\":\"delete\",\"id\":2}\n")
+            .read(b"{\"request\":\"delete\",\"id\":2}\n")
+            .read(b"{\"request\":\"get\",\"queues\":[\"q1\",\"q3\"]}\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"status\":\"ok\",\"id\":0}\n")
+            .write(b"{\"status\":\"ok\",\"id\":1}\n")
+            .write(b"{\"status\":\"ok\",\"id\":2}\n")
+            .write(b"{\"status\":\"ok\",\"id\":1,\"job\":{\"title\":\"b\"},\"pri\":20,\"queue\":\"q2\"}\n")
+            .write(b"{\"status\":\"ok\",\"id\":2,\"job\":{\"title\":\"c\"},\"pri\":15,\"queue\":\"q1\"}\n")
+            .write(b"{\"status\":\"ok\"}\n")
+            .write(b"{\"status\":\"ok\"}\n")
+            .write(b"{\"status\":\"no-job\"}\n")
+            .write(b"{\"status\":\"no-job\"}\n")
+            .build();
+        process(reader, writer, &Shared::spawn(State::default()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn abort() {
+        let state = Shared::spawn(State::default());
+        let mut worker = Client::connect(&state);
+        let mut other = Client::connect(&state);
+
+        worker
+            .send(r#"{"request":"put","queue":"q1","job":{},"pri":1}"#)
+            .await;
+        assert_eq!(worker.recv().await, r#"{"status":"ok","id":0}"#);
+        worker.send(r#"{"request":"get","queues":["q1"]}"#).await;
+        assert_eq!(
+            worker.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+
+        // Only the client working on a job may abort it.
+        other.send(r#"{"request":"abort","id":0}"#).await;
+        assert_eq!(
+            other.recv().await,
+            r#"{"status":"error","error":"job 0 is not being worked on by this client"}"#
+        );
+        other.send(r#"{"request":"abort","id":7}"#).await;
+        assert_eq!(other.recv().await, r#"{"status":"no-job"}"#);
+
+        worker.send(r#"{"request":"abort","id":0}"#).await;
+        assert_eq!(worker.recv().await, r#"{"status":"ok"}"#);
+        let waiting = state.call(|state| matches!(state.jobs[&0], Location::Waiting { .. }));
+        assert!(waiting.await);
+    }
+
+    #[test]
+    fn ids_are_global() {
+        let mut state = State::default();
+        let ids: Vec<_> = ["q1", "q2", "q1", "q3"]
+            .iter()
+            .map(|q| state.put(q.to_string(), Value::Null, 1))
+            .collect();
+        assert_eq!(ids, [0, 1, 2, 3]);
+
+        let job = state.get(7, &names(&["q3"])).unwrap();
+        assert_eq!(job.id, 3);
+        assert!(matches!(
+            state.jobs[&3],
+            Location::InProgress { client: 7, .. }
+        ));
+        assert!(state.delete(1));
+        assert!(state.delete(3));
+        assert!(!state.delete(3));
+        assert!(state.working[&7].is_empty());
+        assert_eq!(state.put("q2".to_string(), Value::Null, 1), 4);
+    }
+
+    #[test]
+    fn restore_from_journal() {
+        let path = std::env::temp_dir().join(format!("job-centre-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut state = State::with_journal(&path).unwrap();
+        for pri in [1, 2, 3] {
+            state.put("q".to_string(), Value::Null, pri);
+        }
+        assert_eq!(state.get(0, &names(&["q"])).unwrap().id, 2);
+        state.delete(1);
+        drop(state);
+
+        // The job that was in progress is waiting again.
+        let mut state = State::with_journal(&path).unwrap();
+        assert_eq!(state.put("q".to_string(), Value::Null, 0), 3);
+        let queues = names(&["q"]);
+        let ids: Vec<_> = std::iter::from_fn(|| state.get(0, &queues).map(|j| j.id)).collect();
+        assert_eq!(ids, [2, 0, 3]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let state = Shared::spawn(State::default());
+        let mut client = Client::connect(&state);
+        let mut waiter = Client::connect(&state);
+        client.send(r#"{"request":"stats"}"#).await;
+        assert_eq!(
+            client.recv().await,
+            r#"{"status":"error","error":"unknown request type \"stats\""}"#
+        );
+
+        state.call(|state| state.stats_enabled = true).await;
+        for queue in ["a", "a", "b"] {
+            let put = format!(r#"{{"request":"put","queue":"{queue}","job":{{}},"pri":1}}"#);
+            client.send(&put).await;
+            client.recv().await;
+        }
+        client.send(r#"{"request":"get","queues":["b"]}"#).await;
+        client.recv().await;
+        waiter
+            .send(r#"{"request":"get","queues":["b","c"],"wait":true}"#)
+            .await;
+        until_waiters(&state, 1).await;
+
+        client.send(r#"{"request":"stats"}"#).await;
+        assert_eq!(
+            client.recv().await,
+            concat!(
+                r#"{"status":"ok","queues":{"#,
+                r#""a":{"waiting":2,"in-progress":0,"waiters":0},"#,
+                r#""b":{"waiting":0,"in-progress":1,"waiters":1},"#,
+                r#""c":{"waiting":0,"in-progress":0,"waiters":1}}}"#
+            )
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_deletes_and_gets() {
+        let state = Shared::spawn(State::default());
+        let queues = Arc::new(names(&["q0", "q1", "q2"]));
+        for i in 0..3000 {
+            let queue = format!("q{}", i % 3);
+            state
+                .call(move |state| state.put(queue, Value::Null, i as u64 % 17))
+                .await;
+        }
+
+        let getters: Vec<_> = (0..4)
+            .map(|client| {
+                let (state, queues) = (state.clone(), queues.clone());
+                tokio::spawn(async move {
+                    let mut got = vec![];
+                    loop {
+                        let queues = queues.clone();
+                        let get = move |state: &mut State| state.get(client, &queues).map(|j| j.id);
+                        match state.call(get).await {
+                            Some(id) => got.push(id),
+                            None => break got,
+                        }
+                    }
+                })
+            })
+            .collect();
+        let deleters: Vec<_> = (0..2)
+            .map(|start| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let mut deleted = vec![];
+                    for id in (start..3000).step_by(2) {
+                        // Only count jobs deleted before anyone got them.
+                        let delete = move |state: &mut State| {
+                            matches!(state.jobs.get(&id), Some(Location::Waiting { .. }))
+                                && state.delete(id)
+                        };
+                        if state.call(delete).await {
+                            deleted.push(id);
+                        }
+                    }
+                    deleted
+                })
+            })
+            .collect();
+        let mut got = vec![];
+        for getter in getters {
+            got.extend(getter.await.unwrap());
+        }
+        let mut deleted = vec![];
+        for deleter in deleters {
+            deleted.extend(deleter.await.unwrap());
+        }
+
+        let got_set: HashSet<_> = got.iter().collect();
+        assert_eq!(got_set.len(), got.len(), "job handed out twice");
+        assert!(deleted.iter().all(|id| !got_set.contains(id)));
+        assert_eq!(got.len() + deleted.len(), 3000);
+
+        let drained = state.call(move |state| {
+            state
+                .jobs
+                .values()
+                .all(|l| matches!(l, Location::InProgress { .. }))
+                && state.get(0, &queues).is_none()
+        });
+        assert!(drained.await);
+    }
+
+    #[tokio::test]
+    async fn wait_for_put() {
+        let state = Shared::spawn(State::default());
+        let mut first = Client::connect(&state);
+        let mut second = Client::connect(&state);
+        let mut producer = Client::connect(&state);
+
+        first
+            .send(r#"{"request":"get","queues":["q1","q2"],"wait":true}"#)
+            .await;
+        until_waiters(&state, 1).await;
+        second
+            .send(r#"{"request":"get","queues":["q2"],"wait":true}"#)
+            .await;
+        until_waiters(&state, 2).await;
+
+        // Each job wakes exactly one waiter, longest waiting first.
+        producer
+            .send(r#"{"request":"put","queue":"q2","job":{},"pri":1}"#)
+            .await;
+        assert_eq!(producer.recv().await, r#"{"status":"ok","id":0}"#);
+        assert_eq!(
+            first.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q2"}"#
+        );
+        producer
+            .send(r#"{"request":"put","queue":"q2","job":{},"pri":2}"#)
+            .await;
+        assert_eq!(producer.recv().await, r#"{"status":"ok","id":1}"#);
+        assert_eq!(
+            second.recv().await,
+            r#"{"status":"ok","id":1,"job":{},"pri":2,"queue":"q2"}"#
+        );
+
+        // Aborted jobs wake waiters too.
+        second
+            .send(r#"{"request":"get","queues":["q2"],"wait":true}"#)
+            .await;
+        until_waiters(&state, 1).await;
+        first.send(r#"{"request":"abort","id":0}"#).await;
+        assert_eq!(first.recv().await, r#"{"status":"ok"}"#);
+        assert_eq!(
+            second.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q2"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn waiter_disconnects() {
+        let state = Shared::spawn(State::default());
+        let mut waiter = Client::connect(&state);
+        let mut producer = Client::connect(&state);
+
+        waiter
+            .send(r#"{"request":"get","queues":["q1"],"wait":true}"#)
+            .await;
+        until_waiters(&state, 1).await;
+        drop(waiter);
+        producer
+            .send(r#"{"request":"put","queue":"q1","job":{},"pri":1}"#)
+            .await;
+        assert_eq!(producer.recv().await, r#"{"status":"ok","id":0}"#);
+
+        producer.send(r#"{"request":"get","queues":["q1"]}"#).await;
+        assert_eq!(
+            producer.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_while_waiting() {
+        let state = Shared::spawn(State::default());
+        let mut waiter = Client::connect(&state);
+        let mut producer = Client::connect(&state);
+        let wait = r#"{"request":"get","queues":["q1"],"wait":true}"#;
+        let abort = r#"{"request":"abort","id":7}"#;
+
+        // Up to MAX_PENDING are answered once the get is.
+        waiter.send(wait).await;
+        until_waiters(&state, 1).await;
+        for _ in 0..MAX_PENDING {
+            waiter.send(abort).await;
+        }
+        producer
+            .send(r#"{"request":"put","queue":"q1","job":{},"pri":1}"#)
+            .await;
+        assert_eq!(producer.recv().await, r#"{"status":"ok","id":0}"#);
+        assert_eq!(
+            waiter.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+        for _ in 0..MAX_PENDING {
+            assert_eq!(waiter.recv().await, r#"{"status":"no-job"}"#);
+        }
+
+        // One more closes the connection, giving back the job.
+        waiter.send(wait).await;
+        until_waiters(&state, 1).await;
+        for _ in 0..=MAX_PENDING {
+            waiter.send(abort).await;
+        }
+        assert_eq!(waiter.lines.next_line().await.unwrap(), None);
+        until_waiters(&state, 0).await;
+        producer.send(r#"{"request":"get","queues":["q1"]}"#).await;
+        assert_eq!(
+            producer.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_mid_get() {
+        let state = Shared::spawn(State::default());
+        let mut worker = Client::connect(&state);
+        let mut other = Client::connect(&state);
+
+        let put = r#"{"request":"put","queue":"q1","job":{},"pri":1}"#;
+        worker.send(put).await;
+        assert_eq!(worker.recv().await, r#"{"status":"ok","id":0}"#);
+        worker.send(put).await;
+        assert_eq!(worker.recv().await, r#"{"status":"ok","id":1}"#);
+        let get = r#"{"request":"get","queues":["q1"]}"#;
+        worker.send(get).await;
+        assert_eq!(
+            worker.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+        worker.send(get).await;
+        assert_eq!(
+            worker.recv().await,
+            r#"{"status":"ok","id":1,"job":{},"pri":1,"queue":"q1"}"#
+        );
+        // Disconnect halfway through another get.
+        worker.writer.write_all(b"{\"request\":\"ge").await.unwrap();
+        drop(worker);
+
+        let wait = r#"{"request":"get","queues":["q1"],"wait":true}"#;
+        other.send(wait).await;
+        assert_eq!(
+            other.recv().await,
+            r#"{"status":"ok","id":0,"job":{},"pri":1,"queue":"q1"}"#
+        );
+        other.send(wait).await;
+        assert_eq!(
+            other.recv().await,
+            r#"{"status":"ok","id":1,"job":{},"pri":1,"queue":"q1"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let state = Shared::spawn(State::default());
+        let server = TestServer::start(|listener| serve(listener, state)).await;
+        let mut producer = server.connect().await;
+        producer
+            .send_line(r#"{"request":"put","queue":"q","job":{"n":1},"pri":5}"#)
+            .await;
+        assert_eq!(producer.recv_line().await, r#"{"status":"ok","id":0}"#);
+
+        // A worker that goes away with the job gives it back.
+        let mut worker = server.connect().await;
+        worker
+            .send_line(r#"{"request":"get","queues":["q"]}"#)
+            .await;
+        let job: Value = worker.recv_json().await;
+        assert_eq!(job["id"], 0);
+        drop(worker);
+
+        let mut worker = server.connect().await;
+        worker
+            .send_line(r#"{"request":"get","queues":["q"],"wait":true}"#)
+            .await;
+        let job: Value = worker.recv_json().await;
+        assert_eq!(job["id"], 0);
+        assert_eq!(job["job"]["n"], 1);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_between_sockets() {
+        let state = Shared::spawn(State::default());
+        let server = TestServer::start(|listener| serve(listener, state)).await;
+        let mut producer = server.connect().await;
+        let mut worker = server.connect().await;
+        let mut other = server.connect().await;
+        producer
+            .send_line(r#"{"request":"put","queue":"q","job":{},"pri":1}"#)
+            .await;
+        assert_eq!(producer.recv_line().await, r#"{"status":"ok","id":0}"#);
+
+        // Queues are shared, but a job in progress belongs to its worker.
+        worker
+            .send_line(r#"{"request":"get","queues":["q"]}"#)
+            .await;
+        let job: Value = worker.recv_json().await;
+        assert_eq!(job["id"], 0);
+        other.send_line(r#"{"request":"get","queues":["q"]}"#).await;
+        assert_eq!(other.recv_line().await, r#"{"status":"no-job"}"#);
+        other.send_line(r#"{"request":"abort","id":0}"#).await;
+        let error: Value = other.recv_json().await;
+        assert_eq!(error["status"], "error");
+
+        // Any client may delete it, though.
+        other.send_line(r#"{"request":"delete","id":0}"#).await;
+        assert_eq!(other.recv_line().await, r#"{"status":"ok"}"#);
+        worker.send_line(r#"{"request":"abort","id":0}"#).await;
+        assert_eq!(worker.recv_line().await, r#"{"status":"no-job"}"#);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let state = Shared::spawn(State::default());
+        let server = TestServer::start(|listener| serve(listener, state)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("jobs", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn transcripts() {
+        replay_dir("testdata/job_centre", |reader, writer| async move {
+            process(reader, writer, &Shared::spawn(State::default())).await
+        })
+        .await;
+    }
+}
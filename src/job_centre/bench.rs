@@ -0,0 +1,200 @@
+//! The entry points for the `job_centre` benchmarks in `benches/`: the
+//! request decoder, gets across many queues, and many producers and workers
+//! sharing one server.
+
+use std::{collections::HashSet, net::SocketAddr, path::Path, sync::Arc};
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::fuzz::decode_all;
+
+use super::{process, queue::Queues, Job, RequestDecoder, Shared, State};
+
+/// `n` request lines, the mix a worker pool sends: puts, then gets, deletes
+/// and the odd abort.
+pub fn traffic(n: usize) -> Vec<u8> {
+    let mut traffic = vec![];
+    for i in 0..n {
+        let request = match i % 4 {
+            0 => format!(
+                r#"{{"request":"put","queue":"queue-{}","job":{{"title":"job {i}","n":{i}}},"pri":{}}}"#,
+                i % 10,
+                i % 1000
+            ),
+            1 => r#"{"request":"get","queues":["queue-1","queue-2","queue-3"],"wait":true}"#
+                .to_string(),
+            2 => format!(r#"{{"request":"delete","id":{i}}}"#),
+            _ => format!(r#"{{"request":"abort","id":{i}}}"#),
+        };
+        traffic.extend(request.as_bytes());
+        traffic.push(b'\n');
+    }
+    traffic
+}
+
+/// Decodes and parses `traffic` arriving in 4 KiB reads, returning how many
+/// valid requests there were.
+pub fn decode(traffic: &[u8]) -> usize {
+    let mut decoded = 0;
+    decode_all(RequestDecoder::default(), traffic, 4096, |request| {
+        decoded += request.is_ok() as usize
+    });
+    decoded
+}
+
+/// Queues of 10 jobs each at scattered priorities, and a get naming some
+/// queues, not necessarily the same ones.
+pub struct ManyQueues {
+    queues: Queues,
+    names: HashSet<String>,
+}
+
+impl ManyQueues {
+    /// Fills queues `q0` up to `full`, and names `q0` up to `named`.
+    pub fn new(full: usize, named: usize) -> ManyQueues {
+        let mut queues = Queues::default();
+        for q in 0..full {
+            for k in 0..10 {
+                queues.push(Job {
+                    id: (q * 10 + k) as u64,
+                    queue: format!("q{q}"),
+                    pri: (q * 7919 + k) as u64 % 1000,
+                    job: Value::Null.into(),
+                });
+            }
+        }
+        let names = (0..named).map(|q| format!("q{q}")).collect();
+        ManyQueues { queues, names }
+    }
+
+    /// Takes the best job across the named queues and puts it back, finding
+    /// it with the index, or by checking each named queue if `scan`.
+    pub fn get(&mut self, scan: bool) {
+        let job = match scan {
+            true => self.queues.pop_by_scan(&self.names),
+            false => self.queues.pop(&self.names),
+        };
+        self.queues.push(job.expect("a job in a named queue"));
+    }
+}
+
+/// Starts a server with no jobs on a free loopback port, journalling to
+/// `journal` if given. Unlike `serve`, it doesn't log each connection.
+pub async fn start(journal: Option<&Path>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = match journal {
+        Some(path) => State::with_journal(path).unwrap(),
+        None => State::default(),
+    };
+    let state = Shared::spawn(state);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let state = state.clone();
+            tokio::spawn(async move {
+                let (reader, writer) = socket.split();
+                process(reader, writer, &state).await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+/// Has `producers` clients put `jobs` jobs each, spread over 50 queues,
+/// while `workers` clients wait on all the queues and delete every job they
+/// get, each client over its own connection to `addr`. Returns once every job
+/// has been deleted, `producers * jobs * 3` requests in all.
+pub async fn many_connections(addr: SocketAddr, producers: usize, workers: usize, jobs: usize) {
+    assert_eq!(producers * jobs % workers, 0, "jobs don't split evenly");
+    let mut tasks = vec![];
+    for p in 0..producers {
+        tasks.push(tokio::spawn(async move {
+            let mut client = Client::connect(addr).await;
+            for i in 0..jobs {
+                let queue = (p + i) % 50;
+                let put = format!(
+                    r#"{{"request":"put","queue":"q{queue}","job":{{"n":{i}}},"pri":{i}}}"#
+                );
+                client.request(&put).await;
+            }
+        }));
+    }
+    let queues: Vec<_> = (0..50).map(|q| format!("\"q{q}\"")).collect();
+    let get = Arc::new(format!(
+        r#"{{"request":"get","queues":[{}],"wait":true}}"#,
+        queues.join(",")
+    ));
+    for _ in 0..workers {
+        let get = get.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut client = Client::connect(addr).await;
+            for _ in 0..producers * jobs / workers {
+                let job: Value = serde_json::from_str(client.request(&get).await).unwrap();
+                let delete = format!(r#"{{"request":"delete","id":{}}}"#, job["id"]);
+                assert_eq!(client.request(&delete).await, r#"{"status":"ok"}"#);
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    line: String,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Client {
+        let socket = TcpStream::connect(addr).await.unwrap();
+        socket.set_nodelay(true).unwrap();
+        Client {
+            reader: BufReader::new(socket),
+            line: String::new(),
+        }
+    }
+
+    /// Sends a request line and waits for the response line.
+    async fn request(&mut self, request: &str) -> &str {
+        let socket = self.reader.get_mut();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        socket.write_all(b"\n").await.unwrap();
+        self.line.clear();
+        self.reader.read_line(&mut self.line).await.unwrap();
+        self.line.trim_end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, many_connections, start, traffic, ManyQueues};
+
+    #[test]
+    fn decodes_traffic() {
+        assert_eq!(decode(&traffic(1000)), 1000);
+    }
+
+    #[test]
+    fn gets_across_queues() {
+        for (full, named) in [(100, 100), (100, 3), (3, 100)] {
+            for scan in [false, true] {
+                let mut queues = ManyQueues::new(full, named);
+                for _ in 0..100 {
+                    queues.get(scan);
+                }
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shares_jobs() {
+        let addr = start(None).await;
+        many_connections(addr, 4, 2, 50).await;
+    }
+}
@@ -0,0 +1,38 @@
+//! The entry point for the `job_centre` fuzz target in `fuzz/`.
+
+use crate::fuzz::{decode_all, setup};
+
+use super::RequestDecoder;
+
+/// Decodes `data` into requests, panicking if anything misbehaves. The first
+/// byte sets the read size.
+pub fn requests(data: &[u8]) {
+    let Some((size, _, stream)) = setup(data) else {
+        return;
+    };
+    // A bad request is an item, not an error, so every line is parsed.
+    let lines = stream.iter().filter(|&&b| b == b'\n').count();
+    let mut requests = 0;
+    decode_all(RequestDecoder::default(), stream, size, |_| requests += 1);
+    assert_eq!(requests, lines);
+}
+
+#[cfg(test)]
+mod test {
+    use super::requests;
+
+    #[test]
+    fn seeds() {
+        let stream = b"{\"request\":\"put\",\"queue\":\"q\",\"job\":{},\"pri\":1}\n\
+            {\"request\":\"get\",\"queues\":[\"q\"],\"wait\":true}\n\
+            {\"request\":\"delete\",\"id\":-1}\n\
+            {\"request\":\"abort\"}\n\
+            \xff\n";
+        requests(&[]);
+        for setup in 0..16 {
+            let mut data = vec![setup];
+            data.extend_from_slice(stream);
+            requests(&data);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Job;
+
+/// Append-only log of every put, delete and abort, one JSON object per line.
+///
+/// Only the set of live jobs is recovered on replay. Jobs that were in progress
+/// come back as waiting, since the clients working on them are gone.
+///
+/// Entries are written in order on a thread of their own, so a request never
+/// waits on the disk. The flip side is that the last few entries before a
+/// crash may be lost, even though their requests were answered.
+pub(super) struct Journal {
+    /// Taken on drop, so the writer sees the end of the entries.
+    entries: Option<mpsc::Sender<Entry>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    Put {
+        id: u64,
+        queue: String,
+        pri: u64,
+        job: Arc<Value>,
+    },
+    Delete {
+        id: u64,
+    },
+    Abort {
+        id: u64,
+    },
+}
+
+/// How long the writer waits for more entries before writing what it has.
+const LINGER: Duration = Duration::from_millis(1);
+
+/// What replaying a journal leaves behind.
+pub(super) struct Replay {
+    pub(super) jobs: Vec<Job>,
+    pub(super) next_id: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if needed, and replays it.
+    pub(super) fn open(path: impl AsRef<Path>) -> Result<(Journal, Replay)> {
+        let path = path.as_ref();
+        let contents = if path.exists() {
+            fs::read(path)?
+        } else {
+            vec![]
+        };
+        let mut jobs = BTreeMap::new();
+        let mut next_id = 0;
+        for (n, line) in contents.split(|&b| b == b'\n').enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let entry = match serde_json::from_slice(line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // Most likely a write cut short by a crash.
+                    println!("{}:{}: skipping entry: {e}", path.display(), n + 1);
+                    continue;
+                }
+            };
+            match entry {
+                Entry::Put {
+                    id,
+                    queue,
+                    pri,
+                    job,
+                } => {
+                    next_id = next_id.max(id + 1);
+                    jobs.insert(
+                        id,
+                        Job {
+                            id,
+                            queue,
+                            pri,
+                            job,
+                        },
+                    );
+                }
+                Entry::Delete { id } => {
+                    jobs.remove(&id);
+                }
+                Entry::Abort { .. } => {}
+            }
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if contents.last().is_some_and(|&b| b != b'\n') {
+            // Don't glue the next entry onto a torn one.
+            file.write_all(b"\n")?;
+        }
+        let jobs = jobs.into_values().collect();
+        let (entries, rx) = mpsc::channel();
+        let journal = Journal {
+            entries: Some(entries),
+            writer: Some(thread::spawn(move || write(file, rx))),
+        };
+        Ok((journal, Replay { jobs, next_id }))
+    }
+
+    pub(super) fn put(&mut self, job: &Job) {
+        self.append(Entry::Put {
+            id: job.id,
+            queue: job.queue.clone(),
+            pri: job.pri,
+            job: job.job.clone(),
+        });
+    }
+
+    pub(super) fn delete(&mut self, id: u64) {
+        self.append(Entry::Delete { id });
+    }
+
+    pub(super) fn abort(&mut self, id: u64) {
+        self.append(Entry::Abort { id });
+    }
+
+    fn append(&mut self, entry: Entry) {
+        if let Some(entries) = &self.entries {
+            // Fails only if the writer panicked, and it's already said why.
+            let _ = entries.send(entry);
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// Waits for everything appended so far to be written.
+    fn drop(&mut self) {
+        drop(self.entries.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes `entries` to `file` until the journal is dropped. Whatever arrives
+/// within `LINGER` of an entry goes out with it in one write, so a busy server
+/// only wakes the writer once per batch.
+fn write(mut file: File, entries: mpsc::Receiver<Entry>) {
+    let mut buf = vec![];
+    while let Ok(entry) = entries.recv() {
+        thread::sleep(LINGER);
+        for entry in std::iter::once(entry).chain(entries.try_iter()) {
+            serde_json::to_writer(&mut buf, &entry).expect("journal entries serialize");
+            buf.push(b'\n');
+        }
+        // The journal is a convenience; losing an entry shouldn't take the
+        // server down.
+        if let Err(e) = file.write_all(&buf) {
+            println!("Journal write failed: {e:?}");
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, sync::Arc};
+
+    use serde_json::json;
+
+    use super::{Job, Journal};
+
+    fn job(id: u64, queue: &str) -> Job {
+        Job {
+            id,
+            queue: queue.to_string(),
+            pri: id * 10,
+            job: Arc::new(json!({ "n": id })),
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let path = std::env::temp_dir().join(format!("job-journal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (mut journal, replay) = Journal::open(&path).unwrap();
+        assert!(replay.jobs.is_empty());
+        assert_eq!(replay.next_id, 0);
+        journal.put(&job(0, "a"));
+        journal.put(&job(1, "b"));
+        journal.put(&job(2, "a"));
+        journal.abort(1);
+        journal.delete(2);
+        drop(journal);
+        // A torn final write is skipped.
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "{\"op\":\"pu").unwrap();
+
+        let (mut journal, replay) = Journal::open(&path).unwrap();
+        let ids: Vec<_> = replay
+            .jobs
+            .iter()
+            .map(|j| (j.id, j.queue.as_str()))
+            .collect();
+        assert_eq!(ids, [(0, "a"), (1, "b")]);
+        assert_eq!(replay.jobs[1].job, Arc::new(json!({ "n": 1 })));
+        assert_eq!(replay.next_id, 3);
+        journal.delete(0);
+        drop(journal);
+
+        let (_, replay) = Journal::open(&path).unwrap();
+        assert_eq!(replay.jobs.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
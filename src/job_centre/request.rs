@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+
+#[derive(Debug)]
+pub(super) enum Request {
+    Put {
+        queue: String,
+        job: Map<String, Value>,
+        pri: u64,
+    },
+    Get {
+        /// A set, built once here, since looking a queue up in it is how a
+        /// get finds its best job, and how a waiting get is matched to later
+        /// puts.
+        queues: HashSet<String>,
+        wait: bool,
+    },
+    Delete {
+        id: u64,
+    },
+    Abort {
+        id: u64,
+    },
+    /// Non-standard: per-queue counts, for watching the server.
+    Stats,
+}
+
+impl Request {
+    /// Parses one request line, checking every field's type. The error lists
+    /// everything wrong with the request, not just the first problem.
+    pub(super) fn parse(line: &[u8]) -> Result<Request, String> {
+        let value: Value =
+            serde_json::from_slice(line).map_err(|e| format!("invalid JSON: {e}"))?;
+        let Value::Object(fields) = value else {
+            return Err("request must be a JSON object".to_string());
+        };
+        let mut fields = Fields {
+            fields,
+            errors: vec![],
+        };
+        let request = match fields.fields.get("request") {
+            Some(Value::String(request)) => request.clone(),
+            Some(_) => return Err("`request` must be a string".to_string()),
+            None => return Err("missing `request`".to_string()),
+        };
+
+        let request = match request.as_str() {
+            "put" => {
+                let queue = fields.string("queue");
+                let job = fields.object("job");
+                let pri = fields.integer("pri");
+                match (queue, job, pri) {
+                    (Some(queue), Some(job), Some(pri)) => Some(Request::Put { queue, job, pri }),
+                    _ => None,
+                }
+            }
+            "get" => {
+                let queues = fields.strings("queues");
+                let wait = fields.boolean("wait");
+                match (queues, wait) {
+                    (Some(queues), Some(wait)) => Some(Request::Get { queues, wait }),
+                    _ => None,
+                }
+            }
+            "delete" => fields.integer("id").map(|id| Request::Delete { id }),
+            "abort" => fields.integer("id").map(|id| Request::Abort { id }),
+            "stats" => Some(Request::Stats),
+            _ => return Err(format!("unknown request type {request:?}")),
+        };
+        request.ok_or_else(|| fields.errors.join(", "))
+    }
+}
+
+/// Typed access to a request's fields, collecting an error for each field
+/// that is missing or has the wrong type.
+struct Fields {
+    fields: Map<String, Value>,
+    errors: Vec<String>,
+}
+
+impl Fields {
+    fn take<T>(
+        &mut self,
+        name: &str,
+        expected: &str,
+        convert: impl FnOnce(Value) -> Option<T>,
+    ) -> Option<T> {
+        let Some(value) = self.fields.remove(name) else {
+            self.errors.push(format!("missing `{name}`"));
+            return None;
+        };
+        let converted = convert(value);
+        if converted.is_none() {
+            self.errors.push(format!("`{name}` must be {expected}"));
+        }
+        converted
+    }
+
+    fn string(&mut self, name: &str) -> Option<String> {
+        self.take(name, "a string", |v| match v {
+            Value::String(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    fn strings<C: FromIterator<String>>(&mut self, name: &str) -> Option<C> {
+        self.take(name, "an array of strings", |v| match v {
+            Value::Array(values) => values
+                .into_iter()
+                .map(|v| match v {
+                    Value::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        })
+    }
+
+    fn object(&mut self, name: &str) -> Option<Map<String, Value>> {
+        self.take(name, "an object", |v| match v {
+            Value::Object(map) => Some(map),
+            _ => None,
+        })
+    }
+
+    fn integer(&mut self, name: &str) -> Option<u64> {
+        self.take(name, "a non-negative integer", |v| v.as_u64())
+    }
+
+    /// An optional boolean, defaulting to false.
+    fn boolean(&mut self, name: &str) -> Option<bool> {
+        if !self.fields.contains_key(name) {
+            return Some(false);
+        }
+        self.take(name, "a boolean", |v| v.as_bool())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Request;
+
+    fn error(line: &str) -> String {
+        Request::parse(line.as_bytes()).unwrap_err()
+    }
+
+    #[test]
+    fn valid() {
+        let put = br#"{"request":"put","queue":"q","job":{"a":[1]},"pri":0,"extra":null}"#;
+        assert!(matches!(
+            Request::parse(put),
+            Ok(Request::Put { pri: 0, .. })
+        ));
+        assert!(matches!(
+            Request::parse(br#"{"request":"get","queues":[]}"#),
+            Ok(Request::Get { wait: false, .. })
+        ));
+        assert!(matches!(
+            Request::parse(br#"{"request":"get","queues":["a"],"wait":true}"#),
+            Ok(Request::Get { wait: true, .. })
+        ));
+        assert!(matches!(
+            Request::parse(br#"{"request":"abort","id":12}"#),
+            Ok(Request::Abort { id: 12 })
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [
+            ("", "invalid JSON: EOF while parsing a value at line 1 column 0"),
+            ("[1, 2]", "request must be a JSON object"),
+            ("{}", "missing `request`"),
+            (r#"{"request":7}"#, "`request` must be a string"),
+            (r#"{"request":"jump"}"#, r#"unknown request type "jump""#),
+            (
+                r#"{"request":"put"}"#,
+                "missing `queue`, missing `job`, missing `pri`",
+            ),
+            (
+                r#"{"request":"put","queue":1,"job":[],"pri":-1}"#,
+                "`queue` must be a string, `job` must be an object, `pri` must be a non-negative integer",
+            ),
+            (
+                r#"{"request":"put","queue":"q","job":{},"pri":1.5}"#,
+                "`pri` must be a non-negative integer",
+            ),
+            (
+                r#"{"request":"get","queues":["a",1],"wait":"yes"}"#,
+                "`queues` must be an array of strings, `wait` must be a boolean",
+            ),
+            (r#"{"request":"get","queues":"a"}"#, "`queues` must be an array of strings"),
+            (r#"{"request":"delete","id":"3"}"#, "`id` must be a non-negative integer"),
+            (r#"{"request":"abort"}"#, "missing `id`"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(error(line), expected, "{line}");
+        }
+    }
+}
@@ -1,5 +1,23 @@
+pub(crate) mod clock;
 pub(crate) mod config;
+pub(crate) mod datagram;
+mod fuzz;
+#[cfg(test)]
+mod testutil;
 
 pub mod bank;
+pub mod budget_chat;
+pub mod check;
+pub mod cli;
+pub mod clients;
+pub mod insecure_sockets;
+pub mod job_centre;
+pub mod line_reversal;
+pub mod pest_control;
 pub mod prime_time;
+pub mod record;
+pub mod replay;
 pub mod smoke;
+pub mod speed_daemon;
+pub mod unusual_database;
+pub mod vcs;
@@ -0,0 +1,157 @@
+use anyhow::{bail, Context, Result};
+use tokio::net::UdpSocket;
+
+use crate::{
+    clock::{Clock, Tokio},
+    config::ADDR,
+    datagram::Datagram,
+};
+
+use self::lrcp::Application;
+
+pub mod bench;
+mod lrcp;
+pub(crate) mod message;
+
+/// Set to override how much a session may send before it's acknowledged.
+const WINDOW_VAR: &str = "LINE_REVERSAL_WINDOW";
+/// How much a session may send before it's acknowledged, by default: enough
+/// for a few dozen messages in flight.
+const WINDOW: usize = 1 << 15;
+/// The longest line accepted, in bytes. The spec promises lines under 10000
+/// bytes.
+const MAX_LINE: usize = 1 << 16;
+
+pub async fn run() -> Result<()> {
+    let window = match std::env::var(WINDOW_VAR) {
+        Ok(window) => window
+            .parse()
+            .with_context(|| format!("{WINDOW_VAR} must be a number of bytes"))?,
+        Err(_) => WINDOW,
+    };
+    let socket = UdpSocket::bind(ADDR).await?;
+    println!("Listening on {ADDR} (UDP)...");
+    serve(&socket, window, Tokio).await
+}
+
+async fn serve(socket: &impl Datagram, window: usize, clock: impl Clock) -> Result<()> {
+    lrcp::serve::<Reverser>(socket, window, clock).await
+}
+
+/// Sends back each line it receives, reversed.
+#[derive(Default)]
+struct Reverser {
+    /// The line so far, until its newline arrives.
+    line: Vec<u8>,
+}
+
+impl Application for Reverser {
+    fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut reversed = vec![];
+        for &b in data {
+            if b != b'\n' {
+                if self.line.len() == MAX_LINE {
+                    bail!("line longer than {MAX_LINE} bytes");
+                }
+                self.line.push(b);
+                continue;
+            }
+            reversed.extend(self.line.drain(..).rev());
+            reversed.push(b'\n');
+        }
+        Ok(reversed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        clients::lrcp::Session,
+        clock::Tokio,
+        datagram::{
+            network::{Faults, Network},
+            Datagram,
+        },
+        testutil::clock::Paused,
+    };
+
+    use super::{lrcp::Application, serve, Reverser, MAX_LINE, WINDOW};
+
+    #[test]
+    fn reverse() {
+        let mut reverser = Reverser::default();
+        assert_eq!(reverser.receive(b"hello\nwor").unwrap(), b"olleh\n");
+        assert_eq!(reverser.receive(b"").unwrap(), b"");
+        assert_eq!(reverser.receive(b"ld\n\n/\\\n").unwrap(), b"dlrow\n\n\\/\n");
+        assert!(reverser.receive(&vec![b'x'; MAX_LINE]).is_ok());
+        assert!(reverser.receive(b"x").is_err());
+    }
+
+    /// Lines sent, and the reversed lines expected back.
+    fn lines() -> (Vec<u8>, Vec<u8>) {
+        let mut sent = vec![];
+        let mut expected = vec![];
+        // Up to a few messages a line.
+        for i in 0..20 {
+            let line = format!("{i}: a/b\\c {}", "x".repeat(i * 150));
+            sent.extend(line.as_bytes());
+            sent.push(b'\n');
+            expected.extend(line.bytes().rev());
+            expected.push(b'\n');
+        }
+        (sent, expected)
+    }
+
+    async fn reverse_over(mut session: Session<impl Datagram>) {
+        let (sent, expected) = lines();
+        session.write(&sent).await.unwrap();
+        let mut received = vec![];
+        while received.len() < expected.len() {
+            received.extend(session.recv().await.unwrap().unwrap());
+        }
+        assert_eq!(received, expected);
+        session.flush().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let task = tokio::spawn(async move { serve(&socket, WINDOW, Tokio).await });
+        let session = Session::connect(&addr).await.unwrap();
+        reverse_over(session).await;
+        task.abort();
+    }
+
+    /// Both ends resend what's lost, and put what's out of order back in
+    /// order, over a network that loses, duplicates and reorders a lot.
+    /// Time is paused, so the resends don't take real seconds.
+    #[tokio::test]
+    async fn faults() {
+        let clock = Paused::start();
+        let faults = Faults {
+            loss: 25,
+            duplicate: 10,
+            reorder: 25,
+            ..Faults::default()
+        };
+        for seed in 0..4 {
+            let network = Network::new(faults, seed);
+            let server = network.endpoint("10.0.0.1:7");
+            let addr = server.addr;
+            let task = tokio::spawn(async move { serve(&server, WINDOW, clock).await });
+            let client = network.endpoint("10.0.0.2:1000");
+            let session = tokio::time::timeout(Duration::from_secs(600), async {
+                let session = Session::open(client, addr, seed as u32).await.unwrap();
+                reverse_over(session).await;
+            });
+            session.await.expect("timed out");
+            task.abort();
+        }
+    }
+}
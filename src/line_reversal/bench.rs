@@ -0,0 +1,83 @@
+//! The entry point for the `line_reversal` benchmarks in `benches/`: lines
+//! reversed over a simulated network that delays and loses datagrams.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{
+    clients::lrcp::Session,
+    clock::Tokio,
+    datagram::network::{Faults, Network},
+};
+
+use super::serve;
+
+/// How long each datagram takes to cross the network, one way.
+pub const DELAY: Duration = Duration::from_millis(10);
+
+/// About `len` bytes of lines of 100 bytes.
+pub fn lines(len: usize) -> Vec<u8> {
+    (0..len / 100)
+        .flat_map(|i| format!("{i:>8} {}\n", "abcdefghij".repeat(9)).into_bytes())
+        .collect()
+}
+
+/// Sends `lines` to a server with a window of `window` bytes, over a network
+/// with a delay of `DELAY` that loses `loss` percent of datagrams, chosen by
+/// `seed`. Returns how long it took from writing the first line to having
+/// them all back reversed.
+///
+/// Run it with time paused, so that the duration is the network's, with
+/// its delays and the seconds waiting to resend what's lost, rather than the
+/// CPU's.
+pub async fn transfer(lines: &[u8], window: usize, loss: u64, seed: u64) -> Duration {
+    let faults = Faults {
+        loss,
+        delay: DELAY,
+        ..Faults::default()
+    };
+    let network = Network::new(faults, seed);
+    let server = network.endpoint("10.0.0.1:7");
+    let addr = server.addr;
+    let task = tokio::spawn(async move { serve(&server, window, Tokio).await });
+    let client = network.endpoint("10.0.0.2:1000");
+    let mut session = Session::open(client, addr, 1).await.unwrap();
+    let expected: Vec<u8> = lines
+        .split_inclusive(|&b| b == b'\n')
+        .flat_map(|line| line[..line.len() - 1].iter().rev().chain(b"\n"))
+        .copied()
+        .collect();
+
+    let start = Instant::now();
+    session.write(lines).await.unwrap();
+    let mut received = vec![];
+    while received.len() < expected.len() {
+        received.extend(session.recv().await.unwrap().expect("closed early"));
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(received, expected);
+    task.abort();
+    elapsed
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testutil::clock::Paused;
+
+    use super::{lines, transfer, DELAY};
+
+    #[tokio::test]
+    async fn transfers() {
+        let _clock = Paused::start();
+        let lines = lines(8 << 10);
+        assert_eq!(lines.len(), 8100);
+        // With nothing lost, a full window takes a round trip, and a small
+        // one has to wait for acks to send more.
+        let full = transfer(&lines, 8 << 10, 0, 0).await;
+        assert!(full >= 2 * DELAY && full < 4 * DELAY, "{full:?}");
+        let small = transfer(&lines, 1 << 10, 0, 0).await;
+        assert!(small > 4 * full, "{small:?}");
+        transfer(&lines, 8 << 10, 25, 1).await;
+    }
+}
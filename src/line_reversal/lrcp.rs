@@ -0,0 +1,487 @@
+//! The server end of LRCP: sessions, each a reliable byte stream over UDP,
+//! driven by the datagrams that arrive and by the time.
+//!
+//! `Sessions` is only the state and what to send next, so the protocol can
+//! be tested without a socket or waiting; `serve` runs it on one.
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use tokio::time::Instant;
+
+use super::message::{data_messages, parse, Message, MAX_LEN, MAX_NUMBER};
+use crate::{clock::Clock, datagram::Datagram};
+
+/// How long to wait for an acknowledgement before sending again.
+pub(crate) const RETRANSMIT: Duration = Duration::from_secs(3);
+
+/// How long to go on sending without hearing from the peer before giving up
+/// on a session.
+pub(crate) const EXPIRY: Duration = Duration::from_secs(60);
+
+/// What runs over each session.
+pub(crate) trait Application: Default {
+    /// Takes the next data from the peer, in order, returning what to send
+    /// back. An error closes the session.
+    fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Answers LRCP messages on `socket`, running an `A` for each session, with
+/// at most `window` bytes sent and not yet acknowledged on each, and resends
+/// and expiry timed by `clock`.
+pub(crate) async fn serve<A: Application>(
+    socket: &impl Datagram,
+    window: usize,
+    clock: impl Clock,
+) -> Result<()> {
+    let mut sessions = Sessions::<A>::new(window);
+    let mut buf = vec![0; MAX_LEN];
+    loop {
+        let deadline = sessions.deadline();
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (n, addr) = received?;
+                sessions.receive(&buf[..n], addr, clock.now());
+            }
+            () = clock.sleep(deadline.map_or(Duration::ZERO, |d| d.saturating_duration_since(clock.now()))), if deadline.is_some() => {
+                sessions.tick(clock.now());
+            }
+        }
+        for (addr, message) in sessions.outgoing() {
+            if let Err(e) = socket.send_to(&message, addr).await {
+                // One unreachable peer shouldn't stop the others being answered
+                println!("{addr}: send failed: {e}");
+            }
+        }
+    }
+}
+
+pub(crate) struct Sessions<A> {
+    sessions: HashMap<u32, Session<A>>,
+    window: usize,
+    /// Messages to send, and where to.
+    outgoing: Vec<(SocketAddr, Vec<u8>)>,
+}
+
+struct Session<A> {
+    /// Where the peer last sent from, which is where to answer.
+    addr: SocketAddr,
+    app: A,
+    /// How much of the peer's stream has been received.
+    received: usize,
+    /// How much of our stream the peer has acknowledged.
+    acked: usize,
+    /// Our stream from `acked` on.
+    unacked: Vec<u8>,
+    /// How far into our stream has been sent.
+    sent: usize,
+    /// When the peer was last heard from.
+    heard: Instant,
+    /// When unacknowledged data is next sent again.
+    retransmit: Instant,
+}
+
+impl<A: Application> Sessions<A> {
+    pub(crate) fn new(window: usize) -> Sessions<A> {
+        Sessions {
+            sessions: HashMap::new(),
+            window,
+            outgoing: vec![],
+        }
+    }
+
+    /// Handles a datagram from `addr`. Anything that isn't a valid message
+    /// is ignored.
+    pub(crate) fn receive(&mut self, datagram: &[u8], addr: SocketAddr, now: Instant) {
+        let Some(message) = parse(datagram) else {
+            return;
+        };
+        let id = match message {
+            Message::Connect(id) => {
+                self.sessions.entry(id).or_insert_with(|| Session {
+                    addr,
+                    app: A::default(),
+                    received: 0,
+                    acked: 0,
+                    unacked: vec![],
+                    sent: 0,
+                    heard: now,
+                    retransmit: now,
+                });
+                self.send(addr, format!("/ack/{id}/0/"));
+                return;
+            }
+            Message::Close(id) => return self.close(id, addr),
+            Message::Data(id, ..) | Message::Ack(id, _) => id,
+        };
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return self.close(id, addr);
+        };
+        session.addr = addr;
+        session.heard = now;
+        match message {
+            Message::Data(_, pos, data) => self.data(id, pos as usize, &data, now),
+            Message::Ack(_, len) => self.ack(id, len as usize, now),
+            Message::Connect(_) | Message::Close(_) => unreachable!("handled above"),
+        }
+    }
+
+    /// Resends unacknowledged data that's due, and expires sessions whose
+    /// peer has stopped answering.
+    pub(crate) fn tick(&mut self, now: Instant) {
+        let mut expired = vec![];
+        for (&id, session) in &mut self.sessions {
+            if session.unacked.is_empty() {
+                continue;
+            }
+            if now >= session.heard + EXPIRY {
+                expired.push((id, session.addr));
+            } else if now >= session.retransmit {
+                let in_flight = &session.unacked[..session.sent - session.acked];
+                for message in data_messages(id, session.acked, in_flight) {
+                    self.outgoing.push((session.addr, message));
+                }
+                session.retransmit = now + RETRANSMIT;
+            }
+        }
+        for (id, addr) in expired {
+            self.close(id, addr);
+        }
+    }
+
+    /// When `tick` next has something to do, if ever.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .filter(|session| !session.unacked.is_empty())
+            .map(|session| session.retransmit.min(session.heard + EXPIRY))
+            .min()
+    }
+
+    /// The messages to send since last asked.
+    pub(crate) fn outgoing(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    fn data(&mut self, id: u32, pos: usize, data: &[u8], now: Instant) {
+        let session = self.sessions.get_mut(&id).expect("checked by receive");
+        let addr = session.addr;
+        if pos > session.received {
+            // Missed some: acknowledge what we have so it's resent.
+            let ack = format!("/ack/{id}/{}/", session.received);
+            return self.send(addr, ack);
+        }
+        // Any of `data` before `received` is a resend.
+        let new = data.get(session.received - pos..).unwrap_or_default();
+        session.received += new.len();
+        let ack = format!("/ack/{id}/{}/", session.received);
+        self.send(addr, ack);
+        if new.is_empty() {
+            return;
+        }
+        let session = self.sessions.get_mut(&id).expect("checked by receive");
+        let reply = match session.app.receive(new) {
+            Ok(reply) => reply,
+            Err(e) => {
+                println!("{addr}: session {id}: {e:?}");
+                return self.close(id, addr);
+            }
+        };
+        if session.acked + session.unacked.len() + reply.len() >= MAX_NUMBER as usize {
+            println!("{addr}: session {id}: stream too long");
+            return self.close(id, addr);
+        }
+        if session.unacked.is_empty() {
+            session.retransmit = now + RETRANSMIT;
+        }
+        session.unacked.extend(reply);
+        self.send_window(id);
+    }
+
+    fn ack(&mut self, id: u32, len: usize, now: Instant) {
+        let session = self.sessions.get_mut(&id).expect("checked by receive");
+        if len < session.acked {
+            return;
+        }
+        if len == session.acked {
+            // The peer is missing what's next, if there's anything. Resend
+            // just the first message of it, as the rest may be on its way.
+            let in_flight = &session.unacked[..session.sent - len];
+            if let Some(message) = data_messages(id, len, in_flight).into_iter().next() {
+                let addr = session.addr;
+                self.outgoing.push((addr, message));
+            }
+            return;
+        }
+        if len > session.sent {
+            // It's acknowledging what was never sent: misbehaving.
+            let addr = session.addr;
+            return self.close(id, addr);
+        }
+        session.unacked.drain(..len - session.acked);
+        session.acked = len;
+        session.retransmit = now + RETRANSMIT;
+        self.send_window(id);
+    }
+
+    /// Sends whatever of the session's stream hasn't been sent yet, up to
+    /// `window` past what's acknowledged.
+    fn send_window(&mut self, id: u32) {
+        let session = self
+            .sessions
+            .get_mut(&id)
+            .expect("sessions are removed last");
+        let end = session.acked + session.unacked.len().min(self.window);
+        if session.sent >= end {
+            return;
+        }
+        let data = &session.unacked[session.sent - session.acked..end - session.acked];
+        for message in data_messages(id, session.sent, data) {
+            self.outgoing.push((session.addr, message));
+        }
+        session.sent = end;
+    }
+
+    fn close(&mut self, id: u32, addr: SocketAddr) {
+        self.sessions.remove(&id);
+        self.send(addr, format!("/close/{id}/"));
+    }
+
+    fn send(&mut self, addr: SocketAddr, message: String) {
+        self.outgoing.push((addr, message.into_bytes()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use anyhow::{bail, Result};
+    use tokio::time::Instant;
+
+    use crate::{
+        clock::Clock,
+        datagram::{
+            network::{Endpoint, Faults, Network},
+            Datagram,
+        },
+        testutil::clock::{assert_on_time, Paused},
+    };
+
+    use super::{serve, Application, Sessions, EXPIRY, MAX_LEN, RETRANSMIT};
+
+    /// Sends back what it receives, and fails on `!`.
+    #[derive(Default)]
+    struct Echo;
+
+    impl Application for Echo {
+        fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+            if data.contains(&b'!') {
+                bail!("!");
+            }
+            Ok(data.to_vec())
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        "10.0.0.2:1000".parse().unwrap()
+    }
+
+    /// Delivers `message` at `now`, returning what's sent in answer.
+    fn exchange(sessions: &mut Sessions<Echo>, message: &str, now: Instant) -> Vec<String> {
+        sessions.receive(message.as_bytes(), peer(), now);
+        outgoing(sessions)
+    }
+
+    fn outgoing(sessions: &mut Sessions<Echo>) -> Vec<String> {
+        sessions
+            .outgoing()
+            .into_iter()
+            .map(|(addr, message)| {
+                assert_eq!(addr, peer());
+                String::from_utf8(message).unwrap()
+            })
+            .collect()
+    }
+
+    fn connected(window: usize) -> (Sessions<Echo>, Instant) {
+        let mut sessions = Sessions::new(window);
+        let now = Instant::now();
+        assert_eq!(exchange(&mut sessions, "/connect/1/", now), ["/ack/1/0/"]);
+        (sessions, now)
+    }
+
+    #[test]
+    fn spec_example() {
+        let (mut sessions, now) = connected(1 << 16);
+        // Connecting again is acknowledged again.
+        assert_eq!(exchange(&mut sessions, "/connect/1/", now), ["/ack/1/0/"]);
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/0/hello\n/", now),
+            ["/ack/1/6/", "/data/1/0/hello\n/"]
+        );
+        assert_eq!(exchange(&mut sessions, "/ack/1/6/", now), [""; 0]);
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/6/a\\/b\\\\/", now),
+            ["/ack/1/10/", "/data/1/6/a\\/b\\\\/"]
+        );
+        assert_eq!(exchange(&mut sessions, "/ack/1/10/", now), [""; 0]);
+        assert_eq!(exchange(&mut sessions, "/close/1/", now), ["/close/1/"]);
+        assert_eq!(exchange(&mut sessions, "/data/1/10/x/", now), ["/close/1/"]);
+        assert_eq!(exchange(&mut sessions, "/ack/2/0/", now), ["/close/2/"]);
+        for invalid in ["/data/1/0/", "/ack/1/x/", "/connect/1", "hello"] {
+            assert!(exchange(&mut sessions, invalid, now).is_empty());
+        }
+    }
+
+    /// Data arriving out of order, or again, is only taken in order.
+    #[test]
+    fn ordering() {
+        let (mut sessions, now) = connected(1 << 16);
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/3/def/", now),
+            ["/ack/1/0/"]
+        );
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/0/abc/", now),
+            ["/ack/1/3/", "/data/1/0/abc/"]
+        );
+        // An overlapping resend: only the new part is taken.
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/1/bcdef/", now),
+            ["/ack/1/6/", "/data/1/3/def/"]
+        );
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/0/abc/", now),
+            ["/ack/1/6/"]
+        );
+        assert_eq!(exchange(&mut sessions, "/ack/1/6/", now), [""; 0]);
+    }
+
+    #[test]
+    fn retransmission() {
+        let (mut sessions, now) = connected(1 << 16);
+        assert_eq!(sessions.deadline(), None);
+        exchange(&mut sessions, "/data/1/0/abcdef/", now);
+        assert_eq!(sessions.deadline(), Some(now + RETRANSMIT));
+        sessions.tick(now + RETRANSMIT / 2);
+        assert!(outgoing(&mut sessions).is_empty());
+
+        sessions.tick(now + RETRANSMIT);
+        assert_eq!(outgoing(&mut sessions), ["/data/1/0/abcdef/"]);
+        // Part acknowledged: only the rest is resent, a full timeout on.
+        let later = now + RETRANSMIT;
+        assert!(exchange(&mut sessions, "/ack/1/2/", later).is_empty());
+        assert_eq!(sessions.deadline(), Some(later + RETRANSMIT));
+        sessions.tick(later + RETRANSMIT);
+        assert_eq!(outgoing(&mut sessions), ["/data/1/2/cdef/"]);
+        // A repeated ack asks for the rest straight away.
+        assert_eq!(
+            exchange(&mut sessions, "/ack/1/2/", later),
+            ["/data/1/2/cdef/"]
+        );
+        // And once it's all acknowledged, nothing's due.
+        assert!(exchange(&mut sessions, "/ack/1/6/", later).is_empty());
+        assert_eq!(sessions.deadline(), None);
+    }
+
+    #[test]
+    fn window() {
+        let (mut sessions, now) = connected(4);
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/0/abcdefghij/", now),
+            ["/ack/1/10/", "/data/1/0/abcd/"]
+        );
+        assert_eq!(exchange(&mut sessions, "/ack/1/2/", now), ["/data/1/4/ef/"]);
+        // Only what's been sent is resent.
+        sessions.tick(now + RETRANSMIT);
+        assert_eq!(outgoing(&mut sessions), ["/data/1/2/cdef/"]);
+        assert_eq!(
+            exchange(&mut sessions, "/ack/1/6/", now + RETRANSMIT),
+            ["/data/1/6/ghij/"]
+        );
+        // What's beyond the window hasn't been sent, so it can't be
+        // acknowledged.
+        let (mut sessions, now) = connected(4);
+        exchange(&mut sessions, "/data/1/0/abcdefghij/", now);
+        assert_eq!(exchange(&mut sessions, "/ack/1/5/", now), ["/close/1/"]);
+    }
+
+    #[test]
+    fn closing() {
+        // Acknowledging what was never sent.
+        let (mut sessions, now) = connected(1 << 16);
+        exchange(&mut sessions, "/data/1/0/abc/", now);
+        assert_eq!(exchange(&mut sessions, "/ack/1/4/", now), ["/close/1/"]);
+        assert!(sessions.sessions.is_empty());
+
+        // The application failing.
+        let (mut sessions, now) = connected(1 << 16);
+        assert_eq!(
+            exchange(&mut sessions, "/data/1/0/a!/", now),
+            ["/ack/1/2/", "/close/1/"]
+        );
+        assert!(sessions.sessions.is_empty());
+
+        // The peer going quiet with data unacknowledged, though it's resent
+        // until then.
+        let (mut sessions, now) = connected(1 << 16);
+        exchange(&mut sessions, "/data/1/0/abc/", now);
+        let mut resent = 0;
+        while let Some(deadline) = sessions.deadline() {
+            sessions.tick(deadline);
+            match &outgoing(&mut sessions)[..] {
+                [message] if message == "/data/1/0/abc/" => resent += 1,
+                [message] if message == "/close/1/" => {
+                    assert!(deadline >= now + EXPIRY);
+                    break;
+                }
+                sent => panic!("unexpected {sent:?}"),
+            }
+        }
+        assert_eq!(resent, 19);
+        assert!(sessions.sessions.is_empty());
+    }
+
+    /// The next message the server sends `client`, and when it arrives.
+    async fn recv(client: &Endpoint, clock: Paused) -> (String, Instant) {
+        let mut buf = [0; MAX_LEN];
+        let (n, _) = client.recv_from(&mut buf).await.unwrap();
+        (String::from_utf8(buf[..n].to_vec()).unwrap(), clock.now())
+    }
+
+    /// The server's timers, over a network: resends come every
+    /// `RETRANSMIT`, and a peer that never answers is closed at
+    /// `EXPIRY`.
+    #[tokio::test]
+    async fn timers() {
+        let clock = Paused::start();
+        let network = Network::new(Faults::default(), 0);
+        let server = network.endpoint("10.0.0.1:7");
+        let addr = server.addr;
+        let task = tokio::spawn(async move { serve::<Echo>(&server, 1 << 16, clock).await });
+        let client = network.endpoint("10.0.0.2:1000");
+
+        client.send_to(b"/connect/1/", addr).await.unwrap();
+        client.send_to(b"/data/1/0/abc/", addr).await.unwrap();
+        let start = clock.now();
+        assert_eq!(recv(&client, clock).await, ("/ack/1/0/".to_string(), start));
+        assert_eq!(recv(&client, clock).await, ("/ack/1/3/".to_string(), start));
+        assert_eq!(
+            recv(&client, clock).await,
+            ("/data/1/0/abc/".to_string(), start)
+        );
+        // Each resend is timed from the one before.
+        let mut last = start;
+        for _ in 1..20 {
+            let (message, at) = recv(&client, clock).await;
+            assert_eq!(message, "/data/1/0/abc/");
+            assert_on_time(at, last + RETRANSMIT);
+            last = at;
+        }
+        let (message, at) = recv(&client, clock).await;
+        assert_eq!(message, "/close/1/");
+        assert_on_time(at, start + EXPIRY);
+        task.abort();
+    }
+}
@@ -0,0 +1,146 @@
+//! The LRCP wire format, shared by the server and the client. A message is
+//! `/`-separated fields between an opening and closing `/`, with `/` and `\`
+//! in data escaped by a `\`.
+
+/// Messages must be shorter than this.
+pub const MAX_LEN: usize = 1000;
+
+/// Numbers in messages must be below this.
+pub(crate) const MAX_NUMBER: u64 = 1 << 31;
+
+/// A message, with its session id.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Message {
+    Connect(u32),
+    Data(u32, u32, Vec<u8>),
+    Ack(u32, u32),
+    Close(u32),
+}
+
+/// Splits `message` into its fields, unescaping them, or `None` if it isn't
+/// one: it doesn't start and end with an unescaped `/`.
+fn fields(message: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut rest = message.strip_prefix(b"/")?.iter();
+    let mut fields = vec![];
+    let mut field = vec![];
+    while let Some(&b) = rest.next() {
+        match b {
+            b'\\' => field.push(*rest.next()?),
+            b'/' => fields.push(std::mem::take(&mut field)),
+            b => field.push(b),
+        }
+    }
+    match field.is_empty() && !fields.is_empty() {
+        true => Some(fields),
+        false => None,
+    }
+}
+
+fn number(field: &[u8]) -> Option<u32> {
+    let n: u64 = std::str::from_utf8(field).ok()?.parse().ok()?;
+    (n < MAX_NUMBER).then_some(n as u32)
+}
+
+/// Parses a message, or `None` if it's invalid and should be ignored.
+pub(crate) fn parse(message: &[u8]) -> Option<Message> {
+    if message.len() >= MAX_LEN {
+        return None;
+    }
+    let fields = fields(message)?;
+    let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+    match fields[..] {
+        [b"connect", session] => Some(Message::Connect(number(session)?)),
+        [b"data", session, pos, data] => {
+            Some(Message::Data(number(session)?, number(pos)?, data.to_vec()))
+        }
+        [b"ack", session, len] => Some(Message::Ack(number(session)?, number(len)?)),
+        [b"close", session] => Some(Message::Close(number(session)?)),
+        _ => None,
+    }
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data {
+        if b == b'/' || b == b'\\' {
+            escaped.push(b'\\');
+        }
+        escaped.push(b);
+    }
+    escaped
+}
+
+/// The data messages carrying `data` from `pos` in the stream, each as much
+/// as fits once escaped.
+pub(crate) fn data_messages(session: u32, mut pos: usize, data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let mut message = format!("/data/{session}/{pos}/").into_bytes();
+        let mut taken = 0;
+        for &b in rest {
+            let len = if b == b'/' || b == b'\\' { 2 } else { 1 };
+            // Leaving room for the closing `/`.
+            if message.len() + len + 1 >= MAX_LEN {
+                break;
+            }
+            message.extend(escape(&[b]));
+            taken += 1;
+        }
+        message.push(b'/');
+        messages.push(message);
+        pos += taken;
+        rest = &rest[taken..];
+    }
+    messages
+}
+
+#[cfg(test)]
+mod test {
+    use super::{data_messages, parse, Message, MAX_LEN};
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse(b"/connect/12345/"), Some(Message::Connect(12345)));
+        assert_eq!(
+            parse(b"/data/1/0/a\\/b\\\\c/"),
+            Some(Message::Data(1, 0, b"a/b\\c".to_vec()))
+        );
+        assert_eq!(parse(b"/data/1/0//"), Some(Message::Data(1, 0, vec![])));
+        assert_eq!(parse(b"/ack/1/5/"), Some(Message::Ack(1, 5)));
+        assert_eq!(parse(b"/close/1/"), Some(Message::Close(1)));
+        for invalid in [
+            &b"/close/1"[..],
+            b"close/1/",
+            b"/close/1/2/",
+            b"/data/1/0/a/b/",
+            b"/ack/1/2147483648/",
+            b"/ack/1/-1/",
+            b"/connect/x/",
+            b"/",
+            b"",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", String::from_utf8_lossy(invalid));
+        }
+    }
+
+    #[test]
+    fn data_splits_after_escaping() {
+        assert_eq!(data_messages(7, 3, b"a/b"), [b"/data/7/3/a\\/b/".to_vec()]);
+        for data in [vec![b'a'; 3000], vec![b'/'; 3000]] {
+            let mut received = vec![];
+            let messages = data_messages(7, 0, &data);
+            for (i, message) in messages.iter().enumerate() {
+                // Full but for the last, or an escape that didn't fit.
+                assert!(message.len() < MAX_LEN);
+                assert!(i == messages.len() - 1 || message.len() >= MAX_LEN - 2);
+                let Some(Message::Data(7, pos, data)) = parse(message) else {
+                    panic!("bad message {}", String::from_utf8_lossy(message));
+                };
+                assert_eq!(pos as usize, received.len());
+                received.extend(data);
+            }
+            assert_eq!(received, data);
+        }
+    }
+}
@@ -1,7 +1,65 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+
+/// The problems `serve` has a server for.
+const SERVERS: &str = "smoke|prime|bank|chat|kv|speed|lrcp|isl|jobs|vcs|pest";
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some("check") = args.first().map(String::as_str) {
+        let [_, problem, addr] = &args[..] else {
+            bail!(
+                "usage: protohackers check <{}> <addr>",
+                protohackers::check::PROBLEMS
+            );
+        };
+        if !protohackers::check::run(problem, addr).await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some("serve") = args.first().map(String::as_str) {
+        let usage = || format!("usage: protohackers serve <{SERVERS}>");
+        let [_, problem] = &args[..] else {
+            bail!(usage());
+        };
+        return match problem.as_str() {
+            "smoke" => protohackers::smoke::run().await,
+            "prime" => protohackers::prime_time::run().await,
+            "bank" => protohackers::bank::run().await,
+            "chat" => protohackers::budget_chat::run().await,
+            "lrcp" => protohackers::line_reversal::run().await,
+            "isl" => protohackers::insecure_sockets::run().await,
+            "jobs" => protohackers::job_centre::run().await,
+            "vcs" => protohackers::vcs::run().await,
+            "pest" => protohackers::pest_control::run().await,
+            "speed" => protohackers::speed_daemon::run().await,
+            "kv" => protohackers::unusual_database::run().await,
+            _ => bail!(usage()),
+        };
+    }
+    if let Some("client") = args.first().map(String::as_str) {
+        return protohackers::cli::run(&args[1..]).await;
+    }
+    if let Some("record") = args.first().map(String::as_str) {
+        let [_, listen, upstream, dir] = &args[..] else {
+            bail!("usage: protohackers record <listen addr> <server addr> <dir>");
+        };
+        return protohackers::record::run(listen, upstream, dir).await;
+    }
+    if let Some("replay") = args.first().map(String::as_str) {
+        let (timing, args) = match &args[1..] {
+            [flag, rest @ ..] if flag == "--timing" => (true, rest),
+            rest => (false, rest),
+        };
+        let [addr, path] = args else {
+            bail!("usage: protohackers replay [--timing] <addr> <capture file or dir>");
+        };
+        if !protohackers::replay::run(addr, path, timing).await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     protohackers::bank::run().await?;
     Ok(())
 }
@@ -0,0 +1,699 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::config::ADDR;
+
+use self::{
+    authority::{Authority, Rejected},
+    message::{Message, MessageCodec, Target},
+    policy::{Change, Counts, Policies},
+};
+
+mod authority;
+pub mod fake_authority;
+pub mod fuzz;
+mod message;
+mod policy;
+
+const AUTHORITY_ADDR: &str = "pestcontrol.protohackers.com:20547";
+/// Set to use a different Authority Server, e.g. `localhost:20547`.
+const AUTHORITY_VAR: &str = "PEST_CONTROL_AUTHORITY";
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+
+    let authority = std::env::var(AUTHORITY_VAR).unwrap_or(AUTHORITY_ADDR.to_string());
+    serve(listener, Arc::new(Sites::new(authority))).await
+}
+
+async fn serve(listener: TcpListener, sites: Arc<Sites>) -> Result<()> {
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        let sites = sites.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer, &sites).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn process<R, W>(reader: R, writer: W, sites: &Sites) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut messages = FramedRead::new(reader, MessageCodec);
+    let mut writer = FramedWrite::new(writer, MessageCodec);
+    // Our Hello goes out straight away, but nothing else is accepted until
+    // the client's Hello has arrived.
+    writer.send(Message::hello()).await?;
+    match messages.next().await {
+        Some(Ok(message)) => {
+            if let Err(e) = message.check_hello() {
+                return writer.send(Message::error(e)).await;
+            }
+        }
+        Some(Err(e)) => return writer.send(Message::error(e.to_string())).await,
+        None => return Ok(()),
+    }
+
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(Message::SiteVisit { site, populations }) => {
+                let counts = match policy::counts(&populations) {
+                    Ok(counts) => counts,
+                    Err(e) => return writer.send(Message::error(e)).await,
+                };
+                // The authority may take a while, or never answer, and the
+                // client has nothing to wait for. Its next message is read
+                // straight away.
+                sites.submit(site, &counts);
+            }
+            Ok(message) => {
+                let error = format!("unexpected {message:?}");
+                return writer.send(Message::error(error)).await;
+            }
+            Err(e) => return writer.send(Message::error(e.to_string())).await,
+        }
+    }
+    Ok(())
+}
+
+/// Every site visited so far, shared by all clients, so that each site has at
+/// most one connection to the authority.
+struct Sites {
+    authority: String,
+    sites: Mutex<HashMap<u32, mpsc::UnboundedSender<Visit>>>,
+}
+
+/// A visit for a site's actor to handle, and where to send the outcome. The
+/// actor logs a failed visit itself, so nobody need be waiting for it.
+struct Visit {
+    counts: HashMap<String, u32>,
+    done: oneshot::Sender<Result<()>>,
+}
+
+impl Sites {
+    fn new(authority: String) -> Sites {
+        Sites {
+            authority,
+            sites: Mutex::default(),
+        }
+    }
+
+    /// Brings the site's policies in line with what was observed, and waits
+    /// until it has. Clients don't wait, but tests do.
+    #[cfg(test)]
+    async fn visit(&self, site: u32, counts: &Counts<'_>) -> Result<()> {
+        self.submit(site, counts)
+            .await
+            .map_err(|_| anyhow::anyhow!("site {site} failed during the visit"))?
+    }
+
+    /// Hands a visit to the site's actor without waiting for it. Visits to a
+    /// site are handled in the order they're submitted.
+    fn submit(&self, site: u32, counts: &Counts<'_>) -> oneshot::Receiver<Result<()>> {
+        let (done, result) = oneshot::channel();
+        let counts = counts.iter().map(|(s, &c)| (s.to_string(), c)).collect();
+        let visit = Visit { counts, done };
+        {
+            let mut sites = self.sites.lock().unwrap();
+            let visits = sites
+                .entry(site)
+                .or_insert_with(|| Site::spawn(site, self.authority.clone()));
+            if let Err(mpsc::error::SendError(visit)) = visits.send(visit) {
+                // The actor died. Start a new one rather than losing the site
+                // for good; it will dial again and relearn the targets.
+                println!("Site {site}: restarting");
+                *visits = Site::spawn(site, self.authority.clone());
+                let _ = visits.send(visit);
+            }
+        }
+        result
+    }
+}
+
+struct Site {
+    id: u32,
+    /// Dialled on the first visit, and again whenever the connection is lost
+    /// or gets out of step.
+    authority: Option<Authority>,
+    targets: Vec<Target>,
+    policies: Policies,
+}
+
+impl Site {
+    fn new(id: u32) -> Site {
+        Site {
+            id,
+            authority: None,
+            targets: vec![],
+            policies: Policies::default(),
+        }
+    }
+
+    /// Starts the site's actor. All of a site's dealings with the authority
+    /// happen there, one visit at a time and in the order they were sent, so
+    /// concurrent visits from different clients can't interleave.
+    fn spawn(id: u32, addr: String) -> mpsc::UnboundedSender<Visit> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Visit>();
+        tokio::spawn(async move {
+            let mut site = Site::new(id);
+            while let Some(visit) = rx.recv().await {
+                let counts = visit.counts.iter().map(|(s, &c)| (s.as_str(), c)).collect();
+                let result = site.visit(&addr, &counts).await;
+                if let Err(e) = &result {
+                    println!("Site {id}: {e:?}");
+                }
+                // Nobody may be waiting; the visit still counts.
+                let _ = visit.done.send(result);
+            }
+        });
+        tx
+    }
+
+    /// Reconciles the site's policies with a visit. If the connection from an
+    /// earlier visit turns out to be gone, it's dialled again and the visit
+    /// retried, once. Failing to reach the authority fails the visit, but only
+    /// this site's.
+    async fn visit(&mut self, addr: &str, counts: &Counts<'_>) -> Result<()> {
+        let cached = self.authority.is_some();
+        let mut result = self.reconcile(addr, counts).await;
+        if let Err(e) = &result {
+            if cached && authority::is_lost(e) {
+                println!(
+                    "Site {}: lost the authority, dialling again: {e:?}",
+                    self.id
+                );
+                self.authority = None;
+                result = self.reconcile(addr, counts).await;
+            }
+        }
+        if result.is_err() {
+            // The connection may be gone, or out of step with us. Start
+            // afresh on the next visit.
+            self.authority = None;
+        }
+        result
+    }
+
+    async fn reconcile(&mut self, addr: &str, counts: &Counts<'_>) -> Result<()> {
+        // Targets are fetched when dialling and kept for as long as the
+        // connection lasts, so only a new connection refreshes them.
+        let authority = match &mut self.authority {
+            Some(authority) => authority,
+            None => {
+                let (authority, targets) = Authority::dial_with_retry(addr, self.id).await?;
+                self.targets = targets;
+                self.authority.insert(authority)
+            }
+        };
+        // Only forget a policy once it's deleted, so one that survives a
+        // failure is retried next time. If the authority refuses a change,
+        // carry on with the rest: a refused delete means the policy is gone
+        // already, and a refused create is tried again on the next visit.
+        for change in self.policies.plan(&self.targets, counts) {
+            match change {
+                Change::Delete { species, policy } => {
+                    let result = authority.delete_policy(policy).await;
+                    allow_rejection(self.id, result)?;
+                    self.policies.deleted(&species);
+                }
+                Change::Create { species, action } => {
+                    let result = authority.create_policy(&species, action).await;
+                    if let Some(policy) = allow_rejection(self.id, result)? {
+                        self.policies.created(species, policy, action);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logs an authority's refusal and carries on, passing other errors through.
+fn allow_rejection<T>(site: u32, result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.is::<Rejected>() => {
+            println!("Site {site}: {e}");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tokio_util::{bytes::BytesMut, codec::Encoder};
+
+    use super::{
+        fake_authority::{FakeAuthority, Fault},
+        message::{Action, Message, MessageCodec},
+        process, serve, Sites,
+    };
+    use crate::testutil::{chaos::Chaos, TestServer};
+
+    /// Waits for the authority to have `policies` for `site`.
+    async fn until_policies(authority: &FakeAuthority, site: u32, policies: &[(&str, Action)]) {
+        let policies: Vec<_> = policies
+            .iter()
+            .map(|&(species, action)| (species.to_string(), action))
+            .collect();
+        let start = Instant::now();
+        while authority.policies(site) != policies {
+            assert!(start.elapsed() < Duration::from_secs(5), "{policies:?}");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn encode(messages: &[Message]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        for message in messages {
+            MessageCodec.encode(message.clone(), &mut buf).unwrap();
+        }
+        buf.to_vec()
+    }
+
+    #[tokio::test]
+    async fn hello_first() {
+        let visit = Message::SiteVisit {
+            site: 1,
+            populations: vec![],
+        };
+        let hello = |protocol: &str, version| Message::Hello {
+            protocol: protocol.to_string(),
+            version,
+        };
+        let cases = [
+            (Message::Ok, "expected Hello, got Ok"),
+            (
+                visit,
+                "expected Hello, got SiteVisit { site: 1, populations: [] }",
+            ),
+            (hello("pestcontrol", 2), "unsupported version 2"),
+            (hello("pestcontrol", 0), "unsupported version 0"),
+            (hello("pest", 1), r#"unknown protocol "pest""#),
+        ];
+        for (first, error) in cases {
+            let reader = tokio_test::io::Builder::new()
+                .read(&encode(&[first, Message::hello()]))
+                .build();
+            let writer = tokio_test::io::Builder::new()
+                .write(&encode(&[Message::hello()]))
+                .write(&encode(&[Message::error(error)]))
+                .build();
+            let sites = Sites::new("127.0.0.1:0".to_string());
+            process(reader, writer, &sites).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn hello_must_be_valid() {
+        // A frame that fails to decode is reported just the same.
+        let reader = tokio_test::io::Builder::new()
+            .read(b"\x50\x00\x00\x00\x06\x00")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&encode(&[Message::hello()]))
+            .write(&encode(&[Message::error("bad checksum")]))
+            .build();
+        let sites = Sites::new("127.0.0.1:0".to_string());
+        process(reader, writer, &sites).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn second_hello() {
+        let reader = tokio_test::io::Builder::new()
+            .read(&encode(&[Message::hello(), Message::hello()]))
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&encode(&[Message::hello()]))
+            .write(&encode(&[Message::error(
+                r#"unexpected Hello { protocol: "pestcontrol", version: 1 }"#,
+            )]))
+            .build();
+        let sites = Sites::new("127.0.0.1:0".to_string());
+        process(reader, writer, &sites).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unexpected_message() {
+        let reader = tokio_test::io::Builder::new()
+            .read(&encode(&[
+                Message::hello(),
+                Message::PolicyResult { policy: 1 },
+            ]))
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&encode(&[Message::hello()]))
+            .write(&encode(&[Message::error(
+                "unexpected PolicyResult { policy: 1 }",
+            )]))
+            .build();
+        let sites = Sites::new("127.0.0.1:0".to_string());
+        process(reader, writer, &sites).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conflicting_counts() {
+        let visit = |populations: &[(&str, u32)]| Message::SiteVisit {
+            site: 1,
+            populations: populations
+                .iter()
+                .map(|&(species, count)| (species.to_string(), count))
+                .collect(),
+        };
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let reader = tokio_test::io::Builder::new()
+            .read(&encode(&[Message::hello()]))
+            .read(&encode(&[visit(&[("dog", 1), ("dog", 1)])]))
+            .read(&encode(&[visit(&[("dog", 1), ("cat", 2), ("dog", 3)])]))
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&encode(&[Message::hello()]))
+            .write(&encode(&[Message::error(
+                r#"conflicting counts for "dog": 1 and 3"#,
+            )]))
+            .build();
+        let sites = Sites::new(authority.addr.clone());
+        process(reader, writer, &sites).await.unwrap();
+        // Only the valid visit takes effect.
+        until_policies(&authority, 1, &[("dog", Action::Conserve)]).await;
+    }
+
+    #[tokio::test]
+    async fn visits_dont_wait_for_the_authority() {
+        let visit = Message::SiteVisit {
+            site: 1,
+            populations: vec![("dog".to_string(), 7)],
+        };
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        authority.set_delay(Duration::from_millis(500));
+        let reader = tokio_test::io::Builder::new()
+            .read(&encode(&[Message::hello(), visit]))
+            .read(&encode(&[Message::Ok]))
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(&encode(&[Message::hello()]))
+            .write(&encode(&[Message::error("unexpected Ok")]))
+            .build();
+        let sites = Sites::new(authority.addr.clone());
+
+        // The error goes out while the visit is still waiting on the dial,
+        // and the visit carries on once the client has gone.
+        let start = Instant::now();
+        process(reader, writer, &sites).await.unwrap();
+        assert!(
+            start.elapsed() < Duration::from_millis(250),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(authority.policies(1).is_empty());
+        until_policies(&authority, 1, &[("dog", Action::Cull)]).await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_visits() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Arc::new(Sites::new(authority.addr.clone()));
+        let visits: Vec<_> = (0..50)
+            .map(|i| {
+                let sites = sites.clone();
+                tokio::spawn(async move {
+                    let count = [0, 3, 9][i % 3];
+                    sites.visit(1, &HashMap::from([("dog", count)])).await
+                })
+            })
+            .collect();
+        for visit in visits {
+            visit.await.unwrap().unwrap();
+        }
+        // Whatever order they ran in, there's never more than one policy.
+        assert!(authority.policies(1).len() <= 1);
+        assert_eq!(authority.dials(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_changes() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Sites::new(authority.addr.clone());
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+
+        // The policy disappears from under us, so deleting it is refused.
+        authority.forget_policies(1);
+        sites.visit(1, &HashMap::from([("dog", 5)])).await.unwrap();
+        assert_eq!(authority.policies(1), [("dog".to_string(), Action::Cull)]);
+        assert_eq!(authority.dials(), 1);
+    }
+
+    #[tokio::test]
+    async fn site_failures_isolated() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Sites::new(authority.addr.clone());
+        // The authority knows nothing of site 2.
+        let err = sites.visit(2, &HashMap::new()).await.unwrap_err();
+        assert_eq!(err.to_string(), "authority error: no such site 2");
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+        assert_eq!(authority.policies(1).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_authority() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        authority.set_targets(2, &[("dog", 2, 4)]);
+        authority.set_delay(Duration::from_millis(100));
+        let sites = Sites::new(authority.addr.clone());
+
+        // Hello, targets and a new policy each wait, but sites wait in
+        // parallel.
+        let start = Instant::now();
+        let dog = HashMap::from([("dog", 1)]);
+        let (one, two) = tokio::join!(sites.visit(1, &dog), sites.visit(2, &dog));
+        one.unwrap();
+        two.unwrap();
+        assert!(
+            start.elapsed() < Duration::from_millis(550),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(authority.policies(1).len(), 1);
+        assert_eq!(authority.policies(2).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bogus_policy_ids() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        authority.set_bogus_policy_ids(true);
+        let sites = Sites::new(authority.addr.clone());
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+
+        // Deleting by the id we were given is refused. That can't be told
+        // apart from the policy having gone, so the real one is left behind,
+        // but the site carries on.
+        sites.visit(1, &HashMap::from([("dog", 5)])).await.unwrap();
+        assert_eq!(
+            authority.policies(1),
+            [
+                ("dog".to_string(), Action::Conserve),
+                ("dog".to_string(), Action::Cull)
+            ]
+        );
+        authority.set_bogus_policy_ids(false);
+        sites.visit(1, &HashMap::from([("dog", 3)])).await.unwrap();
+        assert_eq!(authority.dials(), 1);
+    }
+
+    #[tokio::test]
+    async fn scripted_faults() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        authority.script(&[Fault::Delay(Duration::from_millis(100)), Fault::BadChecksum]);
+        let sites = Sites::new(authority.addr.clone());
+
+        let start = Instant::now();
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(
+            authority.policies(1),
+            [("dog".to_string(), Action::Conserve)]
+        );
+
+        // The delete goes through, but its Ok is garbled, so the visit fails.
+        // The next one dials again and tries the delete again, which is
+        // refused as the policy is gone, and carries on.
+        assert!(sites.visit(1, &HashMap::from([("dog", 3)])).await.is_err());
+        assert!(authority.policies(1).is_empty());
+        sites.visit(1, &HashMap::from([("dog", 3)])).await.unwrap();
+        assert_eq!(authority.dials(), 2);
+        assert!(authority.policies(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn authority_closes_mid_session() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Sites::new(authority.addr.clone());
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+        assert_eq!(
+            authority.policies(1),
+            [("dog".to_string(), Action::Conserve)]
+        );
+
+        // It hangs up on the delete, so the visit dials again and deletes
+        // the policy over the new connection, which later visits keep using.
+        authority.script(&[Fault::Close]);
+        sites.visit(1, &HashMap::from([("dog", 3)])).await.unwrap();
+        assert_eq!(authority.dials(), 2);
+        assert!(authority.policies(1).is_empty());
+        sites.visit(1, &HashMap::from([("dog", 5)])).await.unwrap();
+        assert_eq!(authority.dials(), 2);
+        assert_eq!(authority.policies(1), [("dog".to_string(), Action::Cull)]);
+    }
+
+    #[tokio::test]
+    async fn targets_cached() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4), ("cat", 0, 1)]);
+        let sites = Sites::new(authority.addr.clone());
+
+        sites
+            .visit(1, &HashMap::from([("dog", 1), ("cat", 2)]))
+            .await
+            .unwrap();
+        sites
+            .visit(1, &HashMap::from([("dog", 1), ("cat", 3)]))
+            .await
+            .unwrap();
+        assert_eq!(authority.dials(), 1);
+        assert_eq!(
+            authority.policies(1),
+            [
+                ("cat".to_string(), Action::Cull),
+                ("dog".to_string(), Action::Conserve)
+            ]
+        );
+
+        // New targets only take effect once the connection is re-established,
+        // and policies for species no longer targeted are dropped.
+        authority.set_targets(1, &[("dog", 0, 0)]);
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+        assert_eq!(authority.dials(), 1);
+        authority.hang_up();
+        // Only noticed once there's something to tell the authority, and
+        // then the visit dials again and goes ahead with the new targets.
+        sites.visit(1, &HashMap::from([("dog", 1)])).await.unwrap();
+        assert_eq!(authority.dials(), 1);
+        sites.visit(1, &HashMap::from([("dog", 5)])).await.unwrap();
+        assert_eq!(authority.dials(), 2);
+        assert_eq!(authority.policies(1), [("dog".to_string(), Action::Cull)]);
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Arc::new(Sites::new(authority.addr.clone()));
+        let server = TestServer::start(|listener| serve(listener, sites)).await;
+        let mut client = server.connect().await;
+        let hello = encode(&[Message::hello()]);
+        client.send(&hello).await;
+        assert_eq!(client.recv_exact(hello.len()).await, hello);
+        let visit = Message::SiteVisit {
+            site: 1,
+            populations: vec![("dog".to_string(), 7)],
+        };
+        client.send(&encode(&[visit])).await;
+        until_policies(&authority, 1, &[("dog", Action::Cull)]).await;
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sites_shared_between_sockets() {
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Arc::new(Sites::new(authority.addr.clone()));
+        let server = TestServer::start(|listener| serve(listener, sites)).await;
+        let hello = encode(&[Message::hello()]);
+        let visit = |count| {
+            encode(&[Message::SiteVisit {
+                site: 1,
+                populations: vec![("dog".to_string(), count)],
+            }])
+        };
+
+        let mut first = server.connect().await;
+        first.send(&hello).await;
+        assert_eq!(first.recv_exact(hello.len()).await, hello);
+        first.send(&visit(7)).await;
+        until_policies(&authority, 1, &[("dog", Action::Cull)]).await;
+
+        // A visit from another client to the same site updates the policy the
+        // first one made.
+        let mut second = server.connect().await;
+        second.send(&hello).await;
+        assert_eq!(second.recv_exact(hello.len()).await, hello);
+        second.send(&visit(3)).await;
+        until_policies(&authority, 1, &[]).await;
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let authority = FakeAuthority::start().await;
+        let sites = Arc::new(Sites::new(authority.addr.clone()));
+        let server = TestServer::start(|listener| serve(listener, sites)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("pest", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn chaos() {
+        let visit = Message::SiteVisit {
+            site: 1,
+            populations: vec![("dog".to_string(), 7)],
+        };
+        let authority = FakeAuthority::start().await;
+        authority.set_targets(1, &[("dog", 2, 4)]);
+        let sites = Sites::new(authority.addr.clone());
+        for seed in 0..8 {
+            let reader = tokio_test::io::Builder::new()
+                .read(&encode(&[Message::hello(), visit.clone()]))
+                .build();
+            let writer = tokio_test::io::Builder::new()
+                .write(&encode(&[Message::hello()]))
+                .build();
+            let (reader, writer) = (Chaos::new(reader, seed), Chaos::new(writer, seed));
+            process(reader, writer, &sites).await.unwrap();
+        }
+        until_policies(&authority, 1, &[("dog", Action::Cull)]).await;
+    }
+}
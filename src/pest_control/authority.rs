@@ -0,0 +1,165 @@
+use std::{fmt, io, time::Duration};
+
+use anyhow::{bail, Result};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use super::message::{Action, Message, MessageCodec, Target};
+
+/// How many times to try dialling before giving up, and the delay before the
+/// first retry. The delay doubles after each failure.
+const DIAL_ATTEMPTS: u32 = 5;
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+
+/// An Error sent by the authority in reply to a request. Unlike other
+/// failures, it leaves the connection usable.
+#[derive(Debug)]
+pub(super) struct Rejected(String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authority error: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// The authority hung up.
+#[derive(Debug)]
+struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authority closed the connection")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+/// Whether an error means the connection is gone, rather than that the
+/// authority refused a request or sent something that made no sense.
+pub(super) fn is_lost(e: &anyhow::Error) -> bool {
+    e.is::<Closed>() || e.is::<io::Error>()
+}
+
+/// A connection to the Authority Server, dialled to one site.
+pub(super) struct Authority {
+    conn: Framed<TcpStream, MessageCodec>,
+}
+
+impl Authority {
+    /// Connects to the authority at `addr` and dials `site`, returning the
+    /// site's target populations.
+    pub(super) async fn dial(addr: &str, site: u32) -> Result<(Authority, Vec<Target>)> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut authority = Authority {
+            conn: Framed::new(stream, MessageCodec),
+        };
+        authority.conn.send(Message::hello()).await?;
+        if let Err(e) = authority.recv().await?.check_hello() {
+            authority.conn.send(Message::error(e.clone())).await?;
+            bail!("authority handshake: {e}");
+        }
+        authority.conn.send(Message::DialAuthority { site }).await?;
+        match authority.recv().await? {
+            Message::TargetPopulations {
+                site: target_site,
+                populations,
+            } if target_site == site => Ok((authority, populations)),
+            message => bail!("expected TargetPopulations for site {site}, got {message:?}"),
+        }
+    }
+
+    /// Like `dial`, retrying with backoff if the authority can't be reached.
+    pub(super) async fn dial_with_retry(addr: &str, site: u32) -> Result<(Authority, Vec<Target>)> {
+        let mut delay = FIRST_RETRY;
+        let mut attempt = 1;
+        loop {
+            match Authority::dial(addr, site).await {
+                Ok(dialled) => return Ok(dialled),
+                // Being refused won't change by asking again.
+                Err(e) if attempt < DIAL_ATTEMPTS && !e.is::<Rejected>() => {
+                    println!("Site {site}: dial failed, retrying in {delay:?}: {e:?}");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Creates a policy, returning its id.
+    pub(super) async fn create_policy(&mut self, species: &str, action: Action) -> Result<u32> {
+        let species = species.to_string();
+        self.conn
+            .send(Message::CreatePolicy { species, action })
+            .await?;
+        match self.recv().await? {
+            Message::PolicyResult { policy } => Ok(policy),
+            message => bail!("expected PolicyResult, got {message:?}"),
+        }
+    }
+
+    pub(super) async fn delete_policy(&mut self, policy: u32) -> Result<()> {
+        self.conn.send(Message::DeletePolicy { policy }).await?;
+        match self.recv().await? {
+            Message::Ok => Ok(()),
+            message => bail!("expected OK, got {message:?}"),
+        }
+    }
+
+    /// Reads the next message, turning an Error from the authority into an
+    /// error here.
+    async fn recv(&mut self) -> Result<Message> {
+        match self.conn.next().await {
+            Some(Ok(Message::Error { message })) => Err(Rejected(message).into()),
+            Some(message) => message,
+            None => Err(Closed.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    use super::{
+        super::message::{Message, MessageCodec, Target},
+        Authority,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_dial() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // Hang up on the first two attempts.
+            for _ in 0..2 {
+                drop(listener.accept().await.unwrap());
+            }
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Framed::new(stream, MessageCodec);
+            conn.send(Message::hello()).await.unwrap();
+            assert_eq!(conn.next().await.unwrap().unwrap(), Message::hello());
+            let dial = conn.next().await.unwrap().unwrap();
+            assert_eq!(dial, Message::DialAuthority { site: 7 });
+            let populations = vec![Target {
+                species: "dog".to_string(),
+                min: 1,
+                max: 2,
+            }];
+            let targets = Message::TargetPopulations {
+                site: 7,
+                populations,
+            };
+            conn.send(targets).await.unwrap();
+        });
+
+        let (_, targets) = Authority::dial_with_retry(&addr, 7).await.unwrap();
+        assert_eq!(targets[0].species, "dog");
+    }
+}
@@ -0,0 +1,235 @@
+//! A stand-in for the Authority Server, for testing without the real one.
+//! Targets are configured up front, policies are recorded so they can be
+//! checked, and faults can be injected: slow replies, bogus policy ids, and a
+//! script of faults for the replies to policy requests in turn.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Encoder, Framed},
+};
+
+pub use super::message::Action;
+use super::message::{Message, MessageCodec, Target};
+
+pub struct FakeAuthority {
+    pub addr: String,
+    state: Arc<Mutex<State>>,
+    hang_up: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct State {
+    targets: HashMap<u32, Vec<Target>>,
+    /// Live policies by site, then id.
+    policies: HashMap<u32, HashMap<u32, (String, Action)>>,
+    next_policy: u32,
+    dials: usize,
+    faults: Faults,
+    /// Faults for the coming replies to policy requests, from any site.
+    script: VecDeque<Fault>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Faults {
+    /// How long to wait before every reply.
+    delay: Duration,
+    /// Reply to CreatePolicy with an id that doesn't exist.
+    bogus_policy_ids: bool,
+}
+
+/// What to do to one reply to a CreatePolicy or DeletePolicy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Nothing: send it as it is.
+    None,
+    /// Send it with a checksum that doesn't add up.
+    BadChecksum,
+    /// Hold it back this long, on top of any delay for every reply.
+    Delay(Duration),
+    /// Hang up instead, without acting on the request.
+    Close,
+}
+
+impl FakeAuthority {
+    /// Starts serving on `addr`, in the background.
+    pub async fn bind(addr: &str) -> Result<FakeAuthority> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?.to_string();
+        let state = Arc::new(Mutex::new(State::default()));
+        let hang_up = Arc::new(Notify::new());
+        let (server_state, server_hang_up) = (state.clone(), hang_up.clone());
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let (state, hang_up) = (server_state.clone(), server_hang_up.clone());
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = serve(stream, &state) => {}
+                        _ = hang_up.notified() => {}
+                    }
+                });
+            }
+        });
+        Ok(FakeAuthority {
+            addr,
+            state,
+            hang_up,
+        })
+    }
+
+    /// Starts serving on a free local port.
+    pub async fn start() -> FakeAuthority {
+        FakeAuthority::bind("127.0.0.1:0").await.unwrap()
+    }
+
+    /// Sets a site's targets as `(species, min, max)`. Dialling a site
+    /// without targets is refused.
+    pub fn set_targets(&self, site: u32, targets: &[(&str, u32, u32)]) {
+        let targets = targets
+            .iter()
+            .map(|&(species, min, max)| Target {
+                species: species.to_string(),
+                min,
+                max,
+            })
+            .collect();
+        self.state.lock().unwrap().targets.insert(site, targets);
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().faults.delay = delay;
+    }
+
+    pub fn set_bogus_policy_ids(&self, bogus: bool) {
+        self.state.lock().unwrap().faults.bogus_policy_ids = bogus;
+    }
+
+    /// Sets the faults for the next replies to policy requests, one each, in
+    /// order. Replies after the last are sent as they are.
+    pub fn script(&self, faults: &[Fault]) {
+        self.state.lock().unwrap().script = faults.iter().copied().collect();
+    }
+
+    /// The live policies at a site, sorted by species and then by when they
+    /// were made.
+    pub fn policies(&self, site: u32) -> Vec<(String, Action)> {
+        let state = self.state.lock().unwrap();
+        let Some(policies) = state.policies.get(&site) else {
+            return vec![];
+        };
+        let mut policies: Vec<_> = policies.iter().collect();
+        policies.sort_by_key(|&(id, (species, _))| (species, id));
+        policies
+            .into_iter()
+            .map(|(_, policy)| policy.clone())
+            .collect()
+    }
+
+    /// Drops a site's policies without telling anyone.
+    pub fn forget_policies(&self, site: u32) {
+        self.state.lock().unwrap().policies.remove(&site);
+    }
+
+    /// How many times a site has been dialled, across all sites.
+    pub fn dials(&self) -> usize {
+        self.state.lock().unwrap().dials
+    }
+
+    /// Closes every open connection.
+    pub fn hang_up(&self) {
+        self.hang_up.notify_waiters();
+    }
+}
+
+async fn serve(stream: TcpStream, state: &Mutex<State>) -> Option<()> {
+    let mut conn = Framed::new(stream, MessageCodec);
+    let delay = state.lock().unwrap().faults.delay;
+    tokio::time::sleep(delay).await;
+    conn.send(Message::hello()).await.ok()?;
+    conn.next().await?.ok()?.check_hello().ok()?;
+    let Message::DialAuthority { site } = conn.next().await?.ok()? else {
+        return None;
+    };
+    let (populations, delay) = {
+        let mut state = state.lock().unwrap();
+        state.dials += 1;
+        (state.targets.get(&site).cloned(), state.faults.delay)
+    };
+    tokio::time::sleep(delay).await;
+    let Some(populations) = populations else {
+        let error = Message::error(format!("no such site {site}"));
+        return conn.send(error).await.ok();
+    };
+    let targets = Message::TargetPopulations { site, populations };
+    conn.send(targets).await.ok()?;
+
+    while let Some(Ok(message)) = conn.next().await {
+        let (reply, delay, fault) = {
+            let mut state = state.lock().unwrap();
+            let state = &mut *state;
+            let fault = state.script.pop_front().unwrap_or(Fault::None);
+            if fault == Fault::Close {
+                println!("Site {site}: hanging up");
+                return None;
+            }
+            let policies = state.policies.entry(site).or_default();
+            let reply = match message {
+                Message::CreatePolicy { species, action } => {
+                    state.next_policy += 1;
+                    println!(
+                        "Site {site}: policy {}: {action:?} {species}",
+                        state.next_policy
+                    );
+                    policies.insert(state.next_policy, (species, action));
+                    let policy = match state.faults.bogus_policy_ids {
+                        true => state.next_policy + 1_000_000,
+                        false => state.next_policy,
+                    };
+                    Message::PolicyResult { policy }
+                }
+                Message::DeletePolicy { policy } => match policies.remove(&policy) {
+                    Some(_) => {
+                        println!("Site {site}: policy {policy} deleted");
+                        Message::Ok
+                    }
+                    None => Message::error(format!("no such policy {policy}")),
+                },
+                message => Message::error(format!("unexpected {message:?}")),
+            };
+            (reply, state.faults.delay, fault)
+        };
+        tokio::time::sleep(delay).await;
+        match fault {
+            Fault::None => conn.send(reply).await.ok()?,
+            Fault::BadChecksum => {
+                let mut frame = BytesMut::new();
+                MessageCodec.encode(reply, &mut frame).ok()?;
+                let checksum = frame.len() - 1;
+                frame[checksum] = frame[checksum].wrapping_add(1);
+                println!("Site {site}: sending a bad checksum");
+                conn.get_mut().write_all(&frame).await.ok()?;
+            }
+            Fault::Delay(delay) => {
+                tokio::time::sleep(delay).await;
+                conn.send(reply).await.ok()?;
+            }
+            Fault::Close => unreachable!("hung up before acting"),
+        }
+    }
+    Some(())
+}
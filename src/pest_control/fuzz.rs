@@ -0,0 +1,53 @@
+//! The entry point for the `pest_control` fuzz target in `fuzz/`.
+
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, Encoder},
+};
+
+use crate::fuzz::{decode_all, setup};
+
+use super::message::MessageCodec;
+
+/// Decodes `data` into messages, panicking if anything misbehaves, or if a
+/// message doesn't survive being encoded and decoded again. The first byte
+/// sets the read size.
+pub fn messages(data: &[u8]) {
+    let Some((size, _, stream)) = setup(data) else {
+        return;
+    };
+    decode_all(MessageCodec, stream, size, |message| {
+        let mut encoded = BytesMut::new();
+        MessageCodec.encode(message.clone(), &mut encoded).unwrap();
+        let decoded = MessageCodec.decode(&mut encoded).unwrap();
+        assert_eq!(decoded, Some(message));
+        assert!(encoded.is_empty());
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_util::{bytes::BytesMut, codec::Encoder};
+
+    use super::{super::message::Message, messages};
+
+    #[test]
+    fn seeds() {
+        let mut stream = BytesMut::new();
+        let visit = Message::SiteVisit {
+            site: 12345,
+            populations: vec![("dog".to_string(), 3), ("rat".to_string(), 0)],
+        };
+        for message in [Message::hello(), visit, Message::Ok] {
+            super::MessageCodec.encode(message, &mut stream).unwrap();
+        }
+        // A bad checksum
+        stream.extend_from_slice(&[0x52, 0x00, 0x00, 0x00, 0x06, 0x00]);
+        messages(&[]);
+        for setup in 0..16 {
+            let mut data = vec![setup];
+            data.extend_from_slice(&stream);
+            messages(&data);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::{bail, Result};
+use tokio_util::{
+    bytes::{Buf, BufMut, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Message {
+    Hello {
+        protocol: String,
+        version: u32,
+    },
+    Error {
+        message: String,
+    },
+    Ok,
+    DialAuthority {
+        site: u32,
+    },
+    TargetPopulations {
+        site: u32,
+        populations: Vec<Target>,
+    },
+    CreatePolicy {
+        species: String,
+        action: Action,
+    },
+    DeletePolicy {
+        policy: u32,
+    },
+    PolicyResult {
+        policy: u32,
+    },
+    SiteVisit {
+        site: u32,
+        populations: Vec<(String, u32)>,
+    },
+}
+
+/// The population range a site should keep a species within.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Target {
+    pub(super) species: String,
+    pub(super) min: u32,
+    pub(super) max: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Cull,
+    Conserve,
+}
+
+impl Message {
+    pub(super) fn hello() -> Message {
+        Message::Hello {
+            protocol: "pestcontrol".to_string(),
+            version: 1,
+        }
+    }
+
+    /// Checks that this, the first message from a peer, is a Hello for the
+    /// protocol and version spoken here.
+    pub(super) fn check_hello(&self) -> Result<(), String> {
+        match self {
+            Message::Hello { protocol, .. } if protocol != "pestcontrol" => {
+                Err(format!("unknown protocol {protocol:?}"))
+            }
+            Message::Hello { version: 1, .. } => Ok(()),
+            Message::Hello { version, .. } => Err(format!("unsupported version {version}")),
+            message => Err(format!("expected Hello, got {message:?}")),
+        }
+    }
+
+    pub(super) fn error(message: impl Into<String>) -> Message {
+        Message::Error {
+            message: message.into(),
+        }
+    }
+}
+
+/// Largest frame accepted, so a bogus length can't make us buffer forever.
+const MAX_LENGTH: usize = 1024 * 1024;
+
+/// Frames are a type byte, the u32 length of the whole frame, the content, and
+/// a checksum byte that makes all the bytes of the frame sum to zero.
+pub(super) struct MessageCodec;
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < 5 {
+            // Not enough data
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes(src[1..5].try_into().unwrap()) as usize;
+        if !(6..=MAX_LENGTH).contains(&length) {
+            bail!("bad message length {length}");
+        }
+        if src.len() < length {
+            src.reserve(length - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(length);
+        if frame.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            bail!("bad checksum");
+        }
+        let mut content = Content(&frame[5..length - 1]);
+        let message = match frame[0] {
+            0x50 => Message::Hello {
+                protocol: content.string()?,
+                version: content.u32()?,
+            },
+            0x51 => Message::Error {
+                message: content.string()?,
+            },
+            0x52 => Message::Ok,
+            0x53 => Message::DialAuthority {
+                site: content.u32()?,
+            },
+            0x54 => Message::TargetPopulations {
+                site: content.u32()?,
+                populations: content.array(|c| {
+                    Ok(Target {
+                        species: c.string()?,
+                        min: c.u32()?,
+                        max: c.u32()?,
+                    })
+                })?,
+            },
+            0x55 => Message::CreatePolicy {
+                species: content.string()?,
+                action: match content.u8()? {
+                    0x90 => Action::Cull,
+                    0xa0 => Action::Conserve,
+                    action => bail!("unknown action 0x{action:02x}"),
+                },
+            },
+            0x56 => Message::DeletePolicy {
+                policy: content.u32()?,
+            },
+            0x57 => Message::PolicyResult {
+                policy: content.u32()?,
+            },
+            0x58 => Message::SiteVisit {
+                site: content.u32()?,
+                populations: content.array(|c| Ok((c.string()?, c.u32()?)))?,
+            },
+            kind => bail!("unknown message type 0x{kind:02x}"),
+        };
+        if !content.0.is_empty() {
+            bail!("message content too long");
+        }
+        Ok(Some(message))
+    }
+}
+
+/// The content of a frame, read front to back.
+struct Content<'a>(&'a [u8]);
+
+impl Content<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        if self.0.is_empty() {
+            bail!("message content too short");
+        }
+        Ok(self.0.get_u8())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        if self.0.len() < 4 {
+            bail!("message content too short");
+        }
+        Ok(self.0.get_u32())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.u32()? as usize;
+        if self.0.len() < length {
+            bail!("message content too short");
+        }
+        let (string, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(String::from_utf8_lossy(string).into_owned())
+    }
+
+    fn array<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let count = self.u32()?;
+        // Every item takes at least a byte, so the count can be checked
+        // before allocating for it.
+        if count as usize > self.0.len() {
+            bail!("message content too short");
+        }
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        let kind = match &message {
+            Message::Hello { .. } => 0x50,
+            Message::Error { .. } => 0x51,
+            Message::Ok => 0x52,
+            Message::DialAuthority { .. } => 0x53,
+            Message::TargetPopulations { .. } => 0x54,
+            Message::CreatePolicy { .. } => 0x55,
+            Message::DeletePolicy { .. } => 0x56,
+            Message::PolicyResult { .. } => 0x57,
+            Message::SiteVisit { .. } => 0x58,
+        };
+        dst.put_u8(kind);
+        // Filled in once the content is written.
+        dst.put_u32(0);
+        match message {
+            Message::Hello { protocol, version } => {
+                put_string(dst, &protocol);
+                dst.put_u32(version);
+            }
+            Message::Error { message } => put_string(dst, &message),
+            Message::Ok => {}
+            Message::DialAuthority { site } => dst.put_u32(site),
+            Message::TargetPopulations { site, populations } => {
+                dst.put_u32(site);
+                dst.put_u32(populations.len() as u32);
+                for target in populations {
+                    put_string(dst, &target.species);
+                    dst.put_u32(target.min);
+                    dst.put_u32(target.max);
+                }
+            }
+            Message::CreatePolicy { species, action } => {
+                put_string(dst, &species);
+                dst.put_u8(match action {
+                    Action::Cull => 0x90,
+                    Action::Conserve => 0xa0,
+                });
+            }
+            Message::DeletePolicy { policy } => dst.put_u32(policy),
+            Message::PolicyResult { policy } => dst.put_u32(policy),
+            Message::SiteVisit { site, populations } => {
+                dst.put_u32(site);
+                dst.put_u32(populations.len() as u32);
+                for (species, count) in populations {
+                    put_string(dst, &species);
+                    dst.put_u32(count);
+                }
+            }
+        }
+        let length = dst.len() - start + 1;
+        dst[start + 1..start + 5].copy_from_slice(&(length as u32).to_be_bytes());
+        let sum = dst[start..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        dst.put_u8(sum.wrapping_neg());
+        Ok(())
+    }
+}
+
+fn put_string(dst: &mut BytesMut, string: &str) {
+    dst.put_u32(string.len() as u32);
+    dst.put_slice(string.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_util::{
+        bytes::BytesMut,
+        codec::{Decoder, Encoder},
+    };
+
+    use proptest::prelude::*;
+
+    use crate::testutil::{decode_chunks, split_at};
+
+    use super::{Action, Message, MessageCodec, Target};
+
+    fn decode(bytes: &[u8]) -> Result<Option<Message>, String> {
+        let mut buf = BytesMut::from(bytes);
+        MessageCodec.decode(&mut buf).map_err(|e| e.to_string())
+    }
+
+    /// Builds a frame around the content, with the right length and checksum.
+    fn frame(kind: u8, content: &[u8]) -> Vec<u8> {
+        let mut frame = vec![kind];
+        frame.extend_from_slice(&(content.len() as u32 + 6).to_be_bytes());
+        frame.extend_from_slice(content);
+        let sum = frame.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        frame.push(sum.wrapping_neg());
+        frame
+    }
+
+    #[test]
+    fn hello() {
+        // The example from the problem statement.
+        let bytes = b"\x50\x00\x00\x00\x19\x00\x00\x00\x0bpestcontrol\x00\x00\x00\x01\xce";
+        let mut encoded = BytesMut::new();
+        MessageCodec.encode(Message::hello(), &mut encoded).unwrap();
+        assert_eq!(&encoded[..], bytes);
+
+        let mut buf = BytesMut::from(&bytes[..10]);
+        assert_eq!(MessageCodec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&bytes[10..]);
+        assert_eq!(
+            MessageCodec.decode(&mut buf).unwrap(),
+            Some(Message::hello())
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn site_visit() {
+        let bytes = b"\x58\x00\x00\x00\x24\x00\x00\x30\x39\x00\x00\x00\x02\x00\x00\x00\x03dog\
+            \x00\x00\x00\x01\x00\x00\x00\x03rat\x00\x00\x00\x05\x8c";
+        let mut buf = BytesMut::from(&bytes[..]);
+        assert_eq!(
+            MessageCodec.decode(&mut buf).unwrap(),
+            Some(Message::SiteVisit {
+                site: 12345,
+                populations: vec![("dog".to_string(), 1), ("rat".to_string(), 5)],
+            })
+        );
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut buf = BytesMut::from(&b"\x52\x00\x00\x00\x06\x00"[..]);
+        assert_eq!(
+            MessageCodec.decode(&mut buf).unwrap_err().to_string(),
+            "bad checksum"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = [
+            Message::hello(),
+            Message::error("bad"),
+            Message::Ok,
+            Message::DialAuthority { site: 12345 },
+            Message::TargetPopulations {
+                site: 12345,
+                populations: vec![
+                    Target {
+                        species: "dog".to_string(),
+                        min: 1,
+                        max: 3,
+                    },
+                    Target {
+                        species: "rat".to_string(),
+                        min: 0,
+                        max: 10,
+                    },
+                ],
+            },
+            Message::CreatePolicy {
+                species: "dog".to_string(),
+                action: Action::Conserve,
+            },
+            Message::CreatePolicy {
+                species: "rat".to_string(),
+                action: Action::Cull,
+            },
+            Message::DeletePolicy { policy: 123 },
+            Message::PolicyResult { policy: 123 },
+            Message::SiteVisit {
+                site: 1,
+                populations: vec![],
+            },
+        ];
+        let mut buf = BytesMut::new();
+        for message in &messages {
+            MessageCodec.encode(message.clone(), &mut buf).unwrap();
+        }
+        for message in messages {
+            assert_eq!(MessageCodec.decode(&mut buf).unwrap(), Some(message));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [
+            (frame(0x52, b"\0"), "message content too long"),
+            (frame(0x53, b"\0\0\0"), "message content too short"),
+            (frame(0x53, b"\0\0\0\0\0"), "message content too long"),
+            (frame(0x51, b"\0\0\0\x05abc"), "message content too short"),
+            (frame(0x51, b"\0\0\0\x02abc"), "message content too long"),
+            // An array claiming more items than could fit.
+            (
+                frame(0x58, b"\0\0\0\x01\xff\xff\xff\xff"),
+                "message content too short",
+            ),
+            (frame(0x55, b"\0\0\0\0\x91"), "unknown action 0x91"),
+            (frame(0x60, b""), "unknown message type 0x60"),
+            (b"\x52\0\0\0\x05\xa9".to_vec(), "bad message length 5"),
+            (b"\x52\x7f\0\0\0".to_vec(), "bad message length 2130706432"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(decode(&bytes), Err(expected.to_string()), "{bytes:x?}");
+        }
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        let string = || "[a-z ]{0,12}";
+        let action = prop_oneof![Just(Action::Cull), Just(Action::Conserve)];
+        let target = (string(), any::<u32>(), any::<u32>())
+            .prop_map(|(species, min, max)| Target { species, min, max });
+        prop_oneof![
+            (string(), any::<u32>())
+                .prop_map(|(protocol, version)| Message::Hello { protocol, version }),
+            string().prop_map(|message| Message::Error { message }),
+            Just(Message::Ok),
+            any::<u32>().prop_map(|site| Message::DialAuthority { site }),
+            (any::<u32>(), prop::collection::vec(target, 0..4))
+                .prop_map(|(site, populations)| Message::TargetPopulations { site, populations }),
+            (string(), action)
+                .prop_map(|(species, action)| Message::CreatePolicy { species, action }),
+            any::<u32>().prop_map(|policy| Message::DeletePolicy { policy }),
+            any::<u32>().prop_map(|policy| Message::PolicyResult { policy }),
+            (
+                any::<u32>(),
+                prop::collection::vec((string(), any::<u32>()), 0..4)
+            )
+                .prop_map(|(site, populations)| Message::SiteVisit { site, populations }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn split_round_trip(
+            messages in prop::collection::vec(message(), 0..8),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let mut stream = BytesMut::new();
+            for message in &messages {
+                MessageCodec.encode(message.clone(), &mut stream).unwrap();
+            }
+            let decoded = decode_chunks(MessageCodec, &split_at(&stream, &cuts)).unwrap();
+            prop_assert_eq!(decoded, messages);
+        }
+    }
+}
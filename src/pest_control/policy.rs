@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use super::message::{Action, Target};
+
+/// The count seen of each species on a visit.
+pub(super) type Counts<'a> = HashMap<&'a str, u32>;
+
+/// Collects a visit's counts. A species may be listed more than once, but
+/// only with the same count each time.
+pub(super) fn counts(populations: &[(String, u32)]) -> Result<Counts<'_>, String> {
+    let mut counts = Counts::new();
+    for (species, count) in populations {
+        match counts.insert(species, *count) {
+            Some(previous) if previous != *count => {
+                return Err(format!(
+                    "conflicting counts for {species:?}: {previous} and {count}"
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(counts)
+}
+
+/// The policy in force for each species at a site, as far as we know. A
+/// species has at most one, so a policy is never created twice.
+#[derive(Default)]
+pub(super) struct Policies(HashMap<String, (u32, Action)>);
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Change {
+    Delete { species: String, policy: u32 },
+    Create { species: String, action: Action },
+}
+
+impl Policies {
+    /// Works out what to tell the authority to bring the policies in line with
+    /// the counts from a visit. Species without a count have a count of zero.
+    /// Policies for species that are no longer targeted are deleted first.
+    /// Otherwise a species' old policy is deleted just before its new one is
+    /// created.
+    pub(super) fn plan(&self, targets: &[Target], counts: &Counts) -> Vec<Change> {
+        let mut stale: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(species, _)| !targets.iter().any(|t| &&t.species == species))
+            .map(|(species, &(policy, _))| (policy, species))
+            .collect();
+        // By policy id, rather than in hash order.
+        stale.sort();
+        let mut changes: Vec<_> = stale
+            .into_iter()
+            .map(|(policy, species)| Change::Delete {
+                species: species.clone(),
+                policy,
+            })
+            .collect();
+        for target in targets {
+            let count = counts.get(target.species.as_str()).copied().unwrap_or(0);
+            let action = if count < target.min {
+                Some(Action::Conserve)
+            } else if count > target.max {
+                Some(Action::Cull)
+            } else {
+                None
+            };
+            let current = self.0.get(&target.species);
+            if current.map(|&(_, a)| a) == action {
+                continue;
+            }
+            if let Some(&(policy, _)) = current {
+                changes.push(Change::Delete {
+                    species: target.species.clone(),
+                    policy,
+                });
+            }
+            if let Some(action) = action {
+                changes.push(Change::Create {
+                    species: target.species.clone(),
+                    action,
+                });
+            }
+        }
+        changes
+    }
+
+    pub(super) fn created(&mut self, species: String, policy: u32, action: Action) {
+        self.0.insert(species, (policy, action));
+    }
+
+    pub(super) fn deleted(&mut self, species: &str) {
+        self.0.remove(species);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{
+        super::message::{Action, Target},
+        counts, Change, Policies,
+    };
+
+    fn delete(species: &str, policy: u32) -> Change {
+        Change::Delete {
+            species: species.to_string(),
+            policy,
+        }
+    }
+
+    fn create(species: &str, action: Action) -> Change {
+        Change::Create {
+            species: species.to_string(),
+            action,
+        }
+    }
+
+    #[test]
+    fn plan() {
+        let targets: Vec<_> = [("dog", 2, 4), ("cat", 0, 1), ("rat", 0, 0)]
+            .into_iter()
+            .map(|(species, min, max)| Target {
+                species: species.to_string(),
+                min,
+                max,
+            })
+            .collect();
+        let mut policies = Policies::default();
+        policies.created("dog".to_string(), 1, Action::Conserve);
+        policies.created("cat".to_string(), 2, Action::Cull);
+        policies.created("owl".to_string(), 4, Action::Cull);
+        policies.created("bat".to_string(), 3, Action::Conserve);
+
+        // (counts, changes)
+        let cases = [
+            (
+                vec![("dog", 0), ("cat", 5)],
+                vec![delete("bat", 3), delete("owl", 4)],
+            ),
+            (
+                vec![("dog", 3), ("rat", 1), ("elk", 9)],
+                vec![
+                    delete("bat", 3),
+                    delete("owl", 4),
+                    delete("dog", 1),
+                    delete("cat", 2),
+                    create("rat", Action::Cull),
+                ],
+            ),
+            (
+                vec![("dog", 5), ("cat", 1)],
+                vec![
+                    delete("bat", 3),
+                    delete("owl", 4),
+                    delete("dog", 1),
+                    create("dog", Action::Cull),
+                    delete("cat", 2),
+                ],
+            ),
+        ];
+        for (counts, changes) in cases {
+            let counts: HashMap<_, _> = counts.iter().copied().collect();
+            assert_eq!(policies.plan(&targets, &counts), changes, "{counts:?}");
+        }
+
+        let counts = HashMap::from([("dog", 0), ("cat", 5)]);
+        policies.deleted("owl");
+        policies.deleted("bat");
+        assert_eq!(policies.plan(&targets, &counts), []);
+        assert_eq!(
+            policies.plan(&[], &counts),
+            [delete("dog", 1), delete("cat", 2)]
+        );
+    }
+
+    #[test]
+    fn visit_counts() {
+        let visit = |populations: &[(&str, u32)]| -> Vec<(String, u32)> {
+            populations
+                .iter()
+                .map(|&(species, count)| (species.to_string(), count))
+                .collect()
+        };
+
+        let populations = visit(&[("dog", 1), ("cat", 2), ("dog", 1)]);
+        assert_eq!(
+            counts(&populations),
+            Ok(HashMap::from([("dog", 1), ("cat", 2)]))
+        );
+        assert_eq!(counts(&[]), Ok(HashMap::new()));
+        assert_eq!(
+            counts(&visit(&[("dog", 1), ("cat", 2), ("dog", 0)])),
+            Err(r#"conflicting counts for "dog": 1 and 0"#.to_string())
+        );
+    }
+
+    #[test]
+    fn absent_species_count_zero() {
+        let targets = [Target {
+            species: "dog".to_string(),
+            min: 1,
+            max: 2,
+        }];
+        let counts = HashMap::from([("cat", 5)]);
+        assert_eq!(
+            Policies::default().plan(&targets, &counts),
+            [create("dog", Action::Conserve)]
+        );
+    }
+}
@@ -1,95 +1,356 @@
-use anyhow::Result;
+use std::{borrow::Cow, sync::Arc};
+
+use anyhow::{Context, Result};
+use futures::{
+    future::{self, BoxFuture},
+    stream::FuturesOrdered,
+    FutureExt, StreamExt,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpListener,
+    sync::Semaphore,
 };
+use tokio_util::codec::FramedRead;
 
 use crate::config::ADDR;
 
+use self::{cache::Cache, framing::Framing};
+
+pub mod bench;
+mod cache;
+mod framing;
+pub mod fuzz;
+mod number;
+mod prime;
+
+/// Set to override the longest request line accepted, in bytes.
+const MAX_LINE_VAR: &str = "PRIME_TIME_MAX_LINE";
+/// Set to send this for every malformed request, rather than echoing it.
+const MALFORMED_VAR: &str = "PRIME_TIME_MALFORMED";
+/// Set to change how many numbers the shared cache holds, or to 0 to disable it.
+const CACHE_VAR: &str = "PRIME_TIME_CACHE";
+/// Set to accept an array of numbers in a request, answered with an array.
+const BATCH_VAR: &str = "PRIME_TIME_BATCH";
+/// Set to take each JSON value as a request, rather than each line.
+const VALUES_VAR: &str = "PRIME_TIME_VALUES";
+
+/// The malformed response when the request can't be echoed.
+const MALFORMED: &str = "malformed";
+/// How many requests a connection may have waiting for their answers before
+/// no more are read.
+const MAX_PENDING: usize = 1024;
+
+struct Config {
+    /// Longer lines, or values, are malformed requests, and aren't buffered.
+    max_line: usize,
+    /// The malformed response, if not the request itself.
+    malformed: Option<String>,
+    cache_size: usize,
+    batch: bool,
+    /// Whether requests are framed by JSON values rather than by lines.
+    values: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_line: 1 << 20,
+            malformed: None,
+            cache_size: 10_000,
+            batch: false,
+            values: false,
+        }
+    }
+}
+
+impl Config {
+    fn from_env() -> Result<Config> {
+        let mut config = Config::default();
+        if let Ok(value) = std::env::var(MAX_LINE_VAR) {
+            config.max_line = value
+                .parse()
+                .with_context(|| format!("{MAX_LINE_VAR} must be a number of bytes"))?;
+        }
+        config.malformed = std::env::var(MALFORMED_VAR).ok();
+        if let Ok(value) = std::env::var(CACHE_VAR) {
+            config.cache_size = value
+                .parse()
+                .with_context(|| format!("{CACHE_VAR} must be a number of entries"))?;
+        }
+        config.batch = std::env::var_os(BATCH_VAR).is_some();
+        config.values = std::env::var_os(VALUES_VAR).is_some();
+        Ok(config)
+    }
+}
+
+/// `method` borrows from the line unless it has escapes, like
+/// `"is\u0050rime"`, that have to be decoded.
 #[derive(Deserialize)]
 struct Request<'a> {
-    method: &'a str,
-    number: f64,
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    /// Kept as text, since an f64 would lose the digits of a big integer.
+    #[serde(borrow)]
+    number: &'a RawValue,
 }
 
-#[derive(Serialize)]
-struct Response<'a> {
-    method: &'a str,
-    prime: bool,
+/// `prime` is an array of them for a batch.
+#[derive(Serialize, Deserialize)]
+struct Response<'a, P = bool> {
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    prime: P,
+}
+
+/// What's shared by every connection.
+struct Server {
+    config: Config,
+    cache: Cache,
+    /// Bounds the checks of big numbers running at once, each on a blocking
+    /// thread.
+    checks: Arc<Semaphore>,
+}
+
+impl Server {
+    fn new(config: Config) -> Server {
+        let cache = Cache::new(config.cache_size);
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let checks = Arc::new(Semaphore::new(threads));
+        Server {
+            config,
+            cache,
+            checks,
+        }
+    }
+}
+
+/// The outcome of a request, in the order they came.
+enum Answer {
+    Prime(bool),
+    Batch(Vec<bool>),
+    /// The request line, if it could be read.
+    Malformed(Option<String>),
 }
 
 pub async fn run() -> Result<()> {
     let listener = TcpListener::bind(ADDR).await.unwrap();
     println!("Listening on {ADDR}...");
 
+    let server = Arc::new(Server::new(Config::from_env()?));
+    prime::init();
+    serve(listener, server).await
+}
+
+async fn serve(listener: TcpListener, server: Arc<Server>) -> Result<()> {
     loop {
         let (mut socket, addr) = listener.accept().await?;
         println!("Connected to {addr}");
+        let server = server.clone();
         tokio::spawn(async move {
             let (reader, writer) = socket.split();
-            process(reader, writer).await.unwrap()
+            if let Err(e) = process(reader, writer, &server).await {
+                println!("{addr}: {e:?}");
+            }
+            if let Some(rate) = server.cache.hit_rate() {
+                println!("{addr}: closed, cache hit rate {:.1}%", rate * 100.0);
+            }
         });
     }
 }
 
-async fn process<R, W>(reader: R, mut writer: W) -> Result<()>
+/// Answers requests as they're read, with checks of big numbers running
+/// alongside each other and further reading, so that small numbers behind a
+/// big one are ready when it is. Responses still go out in request order.
+async fn process<R, W>(reader: R, mut writer: W, server: &Arc<Server>) -> Result<()>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let mut lines = BufReader::new(reader).lines();
-    while let Some(line) = lines.next_line().await? {
-        match serde_json::from_str::<Request>(&line) {
-            Ok(req) => {
-                let method = req.method;
-                if method != "isPrime" {
-                    malformed_response(writer).await?;
-                    return Ok(());
-                }
-                let prime = if req.number.fract() == 0.0 {
-                    is_prime(req.number as i64)
-                } else {
-                    false
+    let framing = Framing::new(server.config.values, server.config.max_line);
+    let mut lines = FramedRead::new(reader, framing);
+    let mut answers = FuturesOrdered::new();
+    let mut reading = true;
+    loop {
+        tokio::select! {
+            line = lines.next(), if reading && answers.len() < MAX_PENDING => {
+                let answer = match line {
+                    Some(Ok(line)) => match answer(&line, server) {
+                        Some(answer) => answer,
+                        None => {
+                            // Nothing after this gets an answer.
+                            reading = false;
+                            future::ready(Answer::Malformed(Some(line))).boxed()
+                        }
+                    },
+                    Some(Err(e)) => {
+                        // Too long, not UTF-8, or not JSON when framing by values
+                        println!("Bad request: {e}");
+                        reading = false;
+                        future::ready(Answer::Malformed(None)).boxed()
+                    }
+                    None => {
+                        reading = false;
+                        continue;
+                    }
                 };
-                let res = Response { method, prime };
-                let res = serde_json::to_string(&res)?;
-                writer.write(res.as_bytes()).await?;
-                writer.write_u8(b'\n').await?;
-            }
-            Err(_) => {
-                malformed_response(writer).await?;
-                return Ok(());
+                answers.push_back(answer);
             }
+            Some(answer) = answers.next() => match answer {
+                Answer::Prime(prime) => writer.write_all(&response(prime)?).await?,
+                Answer::Batch(primes) => writer.write_all(&response(primes)?).await?,
+                Answer::Malformed(line) => {
+                    return malformed_response(writer, &server.config, line.as_deref()).await;
+                }
+            },
+            else => return Ok(()),
         }
     }
-    Ok(())
 }
 
-fn is_prime(num: i64) -> bool {
-    if num < 2 {
-        return false;
+/// Answers a request, or `None` if it's malformed. The answer is ready straight
+/// away unless it's for a number beyond u64, which is checked on a blocking
+/// thread.
+fn answer(line: &str, server: &Arc<Server>) -> Option<BoxFuture<'static, Answer>> {
+    let (numbers, batch) = parse(line, server.config.batch)?;
+    let candidates: Vec<_> = numbers.into_iter().map(number::candidate).collect();
+    let answer = move |primes: Vec<bool>| match batch {
+        true => Answer::Batch(primes),
+        false => Answer::Prime(primes[0]),
+    };
+    if candidates.iter().flatten().all(|num| num.bits() <= 64) {
+        let primes = candidates
+            .iter()
+            .map(|num| num.as_ref().is_some_and(prime::is_prime));
+        return Some(future::ready(answer(primes.collect())).boxed());
     }
-    for n in 2..=((num as f64).sqrt().floor() as i64) {
-        if num % n == 0 {
-            return false;
-        }
+    let server = server.clone();
+    let answer = async move {
+        let _permit = server.checks.clone().acquire_owned().await.unwrap();
+        let primes = tokio::task::spawn_blocking(move || {
+            let check = |num: &_| server.cache.get(num, prime::is_prime);
+            candidates
+                .iter()
+                .map(|num| num.as_ref().is_some_and(check))
+                .collect()
+        });
+        answer(primes.await.unwrap())
+    };
+    Some(answer.boxed())
+}
+
+/// The numbers in a request, and whether they're a batch, or `None` if it's
+/// malformed. Without `batch`, a request has exactly one number.
+fn parse(line: &str, batch: bool) -> Option<(Vec<&str>, bool)> {
+    // serde would also take the fields from an array, in order.
+    if !line.trim_start().starts_with('{') {
+        return None;
     }
-    true
+    let req = serde_json::from_str::<Request>(line).ok()?;
+    let number = req.number.get();
+    if req.method != "isPrime" {
+        return None;
+    }
+    if is_number(number) {
+        return Some((vec![number], false));
+    }
+    if !batch {
+        return None;
+    }
+    let numbers: Vec<&RawValue> = serde_json::from_str(number).ok()?;
+    let numbers: Vec<_> = numbers.into_iter().map(RawValue::get).collect();
+    numbers
+        .iter()
+        .all(|n| is_number(n))
+        .then_some((numbers, true))
 }
 
-async fn malformed_response<W>(mut writer: W) -> Result<()>
+/// A response line.
+fn response(prime: impl Serialize) -> Result<Vec<u8>> {
+    let res = Response {
+        method: "isPrime".into(),
+        prime,
+    };
+    let mut res = serde_json::to_vec(&res)?;
+    res.push(b'\n');
+    Ok(res)
+}
+
+/// Whether a JSON value, valid as it's been parsed, is a number.
+fn is_number(value: &str) -> bool {
+    value.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+}
+
+/// Sends the one malformed response a client gets, and hangs up.
+///
+/// Unless a payload is configured, that's the `line` the client sent, which
+/// can't be mistaken for a response, being a malformed request. The exception
+/// is a request that happens to be a well-formed response, like
+/// `{"method":"isPrime","prime":true}`, so that gets the fallback, as does a
+/// line that can't be echoed because it was too long or not UTF-8.
+async fn malformed_response<W>(mut writer: W, config: &Config, line: Option<&str>) -> Result<()>
 where
     W: AsyncWrite + Unpin,
 {
-    writer.write(b"Malformed Response").await?;
+    let payload = match (&config.malformed, line) {
+        (Some(payload), _) => payload.as_str(),
+        (None, Some(line)) if !is_response(line) => line,
+        (None, _) => MALFORMED,
+    };
+    writer.write_all(format!("{payload}\n").as_bytes()).await?;
     writer.shutdown().await?;
     Ok(())
 }
 
+/// Whether a line is a well-formed response.
+fn is_response(line: &str) -> bool {
+    serde_json::from_str::<Response>(line).is_ok_and(|res| res.method == "isPrime")
+}
+
 #[cfg(test)]
 mod test {
-    use super::process;
+    use std::{
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::AsyncWrite;
+
+    use crate::{
+        clients::prime_time::Client,
+        testutil::{duplex, transcript::replay_dir, TestServer},
+    };
+
+    use super::{process, serve, Config, Server};
+
+    /// Records each write, taking no more than `max` bytes of it.
+    struct Writes {
+        max: usize,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = buf.len().min(self.max);
+            self.writes.push(buf[..n].to_vec());
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
 
     #[tokio::test]
     async fn is_prime() {
@@ -102,6 +363,7 @@ mod test {
             .read(b"{\"method\": \"isPrime\", \"number\": 15}\n")
             .read(b"{\"method\": \"isPrime\", \"number\": 13.0}\n")
             .read(b"{\"method\": \"isPrime\", \"number\": 15.0}\n")
+            .read(b"{\"method\": \"is\\u0050rime\", \"number\": 7}\n")
             .build();
         let writer = tokio_test::io::Builder::new()
             .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
@@ -112,8 +374,9 @@ mod test {
             .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
             .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
             .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
             .build();
-        let _ = process(reader, writer).await;
+        let _ = process(reader, writer, &Arc::new(Server::new(Config::default()))).await;
     }
 
     #[tokio::test]
@@ -122,9 +385,9 @@ mod test {
             .read(b"{\"method\": \"isntPrime\", \"number\": 1.23}\n")
             .build();
         let writer = tokio_test::io::Builder::new()
-            .write(b"Malformed Response")
+            .write(b"{\"method\": \"isntPrime\", \"number\": 1.23}\n")
             .build();
-        let _ = process(reader, writer).await;
+        let _ = process(reader, writer, &Arc::new(Server::new(Config::default()))).await;
     }
 
     #[tokio::test]
@@ -137,7 +400,29 @@ mod test {
             .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
             .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
             .build();
-        let _ = process(reader, writer).await;
+        let _ = process(reader, writer, &Arc::new(Server::new(Config::default()))).await;
+    }
+
+    #[tokio::test]
+    async fn big_numbers() {
+        let reader = tokio_test::io::Builder::new()
+            // 2^53 + 1, a multiple of 3, and 2^64 + 1, a multiple of 274177
+            .read(b"{\"method\": \"isPrime\", \"number\": 9007199254740993}\n")
+            .read(b"{\"method\": \"isPrime\", \"number\": 18446744073709551617}\n")
+            .read(b"{\"method\": \"isPrime\", \"number\": 18446744073709551617.5}\n")
+            .read(b"{\"method\": \"isPrime\", \"number\": -13}\n")
+            .read(b"{\"method\": \"isPrime\", \"number\": \"13\"}\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\": \"isPrime\", \"number\": \"13\"}\n")
+            .build();
+        process(reader, writer, &Arc::new(Server::new(Config::default())))
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
@@ -149,6 +434,272 @@ mod test {
         let writer = tokio_test::io::Builder::new()
             .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
             .build();
-        let _ = process(reader, writer).await;
+        let _ = process(reader, writer, &Arc::new(Server::new(Config::default()))).await;
+    }
+
+    #[tokio::test]
+    async fn line_too_long() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"method\": \"isPrime\", \"number\": 97}\n")
+            .read(b"{\"method\": \"isPrime\", ")
+            .read(b"\"number\": 9700000}\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"malformed\n")
+            .build();
+        let config = Config {
+            max_line: 36,
+            ..Config::default()
+        };
+        process(reader, writer, &Arc::new(Server::new(config)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn malformed_responses() {
+        let cases: [(&[u8], _, &[u8]); 6] = [
+            (b"{}\n", None, b"{}\n"),
+            (b"[\"isPrime\",7]\n", None, b"[\"isPrime\",7]\n"),
+            (b"{}\n", Some("bad".to_string()), b"bad\n"),
+            (b"garbage\r\n", None, b"garbage\n"),
+            // A response would pass for a well-formed one if echoed.
+            (
+                b"{\"method\":\"isPrime\",\"prime\":true}\n",
+                None,
+                b"malformed\n",
+            ),
+            (
+                b"{\"method\":\"is\\u0050rime\",\"prime\":true}\n",
+                None,
+                b"malformed\n",
+            ),
+        ];
+        for (request, malformed, response) in cases {
+            let reader = tokio_test::io::Builder::new().read(request).build();
+            let writer = tokio_test::io::Builder::new().write(response).build();
+            let config = Config {
+                malformed,
+                ..Config::default()
+            };
+            process(reader, writer, &Arc::new(Server::new(config)))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined() {
+        let mut requests = vec![];
+        for number in [
+            // 2^521 - 1 and 2^607 - 1, checked on blocking threads
+            "6864797660130609714981900799081393217269435300143305409394463459185543183397656052122559640661454554977296311391480858037121987999716643812574028291115057151",
+            "531137992816767098689588206552468627329593117727031923199444138200403559860852242739162502265229285668889329486246501015346579337652707239409519978766587351943831270835393219031728127",
+            "7",
+            "8",
+        ] {
+            requests.extend_from_slice(
+                format!("{{\"method\":\"isPrime\",\"number\":{number}}}\n").as_bytes(),
+            );
+        }
+        requests.extend_from_slice(b"{}\n");
+        let reader = tokio_test::io::Builder::new().read(&requests).build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{}\n")
+            .build();
+        let server = Arc::new(Server::new(Config::default()));
+        process(reader, writer, &server).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn single_writes() {
+        let requests =
+            b"{\"method\":\"isPrime\",\"number\":7}\n{\"method\":\"isPrime\",\"number\":8}\n";
+        let responses = [
+            &b"{\"method\":\"isPrime\",\"prime\":true}\n"[..],
+            &b"{\"method\":\"isPrime\",\"prime\":false}\n"[..],
+        ];
+        let server = Arc::new(Server::new(Config::default()));
+
+        let mut writer = Writes {
+            max: usize::MAX,
+            writes: vec![],
+        };
+        process(&requests[..], &mut writer, &server).await.unwrap();
+        assert_eq!(writer.writes, responses);
+
+        // Short writes are carried on with, not dropped.
+        let mut writer = Writes {
+            max: 5,
+            writes: vec![],
+        };
+        process(&requests[..], &mut writer, &server).await.unwrap();
+        assert!(writer.writes.iter().all(|write| write.len() <= 5));
+        assert_eq!(writer.writes.concat(), responses.concat());
+    }
+
+    #[tokio::test]
+    async fn batch() {
+        let request =
+            b"{\"method\":\"isPrime\",\"number\":[7,8.0,-7,618970019642690137449562111]}\n";
+        let cases: [(_, &[u8]); 2] = [
+            (
+                true,
+                b"{\"method\":\"isPrime\",\"prime\":[true,false,false,true]}\n",
+            ),
+            (false, request),
+        ];
+        for (batch, response) in cases {
+            let reader = tokio_test::io::Builder::new().read(request).build();
+            let writer = tokio_test::io::Builder::new().write(response).build();
+            let config = Config {
+                batch,
+                ..Config::default()
+            };
+            process(reader, writer, &Arc::new(Server::new(config)))
+                .await
+                .unwrap();
+        }
+
+        // Still malformed with anything but numbers in it.
+        let request = b"{\"method\":\"isPrime\",\"number\":[7,\"8\"]}\n";
+        let reader = tokio_test::io::Builder::new().read(request).build();
+        let writer = tokio_test::io::Builder::new().write(request).build();
+        let config = Config {
+            batch: true,
+            ..Config::default()
+        };
+        process(reader, writer, &Arc::new(Server::new(config)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn values() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"{\"method\":\"isPrime\",\n\"number\":7}{\"method\":")
+            .read(b"\"isPrime\",\"number\":8}\n")
+            .read(b"{\"method\":\"isPrime\",\"number\":7} garbage")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":false}\n")
+            .write(b"{\"method\":\"isPrime\",\"prime\":true}\n")
+            .write(b"malformed\n")
+            .build();
+        let config = Config {
+            values: true,
+            ..Config::default()
+        };
+        process(reader, writer, &Arc::new(Server::new(config)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let server = Arc::new(Server::new(Config::default()));
+        let server = TestServer::start(|listener| serve(listener, server)).await;
+        let mut client = server.connect().await;
+        client.send_line(r#"{"method":"isPrime","number":7}"#).await;
+        client
+            .send_line(r#"{"method":"isPrime","number":618970019642690137449562111}"#)
+            .await;
+        client
+            .send_line(r#"{"method":"isPrime","number":"7"}"#)
+            .await;
+        assert_eq!(
+            client.recv_line().await,
+            r#"{"method":"isPrime","prime":true}"#
+        );
+        assert_eq!(
+            client.recv_line().await,
+            r#"{"method":"isPrime","prime":true}"#
+        );
+        assert_eq!(
+            client.recv_line().await,
+            r#"{"method":"isPrime","number":"7"}"#
+        );
+        assert!(client.recv_to_end().await.is_empty());
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplex_client() {
+        let config = Config {
+            batch: true,
+            ..Config::default()
+        };
+        let server = Arc::new(Server::new(config));
+        let (reader, writer) =
+            duplex(|reader, writer| async move { process(reader, writer, &server).await });
+        let mut client = Client::new(reader, writer);
+        assert!(client.is_prime("7").await.unwrap());
+        assert!(!client.is_prime("8").await.unwrap());
+        assert_eq!(
+            client.batch(&["2", "4", "7.0"]).await.unwrap(),
+            [true, false, true]
+        );
+    }
+
+    /// Round trips of small requests, through `process` directly over a pipe
+    /// and through the server over localhost, to tell the handler's cost from
+    /// the TCP stack's.
+    ///
+    /// cargo test --release -- --ignored --nocapture round_trips
+    #[tokio::test]
+    #[ignore]
+    async fn round_trips() {
+        let n = 100_000;
+        let server = Arc::new(Server::new(Config::default()));
+        let piped = {
+            let server = server.clone();
+            duplex(|reader, writer| async move { process(reader, writer, &server).await })
+        };
+        let mut client = Client::new(piped.0, piped.1);
+        let start = std::time::Instant::now();
+        for i in 0..n {
+            client.is_prime(&i.to_string()).await.unwrap();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "duplex: {n} round trips in {elapsed:?} ({:?} each)",
+            elapsed / n
+        );
+
+        let server = TestServer::start(|listener| serve(listener, server)).await;
+        let mut client = Client::connect(&server.addr.to_string()).await.unwrap();
+        let start = std::time::Instant::now();
+        for i in 0..n {
+            client.is_prime(&i.to_string()).await.unwrap();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "tcp:    {n} round trips in {elapsed:?} ({:?} each)",
+            elapsed / n
+        );
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let server = Arc::new(Server::new(Config::default()));
+        let server = TestServer::start(|listener| serve(listener, server)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("prime", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn transcripts() {
+        let server = Arc::new(Server::new(Config::default()));
+        replay_dir("testdata/prime_time", |reader, writer| {
+            let server = server.clone();
+            async move { process(reader, writer, &server).await }
+        })
+        .await;
     }
 }
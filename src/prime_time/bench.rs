@@ -0,0 +1,43 @@
+//! The entry points for the `prime_time` benchmarks in `benches/`.
+
+use crate::fuzz::decode_all;
+
+use super::framing::Framing;
+
+/// `n` request lines: mostly small numbers, with some big ones and some with
+/// extra fields.
+pub fn traffic(n: usize) -> Vec<u8> {
+    let mut traffic = vec![];
+    for i in 0..n {
+        let request = match i % 10 {
+            0 => format!("{{\"method\":\"isPrime\",\"number\":{}}}", "9".repeat(60)),
+            1 => format!("{{\"method\":\"isPrime\",\"number\":{i}.5,\"extra\":[1,2]}}"),
+            _ => format!("{{\"method\":\"isPrime\",\"number\":{i}}}"),
+        };
+        traffic.extend(request.as_bytes());
+        traffic.push(b'\n');
+    }
+    traffic
+}
+
+/// Frames `traffic` arriving in 4 KiB reads, by values or by lines, returning
+/// how many requests there were.
+pub fn frame(traffic: &[u8], values: bool) -> usize {
+    let mut framed = 0;
+    decode_all(Framing::new(values, 1 << 20), traffic, 4096, |_| {
+        framed += 1
+    });
+    framed
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame, traffic};
+
+    #[test]
+    fn frames_traffic() {
+        for values in [false, true] {
+            assert_eq!(frame(&traffic(1000), values), 1000);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use num_bigint::BigUint;
+
+/// Remembers whether numbers are prime, for every connection to share, as the
+/// checker asks about the same numbers again and again.
+///
+/// It's bounded, with the oldest number forgotten first. Only numbers beyond
+/// u64 are worth keeping: Miller–Rabin on a u64 costs about what a lookup
+/// does.
+pub(super) struct Cache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    primes: HashMap<BigUint, bool>,
+    /// The numbers in `primes`, oldest first.
+    order: VecDeque<BigUint>,
+}
+
+impl Cache {
+    pub(super) fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether `num` is prime, computed by `is_prime` unless it's cached. The
+    /// lock isn't held while computing, so two connections may both compute
+    /// a number at once.
+    pub(super) fn get(&self, num: &BigUint, is_prime: impl FnOnce(&BigUint) -> bool) -> bool {
+        if self.capacity == 0 || num.bits() <= 64 {
+            return is_prime(num);
+        }
+        if let Some(&prime) = self.entries.lock().unwrap().primes.get(num) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return prime;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let prime = is_prime(num);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.primes.insert(num.clone(), prime).is_none() {
+            entries.order.push_back(num.clone());
+        }
+        if entries.order.len() > self.capacity {
+            let oldest = entries.order.pop_front().unwrap();
+            entries.primes.remove(&oldest);
+        }
+        prime
+    }
+
+    /// The share of lookups answered from the cache, from 0 to 1, or `None`
+    /// before the first.
+    pub(super) fn hit_rate(&self) -> Option<f64> {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let lookups = hits + self.misses.load(Ordering::Relaxed);
+        (lookups > 0).then(|| hits as f64 / lookups as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::Cache;
+
+    #[test]
+    fn bounded() {
+        let big = |n: u32| (BigUint::from(1u32) << 64) + n;
+        let cache = Cache::new(2);
+        assert_eq!(cache.hit_rate(), None);
+        assert!(cache.get(&big(1), |_| true));
+        assert!(!cache.get(&big(2), |_| false));
+        // Cached, so not computed again.
+        assert!(cache.get(&big(1), |_| unreachable!()));
+        assert_eq!(cache.hit_rate(), Some(1.0 / 3.0));
+
+        // The oldest goes first.
+        cache.get(&big(3), |_| false);
+        assert!(!cache.get(&big(1), |_| false));
+        assert!(!cache.get(&big(3), |_| unreachable!()));
+
+        // Small numbers aren't worth caching.
+        cache.get(&BigUint::from(7u32), |_| true);
+        assert!(!cache.get(&BigUint::from(7u32), |_| false));
+        assert_eq!(cache.hit_rate(), Some(2.0 / 6.0));
+    }
+}
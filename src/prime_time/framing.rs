@@ -0,0 +1,175 @@
+use serde::de::IgnoredAny;
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::{Decoder, LinesCodec},
+};
+
+/// Splits a stream into requests: one per line, as the spec has it, or one
+/// per top-level JSON value, however they're spread over lines.
+pub(super) enum Framing {
+    Lines(LinesCodec),
+    Values { max_length: usize },
+}
+
+impl Framing {
+    pub(super) fn new(values: bool, max_length: usize) -> Framing {
+        match values {
+            true => Framing::Values { max_length },
+            false => Framing::Lines(LinesCodec::new_with_max_length(max_length)),
+        }
+    }
+}
+
+impl Decoder for Framing {
+    type Item = String;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            Framing::Lines(codec) => Ok(codec.decode(src)?),
+            Framing::Values { max_length } => decode_value(src, *max_length),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            Framing::Lines(codec) => Ok(codec.decode_eof(src)?),
+            Framing::Values { max_length } => match decode_value(src, *max_length)? {
+                Some(value) => Ok(Some(value)),
+                None if src.is_empty() => Ok(None),
+                None => {
+                    src.clear();
+                    anyhow::bail!("stream ended inside a value")
+                }
+            },
+        }
+    }
+}
+
+/// Takes the first JSON value off `src`, skipping whitespace before it. Values
+/// may be split across reads, or share one, but anything between them other
+/// than whitespace is an error.
+fn decode_value(src: &mut BytesMut, max_length: usize) -> anyhow::Result<Option<String>> {
+    let start = src.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    src.advance(start);
+    if src.is_empty() {
+        return Ok(None);
+    }
+    let mut values = serde_json::Deserializer::from_slice(src).into_iter::<IgnoredAny>();
+    match values.next() {
+        Some(Ok(_)) => {
+            let end = values.byte_offset();
+            let value = src.split_to(end);
+            Ok(Some(String::from_utf8(value.to_vec())?))
+        }
+        Some(Err(e)) if incomplete(src, &e) && src.len() <= max_length => Ok(None),
+        Some(Err(e)) if incomplete(src, &e) => {
+            src.clear();
+            anyhow::bail!("value longer than {max_length} bytes")
+        }
+        Some(Err(e)) => {
+            src.clear();
+            Err(e.into())
+        }
+        None => Ok(None),
+    }
+}
+
+/// Whether `src` may just be missing the rest of a value. A number cut short,
+/// like `1.` or `2e`, isn't reported as EOF, but as invalid at the last byte.
+fn incomplete(src: &[u8], e: &serde_json::Error) -> bool {
+    if e.is_eof() {
+        return true;
+    }
+    let line_start = match e.line() {
+        1 => 0,
+        line => {
+            let mut newlines = src.iter().enumerate().filter(|&(_, &b)| b == b'\n');
+            newlines.nth(line - 2).map_or(src.len(), |(i, _)| i + 1)
+        }
+    };
+    line_start + e.column() >= src.len()
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+    use crate::testutil::{decode_chunks, split_at};
+
+    use super::Framing;
+
+    #[test]
+    fn values() {
+        let mut framing = Framing::new(true, 1024);
+        let mut buf = BytesMut::new();
+        let mut values = vec![];
+        for chunk in [
+            &b"{\"a\":"[..],
+            b"1}{\"b\"",
+            b":\n[2.",
+            b"5]}  \n",
+            b"{\"c\":3}\n\"d\"",
+            b" 4",
+        ] {
+            buf.extend_from_slice(chunk);
+            while let Some(value) = framing.decode(&mut buf).unwrap() {
+                values.push(value);
+            }
+        }
+        while let Some(value) = framing.decode_eof(&mut buf).unwrap() {
+            values.push(value);
+        }
+        assert_eq!(
+            values,
+            ["{\"a\":1}", "{\"b\":\n[2.5]}", "{\"c\":3}", "\"d\"", "4"]
+        );
+    }
+
+    #[test]
+    fn garbage() {
+        let mut framing = Framing::new(true, 1024);
+        let mut buf = BytesMut::from(&b"{\"a\":1} x {\"b\":2}"[..]);
+        assert_eq!(framing.decode(&mut buf).unwrap().unwrap(), "{\"a\":1}");
+        assert!(framing.decode(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(&b"{\"a\":"[..]);
+        assert_eq!(framing.decode(&mut buf).unwrap(), None);
+        assert!(framing.decode_eof(&mut buf).is_err());
+    }
+
+    #[test]
+    fn too_long() {
+        let mut framing = Framing::new(true, 8);
+        let mut buf = BytesMut::from(&b"[1,2,3,"[..]);
+        assert_eq!(framing.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"4,5");
+        assert!(framing.decode(&mut buf).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn split_values(
+            numbers in prop::collection::vec(
+                prop_oneof![
+                    any::<i64>().prop_map(|n| n.to_string()),
+                    any::<f64>().prop_filter("finite", |n| n.is_finite()).prop_map(|n| format!("{n:e}")),
+                    "[1-9][0-9]{20,40}",
+                ],
+                0..8,
+            ),
+            values in any::<bool>(),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let requests: Vec<_> = numbers
+                .iter()
+                .map(|n| format!("{{\"method\":\"isPrime\",\"number\":{n}}}"))
+                .collect();
+            let stream: String = requests.iter().map(|r| format!("{r}\n")).collect();
+            let framing = Framing::new(values, 1024);
+            let framed = decode_chunks(framing, &split_at(stream.as_bytes(), &cuts)).unwrap();
+            prop_assert_eq!(framed, requests);
+        }
+    }
+}
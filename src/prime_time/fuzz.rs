@@ -0,0 +1,57 @@
+//! The entry point for the `prime_time` fuzz target in `fuzz/`.
+
+use serde::de::IgnoredAny;
+
+use crate::fuzz::{decode_all, setup};
+
+use super::{framing::Framing, number, parse};
+
+/// The longest request the fuzzed framing accepts, small so that it's hit.
+const MAX_LENGTH: usize = 64;
+
+/// Frames `data` into requests and parses them, panicking if anything
+/// misbehaves. After the read size, bit 4 of the first byte picks framing by
+/// values rather than lines, and bit 5 accepts batches.
+pub fn requests(data: &[u8]) {
+    let Some((size, setup, stream)) = setup(data) else {
+        return;
+    };
+    let values = setup & 0x10 != 0;
+    let batch = setup & 0x20 != 0;
+    decode_all(Framing::new(values, MAX_LENGTH), stream, size, |request| {
+        assert!(request.len() <= MAX_LENGTH, "{request:?}");
+        match values {
+            true => assert!(serde_json::from_str::<IgnoredAny>(&request).is_ok()),
+            false => assert!(!request.contains('\n')),
+        }
+        if let Some((numbers, is_batch)) = parse(&request, batch) {
+            assert!(batch || !is_batch);
+            for text in numbers {
+                if let Some(num) = number::candidate(text) {
+                    // Multiples of ten other than 0 are never built.
+                    let zero = 0u32.into();
+                    assert!(num == zero || &num % 10u32 != zero, "{text}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::requests;
+
+    #[test]
+    fn seeds() {
+        let stream = b"{\"method\":\"isPrime\",\"number\":7}\n\
+            {\"method\":\"isPrime\",\"number\":[1e3, 2.50, -3]}\n\
+            {\"method\":\"isPrime\",\"number\":12345678901234567890123}\n\
+            {\"method\":\"isPrime\" garbage\n";
+        requests(&[]);
+        for setup in 0..64 {
+            let mut data = vec![setup];
+            data.extend_from_slice(stream);
+            requests(&data);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use num_bigint::BigUint;
+
+/// The number to test for primality given a JSON number's text, or `None` if
+/// it can't be prime: it's negative, not an integer, or a multiple of ten.
+///
+/// The text is read exactly rather than through an f64, so integers beyond
+/// 2^53 keep every digit, and `2.0` or `0.2e1` are still 2. Nothing is built
+/// for a multiple of ten, so `1e1000000000` costs no more than `10`.
+pub(super) fn candidate(text: &str) -> Option<BigUint> {
+    if text.starts_with('-') {
+        // -0 is as prime as 0.
+        return None;
+    }
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None => (text, "0"),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    let significant = digits.trim_end_matches('0');
+    if significant.trim_start_matches('0').is_empty() {
+        return Some(BigUint::ZERO);
+    }
+
+    // The value is `significant` times ten to the `scale`.
+    let exponent = exponent.strip_prefix('+').unwrap_or(exponent);
+    let exponent = exponent
+        .parse::<i64>()
+        .unwrap_or(match exponent.starts_with('-') {
+            true => i64::MIN / 2,
+            false => i64::MAX / 2,
+        });
+    let zeros = (digits.len() - significant.len()) as i64;
+    let scale = exponent - fraction.len() as i64 + zeros;
+    match scale {
+        0 => significant.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::candidate;
+
+    #[test]
+    fn candidates() {
+        let cases = [
+            ("0", Some(0u32.into())),
+            ("7", Some(7u32.into())),
+            ("7.0", Some(7u32.into())),
+            ("7.000e0", Some(7u32.into())),
+            ("0.7e1", Some(7u32.into())),
+            ("700e-2", Some(7u32.into())),
+            ("0.0", Some(BigUint::ZERO)),
+            ("0e99999999999999999999", Some(BigUint::ZERO)),
+            ("-0", None),
+            ("-7", None),
+            ("7.5", None),
+            ("0.07e1", None),
+            ("70", None),
+            ("7e1", None),
+            ("1e1000000000", None),
+            ("1e-99999999999999999999", None),
+            (
+                "18446744073709551617",
+                Some("18446744073709551617".parse().unwrap()),
+            ),
+            (
+                "1.8446744073709551617e19",
+                Some("18446744073709551617".parse().unwrap()),
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(candidate(text), expected, "{text}");
+        }
+    }
+}
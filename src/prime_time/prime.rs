@@ -0,0 +1,188 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::LazyLock,
+};
+
+use num_bigint::BigUint;
+
+/// Bases that make Miller–Rabin exact below 2^64.
+const U64_BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+/// Random bases tried for a number beyond u64, after the fixed ones. A
+/// composite passes each with probability at most 1/4.
+const ROUNDS: u64 = 16;
+
+/// Numbers below this are looked up in `SIEVE`, which covers most of what the
+/// checker asks about.
+const SIEVE_LIMIT: u64 = 1_000_000;
+
+/// Bit `n` is set if `n` is prime, up to `SIEVE_LIMIT`.
+static SIEVE: LazyLock<Vec<u64>> = LazyLock::new(|| {
+    let limit = SIEVE_LIMIT as usize;
+    let mut composite = vec![false; limit];
+    for n in 2..limit.isqrt() + 1 {
+        if !composite[n] {
+            for multiple in (n * n..limit).step_by(n) {
+                composite[multiple] = true;
+            }
+        }
+    }
+    let mut bits = vec![0; limit.div_ceil(64)];
+    for n in 2..limit {
+        if !composite[n] {
+            bits[n / 64] |= 1 << (n % 64);
+        }
+    }
+    bits
+});
+
+/// Builds the sieve now, rather than in the first check that needs it.
+pub(super) fn init() {
+    LazyLock::force(&SIEVE);
+}
+
+pub(super) fn is_prime(num: &BigUint) -> bool {
+    if let Ok(num) = u64::try_from(num) {
+        return is_prime_u64(num);
+    }
+    if U64_BASES.iter().any(|&p| num % p == BigUint::ZERO) {
+        return false;
+    }
+    let one = BigUint::from(1u32);
+    let minus_one = num - &one;
+    let shift = minus_one.trailing_zeros().unwrap();
+    let odd = &minus_one >> shift;
+    let witness = |base: &BigUint| {
+        let mut x = base.modpow(&odd, num);
+        if x == one || x == minus_one {
+            return false;
+        }
+        for _ in 1..shift {
+            x = &x * &x % num;
+            if x == minus_one {
+                return false;
+            }
+        }
+        true
+    };
+    if U64_BASES.iter().any(|&base| witness(&base.into())) {
+        return false;
+    }
+    // RandomState is seeded afresh each time, so the random bases can't be
+    // picked for ahead of time.
+    let random = RandomState::new();
+    let range = num - 3u32;
+    !(0..ROUNDS).any(|i| {
+        let digits = (0..num.bits().div_ceil(32))
+            .map(|j| random.hash_one((i, j)) as u32)
+            .collect();
+        witness(&(BigUint::new(digits) % &range + 2u32))
+    })
+}
+
+fn is_prime_u64(num: u64) -> bool {
+    if num < SIEVE_LIMIT {
+        return SIEVE[num as usize / 64] & (1 << (num % 64)) != 0;
+    }
+    miller_rabin(num)
+}
+
+/// Deterministic Miller–Rabin.
+fn miller_rabin(num: u64) -> bool {
+    if num < 2 {
+        return false;
+    }
+    for p in U64_BASES {
+        if num.is_multiple_of(p) {
+            return num == p;
+        }
+    }
+    let shift = (num - 1).trailing_zeros();
+    let odd = (num - 1) >> shift;
+    let mul = |a: u64, b: u64| (a as u128 * b as u128 % num as u128) as u64;
+    let pow = |mut base: u64, mut exp: u64| {
+        let mut result = 1;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = mul(result, base);
+            }
+            base = mul(base, base);
+            exp >>= 1;
+        }
+        result
+    };
+    U64_BASES.iter().all(|&base| {
+        let mut x = pow(base, odd);
+        if x == 1 || x == num - 1 {
+            return true;
+        }
+        for _ in 1..shift {
+            x = mul(x, x);
+            if x == num - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigUint;
+
+    use super::{is_prime, is_prime_u64, miller_rabin, SIEVE_LIMIT};
+
+    fn trial_division(num: u64) -> bool {
+        num >= 2 && (2..=num.isqrt()).all(|n| !num.is_multiple_of(n))
+    }
+
+    #[test]
+    fn small() {
+        for num in 0..20_000 {
+            assert_eq!(is_prime_u64(num), trial_division(num), "{num}");
+            assert_eq!(miller_rabin(num), trial_division(num), "{num}");
+        }
+    }
+
+    #[test]
+    fn sieve_edge() {
+        for num in SIEVE_LIMIT - 1000..SIEVE_LIMIT + 1000 {
+            assert_eq!(is_prime_u64(num), trial_division(num), "{num}");
+        }
+    }
+
+    #[test]
+    fn large() {
+        let cases = [
+            // Strong pseudoprimes to base 2, and to the first 7 primes
+            (2047, false),
+            (3215031751, false),
+            (341550071728321, false),
+            // Carmichael numbers
+            (561, false),
+            (8911, false),
+            ((1 << 61) - 1, true),
+            (u64::MAX - 58, true),
+            (u64::MAX, false),
+            // (2^31 - 1)^2
+            (4611686014132420609, false),
+        ];
+        for (num, expected) in cases {
+            assert_eq!(miller_rabin(num), expected, "{num}");
+            assert_eq!(is_prime_u64(num), expected, "{num}");
+            assert_eq!(is_prime(&num.into()), expected, "{num}");
+        }
+    }
+
+    #[test]
+    fn big() {
+        let mersenne = |p| (BigUint::from(1u32) << p) - 1u32;
+        assert!(is_prime(&mersenne(89)));
+        assert!(is_prime(&mersenne(127)));
+        assert!(is_prime(&mersenne(521)));
+        assert!(!is_prime(&mersenne(128)));
+        assert!(!is_prime(&(mersenne(61) * mersenne(89))));
+        assert!(!is_prime(&(mersenne(127) * mersenne(127))));
+        // 2^64 + 1
+        assert!(!is_prime(&((BigUint::from(1u32) << 64) + 1u32)));
+    }
+}
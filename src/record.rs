@@ -0,0 +1,240 @@
+//! A proxy in front of a problem server that records everything passing
+//! through it, for seeing exactly what a checker sent when a run fails.
+//!
+//! Each connection gets a session id, and a capture file named after the time
+//! it was accepted and its id. After a `#` header, each line is one read, in
+//! either direction:
+//!
+//! - `<ms> > data`, a chunk the client sent
+//! - `<ms> < data`, a chunk the server sent back
+//!
+//! `ms` is the time since the connection was accepted, and the data is
+//! escaped like the golden transcripts, so `\n`, `\r`, `\t`, `\\` and `\xNN`.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Accepts connections on `listen` and proxies each to `upstream`, writing
+/// capture files to `dir`.
+pub async fn run(listen: &str, upstream: &str, dir: &str) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {dir}"))?;
+    let listener = TcpListener::bind(listen).await?;
+    println!("recording {listen} -> {upstream} into {dir}");
+    serve(listener, upstream.to_string(), PathBuf::from(dir)).await
+}
+
+async fn serve(listener: TcpListener, upstream: String, dir: PathBuf) -> Result<()> {
+    for session in 1.. {
+        let (client, peer) = listener.accept().await?;
+        let upstream = upstream.clone();
+        let dir = dir.clone();
+        tokio::spawn(async move {
+            if let Err(e) = proxy(client, &upstream, &dir, session, &peer.to_string()).await {
+                println!("session {session}: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn proxy(
+    mut client: TcpStream,
+    upstream: &str,
+    dir: &Path,
+    session: u64,
+    peer: &str,
+) -> Result<()> {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = dir.join(format!("{started}-{session}.txt"));
+    let mut file = File::create(&path).with_context(|| format!("{}", path.display()))?;
+    writeln!(
+        file,
+        "# session {session} from {peer} to {upstream}, at {started}"
+    )?;
+    let capture = Capture {
+        file: Arc::new(Mutex::new(file)),
+        started: Instant::now(),
+    };
+    println!("session {session}: {peer} -> {}", path.display());
+
+    let mut server = TcpStream::connect(upstream)
+        .await
+        .with_context(|| format!("connecting to {upstream}"))?;
+    let (client_reader, client_writer) = client.split();
+    let (server_reader, server_writer) = server.split();
+    let (sent, received) = tokio::join!(
+        capture.copy(client_reader, server_writer, '>'),
+        capture.copy(server_reader, client_writer, '<'),
+    );
+    writeln!(capture.file.lock().unwrap(), "# closed")?;
+    sent.context("client to server")?;
+    received.context("server to client")?;
+    Ok(())
+}
+
+/// One session's capture file, shared by both directions.
+struct Capture {
+    file: Arc<Mutex<File>>,
+    started: Instant,
+}
+
+impl Capture {
+    /// Copies `reader` to `writer` until EOF, recording each read with
+    /// `direction`, and then shuts `writer` down.
+    async fn copy(
+        &self,
+        mut reader: impl AsyncRead + Unpin,
+        mut writer: impl AsyncWrite + Unpin,
+        direction: char,
+    ) -> Result<()> {
+        let mut buf = vec![0; 64 << 10];
+        loop {
+            let n = reader.read(&mut buf).await.context("read failed")?;
+            if n == 0 {
+                break;
+            }
+            self.record(direction, &buf[..n])?;
+            writer.write_all(&buf[..n]).await.context("write failed")?;
+        }
+        writer.shutdown().await.context("shutdown failed")?;
+        Ok(())
+    }
+
+    fn record(&self, direction: char, data: &[u8]) -> Result<()> {
+        let ms = self.started.elapsed().as_millis();
+        let line = format!("{ms} {direction} {}\n", escape(data));
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Escapes `data` for a capture line, leaving printable ASCII as it is.
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in data {
+        match byte {
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => write!(escaped, "\\x{byte:02x}").unwrap(),
+        }
+    }
+    escaped
+}
+
+/// Undoes `escape`, for reading captures and transcripts back.
+pub fn unescape(data: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16).map_err(|_| format!("bad \\x{hex}"))?;
+                bytes.push(byte);
+            }
+            c => return Err(format!("bad escape \\{}", c.unwrap_or(' '))),
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use tokio::net::TcpListener;
+
+    use super::{escape, serve, unescape};
+    use crate::testutil::TestServer;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape(b"a b\n"), "a b\\n");
+        assert_eq!(escape(b"\\\t\r\x00\xff"), "\\\\\\t\\r\\x00\\xff");
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(unescape(&escape(&bytes)).unwrap(), bytes);
+    }
+
+    #[tokio::test]
+    async fn records_both_directions() {
+        let dir = std::env::temp_dir().join(format!("record-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let echo = TestServer::start(|listener: TcpListener| async move {
+            loop {
+                let (mut stream, _) = listener.accept().await?;
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    tokio::io::copy(&mut reader, &mut writer).await
+                });
+            }
+        })
+        .await;
+        let upstream = echo.addr.to_string();
+        let record_dir = PathBuf::from(&dir);
+        let proxy = TestServer::start(|listener| serve(listener, upstream, record_dir)).await;
+
+        let mut client = proxy.connect().await;
+        client.send_line("hello").await;
+        assert_eq!(client.recv_line().await, "hello");
+        client.close().await;
+        assert_eq!(client.recv_to_end().await, b"");
+
+        // The proxy finishes the file once both directions have closed.
+        let mut waited = 0;
+        let path = loop {
+            assert!(waited < 500, "the capture was never closed");
+            waited += 1;
+            let paths: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+            if let [Ok(entry)] = &paths[..] {
+                let path = entry.path();
+                if fs::read_to_string(&path).unwrap().ends_with("# closed\n") {
+                    break path;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        let capture = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = capture.lines().collect();
+        assert!(
+            lines[0].starts_with("# session 1 from 127.0.0.1:"),
+            "{capture}"
+        );
+        let data: Vec<_> = lines[1..lines.len() - 1]
+            .iter()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(data, ["> hello\\n", "< hello\\n"]);
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .ends_with("-1.txt"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//! Replays the client side of capture files written by `record` against a
+//! server, and checks that it sends back what was recorded.
+//!
+//! Only the whole of what the server sends is compared, not how it was split
+//! into reads. Without `timing` the chunks are sent as fast as possible, one
+//! write each; with it, each is sent as long after connecting as it was
+//! recorded.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::Instant,
+};
+
+use crate::record::unescape;
+
+/// How long to wait for more of the server's response before giving up on it.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Replays `path`, a capture file or a directory of them, against `addr`,
+/// printing each result. Whether they all matched.
+pub async fn run(addr: &str, path: &str, timing: bool) -> Result<bool> {
+    let path = Path::new(path);
+    let mut paths: Vec<PathBuf> = match path.is_dir() {
+        true => fs::read_dir(path)
+            .with_context(|| format!("{}", path.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<_>>()?,
+        false => vec![path.to_path_buf()],
+    };
+    paths.sort();
+    let mut passed = 0;
+    for path in &paths {
+        let text = fs::read_to_string(path).with_context(|| format!("{}", path.display()))?;
+        let capture = parse(&text).with_context(|| format!("{}", path.display()))?;
+        match replay(addr, &capture, timing).await {
+            Ok(()) => {
+                println!("PASS {}", path.display());
+                passed += 1;
+            }
+            Err(e) => println!("FAIL {}: {e:#}", path.display()),
+        }
+    }
+    println!("{passed}/{} matched", paths.len());
+    Ok(passed == paths.len())
+}
+
+/// What a capture file recorded: the chunks the client sent, with when, and
+/// everything the server sent back.
+#[derive(Debug, Default, PartialEq)]
+struct Capture {
+    sent: Vec<(Duration, Vec<u8>)>,
+    received: Vec<u8>,
+}
+
+fn parse(text: &str) -> Result<Capture> {
+    let mut capture = Capture::default();
+    for (i, line) in text.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once(' ').and_then(|(ms, rest)| {
+            let ms = Duration::from_millis(ms.parse().ok()?);
+            let (direction, data) = rest.split_at_checked(2)?;
+            Some((ms, direction, data))
+        });
+        let Some((ms, direction, data)) = parsed else {
+            bail!("line {}: expected `<ms> > data` or `<ms> < data`", i + 1);
+        };
+        let data = unescape(data).map_err(|e| anyhow::anyhow!("line {}: {e}", i + 1))?;
+        match direction {
+            "> " => capture.sent.push((ms, data)),
+            "< " => capture.received.extend(data),
+            _ => bail!("line {}: expected `>` or `<`, got {direction:?}", i + 1),
+        }
+    }
+    Ok(capture)
+}
+
+async fn replay(addr: &str, capture: &Capture, timing: bool) -> Result<()> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("connecting to {addr}"))?;
+    let (mut reader, mut writer) = stream.into_split();
+    let started = Instant::now();
+    let send = async {
+        for (at, chunk) in &capture.sent {
+            if timing {
+                tokio::time::sleep_until(started + *at).await;
+            }
+            writer.write_all(chunk).await?;
+        }
+        anyhow::Ok(())
+    };
+    // Reads until there's as much as was recorded, so that a server which
+    // keeps the connection open doesn't have to be waited out.
+    let receive = async {
+        let mut received = vec![];
+        let mut buf = vec![0; 64 << 10];
+        while received.len() < capture.received.len() {
+            let n = match tokio::time::timeout(IDLE_TIMEOUT, reader.read(&mut buf)).await {
+                Ok(n) => n?,
+                Err(_) => break,
+            };
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+        anyhow::Ok(received)
+    };
+    let (sent, received) = tokio::join!(send, receive);
+    sent.context("send failed")?;
+    let received = received.context("receive failed")?;
+    if let Some(e) = diff(&capture.received, &received) {
+        bail!(e);
+    }
+    Ok(())
+}
+
+/// Where `received` first differs from `expected`, with some context.
+pub(crate) fn diff(expected: &[u8], received: &[u8]) -> Option<String> {
+    let at = expected
+        .iter()
+        .zip(received)
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(received.len()));
+    if expected.len() == received.len() && at == expected.len() {
+        return None;
+    }
+    let context = |bytes: &[u8]| {
+        let start = at.saturating_sub(40);
+        let end = (at + 40).min(bytes.len());
+        bytes[start..end].escape_ascii().to_string()
+    };
+    Some(format!(
+        "output differs at byte {at} ({} expected, {} received)\n\
+         expected: ...{}\n\
+         received: ...{}",
+        expected.len(),
+        received.len(),
+        context(expected),
+        context(received)
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::{diff, parse, replay, Capture};
+    use crate::testutil::TestServer;
+
+    #[test]
+    fn parsing() {
+        let capture =
+            parse("# session 1\n0 > a\\n\n12 < b\n15 > c\\x00\n20 < \\td\n# closed\n").unwrap();
+        assert_eq!(
+            capture,
+            Capture {
+                sent: vec![
+                    (Duration::ZERO, b"a\n".to_vec()),
+                    (Duration::from_millis(15), b"c\0".to_vec()),
+                ],
+                received: b"b\td".to_vec(),
+            }
+        );
+        assert!(parse("> a").is_err());
+        assert!(parse("x > a").is_err());
+        assert!(parse("0 = a").is_err());
+        assert!(parse("0 > \\q").is_err());
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(diff(b"abc", b"abc"), None);
+        let e = diff(b"abc\n", b"abd\n").unwrap();
+        assert!(e.starts_with("output differs at byte 2"), "{e}");
+        let e = diff(b"abc", b"ab").unwrap();
+        assert!(
+            e.starts_with("output differs at byte 2 (3 expected, 2 received)"),
+            "{e}"
+        );
+    }
+
+    #[tokio::test]
+    async fn against_echo() {
+        let server = TestServer::start(|listener: TcpListener| async move {
+            loop {
+                let (mut stream, _) = listener.accept().await?;
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    tokio::io::copy(&mut reader, &mut writer).await
+                });
+            }
+        })
+        .await;
+        let addr = server.addr.to_string();
+        let capture = parse("0 > hello \n5 > world\n5 < hello world\n").unwrap();
+        replay(&addr, &capture, true).await.unwrap();
+        let capture = parse("0 > hello\n1 < help\n").unwrap();
+        let e = replay(&addr, &capture, false).await.unwrap_err();
+        assert!(e.to_string().starts_with("output differs at byte 3"), "{e}");
+    }
+}
@@ -1,46 +1,332 @@
-use anyhow::Result;
+use std::{
+    fmt,
+    io::{self, IoSlice},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
-    net::TcpListener,
+    net::{TcpListener, UdpSocket},
+    sync::mpsc,
 };
 
-use crate::config::ADDR;
+use crate::{config::ADDR, datagram::Datagram};
+
+use self::dump::Dumper;
+
+pub mod bench;
+mod dump;
+
+/// Set to change the size of each connection's read buffer, in bytes.
+const BUFFER_VAR: &str = "SMOKE_BUFFER";
+/// Set to close connections once they've echoed this many bytes.
+const MAX_BYTES_VAR: &str = "SMOKE_MAX_BYTES";
+/// Set to close connections this many seconds after they're accepted.
+const MAX_LIFETIME_VAR: &str = "SMOKE_MAX_LIFETIME";
+/// Set to print a hex dump of what each connection sends.
+const DEBUG_VAR: &str = "SMOKE_DEBUG";
+/// Set to echo UDP datagrams instead of TCP streams.
+const UDP_VAR: &str = "SMOKE_UDP";
+/// Set to read and write concurrently, writing whatever reads have piled up
+/// with one vectored write. Over localhost this is no faster than echoing
+/// each read in turn, as the `smoke` bench shows, so it's off by default.
+const SPLIT_VAR: &str = "SMOKE_SPLIT";
+
+/// How many bytes a second of each connection's traffic are dumped.
+const DUMP_RATE: usize = 4096;
+
+/// How many buffers a split connection reads ahead of its writes.
+const SPLIT_BUFFERS: usize = 8;
+
+struct Config {
+    buffer: usize,
+    max_bytes: Option<u64>,
+    max_lifetime: Option<Duration>,
+    debug: bool,
+    udp: bool,
+    split: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            buffer: 64 << 10,
+            max_bytes: None,
+            max_lifetime: None,
+            debug: false,
+            udp: false,
+            split: false,
+        }
+    }
+}
+
+impl Config {
+    fn from_env() -> Result<Config> {
+        let mut config = Config::default();
+        if let Ok(value) = std::env::var(BUFFER_VAR) {
+            config.buffer = value
+                .parse()
+                .ok()
+                .filter(|&buffer| buffer > 0)
+                .with_context(|| format!("{BUFFER_VAR} must be a positive number of bytes"))?;
+        }
+        if let Ok(value) = std::env::var(MAX_BYTES_VAR) {
+            let max_bytes = value
+                .parse()
+                .with_context(|| format!("{MAX_BYTES_VAR} must be a number of bytes"))?;
+            config.max_bytes = Some(max_bytes);
+        }
+        if let Ok(value) = std::env::var(MAX_LIFETIME_VAR) {
+            let secs = value
+                .parse()
+                .with_context(|| format!("{MAX_LIFETIME_VAR} must be a number of seconds"))?;
+            config.max_lifetime = Some(Duration::from_secs(secs));
+        }
+        config.debug = std::env::var_os(DEBUG_VAR).is_some();
+        config.udp = std::env::var_os(UDP_VAR).is_some();
+        config.split = std::env::var_os(SPLIT_VAR).is_some();
+        Ok(config)
+    }
+}
+
+/// Why a connection was closed.
+#[derive(Debug, PartialEq)]
+enum Closed {
+    Eof,
+    MaxBytes,
+    MaxLifetime,
+}
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Closed::Eof => write!(f, "EOF"),
+            Closed::MaxBytes => write!(f, "byte limit reached"),
+            Closed::MaxLifetime => write!(f, "lifetime limit reached"),
+        }
+    }
+}
 
 pub async fn run() -> Result<()> {
+    let config = Arc::new(Config::from_env()?);
+    if config.udp {
+        let socket = UdpSocket::bind(ADDR).await?;
+        println!("Listening on {ADDR} (UDP)...");
+        return echo_datagrams(&socket, &config).await;
+    }
+
     let listener = TcpListener::bind(ADDR).await.unwrap();
     println!("Listening on {ADDR}...");
+    serve(listener, config).await
+}
 
+async fn serve(listener: TcpListener, config: Arc<Config>) -> Result<()> {
     loop {
         let (mut socket, addr) = listener.accept().await?;
         println!("Connected to {addr}");
+        let config = config.clone();
+        let dumper = config
+            .debug
+            .then(|| Dumper::new(addr.to_string(), DUMP_RATE));
         tokio::spawn(async move {
             let (reader, writer) = socket.split();
-            process(reader, writer).await
+            match process(reader, writer, &config, dumper).await {
+                Ok((closed, echoed)) => println!("{addr}: {closed}, after {echoed} bytes"),
+                Err(e) => println!("{addr}: {e:#}"),
+            }
         });
     }
 }
 
-async fn process<R, W>(mut reader: R, mut writer: W)
+/// Echoes everything read until EOF or a limit, then shuts the writer down.
+/// Returns why, and the number of bytes echoed.
+async fn process<R, W>(
+    mut reader: R,
+    mut writer: W,
+    config: &Config,
+    mut dumper: Option<Dumper>,
+) -> Result<(Closed, u64)>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let mut buf = vec![0; 4096];
+    let mut echoed = 0;
+    let echo = async {
+        match config.split {
+            true => echo_split(&mut reader, &mut writer, config, &mut dumper, &mut echoed).await,
+            false => echo(&mut reader, &mut writer, config, &mut dumper, &mut echoed).await,
+        }
+    };
+    let closed = match config.max_lifetime {
+        Some(lifetime) => tokio::time::timeout(lifetime, echo)
+            .await
+            .unwrap_or(Ok(Closed::MaxLifetime))?,
+        None => echo.await?,
+    };
+    writer.shutdown().await.context("shutdown failed")?;
+    Ok((closed, echoed))
+}
 
+/// Nothing past `max_bytes` is echoed.
+async fn echo<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    config: &Config,
+    dumper: &mut Option<Dumper>,
+    echoed: &mut u64,
+) -> Result<Closed>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; config.buffer];
     loop {
-        let n = reader.read(&mut buf).await.expect("read error");
+        let n = reader.read(&mut buf).await.context("read failed")?;
+        if n == 0 {
+            return Ok(Closed::Eof);
+        }
+        if let Some(dump) = dumper.as_mut().and_then(|d| d.dump(*echoed, &buf[..n])) {
+            print!("{dump}");
+        }
+        let allowed = match config.max_bytes {
+            Some(max) => n.min((max - *echoed) as usize),
+            None => n,
+        };
+        writer
+            .write_all(&buf[..allowed])
+            .await
+            .context("write failed")?;
+        *echoed += allowed as u64;
+        if allowed < n || config.max_bytes == Some(*echoed) {
+            return Ok(Closed::MaxBytes);
+        }
+    }
+}
+
+/// Like `echo`, but reads while the last reads are being written. Whatever
+/// buffers have filled up by the time a write finishes go out together in one
+/// vectored write.
+async fn echo_split<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    config: &Config,
+    dumper: &mut Option<Dumper>,
+    echoed: &mut u64,
+) -> Result<Closed>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (full_tx, full_rx) = mpsc::channel(SPLIT_BUFFERS);
+    let (free_tx, mut free_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let read = async move {
+        loop {
+            let mut buf = free_rx.try_recv().unwrap_or_default();
+            buf.resize(config.buffer, 0);
+            let n = reader.read(&mut buf).await.context("read failed")?;
+            if n == 0 {
+                return Ok(Closed::Eof);
+            }
+            if let Some(dump) = dumper.as_mut().and_then(|d| d.dump(*echoed, &buf[..n])) {
+                print!("{dump}");
+            }
+            let allowed = match config.max_bytes {
+                Some(max) => n.min((max - *echoed) as usize),
+                None => n,
+            };
+            buf.truncate(allowed);
+            *echoed += allowed as u64;
+            // The writer only hangs up on an error, which it returns.
+            let _ = full_tx.send(buf).await;
+            if allowed < n || config.max_bytes == Some(*echoed) {
+                return Ok(Closed::MaxBytes);
+            }
+        }
+    };
+    let (closed, ()) = tokio::try_join!(read, write_batches(writer, full_rx, free_tx))?;
+    Ok(closed)
+}
+
+/// Writes the buffers from `full` until it closes, as many at a time as have
+/// arrived, handing each back on `free` once it's written.
+async fn write_batches<W>(
+    writer: &mut W,
+    mut full: mpsc::Receiver<Vec<u8>>,
+    free: mpsc::UnboundedSender<Vec<u8>>,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut batch = Vec::with_capacity(SPLIT_BUFFERS);
+    while full.recv_many(&mut batch, SPLIT_BUFFERS).await > 0 {
+        write_all_vectored(writer, &batch)
+            .await
+            .context("write failed")?;
+        for buf in batch.drain(..) {
+            let _ = free.send(buf);
+        }
+    }
+    Ok(())
+}
+
+async fn write_all_vectored<W>(writer: &mut W, bufs: &[Vec<u8>]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut slices: Vec<_> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+    let mut slices = &mut slices[..];
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        let n = writer.write_vectored(slices).await?;
         if n == 0 {
-            println!("EOF");
-            break;
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut slices, n);
+    }
+    Ok(())
+}
+
+/// Sends each datagram back to where it came from. Limits on bytes and
+/// lifetime are per connection, so they don't apply here.
+async fn echo_datagrams(socket: &impl Datagram, config: &Config) -> Result<()> {
+    // The largest UDP payload, so nothing is truncated
+    let mut buf = vec![0; 65536];
+    let mut dumpers = std::collections::HashMap::new();
+    loop {
+        let (n, addr) = socket.recv_from(&mut buf).await?;
+        if config.debug {
+            let dumper = dumpers
+                .entry(addr)
+                .or_insert_with(|| Dumper::new(addr.to_string(), DUMP_RATE));
+            if let Some(dump) = dumper.dump(0, &buf[..n]) {
+                print!("{dump}");
+            }
+        }
+        if let Err(e) = socket.send_to(&buf[..n], addr).await {
+            // One unreachable sender shouldn't stop the others being answered
+            println!("{addr}: send failed: {e}");
         }
-        println!("Received {n} bytes: {:?}", &buf[..n]);
-        writer.write(&buf[..n]).await.expect("write error");
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::process;
+    use std::{io, sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UdpSocket,
+    };
+
+    use crate::{
+        datagram::network::{Faults, Network},
+        datagram::Datagram,
+        testutil::{chaos::Chaos, TestServer},
+    };
+
+    use super::{echo_datagrams, process, serve, Closed, Config};
 
     #[tokio::test]
     async fn echo() {
@@ -52,6 +338,208 @@ mod test {
             .write(b"abc")
             .write(b"123")
             .build();
-        let _ = process(reader, writer).await;
+        let closed = process(reader, writer, &Config::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(closed, (Closed::Eof, 6));
+    }
+
+    #[tokio::test]
+    async fn small_buffer() {
+        let reader = tokio_test::io::Builder::new().read(b"abcde").build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"ab")
+            .write(b"cd")
+            .write(b"e")
+            .build();
+        let config = Config {
+            buffer: 2,
+            ..Config::default()
+        };
+        let closed = process(reader, writer, &config, None).await.unwrap();
+        assert_eq!(closed, (Closed::Eof, 5));
+    }
+
+    #[tokio::test]
+    async fn max_bytes() {
+        let config = Config {
+            max_bytes: Some(5),
+            ..Config::default()
+        };
+        let reader = tokio_test::io::Builder::new()
+            .read(b"abc")
+            .read(b"defg")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"abc")
+            .write(b"de")
+            .build();
+        let closed = process(reader, writer, &config, None).await.unwrap();
+        assert_eq!(closed, (Closed::MaxBytes, 5));
+
+        // Reaching the limit exactly closes without waiting for more
+        let reader = tokio_test::io::Builder::new().read(b"abcde").build();
+        let writer = tokio_test::io::Builder::new().write(b"abcde").build();
+        let closed = process(reader, writer, &config, None).await.unwrap();
+        assert_eq!(closed, (Closed::MaxBytes, 5));
+    }
+
+    #[tokio::test]
+    async fn split() {
+        let config = Config {
+            split: true,
+            max_bytes: Some(8),
+            ..Config::default()
+        };
+        let reader = tokio_test::io::Builder::new()
+            .read(b"abc")
+            .read(b"123")
+            .read(b"xyz")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"abc")
+            .write(b"123")
+            .write(b"xy")
+            .build();
+        let closed = process(reader, writer, &config, None).await.unwrap();
+        assert_eq!(closed, (Closed::MaxBytes, 8));
+
+        let reader = tokio_test::io::Builder::new().read(b"abc").build();
+        let writer = tokio_test::io::Builder::new()
+            .write_error(io::ErrorKind::BrokenPipe.into())
+            .build();
+        let e = process(reader, writer, &config, None).await.unwrap_err();
+        assert!(format!("{e:#}").starts_with("write failed: "), "{e:#}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_lifetime() {
+        let config = Config {
+            max_lifetime: Some(Duration::from_secs(10)),
+            ..Config::default()
+        };
+        let (client, server) = tokio::io::duplex(64);
+        let (reader, writer) = tokio::io::split(server);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        client_writer.write_all(b"abc").await.unwrap();
+        let closed = process(reader, writer, &config, None).await.unwrap();
+        assert_eq!(closed, (Closed::MaxLifetime, 3));
+        let mut echoed = vec![];
+        client_reader.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"abc");
+    }
+
+    #[tokio::test]
+    async fn reset() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"abc")
+            .read_error(io::ErrorKind::ConnectionReset.into())
+            .build();
+        let writer = tokio_test::io::Builder::new().write(b"abc").build();
+        let e = process(reader, writer, &Config::default(), None)
+            .await
+            .unwrap_err();
+        assert!(format!("{e:#}").starts_with("read failed: "), "{e:#}");
+
+        let reader = tokio_test::io::Builder::new().read(b"abc").build();
+        let writer = tokio_test::io::Builder::new()
+            .write_error(io::ErrorKind::BrokenPipe.into())
+            .build();
+        let e = process(reader, writer, &Config::default(), None)
+            .await
+            .unwrap_err();
+        assert!(format!("{e:#}").starts_with("write failed: "), "{e:#}");
+    }
+
+    #[tokio::test]
+    async fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { echo_datagrams(&server, &Config::default()).await });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        let mut buf = vec![0; 65536];
+        for datagram in [&b"abc"[..], b"", &[7; 1200]] {
+            client.send(datagram).await.unwrap();
+            let n = client.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], datagram);
+        }
+    }
+
+    #[tokio::test]
+    async fn udp_faults() {
+        let faults = Faults {
+            loss: 10,
+            duplicate: 10,
+            reorder: 10,
+            ..Faults::default()
+        };
+        for seed in 0..8 {
+            let network = Network::new(faults, seed);
+            let server = network.endpoint("10.0.0.1:7");
+            let addr = server.addr;
+            let task =
+                tokio::spawn(async move { echo_datagrams(&server, &Config::default()).await });
+            let client = network.endpoint("10.0.0.2:1000");
+            for i in 0..100u8 {
+                client.send_to(&[i; 3], addr).await.unwrap();
+            }
+            // Every echo is something sent, from the server, whole, though
+            // some are lost or come twice or out of order.
+            let mut echoes = 0;
+            let mut buf = [0; 16];
+            while let Ok(Ok((n, from))) =
+                tokio::time::timeout(Duration::from_millis(20), client.recv_from(&mut buf)).await
+            {
+                assert_eq!(from, addr);
+                assert_eq!(n, 3);
+                assert!(buf[0] < 100 && buf[..3] == [buf[0]; 3], "{buf:?}");
+                echoes += 1;
+            }
+            assert!((50..150).contains(&echoes), "seed {seed}: {echoes} echoes");
+            task.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let config = Arc::new(Config::default());
+        let server = TestServer::start(|listener| serve(listener, config)).await;
+        let mut clients = [server.connect().await, server.connect().await];
+        for (i, client) in clients.iter_mut().enumerate() {
+            client.send(format!("hello {i}").as_bytes()).await;
+            client.close().await;
+        }
+        for (i, client) in clients.iter_mut().enumerate() {
+            assert_eq!(client.recv_to_end().await, format!("hello {i}").as_bytes());
+        }
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let config = Arc::new(Config::default());
+        let server = TestServer::start(|listener| serve(listener, config)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("smoke", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn chaos() {
+        for seed in 0..8 {
+            let reader = tokio_test::io::Builder::new()
+                .read(b"hello, ")
+                .read(b"world\n")
+                .build();
+            let writer = tokio_test::io::Builder::new()
+                .write(b"hello, world\n")
+                .build();
+            let (reader, writer) = (Chaos::new(reader, seed), Chaos::new(writer, seed));
+            let closed = process(reader, writer, &Config::default(), None)
+                .await
+                .unwrap();
+            assert_eq!(closed, (Closed::Eof, 13));
+        }
     }
 }
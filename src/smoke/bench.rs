@@ -0,0 +1,81 @@
+//! The entry points for the `smoke` benchmarks in `benches/`.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{process, Config};
+
+/// Starts an echo server on a free loopback port, reading `buffer` bytes at a
+/// time, and reading and writing concurrently if `split`. Unlike `serve`, it
+/// doesn't log each connection.
+pub async fn start(buffer: usize, split: bool) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(Config {
+        buffer,
+        split,
+        ..Config::default()
+    });
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let config = config.clone();
+            tokio::spawn(async move {
+                let (reader, writer) = socket.split();
+                process(reader, writer, &config, None).await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+/// Echoes `total` bytes through the server at `addr`, split between
+/// `connections`, each sending and receiving at once.
+pub async fn echo(addr: SocketAddr, connections: usize, total: usize) {
+    let clients: Vec<_> = (0..connections)
+        .map(|_| {
+            tokio::spawn(async move {
+                let socket = TcpStream::connect(addr).await.unwrap();
+                let (mut reader, mut writer) = socket.into_split();
+                let each = total / connections;
+                let send = tokio::spawn(async move {
+                    let chunk = vec![b'x'; 64 << 10];
+                    for _ in 0..each / chunk.len() {
+                        writer.write_all(&chunk).await.unwrap();
+                    }
+                    writer.shutdown().await.unwrap();
+                });
+                let mut buf = vec![0; 64 << 10];
+                let mut received = 0;
+                loop {
+                    match reader.read(&mut buf).await.unwrap() {
+                        0 => break,
+                        n => received += n,
+                    }
+                }
+                send.await.unwrap();
+                assert_eq!(received, each);
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{echo, start};
+
+    #[tokio::test]
+    async fn echoes() {
+        for split in [false, true] {
+            let addr = start(4096, split).await;
+            echo(addr, 2, 1 << 20).await;
+        }
+    }
+}
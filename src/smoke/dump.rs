@@ -0,0 +1,105 @@
+use std::fmt::Write;
+
+use tokio::time::{Duration, Instant};
+
+/// Formats `bytes` like `xxd`: 16 per line, after the offset of the first,
+/// in hex and then as ASCII, with `.` for anything unprintable.
+pub(super) fn hex_dump(offset: u64, bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, byte) in line.iter().enumerate() {
+            if j > 0 && j % 2 == 0 {
+                hex.push(' ');
+            }
+            write!(hex, "{byte:02x}").unwrap();
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                true => b as char,
+                false => '.',
+            })
+            .collect();
+        let offset = offset + 16 * i as u64;
+        writeln!(dump, "{offset:08x}: {hex:<39}  {ascii}").unwrap();
+    }
+    dump
+}
+
+/// Dumps a connection's traffic, up to `rate` bytes a second, so that a fast
+/// client doesn't spend all its time being printed.
+pub(super) struct Dumper {
+    name: String,
+    rate: usize,
+    window: Instant,
+    shown: usize,
+    /// Bytes not shown since the last dump.
+    skipped: u64,
+}
+
+impl Dumper {
+    pub(super) fn new(name: String, rate: usize) -> Dumper {
+        Dumper {
+            name,
+            rate,
+            window: Instant::now(),
+            shown: 0,
+            skipped: 0,
+        }
+    }
+
+    /// The dump of `bytes`, read at `offset`, or as much of it as the rate
+    /// allows. `None` if none of it is shown.
+    pub(super) fn dump(&mut self, offset: u64, bytes: &[u8]) -> Option<String> {
+        if self.window.elapsed() >= Duration::from_secs(1) {
+            self.window = Instant::now();
+            self.shown = 0;
+        }
+        let shown = bytes.len().min(self.rate - self.shown);
+        self.shown += shown;
+        if shown == 0 {
+            self.skipped += bytes.len() as u64;
+            return None;
+        }
+        let mut dump = String::new();
+        if self.skipped > 0 {
+            writeln!(dump, "{}: ({} bytes not shown)", self.name, self.skipped).unwrap();
+        }
+        writeln!(dump, "{}: received {} bytes", self.name, bytes.len()).unwrap();
+        dump += &hex_dump(offset, &bytes[..shown]);
+        self.skipped = (bytes.len() - shown) as u64;
+        Some(dump)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::time::Duration;
+
+    use super::{hex_dump, Dumper};
+
+    #[test]
+    fn xxd() {
+        assert_eq!(
+            hex_dump(16, b"Hello, world!\n\x00\xffabc"),
+            "00000010: 4865 6c6c 6f2c 2077 6f72 6c64 210a 00ff  Hello, world!...\n\
+             00000020: 6162 63                                  abc\n"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited() {
+        let mut dumper = Dumper::new("test".to_string(), 20);
+        let dump = dumper.dump(0, &[b'a'; 16]).unwrap();
+        assert_eq!(dump.lines().count(), 2);
+        // Only 4 more bytes this second
+        let dump = dumper.dump(16, &[b'b'; 16]).unwrap();
+        assert!(dump.ends_with("00000010: 6262 6262                                bbbb\n"));
+        assert_eq!(dumper.dump(32, b"c"), None);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let dump = dumper.dump(33, b"d").unwrap();
+        assert!(dump.starts_with("test: (13 bytes not shown)\n"));
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    future::pending,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::mpsc,
+    time::Interval,
+};
+use tokio_util::codec::FramedRead;
+
+use crate::{
+    clock::{Clock, Tokio},
+    config::ADDR,
+};
+
+use self::{
+    message::{Camera, Request, RequestDecoder},
+    tickets::{Roads, Ticket},
+};
+
+mod message;
+mod tickets;
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+    serve(listener, Tokio).await
+}
+
+/// Serves each client, with heartbeats timed by `clock`.
+async fn serve<C>(listener: TcpListener, clock: C) -> Result<()>
+where
+    C: Clock + Copy + Send + Sync + 'static,
+{
+    let roads = Arc::new(Mutex::new(Roads::default()));
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        // Heartbeats and tickets are small, and shouldn't wait for an ACK.
+        socket.set_nodelay(true)?;
+        let roads = roads.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer, &roads, &clock).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+/// A client, which may identify as a camera or a dispatcher, and ask for
+/// heartbeats, once each.
+#[derive(Default)]
+struct Session {
+    camera: Option<Camera>,
+    /// Its id with `Roads`, and where its tickets arrive.
+    dispatcher: Option<(u64, mpsc::UnboundedReceiver<Ticket>)>,
+    wants_heartbeat: bool,
+    heartbeat: Option<Interval>,
+}
+
+/// Serves a client until it closes the connection, or breaks the protocol,
+/// which is answered with an error message before closing.
+async fn process<R, W>(
+    reader: R,
+    mut writer: W,
+    roads: &Mutex<Roads>,
+    clock: &impl Clock,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut requests = FramedRead::new(reader, RequestDecoder);
+    let mut session = Session::default();
+    let result: Result<()> = async {
+        loop {
+            tokio::select! {
+                request = requests.next() => match request {
+                    Some(request) => session.handle(request?, roads, clock)?,
+                    None => return Ok(()),
+                },
+                () = heartbeat(&mut session.heartbeat) => {
+                    writer.write_all(message::HEARTBEAT).await?
+                }
+                ticket = next_ticket(&mut session.dispatcher) => {
+                    writer.write_all(&message::ticket(&ticket)).await?
+                }
+            }
+        }
+    }
+    .await;
+    if let Some((id, _)) = session.dispatcher {
+        roads.lock().unwrap().remove_dispatcher(id);
+    }
+    if let Err(e) = &result {
+        // Whatever went wrong, it's worth a try, but it may be how.
+        let _ = writer.write_all(&message::error(&e.to_string())).await;
+    }
+    result
+}
+
+impl Session {
+    fn handle(&mut self, request: Request, roads: &Mutex<Roads>, clock: &impl Clock) -> Result<()> {
+        match request {
+            Request::Plate { plate, timestamp } => {
+                let Some(camera) = self.camera else {
+                    bail!("only a camera can report a plate");
+                };
+                roads.lock().unwrap().observe(camera, &plate, timestamp);
+            }
+            Request::WantHeartbeat { interval } => {
+                if self.wants_heartbeat {
+                    bail!("already asked for a heartbeat");
+                }
+                self.wants_heartbeat = true;
+                if interval > 0 {
+                    let period = Duration::from_millis(u64::from(interval) * 100);
+                    self.heartbeat = Some(clock.interval(period));
+                }
+            }
+            Request::IAmCamera(camera) => {
+                self.identify()?;
+                self.camera = Some(camera);
+            }
+            Request::IAmDispatcher { roads: dispatching } => {
+                self.identify()?;
+                let (tx, rx) = mpsc::unbounded_channel();
+                let id = roads.lock().unwrap().add_dispatcher(&dispatching, tx);
+                self.dispatcher = Some((id, rx));
+            }
+        }
+        Ok(())
+    }
+
+    fn identify(&self) -> Result<()> {
+        if self.camera.is_some() || self.dispatcher.is_some() {
+            bail!("already identified");
+        }
+        Ok(())
+    }
+}
+
+/// Waits for the next heartbeat, if the client asked for them.
+async fn heartbeat(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(heartbeat) => {
+            heartbeat.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+/// Waits for the next ticket, if the client is a dispatcher.
+async fn next_ticket(dispatcher: &mut Option<(u64, mpsc::UnboundedReceiver<Ticket>)>) -> Ticket {
+    match dispatcher {
+        // Roads holds the sender until the session removes it.
+        Some((_, tickets)) => match tickets.recv().await {
+            Some(ticket) => ticket,
+            None => pending().await,
+        },
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, time::Duration};
+
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+    use crate::{
+        clients::speed_daemon::{Client, Message, Ticket},
+        clock::{Clock, Tokio},
+        testutil::{
+            clock::{assert_on_time, Paused},
+            duplex, TestServer, TIMEOUT,
+        },
+    };
+
+    use super::{process, serve, Roads};
+
+    type TcpClient = Client<OwnedReadHalf, OwnedWriteHalf>;
+
+    async fn connect(server: &TestServer) -> TcpClient {
+        Client::connect(&server.addr.to_string()).await.unwrap()
+    }
+
+    async fn camera(server: &TestServer, road: u16, mile: u16, limit: u16) -> TcpClient {
+        let mut client = connect(server).await;
+        client.camera(road, mile, limit).await.unwrap();
+        client
+    }
+
+    async fn dispatcher(server: &TestServer, roads: &[u16]) -> TcpClient {
+        let mut client = connect(server).await;
+        client.dispatcher(roads).await.unwrap();
+        client
+    }
+
+    async fn recv(client: &mut TcpClient) -> Option<Message> {
+        tokio::time::timeout(TIMEOUT, client.recv())
+            .await
+            .expect("timed out waiting for the server")
+            .unwrap()
+    }
+
+    fn spec_ticket() -> Ticket {
+        Ticket {
+            plate: "UN1X".to_string(),
+            road: 123,
+            mile1: 8,
+            timestamp1: 0,
+            mile2: 9,
+            timestamp2: 45,
+            speed: 8000,
+        }
+    }
+
+    #[tokio::test]
+    async fn spec_example() {
+        let server = TestServer::start(|listener| serve(listener, Tokio)).await;
+        let mut first = camera(&server, 123, 8, 60).await;
+        let mut second = camera(&server, 123, 9, 60).await;
+        let mut dispatcher = dispatcher(&server, &[123]).await;
+        first.plate("UN1X", 0).await.unwrap();
+        second.plate("UN1X", 45).await.unwrap();
+        assert_eq!(
+            recv(&mut dispatcher).await,
+            Some(Message::Ticket(spec_ticket()))
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatcher_comes_later() {
+        let server = TestServer::start(|listener| serve(listener, Tokio)).await;
+        let mut first = camera(&server, 123, 8, 60).await;
+        let mut second = camera(&server, 123, 9, 60).await;
+        first.plate("UN1X", 0).await.unwrap();
+        second.plate("UN1X", 45).await.unwrap();
+        // A heartbeat on the camera's connection shows the server has had
+        // its plate.
+        second.want_heartbeat(1).await.unwrap();
+        assert_eq!(recv(&mut second).await, Some(Message::Heartbeat));
+        let mut elsewhere = dispatcher(&server, &[1]).await;
+        let mut dispatcher = dispatcher(&server, &[1, 123]).await;
+        assert_eq!(
+            recv(&mut dispatcher).await,
+            Some(Message::Ticket(spec_ticket()))
+        );
+        elsewhere.want_heartbeat(1).await.unwrap();
+        assert_eq!(recv(&mut elsewhere).await, Some(Message::Heartbeat));
+    }
+
+    #[tokio::test]
+    async fn heartbeats() {
+        let server = TestServer::start(|listener| serve(listener, Tokio)).await;
+        // Before identifying, and as a camera.
+        let mut client = connect(&server).await;
+        client.want_heartbeat(1).await.unwrap();
+        assert_eq!(recv(&mut client).await, Some(Message::Heartbeat));
+        client.camera(1, 2, 3).await.unwrap();
+        assert_eq!(recv(&mut client).await, Some(Message::Heartbeat));
+    }
+
+    /// Heartbeats come at exactly the interval asked for, from when it's
+    /// asked for, and none come for an interval of 0.
+    #[tokio::test]
+    async fn heartbeat_timing() {
+        let clock = Paused::start();
+        let (reader, writer) = duplex(|reader, writer| async move {
+            let roads = Mutex::new(Roads::default());
+            process(reader, writer, &roads, &clock).await
+        });
+        let mut client = Client::new(reader, writer);
+        client.want_heartbeat(25).await.unwrap();
+        let start = clock.now();
+        for i in 1..=4 {
+            assert_eq!(client.recv().await.unwrap(), Some(Message::Heartbeat));
+            assert_on_time(clock.now(), start + Duration::from_millis(2500) * i);
+        }
+
+        let (reader, writer) = duplex(|reader, writer| async move {
+            let roads = Mutex::new(Roads::default());
+            process(reader, writer, &roads, &clock).await
+        });
+        let mut client = Client::new(reader, writer);
+        client.want_heartbeat(0).await.unwrap();
+        let day = Duration::from_secs(86400);
+        assert!(tokio::time::timeout(day, client.recv()).await.is_err());
+    }
+
+    /// Runs the camera simulator and the dispatcher CLI against the server,
+    /// the way the README suggests checking a scenario.
+    #[tokio::test]
+    async fn simulator_and_dispatcher() {
+        let server = TestServer::start(|listener| serve(listener, Tokio)).await;
+        let addr = server.addr.to_string();
+        let dir = std::env::temp_dir().join(format!("speed-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let scenario = dir.join("scenario.txt");
+        let record = dir.join("tickets.txt");
+        std::fs::write(
+            &scenario,
+            "road 123 limit 60 cameras 8,9,20\n\
+            road 7 limit 30 cameras 0,5\n\
+            # Speeding twice on day 0, but only one ticket.\n\
+            car UN1X road 123 at 0 mile 8 speed 80\n\
+            car SLOW road 123 at 0 mile 8 speed 50\n\
+            car RE5 road 7 at 86400 mile 0 speed 60\n",
+        )
+        .unwrap();
+        let args = |args: &[&str]| -> Vec<String> {
+            [&["--addr", &addr], args]
+                .concat()
+                .into_iter()
+                .map(String::from)
+                .collect()
+        };
+
+        let dispatcher = args(&[
+            "speed-dispatcher",
+            "--record",
+            record.to_str().unwrap(),
+            "123",
+            "7",
+        ]);
+        // The dispatcher runs until the server closes, so it's raced with
+        // the cameras and waiting for its tickets.
+        let cameras = args(&["speed-cameras", scenario.to_str().unwrap()]);
+        let recorded = async {
+            crate::cli::run(&cameras).await.unwrap();
+            loop {
+                let recorded = std::fs::read_to_string(&record).unwrap_or_default();
+                if recorded.lines().count() >= 2 {
+                    return recorded;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        };
+        let recorded = tokio::time::timeout(TIMEOUT, async {
+            tokio::select! {
+                result = crate::cli::run(&dispatcher) => panic!("dispatcher ended: {result:?}"),
+                recorded = recorded => recorded,
+            }
+        })
+        .await
+        .expect("timed out waiting for tickets");
+        let mut recorded: Vec<_> = recorded.lines().collect();
+        recorded.sort();
+        assert_eq!(
+            recorded,
+            ["RE5 7 0 86400 5 86700 6000", "UN1X 123 8 0 9 45 8000"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let server = TestServer::start(|listener| serve(listener, Tokio)).await;
+        let mut client = connect(&server).await;
+        client.plate("UN1X", 0).await.unwrap();
+        let error = "only a camera can report a plate".to_string();
+        assert_eq!(recv(&mut client).await, Some(Message::Error(error)));
+        assert_eq!(recv(&mut client).await, None);
+
+        let mut client = camera(&server, 1, 2, 3).await;
+        client.dispatcher(&[1]).await.unwrap();
+        let error = "already identified".to_string();
+        assert_eq!(recv(&mut client).await, Some(Message::Error(error)));
+        assert_eq!(recv(&mut client).await, None);
+
+        let mut client = connect(&server).await;
+        client.want_heartbeat(0).await.unwrap();
+        client.want_heartbeat(0).await.unwrap();
+        let error = "already asked for a heartbeat".to_string();
+        assert_eq!(recv(&mut client).await, Some(Message::Error(error)));
+
+        let mut client = server.connect().await;
+        client.send(b"\x41").await;
+        // An error message of "unknown message type 0x41".
+        assert_eq!(
+            client.recv_to_end().await,
+            b"\x10\x19unknown message type 0x41"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! The wire format: a type byte, then big-endian integers, and strings of up
+//! to 255 bytes prefixed with their length.
+
+use anyhow::{bail, Result};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::Decoder,
+};
+
+use super::tickets::Ticket;
+
+pub(super) const HEARTBEAT: &[u8] = &[0x41];
+
+/// A camera at `mile` on `road`, where the limit is `limit` miles per hour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Camera {
+    pub(super) road: u16,
+    pub(super) mile: u16,
+    pub(super) limit: u16,
+}
+
+/// What a client sends the server.
+#[derive(Debug, PartialEq)]
+pub(super) enum Request {
+    Plate {
+        plate: String,
+        timestamp: u32,
+    },
+    /// In tenths of a second, or 0 for none.
+    WantHeartbeat {
+        interval: u32,
+    },
+    IAmCamera(Camera),
+    IAmDispatcher {
+        roads: Vec<u16>,
+    },
+}
+
+pub(super) struct RequestDecoder;
+
+impl Decoder for RequestDecoder {
+    type Item = Request;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(&kind) = src.first() else {
+            return Ok(None);
+        };
+        if !matches!(kind, 0x20 | 0x40 | 0x80 | 0x81) {
+            bail!("unknown message type {kind:#04x}");
+        }
+        let mut fields = Fields(&src[1..]);
+        let Some(request) = parse(kind, &mut fields) else {
+            return Ok(None);
+        };
+        let len = src.len() - fields.0.len();
+        src.advance(len);
+        Ok(Some(request))
+    }
+}
+
+/// The message of type `kind` in `fields`, or None if it hasn't all arrived.
+fn parse(kind: u8, fields: &mut Fields) -> Option<Request> {
+    Some(match kind {
+        0x20 => Request::Plate {
+            plate: fields.str()?,
+            timestamp: fields.u32()?,
+        },
+        0x40 => Request::WantHeartbeat {
+            interval: fields.u32()?,
+        },
+        0x80 => Request::IAmCamera(Camera {
+            road: fields.u16()?,
+            mile: fields.u16()?,
+            limit: fields.u16()?,
+        }),
+        0x81 => {
+            let len = fields.u8()?;
+            let roads = (0..len).map(|_| fields.u16()).collect::<Option<_>>()?;
+            Request::IAmDispatcher { roads }
+        }
+        _ => unreachable!("checked by the decoder"),
+    })
+}
+
+/// What's left of a message, read from the front.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take().map(u8::from_be_bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+pub(super) fn error(message: &str) -> Vec<u8> {
+    let mut bytes = vec![0x10];
+    push_str(&mut bytes, message);
+    bytes
+}
+
+pub(super) fn ticket(ticket: &Ticket) -> Vec<u8> {
+    let mut bytes = vec![0x21];
+    push_str(&mut bytes, &ticket.plate);
+    bytes.extend(ticket.road.to_be_bytes());
+    bytes.extend(ticket.mile1.to_be_bytes());
+    bytes.extend(ticket.timestamp1.to_be_bytes());
+    bytes.extend(ticket.mile2.to_be_bytes());
+    bytes.extend(ticket.timestamp2.to_be_bytes());
+    bytes.extend(ticket.speed.to_be_bytes());
+    bytes
+}
+
+/// Appends `s`, cut to the 255 bytes a string can hold.
+fn push_str(bytes: &mut Vec<u8>, s: &str) {
+    let s = &s.as_bytes()[..s.len().min(255)];
+    bytes.push(s.len() as u8);
+    bytes.extend(s);
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use crate::testutil::{decode_chunks, split_at};
+
+    use super::{Camera, Request, RequestDecoder};
+
+    /// The spec's examples of each message a client sends, one after another.
+    const EXAMPLES: &[u8] = b"\x20\x04UN1X\x00\x00\x03\xe8\
+        \x40\x00\x00\x00\x0a\
+        \x80\x00\x42\x00\x64\x00\x3c\
+        \x81\x03\x00\x42\x01\x70\x13\x88";
+
+    fn examples() -> Vec<Request> {
+        vec![
+            Request::Plate {
+                plate: "UN1X".to_string(),
+                timestamp: 1000,
+            },
+            Request::WantHeartbeat { interval: 10 },
+            Request::IAmCamera(Camera {
+                road: 66,
+                mile: 100,
+                limit: 60,
+            }),
+            Request::IAmDispatcher {
+                roads: vec![66, 368, 5000],
+            },
+        ]
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            decode_chunks(RequestDecoder, &[EXAMPLES]).unwrap(),
+            examples()
+        );
+        let error = decode_chunks(RequestDecoder, &[b"\x41"]).err().unwrap();
+        assert_eq!(error.to_string(), "unknown message type 0x41");
+        // The unknown type is an error even if good messages came first.
+        let sent = [EXAMPLES, b"\x21"].concat();
+        assert!(decode_chunks(RequestDecoder, &[&sent]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn split(cuts in prop::collection::vec(any::<usize>(), 0..8)) {
+            let decoded = decode_chunks(RequestDecoder, &split_at(EXAMPLES, &cuts)).unwrap();
+            prop_assert_eq!(decoded, examples());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! What the cameras have seen, the tickets it adds up to, and the
+//! dispatchers they're sent to.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
+};
+
+use tokio::sync::mpsc;
+
+use super::message::Camera;
+
+/// Seconds in a day, for the one ticket per car per day.
+const DAY: u32 = 86400;
+
+/// A ticket, with `speed` in hundredths of a mile per hour, from the earlier
+/// observation at `mile1` to the later one at `mile2`.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Ticket {
+    pub(super) plate: String,
+    pub(super) road: u16,
+    pub(super) mile1: u16,
+    pub(super) timestamp1: u32,
+    pub(super) mile2: u16,
+    pub(super) timestamp2: u32,
+    pub(super) speed: u16,
+}
+
+/// The state shared by every connection.
+#[derive(Default)]
+pub(super) struct Roads {
+    /// Where each car has been seen on each road, by timestamp.
+    seen: HashMap<(String, u16), BTreeMap<u32, u16>>,
+    /// The days each car has had a ticket for.
+    ticketed: HashMap<String, HashSet<u32>>,
+    /// The dispatchers for each road, by id, the first to connect first.
+    dispatchers: HashMap<u16, BTreeMap<u64, mpsc::UnboundedSender<Ticket>>>,
+    next_id: u64,
+    /// Tickets for roads with no dispatcher yet, sent to the first to come.
+    pending: HashMap<u16, Vec<Ticket>>,
+}
+
+impl Roads {
+    /// Records `camera` seeing `plate` at `timestamp`, and sends a ticket
+    /// for each observation either side of it in time, if the car was
+    /// speeding between them and had no ticket yet for any of the days
+    /// between. Only the first observation at a timestamp counts.
+    pub(super) fn observe(&mut self, camera: Camera, plate: &str, timestamp: u32) {
+        let seen = self
+            .seen
+            .entry((plate.to_string(), camera.road))
+            .or_default();
+        if seen.contains_key(&timestamp) {
+            return;
+        }
+        seen.insert(timestamp, camera.mile);
+        let now = (timestamp, camera.mile);
+        let before = seen.range(..timestamp).next_back();
+        let after = seen
+            .range((Bound::Excluded(timestamp), Bound::Unbounded))
+            .next();
+        let pairs = [
+            before.map(|(&t, &mile)| ((t, mile), now)),
+            after.map(|(&t, &mile)| (now, (t, mile))),
+        ];
+        for (first, second) in pairs.into_iter().flatten() {
+            let Some(speed) = speed(camera.limit, first, second) else {
+                continue;
+            };
+            let days = first.0 / DAY..=second.0 / DAY;
+            let ticketed = self.ticketed.entry(plate.to_string()).or_default();
+            if days.clone().any(|day| ticketed.contains(&day)) {
+                continue;
+            }
+            ticketed.extend(days);
+            self.dispatch(Ticket {
+                plate: plate.to_string(),
+                road: camera.road,
+                mile1: first.1,
+                timestamp1: first.0,
+                mile2: second.1,
+                timestamp2: second.0,
+                speed,
+            });
+        }
+    }
+
+    /// Registers a dispatcher for `roads`, sending it any tickets waiting
+    /// for one, and returns its id for `remove_dispatcher`.
+    pub(super) fn add_dispatcher(
+        &mut self,
+        roads: &[u16],
+        tickets: mpsc::UnboundedSender<Ticket>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        for &road in roads {
+            let dispatchers = self.dispatchers.entry(road).or_default();
+            dispatchers.insert(id, tickets.clone());
+            for ticket in self.pending.remove(&road).unwrap_or_default() {
+                self.dispatch(ticket);
+            }
+        }
+        id
+    }
+
+    pub(super) fn remove_dispatcher(&mut self, id: u64) {
+        for dispatchers in self.dispatchers.values_mut() {
+            dispatchers.remove(&id);
+        }
+    }
+
+    /// Sends `ticket` to a dispatcher for its road, or keeps it until there
+    /// is one.
+    fn dispatch(&mut self, mut ticket: Ticket) {
+        let dispatchers = self.dispatchers.entry(ticket.road).or_default();
+        // A dispatcher whose connection has gone, but hasn't been removed
+        // yet, hands the ticket back.
+        while let Some(entry) = dispatchers.first_entry() {
+            match entry.get().send(ticket) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(returned)) => {
+                    ticket = returned;
+                    entry.remove();
+                }
+            }
+        }
+        self.pending.entry(ticket.road).or_default().push(ticket);
+    }
+}
+
+/// The average speed between two observations, in hundredths of a mile per
+/// hour, if it's 0.5 mph or more over `limit`.
+fn speed(limit: u16, (t1, mile1): (u32, u16), (t2, mile2): (u32, u16)) -> Option<u16> {
+    let seconds = u64::from(t2 - t1);
+    let miles = u64::from(mile1.abs_diff(mile2));
+    if miles * 3600 * 100 < (u64::from(limit) * 100 + 50) * seconds {
+        return None;
+    }
+    let speed = (miles * 3600 * 100 + seconds / 2) / seconds;
+    Some(speed.try_into().unwrap_or(u16::MAX))
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::{speed, Camera, Roads, Ticket, DAY};
+
+    fn camera(mile: u16) -> Camera {
+        Camera {
+            road: 123,
+            mile,
+            limit: 60,
+        }
+    }
+
+    #[test]
+    fn speeds() {
+        // The spec's example: a mile in 45 seconds.
+        assert_eq!(speed(60, (0, 8), (45, 9)), Some(8000));
+        assert_eq!(speed(60, (0, 9), (45, 8)), Some(8000));
+        // 60.5 mph is a ticket, and just under isn't.
+        assert_eq!(speed(60, (0, 0), (7200, 121)), Some(6050));
+        assert_eq!(speed(60, (0, 0), (7201, 121)), None);
+        assert_eq!(speed(60, (0, 0), (1, u16::MAX)), Some(u16::MAX));
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut roads = Roads::default();
+        let (tx, mut tickets) = mpsc::unbounded_channel();
+        roads.add_dispatcher(&[123], tx);
+        // The later observation arrives first, and the ticket is still
+        // from the earlier to the later.
+        roads.observe(camera(9), "UN1X", 45);
+        roads.observe(camera(8), "UN1X", 0);
+        // The same timestamp again counts for nothing.
+        roads.observe(camera(20), "UN1X", 45);
+        let ticket = Ticket {
+            plate: "UN1X".to_string(),
+            road: 123,
+            mile1: 8,
+            timestamp1: 0,
+            mile2: 9,
+            timestamp2: 45,
+            speed: 8000,
+        };
+        assert_eq!(tickets.try_recv().unwrap(), ticket);
+        assert!(tickets.try_recv().is_err());
+    }
+
+    #[test]
+    fn one_a_day() {
+        let mut roads = Roads::default();
+        let (tx, mut tickets) = mpsc::unbounded_channel();
+        roads.add_dispatcher(&[123], tx);
+        // Speeding across the end of day 0, then on day 1, then on day 2.
+        roads.observe(camera(0), "UN1X", DAY - 60);
+        roads.observe(camera(10), "UN1X", DAY + 60);
+        roads.observe(camera(20), "UN1X", DAY + 600);
+        roads.observe(camera(30), "UN1X", 2 * DAY);
+        roads.observe(camera(40), "UN1X", 2 * DAY + 60);
+        let days: Vec<_> = std::iter::from_fn(|| tickets.try_recv().ok())
+            .map(|ticket| (ticket.timestamp1 / DAY, ticket.timestamp2 / DAY))
+            .collect();
+        assert_eq!(days, [(0, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn dispatchers() {
+        let mut roads = Roads::default();
+        let (tx, mut other) = mpsc::unbounded_channel();
+        roads.add_dispatcher(&[1], tx);
+        // A dispatcher whose connection has gone is skipped, so the ticket's
+        // held until another dispatcher for the road comes.
+        let (tx, gone) = mpsc::unbounded_channel();
+        roads.add_dispatcher(&[123], tx);
+        drop(gone);
+        roads.observe(camera(8), "UN1X", 0);
+        roads.observe(camera(9), "UN1X", 45);
+        assert!(other.try_recv().is_err());
+        let (tx, mut tickets) = mpsc::unbounded_channel();
+        let id = roads.add_dispatcher(&[1, 123], tx);
+        assert_eq!(tickets.try_recv().unwrap().plate, "UN1X");
+
+        roads.observe(camera(8), "XYZ", 0);
+        roads.observe(camera(9), "XYZ", 45);
+        assert_eq!(tickets.try_recv().unwrap().plate, "XYZ");
+        roads.remove_dispatcher(id);
+        roads.observe(camera(8), "ABC", 0);
+        roads.observe(camera(9), "ABC", 45);
+        assert!(tickets.try_recv().is_err());
+        assert_eq!(roads.pending[&123][0].plate, "ABC");
+    }
+}
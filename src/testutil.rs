@@ -0,0 +1,186 @@
+//! Runs a server on an ephemeral port inside the test process, for end-to-end
+//! tests over real sockets, and a client to talk to it.
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+    },
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    task::JoinHandle,
+};
+use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+pub(crate) mod alloc;
+pub(crate) mod chaos;
+pub(crate) mod clock;
+pub(crate) mod transcript;
+
+/// How long a client waits for the server before failing the test, rather
+/// than hanging it.
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server stopped when dropped, or by `shutdown`.
+pub(crate) struct TestServer {
+    pub(crate) addr: SocketAddr,
+    task: JoinHandle<Result<()>>,
+}
+
+impl TestServer {
+    /// Binds to a free port on localhost, and hands the listener to `serve`.
+    pub(crate) async fn start<F, Fut>(serve: F) -> TestServer
+    where
+        F: FnOnce(TcpListener) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(serve(listener));
+        TestServer { addr, task }
+    }
+
+    pub(crate) async fn connect(&self) -> TestClient {
+        TestClient::connect(self.addr).await
+    }
+
+    /// Stops accepting, returning the server's error if it had already failed.
+    /// Connections it spawned may outlive it.
+    pub(crate) async fn shutdown(mut self) -> Result<()> {
+        self.task.abort();
+        match (&mut self.task).await {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Panics rather than returning errors, and if the server takes longer than
+/// `TIMEOUT`, since it's only for tests.
+pub(crate) struct TestClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TestClient {
+    pub(crate) async fn connect(addr: SocketAddr) -> TestClient {
+        let stream = timeout(TcpStream::connect(addr)).await.unwrap();
+        let (reader, writer) = stream.into_split();
+        TestClient {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    pub(crate) async fn send(&mut self, bytes: &[u8]) {
+        timeout(self.writer.write_all(bytes)).await.unwrap();
+    }
+
+    pub(crate) async fn send_line(&mut self, line: &str) {
+        self.send(format!("{line}\n").as_bytes()).await;
+    }
+
+    /// Closes our side, so the server sees EOF.
+    pub(crate) async fn close(&mut self) {
+        timeout(self.writer.shutdown()).await.unwrap();
+    }
+
+    pub(crate) async fn recv_exact(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        timeout(self.reader.read_exact(&mut buf)).await.unwrap();
+        buf
+    }
+
+    /// The next line, without its newline.
+    pub(crate) async fn recv_line(&mut self) -> String {
+        let mut line = String::new();
+        timeout(self.reader.read_line(&mut line)).await.unwrap();
+        assert!(line.ends_with('\n'), "expected a line, got {line:?}");
+        line.pop();
+        line
+    }
+
+    pub(crate) async fn recv_json<T: DeserializeOwned>(&mut self) -> T {
+        let line = self.recv_line().await;
+        serde_json::from_str(&line).unwrap_or_else(|e| panic!("{line:?}: {e}"))
+    }
+
+    /// Everything until the server closes the connection.
+    pub(crate) async fn recv_to_end(&mut self) -> Vec<u8> {
+        let mut buf = vec![];
+        timeout(self.reader.read_to_end(&mut buf)).await.unwrap();
+        buf
+    }
+}
+
+/// Either end of an in-memory pipe made by `duplex`.
+pub(crate) type DuplexReader = ReadHalf<DuplexStream>;
+pub(crate) type DuplexWriter = WriteHalf<DuplexStream>;
+
+/// Spawns `handler` on one end of an in-memory pipe, and returns the other,
+/// for wiring a client straight to a handler without sockets. The handler
+/// sees EOF once the returned writer is dropped.
+pub(crate) fn duplex<Fut>(
+    handler: impl FnOnce(DuplexReader, DuplexWriter) -> Fut,
+) -> (DuplexReader, DuplexWriter)
+where
+    Fut: Future + Send + 'static,
+    Fut::Output: Send,
+{
+    let (client, server) = tokio::io::duplex(64 << 10);
+    let (reader, writer) = tokio::io::split(server);
+    tokio::spawn(handler(reader, writer));
+    tokio::io::split(client)
+}
+
+/// Splits `stream` at each of `cuts`, taken modulo its length, for feeding a
+/// decoder split at arbitrary boundaries.
+pub(crate) fn split_at<'a>(stream: &'a [u8], cuts: &[usize]) -> Vec<&'a [u8]> {
+    let mut cuts: Vec<_> = cuts.iter().map(|cut| cut % (stream.len() + 1)).collect();
+    cuts.sort();
+    let mut chunks = vec![];
+    let mut start = 0;
+    for cut in cuts.into_iter().chain([stream.len()]) {
+        chunks.push(&stream[start..cut]);
+        start = cut;
+    }
+    chunks
+}
+
+/// Feeds `chunks` to `decoder` in turn, as reads from a connection, then ends
+/// the stream, returning everything decoded.
+pub(crate) fn decode_chunks<D: Decoder>(
+    mut decoder: D,
+    chunks: &[&[u8]],
+) -> Result<Vec<D::Item>, D::Error> {
+    let mut buf = BytesMut::new();
+    let mut items = vec![];
+    for chunk in chunks {
+        buf.extend_from_slice(chunk);
+        while let Some(item) = decoder.decode(&mut buf)? {
+            items.push(item);
+        }
+    }
+    while let Some(item) = decoder.decode_eof(&mut buf)? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+async fn timeout<T>(future: impl Future<Output = T>) -> T {
+    tokio::time::timeout(TIMEOUT, future)
+        .await
+        .expect("timed out waiting for the server")
+}
@@ -0,0 +1,64 @@
+//! Counts the allocations each thread makes, so that a test can bound what a
+//! hot path allocates. The counts are per thread, so tests running alongside
+//! don't disturb them, and code under test shouldn't be moved to another
+//! thread between readings.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn count() {
+    // Fails only while the thread is being torn down, when nothing reads it.
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// How many allocations and reallocations `f` made on this thread.
+pub(crate) fn allocations(f: impl FnOnce()) -> u64 {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[cfg(test)]
+mod test {
+    use super::allocations;
+
+    #[test]
+    fn counts() {
+        assert_eq!(allocations(|| {}), 0);
+        assert_eq!(allocations(|| drop(std::hint::black_box(vec![1u8]))), 1);
+        let mut v: Vec<u8> = Vec::with_capacity(1);
+        assert_eq!(allocations(|| v.extend([1, 2])), 1);
+    }
+}
@@ -0,0 +1,130 @@
+//! An IO wrapper that behaves like a bad network, for shaking out framing and
+//! partial write assumptions in handlers: reads return arbitrary prefixes of
+//! what's there, writes take arbitrary prefixes of what they're given, and
+//! either may be `Pending` for a while first.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub(crate) struct Chaos<T> {
+    inner: T,
+    /// `None` passes everything straight through.
+    state: Option<u64>,
+}
+
+impl<T> Chaos<T> {
+    /// The same `seed` always makes the same chaos.
+    pub(crate) fn new(inner: T, seed: u64) -> Chaos<T> {
+        Chaos {
+            inner,
+            // Never zero, which xorshift can't leave
+            state: Some(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1),
+        }
+    }
+
+    /// No chaos at all, for running the same test with and without it.
+    pub(crate) fn off(inner: T) -> Chaos<T> {
+        Chaos { inner, state: None }
+    }
+
+    fn next(&mut self) -> Option<u64> {
+        let state = self.state.as_mut()?;
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        Some(*state)
+    }
+
+    /// One in four polls is `Pending`, waking straight away so that it's
+    /// polled again, as if the data were still on its way.
+    fn stall(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.next().is_some_and(|n| n % 4 == 0) {
+            cx.waker().wake_by_ref();
+            return true;
+        }
+        false
+    }
+
+    /// How much of `len` bytes to let through, at least one unless there are
+    /// none.
+    fn take(&mut self, len: usize) -> usize {
+        match (len, self.next()) {
+            (0, _) | (_, None) => len,
+            (len, Some(n)) => 1 + (n % len as u64) as usize,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Chaos<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        let n = self.take(buf.remaining());
+        let mut limited = ReadBuf::new(&mut buf.initialize_unfilled()[..n]);
+        let poll = Pin::new(&mut self.inner).poll_read(cx, &mut limited);
+        let filled = limited.filled().len();
+        buf.advance(filled);
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Chaos<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        let n = self.take(buf.len());
+        Pin::new(&mut self.inner).poll_write(cx, &buf[..n])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::Chaos;
+
+    #[tokio::test]
+    async fn whole_stream_survives() {
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        for seed in 0..8 {
+            let mut reader = Chaos::new(&data[..], seed);
+            let mut read = vec![];
+            reader.read_to_end(&mut read).await.unwrap();
+            assert_eq!(read, data);
+
+            let mut writer = Chaos::new(vec![], seed);
+            writer.write_all(&data).await.unwrap();
+            writer.shutdown().await.unwrap();
+            assert_eq!(writer.inner, data);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! A paused clock, for testing timers without waiting for them.
+
+use std::{future::Future, time::Duration};
+
+use tokio::time::{Instant, Interval};
+
+use crate::clock::{Clock, Tokio};
+
+/// Tokio's timers with time paused for the whole runtime. Time only passes
+/// when every task is waiting on a timer, and then jumps straight to the
+/// next one. So a test sees timers fire when they're due,
+/// however long they are, to within the millisecond tokio's timers round
+/// up by.
+#[derive(Clone, Copy)]
+pub(crate) struct Paused(());
+
+impl Paused {
+    /// Pauses time. Needs a current-thread runtime, as `#[tokio::test]`
+    /// gives, and panics if time is paused already.
+    pub(crate) fn start() -> Paused {
+        tokio::time::pause();
+        Paused(())
+    }
+}
+
+/// Asserts that something timed for `due` happened `at` it, as near as
+/// tokio's timers go: no earlier, and at most the millisecond they round up
+/// by later.
+#[track_caller]
+pub(crate) fn assert_on_time(at: Instant, due: Instant) {
+    assert!(
+        at >= due && at - due <= Duration::from_millis(1),
+        "{:?} late",
+        at.checked_duration_since(due)
+    );
+}
+
+impl Clock for Paused {
+    fn now(&self) -> Instant {
+        Tokio.now()
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        Tokio.sleep(duration)
+    }
+
+    fn interval(&self, period: Duration) -> Interval {
+        Tokio.interval(period)
+    }
+}
@@ -0,0 +1,146 @@
+//! Golden transcripts: files of what a client sent, chunk by chunk, and what
+//! the server should send back, replayed through a handler and diffed.
+//!
+//! Each line of a transcript is one of:
+//!
+//! - `> data`, a chunk the client sends, arriving as one read
+//! - `< data`, bytes the server should send, appended to those before
+//! - `# comment`, or blank
+//!
+//! Data is taken as written after the space, with the escapes `\n`, `\r`,
+//! `\t`, `\\` and `\xNN`. Only the whole output is compared, not how it was
+//! split into writes, and whether the handler returns an error isn't checked.
+//!
+//! Each transcript is replayed as written, and then through `Chaos` with a
+//! few seeds, so that it also covers reads and writes split anywhere.
+
+use std::{
+    fs,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::chaos::Chaos;
+use crate::{record::unescape, replay::diff};
+
+/// How many `Chaos` seeds each transcript is replayed with.
+const CHAOS_SEEDS: u64 = 8;
+
+/// The client's side of a transcript, handing out one chunk per read and EOF
+/// after the last.
+pub(crate) struct Sent(Vec<Vec<u8>>);
+
+impl AsyncRead for Sent {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(chunk) = self.0.first_mut() {
+            let n = chunk.len().min(buf.remaining());
+            buf.put_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.0.remove(0);
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Collects everything the handler writes.
+#[derive(Clone, Default)]
+pub(crate) struct Received(Arc<Mutex<Vec<u8>>>);
+
+impl AsyncWrite for Received {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Replays every transcript in `dir`, relative to the crate root, through a
+/// fresh `handler` each time, panicking with a diff of the first that
+/// differs.
+pub(crate) async fn replay_dir<F, Fut, T>(dir: &str, handler: F)
+where
+    F: Fn(Chaos<Sent>, Chaos<Received>) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no transcripts in {}", dir.display());
+    for path in paths {
+        let text = fs::read_to_string(&path).unwrap();
+        let (sent, expected) = parse(&text).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        for seed in [None].into_iter().chain((0..CHAOS_SEEDS).map(Some)) {
+            let received = Received::default();
+            let (reader, writer) = match seed {
+                Some(seed) => (
+                    Chaos::new(Sent(sent.clone()), seed),
+                    Chaos::new(received.clone(), seed),
+                ),
+                None => (Chaos::off(Sent(sent.clone())), Chaos::off(received.clone())),
+            };
+            handler(reader, writer).await;
+            let received = received.0.lock().unwrap();
+            if let Some(e) = diff(&expected, &received) {
+                panic!("{} (chaos seed {seed:?}): {e}", path.display());
+            }
+        }
+    }
+}
+
+/// The chunks sent, and everything expected back.
+fn parse(text: &str) -> Result<(Vec<Vec<u8>>, Vec<u8>), String> {
+    let mut sent = vec![];
+    let mut expected = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let error = |e| format!("line {}: {e}", i + 1);
+        match line.split_at_checked(2) {
+            Some(("> ", data)) => sent.push(unescape(data).map_err(error)?),
+            Some(("< ", data)) => expected.extend(unescape(data).map_err(error)?),
+            _ if line.is_empty() || line.starts_with('#') => {}
+            _ => return Err(error(format!("expected `> `, `< ` or `#`: {line:?}"))),
+        }
+    }
+    Ok((sent, expected))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    #[test]
+    fn parsing() {
+        let (sent, expected) =
+            parse("# a comment\n\n> a\\x00\\\\b\\n\n< ok\n> c\n< \\tdone\\n\n").unwrap();
+        assert_eq!(sent, [b"a\x00\\b\n".to_vec(), b"c".to_vec()]);
+        assert_eq!(expected, b"ok\tdone\n");
+        assert!(parse("oops").is_err());
+        assert!(parse("> \\q").is_err());
+        assert!(parse("> \\xzz").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tokio::net::UdpSocket;
+
+use crate::{config::ADDR, datagram::Datagram};
+
+/// Requests and responses must be shorter than this, so anything this long
+/// is dropped.
+const MAX_LEN: usize = 1000;
+
+/// The value of the `version` key, which inserts can't change.
+const VERSION: &str = concat!("protohackers ", env!("CARGO_PKG_VERSION"));
+
+pub async fn run() -> Result<()> {
+    let socket = UdpSocket::bind(ADDR).await?;
+    println!("Listening on {ADDR} (UDP)...");
+    serve(&socket).await
+}
+
+/// Answers requests from anyone: `key=value` inserts, up to the first `=`,
+/// and anything else retrieves a key. Retrieving a missing key goes
+/// unanswered.
+async fn serve(socket: &impl Datagram) -> Result<()> {
+    let mut values: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    let mut buf = vec![0; MAX_LEN];
+    loop {
+        let (n, addr) = socket.recv_from(&mut buf).await?;
+        if n == MAX_LEN {
+            continue;
+        }
+        let request = &buf[..n];
+        let response = match request.iter().position(|&b| b == b'=') {
+            Some(i) => {
+                let (key, value) = (&request[..i], &request[i + 1..]);
+                if key != b"version" {
+                    values.insert(key.to_vec(), value.to_vec());
+                }
+                continue;
+            }
+            None if request == b"version" => [request, b"=", VERSION.as_bytes()].concat(),
+            None => match values.get(request) {
+                Some(value) => [request, b"=", value].concat(),
+                None => continue,
+            },
+        };
+        if let Err(e) = socket.send_to(&response, addr).await {
+            // One unreachable sender shouldn't stop the others being answered
+            println!("{addr}: send failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        clients::unusual_database::Client,
+        datagram::{
+            network::{Endpoint, Faults, Network},
+            Datagram,
+        },
+    };
+
+    use super::{serve, MAX_LEN, VERSION};
+
+    /// Sends each of `requests`, returning the responses that come back.
+    async fn exchange(client: &Endpoint, server: SocketAddr, requests: &[&[u8]]) -> Vec<Vec<u8>> {
+        for request in requests {
+            client.send_to(request, server).await.unwrap();
+        }
+        let mut responses = vec![];
+        let mut buf = [0; 2 * MAX_LEN];
+        while let Ok(Ok((n, from))) =
+            tokio::time::timeout(Duration::from_millis(20), client.recv_from(&mut buf)).await
+        {
+            assert_eq!(from, server);
+            responses.push(buf[..n].to_vec());
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let network = Network::new(Faults::default(), 0);
+        let server = network.endpoint("10.0.0.1:7");
+        let client = network.endpoint("10.0.0.2:1000");
+        let addr = server.addr;
+        let task = tokio::spawn(async move { serve(&server).await });
+
+        let long = [b"key=".as_slice(), &[b'x'; MAX_LEN - 4]].concat();
+        let responses = exchange(
+            &client,
+            addr,
+            &[
+                b"foo=bar",
+                b"foo",
+                b"foo=bar=baz",
+                b"foo",
+                b"=empty key",
+                b"",
+                b"empty value=",
+                b"empty value",
+                b"missing",
+                b"version=changed",
+                b"version",
+                &long,
+                b"key",
+            ],
+        )
+        .await;
+        let version = [b"version=", VERSION.as_bytes()].concat();
+        let expected: Vec<&[u8]> = vec![
+            b"foo=bar",
+            b"foo=bar=baz",
+            b"=empty key",
+            b"empty value=",
+            &version,
+        ];
+        assert_eq!(responses, expected);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn client_and_checks() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let task = tokio::spawn(async move { serve(&socket).await });
+        let mut client = Client::connect(&addr).await.unwrap();
+        client.set_timeout(Duration::from_millis(100));
+        client.set("colour", "blue").await.unwrap();
+        assert_eq!(client.get("colour").await.unwrap().as_deref(), Some("blue"));
+        assert_eq!(client.get("nothing").await.unwrap(), None);
+        assert!(crate::check::run("kv", &addr).await.unwrap());
+        task.abort();
+    }
+}
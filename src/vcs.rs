@@ -0,0 +1,337 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+};
+use tokio_util::codec::FramedRead;
+
+use crate::config::ADDR;
+
+use self::{
+    command::{Command, CommandDecoder},
+    store::{Entry, Store},
+};
+
+pub mod client;
+mod command;
+mod disk;
+pub mod fuzz;
+mod path;
+mod store;
+
+/// Set to a directory to keep files there and load them on startup.
+const DIR_VAR: &str = "VCS_DIR";
+/// Set to override the largest PUT accepted, in bytes.
+const MAX_FILE_SIZE_VAR: &str = "VCS_MAX_FILE_SIZE";
+
+pub async fn run() -> Result<()> {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    println!("Listening on {ADDR}...");
+
+    let mut store = match std::env::var_os(DIR_VAR) {
+        Some(dir) => Store::open(dir)?,
+        None => Store::default(),
+    };
+    if let Ok(size) = std::env::var(MAX_FILE_SIZE_VAR) {
+        store.max_file_size = size
+            .parse()
+            .with_context(|| format!("{MAX_FILE_SIZE_VAR} must be a number of bytes"))?;
+    }
+    serve(listener, Arc::new(Mutex::new(store))).await
+}
+
+async fn serve(listener: TcpListener, store: Arc<Mutex<Store>>) -> Result<()> {
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        println!("Connected to {addr}");
+        let store = store.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = socket.split();
+            if let Err(e) = process(reader, writer, &store).await {
+                println!("{addr}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn process<R, W>(reader: R, mut writer: W, store: &Mutex<Store>) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let decoder = CommandDecoder::new(store.lock().unwrap().max_file_size);
+    let mut commands = FramedRead::new(reader, decoder);
+    writer.write_all(b"READY\n").await?;
+    while let Some(command) = commands.next().await {
+        let command = command?;
+        let illegal = matches!(command, Command::Illegal(_));
+        let mut response = respond(store, command);
+        if illegal {
+            writer.write_all(&response).await?;
+            return Ok(());
+        }
+        response.extend_from_slice(b"READY\n");
+        writer.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+fn respond(store: &Mutex<Store>, command: Command) -> Vec<u8> {
+    match command {
+        Command::Help => b"OK usage: HELP|GET|PUT|LIST\n".to_vec(),
+        Command::Put { path, .. }
+        | Command::PutRejected { path, .. }
+        | Command::Get { path, .. }
+            if !path::is_valid_file(&path) =>
+        {
+            b"ERR illegal file name\n".to_vec()
+        }
+        Command::PutRejected { error, .. } => format!("ERR {error}\n").into_bytes(),
+        Command::List { dir } if !path::is_valid_dir(&dir) => b"ERR illegal dir name\n".to_vec(),
+        Command::Put { path, data } => {
+            let revision = store.lock().unwrap().put(&path, data);
+            format!("OK r{revision}\n").into_bytes()
+        }
+        Command::Get { path, revision } => {
+            let data = match revision.as_deref().map(parse_revision) {
+                Some(None) => Err("no such revision"),
+                revision => store.lock().unwrap().get(&path, revision.flatten()),
+            };
+            match data {
+                Ok(data) => {
+                    let mut response = format!("OK {}\n", data.len()).into_bytes();
+                    response.extend_from_slice(&data);
+                    response
+                }
+                Err(e) => format!("ERR {e}\n").into_bytes(),
+            }
+        }
+        Command::List { dir } => {
+            let entries = store.lock().unwrap().list(&dir);
+            let mut response = format!("OK {}\n", entries.len());
+            for (name, entry) in entries {
+                match entry {
+                    Entry::File(revision) => response += &format!("{name} r{revision}\n"),
+                    Entry::Dir => response += &format!("{name} DIR\n"),
+                }
+            }
+            response.into_bytes()
+        }
+        Command::Usage(usage) => format!("ERR usage: {usage}\n").into_bytes(),
+        Command::Illegal(method) => format!("ERR illegal method: {method}\n").into_bytes(),
+        Command::TooLong => b"ERR line too long\n".to_vec(),
+    }
+}
+
+/// Parses a revision as given to GET: `r3`, or just `3`. Like the reference
+/// server, anything after the number is ignored, so `r3x` is revision 3.
+fn parse_revision(revision: &str) -> Option<usize> {
+    let revision = revision.strip_prefix(['r', 'R']).unwrap_or(revision);
+    let end = revision
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(revision.len());
+    revision[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::testutil::{transcript::replay_dir, TestServer};
+
+    use super::{client::Client, parse_revision, process, serve, Store};
+
+    #[tokio::test]
+    async fn help_and_errors() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"help\n")
+            .read(b"GET\n")
+            .read(b"jump /a\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"OK usage: HELP|GET|PUT|LIST\n")
+            .write(b"READY\n")
+            .write(b"ERR usage: GET file [revision]\n")
+            .write(b"READY\n")
+            .write(b"ERR illegal method: jump\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn long_line() {
+        // The rest of the line is skipped, and the next line is read.
+        let reader = tokio_test::io::Builder::new()
+            .read(b"GET /")
+            .read(&[b'a'; 1 << 16])
+            .read(b"\nHELP\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"ERR line too long\nREADY\n")
+            .write(b"OK usage: HELP|GET|PUT|LIST\nREADY\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn illegal_names() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"PUT a 2\nx\n")
+            .read(b"PUT /a/ 2\nx\n")
+            .read(b"GET /a//b\n")
+            .read(b"LIST /a//\n")
+            .read(b"GET /a\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"ERR illegal file name\nREADY\n")
+            .write(b"ERR illegal file name\nREADY\n")
+            .write(b"ERR illegal file name\nREADY\n")
+            .write(b"ERR illegal dir name\nREADY\n")
+            .write(b"ERR no such file\nREADY\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn binary_put() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"PUT /a 3\nab\xff")
+            .read(b"GET /a\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"ERR text files only\nREADY\n")
+            .write(b"ERR no such file\nREADY\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"PUT /a 5\nab")
+            .read(b"cde")
+            .read(b"PUT /a 4\nabcd")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"ERR file too large\nREADY\n")
+            .write(b"OK r1\nREADY\n")
+            .build();
+        let mut store = Store::default();
+        store.max_file_size = 4;
+        process(reader, writer, &Mutex::new(store)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_get() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"GET /a\n")
+            .read(b"PUT /a 4\none\n")
+            .read(b"PUT /a 4\ntwo\n")
+            .read(b"GET /a\n")
+            .read(b"GET /a r1\n")
+            .read(b"GET /a r3\n")
+            .read(b"GET /a x\n")
+            .read(b"GET /a 1\n")
+            .read(b"PUT /a 4\ntwo\n")
+            .read(b"PUT /b 4\ntwo\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"ERR no such file\nREADY\n")
+            .write(b"OK r1\nREADY\n")
+            .write(b"OK r2\nREADY\n")
+            .write(b"OK 4\ntwo\nREADY\n")
+            .write(b"OK 4\none\nREADY\n")
+            .write(b"ERR no such revision\nREADY\n")
+            .write(b"ERR no such revision\nREADY\n")
+            .write(b"OK 4\none\nREADY\n")
+            .write(b"OK r2\nREADY\n")
+            .write(b"OK r1\nREADY\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn list() {
+        let reader = tokio_test::io::Builder::new()
+            .read(b"LIST /\n")
+            .read(b"PUT /a/b 1\nx")
+            .read(b"PUT /a/c/d 1\nx")
+            .read(b"PUT /a/b 1\ny")
+            .read(b"LIST /\n")
+            .read(b"LIST /a\n")
+            .build();
+        let writer = tokio_test::io::Builder::new()
+            .write(b"READY\n")
+            .write(b"OK 0\nREADY\n")
+            .write(b"OK r1\nREADY\n")
+            .write(b"OK r1\nREADY\n")
+            .write(b"OK r2\nREADY\n")
+            .write(b"OK 1\na/ DIR\nREADY\n")
+            .write(b"OK 2\nb r2\nc/ DIR\nREADY\n")
+            .build();
+        process(reader, writer, &Mutex::default()).await.unwrap();
+    }
+
+    #[test]
+    fn revisions() {
+        let cases = [
+            ("r3", Some(3)),
+            ("3", Some(3)),
+            ("R3", Some(3)),
+            ("r03", Some(3)),
+            ("r3x", Some(3)),
+            ("r0", Some(0)),
+            ("r", None),
+            ("", None),
+            ("x", None),
+            ("rr3", None),
+            ("r-1", None),
+            ("r99999999999999999999999", None),
+        ];
+        for (revision, expected) in cases {
+            assert_eq!(parse_revision(revision), expected, "{revision:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let store = Arc::new(Mutex::new(Store::default()));
+        let server = TestServer::start(|listener| serve(listener, store)).await;
+        let addr = server.addr.to_string();
+        let mut writer = Client::connect(&addr).await.unwrap();
+        assert_eq!(writer.put("/a/b.txt", b"one\n").await.unwrap(), 1);
+        assert_eq!(writer.put("/a/b.txt", b"two\n").await.unwrap(), 2);
+
+        // Files are shared by every connection.
+        let mut reader = Client::connect(&addr).await.unwrap();
+        assert_eq!(reader.get("/a/b.txt", None).await.unwrap(), b"two\n");
+        assert_eq!(reader.get("/a/b.txt", Some(1)).await.unwrap(), b"one\n");
+        assert_eq!(reader.list("/a").await.unwrap(), ["b.txt r2"]);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let store = Arc::new(Mutex::new(Store::default()));
+        let server = TestServer::start(|listener| serve(listener, store)).await;
+        let addr = server.addr.to_string();
+        assert!(crate::check::run("vcs", &addr).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn transcripts() {
+        replay_dir("testdata/vcs", |reader, writer| async move {
+            process(reader, writer, &Mutex::default()).await
+        })
+        .await;
+    }
+}
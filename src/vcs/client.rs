@@ -0,0 +1,157 @@
+//! A client for the VCS protocol, for tools and end-to-end tests.
+
+use anyhow::{anyhow, bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+pub struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Client::new(reader, writer).await
+    }
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Starts a session, waiting for the server to be ready.
+    pub async fn new(reader: R, writer: W) -> Result<Self> {
+        let mut client = Client {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        client.ready().await?;
+        Ok(client)
+    }
+
+    /// Stores a new revision of `path`, returning its number.
+    pub async fn put(&mut self, path: &str, data: &[u8]) -> Result<usize> {
+        let mut request = format!("PUT {path} {}\n", data.len()).into_bytes();
+        request.extend_from_slice(data);
+        self.writer.write_all(&request).await?;
+        let ok = self.response().await?;
+        let revision = ok
+            .strip_prefix('r')
+            .and_then(|r| r.parse().ok())
+            .ok_or_else(|| anyhow!("bad PUT response: {ok:?}"))?;
+        self.ready().await?;
+        Ok(revision)
+    }
+
+    /// Fetches a revision of `path`, or the latest one.
+    pub async fn get(&mut self, path: &str, revision: Option<usize>) -> Result<Vec<u8>> {
+        let request = match revision {
+            Some(revision) => format!("GET {path} r{revision}\n"),
+            None => format!("GET {path}\n"),
+        };
+        self.writer.write_all(request.as_bytes()).await?;
+        let ok = self.response().await?;
+        let Ok(length) = ok.parse() else {
+            bail!("bad GET response: {ok:?}");
+        };
+        let mut data = vec![0; length];
+        self.reader.read_exact(&mut data).await?;
+        self.ready().await?;
+        Ok(data)
+    }
+
+    /// Lists the entries in `dir`, as `name rN` for files and `name/ DIR` for
+    /// directories.
+    pub async fn list(&mut self, dir: &str) -> Result<Vec<String>> {
+        self.writer
+            .write_all(format!("LIST {dir}\n").as_bytes())
+            .await?;
+        let ok = self.response().await?;
+        let Ok(count) = ok.parse() else {
+            bail!("bad LIST response: {ok:?}");
+        };
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(self.line().await?);
+        }
+        self.ready().await?;
+        Ok(entries)
+    }
+
+    /// Reads an `OK` or `ERR` line, returning the rest of an `OK` line and
+    /// turning an `ERR` into an error. The `READY` after an error is consumed.
+    async fn response(&mut self) -> Result<String> {
+        let line = self.line().await?;
+        if let Some(ok) = line.strip_prefix("OK ") {
+            return Ok(ok.to_string());
+        }
+        if let Some(err) = line.strip_prefix("ERR ") {
+            self.ready().await?;
+            bail!("{err}");
+        }
+        bail!("unexpected response: {line:?}");
+    }
+
+    async fn ready(&mut self) -> Result<()> {
+        match self.line().await?.as_str() {
+            "READY" => Ok(()),
+            line => bail!("expected READY, got {line:?}"),
+        }
+    }
+
+    async fn line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            bail!("server closed the connection");
+        }
+        Ok(line.trim_end_matches('\n').to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use tokio::io::DuplexStream;
+
+    use super::super::{process, store::Store};
+    use super::Client;
+
+    async fn connect(store: &'static Mutex<Store>) -> Client<DuplexStream, DuplexStream> {
+        let (client_reader, server_writer) = tokio::io::duplex(4096);
+        let (server_reader, client_writer) = tokio::io::duplex(4096);
+        tokio::spawn(process(server_reader, server_writer, store));
+        Client::new(client_reader, client_writer).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn end_to_end() {
+        let store = Box::leak(Box::default());
+        let mut client = connect(store).await;
+        assert_eq!(client.put("/a/b", b"one\n").await.unwrap(), 1);
+        assert_eq!(client.put("/a/b", b"two\n").await.unwrap(), 2);
+        assert_eq!(client.put("/c", &[b'x'; 100_000]).await.unwrap(), 1);
+
+        // Another session sees the same files.
+        let mut other = connect(store).await;
+        assert_eq!(other.get("/a/b", None).await.unwrap(), b"two\n");
+        assert_eq!(other.get("/a/b", Some(1)).await.unwrap(), b"one\n");
+        assert_eq!(other.get("/c", None).await.unwrap().len(), 100_000);
+        assert_eq!(other.list("/").await.unwrap(), ["a/ DIR", "c r1"]);
+        assert_eq!(other.list("/a").await.unwrap(), ["b r2"]);
+
+        let err = other.get("/a/b", Some(3)).await.unwrap_err();
+        assert_eq!(err.to_string(), "no such revision");
+        let err = other.put("/d", b"\x00").await.unwrap_err();
+        assert_eq!(err.to_string(), "text files only");
+        // The session is still usable after errors.
+        assert_eq!(other.put("/d", b"d").await.unwrap(), 1);
+    }
+}
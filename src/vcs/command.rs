@@ -0,0 +1,458 @@
+use tokio_util::{
+    bytes::{Buf, Bytes, BytesMut},
+    codec::Decoder,
+};
+
+/// The longest command line accepted, in bytes.
+const MAX_LINE: usize = 1 << 16;
+/// The most a PUT reserves for its data before any arrives. Past that, the
+/// buffer grows with the data, so a client claiming a large file and then
+/// sending nothing ties up no more than this.
+const MAX_PUT_RESERVE: usize = 1 << 16;
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Command {
+    Help,
+    Get {
+        path: String,
+        revision: Option<String>,
+    },
+    Put {
+        path: String,
+        data: Bytes,
+    },
+    /// A PUT whose data was skipped rather than stored; holds the reason.
+    PutRejected {
+        path: String,
+        error: &'static str,
+    },
+    List {
+        dir: String,
+    },
+    /// A known method with the wrong arguments; holds the usage string.
+    Usage(&'static str),
+    Illegal(String),
+    /// A line longer than the limit, the rest of which is skipped.
+    TooLong,
+}
+
+/// Decodes commands, which are ASCII lines, except that a PUT line is followed
+/// by exactly `length` raw bytes of file data.
+///
+/// File data is moved out of the read buffer as it arrives, and checked on the
+/// way. Data that is too large or not text is dropped as it arrives instead.
+/// So is a command line longer than `max_line`, once it's known to be too
+/// long, up to and including its newline. Memory use is bounded by the
+/// maximum file size plus the maximum line length, and by what has actually
+/// arrived.
+pub(super) struct CommandDecoder {
+    max_file_size: usize,
+    max_line: usize,
+    /// A PUT whose data hasn't fully arrived yet.
+    put: Option<PendingPut>,
+    /// How far into the buffer is known to hold no newline, so that each read
+    /// only scans what's new.
+    scanned: usize,
+    /// Whether the buffer is the rest of a line that was too long.
+    discarding: bool,
+}
+
+struct PendingPut {
+    path: String,
+    /// Bytes still to come.
+    remaining: usize,
+    /// The data so far, or why it's being skipped.
+    data: Result<BytesMut, &'static str>,
+}
+
+impl CommandDecoder {
+    pub(super) fn new(max_file_size: usize) -> CommandDecoder {
+        CommandDecoder {
+            max_file_size,
+            max_line: MAX_LINE,
+            put: None,
+            scanned: 0,
+            discarding: false,
+        }
+    }
+
+    fn start_put(&mut self, path: &str, length: usize) {
+        let data = match length <= self.max_file_size {
+            true => Ok(BytesMut::with_capacity(length.min(MAX_PUT_RESERVE))),
+            false => Err("file too large"),
+        };
+        self.put = Some(PendingPut {
+            path: path.to_string(),
+            remaining: length,
+            data,
+        });
+    }
+
+    fn decode_data(&mut self, src: &mut BytesMut) -> Option<Command> {
+        let put = self.put.as_mut().unwrap();
+        let n = src.len().min(put.remaining);
+        if let Ok(data) = &mut put.data {
+            let chunk = &src[..n];
+            match chunk.iter().all(|&b| is_text(b)) {
+                true => data.extend_from_slice(chunk),
+                false => put.data = Err("text files only"),
+            }
+        }
+        src.advance(n);
+        put.remaining -= n;
+        if put.remaining > 0 {
+            return None;
+        }
+
+        let put = self.put.take().unwrap();
+        Some(match put.data {
+            Ok(data) => Command::Put {
+                path: put.path,
+                data: data.freeze(),
+            },
+            Err(error) => Command::PutRejected {
+                path: put.path,
+                error,
+            },
+        })
+    }
+}
+
+impl Decoder for CommandDecoder {
+    type Item = Command;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.put.is_some() {
+            return Ok(self.decode_data(src));
+        }
+
+        let Some(i) = src[self.scanned..].iter().position(|&b| b == b'\n') else {
+            if self.discarding {
+                src.clear();
+                self.scanned = 0;
+            } else if src.len() > self.max_line {
+                src.clear();
+                self.scanned = 0;
+                self.discarding = true;
+                return Ok(Some(Command::TooLong));
+            } else {
+                // Not enough data
+                self.scanned = src.len();
+            }
+            return Ok(None);
+        };
+        let n = self.scanned + i;
+        self.scanned = 0;
+        let line = src.split_to(n + 1);
+        if std::mem::take(&mut self.discarding) {
+            return self.decode(src);
+        }
+        if n > self.max_line {
+            return Ok(Some(Command::TooLong));
+        }
+        let line = String::from_utf8_lossy(&line[..n]);
+        let args: Vec<_> = line.split_ascii_whitespace().collect();
+        let (method, args) = args.split_first().unwrap_or((&"", &[]));
+        let command = match (method.to_ascii_uppercase().as_str(), args) {
+            ("HELP", _) => Command::Help,
+            ("GET", [path]) => Command::Get {
+                path: path.to_string(),
+                revision: None,
+            },
+            ("GET", [path, revision]) => Command::Get {
+                path: path.to_string(),
+                revision: Some(revision.to_string()),
+            },
+            ("GET", _) => Command::Usage("GET file [revision]"),
+            ("PUT", [path, length]) => match length.parse() {
+                Ok(length) => {
+                    self.start_put(path, length);
+                    return self.decode(src);
+                }
+                Err(_) => Command::Usage("PUT file length newline data"),
+            },
+            ("PUT", _) => Command::Usage("PUT file length newline data"),
+            ("LIST", [dir]) => Command::List {
+                dir: dir.to_string(),
+            },
+            ("LIST", _) => Command::Usage("LIST dir"),
+            _ => Command::Illegal(method.to_string()),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Printable ASCII, newlines and tabs.
+fn is_text(b: u8) -> bool {
+    matches!(b, b' '..=b'~' | b'\n' | b'\t')
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+    use proptest::prelude::*;
+
+    use crate::testutil::{decode_chunks, split_at};
+
+    use super::{Command, CommandDecoder, MAX_PUT_RESERVE};
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<Command> {
+        let mut decoder = CommandDecoder::new(usize::MAX);
+        let mut buf = BytesMut::new();
+        let mut commands = vec![];
+        for chunk in chunks {
+            buf.extend_from_slice(chunk);
+            while let Some(command) = decoder.decode(&mut buf).unwrap() {
+                commands.push(command);
+            }
+        }
+        assert!(buf.is_empty());
+        commands
+    }
+
+    fn put(path: &str, data: &'static [u8]) -> Command {
+        Command::Put {
+            path: path.to_string(),
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let commands = decode_all(&[b"help\nGET /a\nget /a r2\nList /\nfoo bar\n\n"]);
+        assert_eq!(
+            commands,
+            [
+                Command::Help,
+                Command::Get {
+                    path: "/a".to_string(),
+                    revision: None
+                },
+                Command::Get {
+                    path: "/a".to_string(),
+                    revision: Some("r2".to_string())
+                },
+                Command::List {
+                    dir: "/".to_string()
+                },
+                Command::Illegal("foo".to_string()),
+                Command::Illegal("".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn usage() {
+        let commands = decode_all(&[b"GET\nGET /a 1 2\nPUT /a\nPUT /a x\nLIST\nLIST / /\n"]);
+        assert_eq!(
+            commands,
+            [
+                Command::Usage("GET file [revision]"),
+                Command::Usage("GET file [revision]"),
+                Command::Usage("PUT file length newline data"),
+                Command::Usage("PUT file length newline data"),
+                Command::Usage("LIST dir"),
+                Command::Usage("LIST dir"),
+            ]
+        );
+    }
+
+    #[test]
+    fn put_data() {
+        // Data may contain newlines, and is followed straight by the next line.
+        let commands = decode_all(&[b"PUT /a 6\nab\ncd\nGET /a\nPUT /b 0\nHELP\n"]);
+        assert_eq!(
+            commands,
+            [
+                put("/a", b"ab\ncd\n"),
+                Command::Get {
+                    path: "/a".to_string(),
+                    revision: None
+                },
+                put("/b", b""),
+                Command::Help,
+            ]
+        );
+    }
+
+    #[test]
+    fn put_split() {
+        let commands = decode_all(&[b"PU", b"T /a 5\nhel", b"lo", b"HELP", b"\n"]);
+        assert_eq!(commands, [put("/a", b"hello"), Command::Help]);
+    }
+
+    #[test]
+    fn put_binary() {
+        let commands = decode_all(&[b"PUT /a 4\nab\x00d", b"PUT /b 3\n\tc\n"]);
+        assert_eq!(
+            commands,
+            [
+                Command::PutRejected {
+                    path: "/a".to_string(),
+                    error: "text files only"
+                },
+                put("/b", b"\tc\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn put_not_buffered() {
+        let mut decoder = CommandDecoder::new(usize::MAX);
+        let mut buf = BytesMut::from(&b"PUT /a 3000\n"[..]);
+        for _ in 0..2 {
+            buf.extend_from_slice(&[b'x'; 1000]);
+            assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+            assert!(buf.is_empty());
+        }
+        buf.extend_from_slice(&[b'x'; 1000]);
+        buf.extend_from_slice(b"HELP\n");
+        let Some(Command::Put { data, .. }) = decoder.decode(&mut buf).unwrap() else {
+            panic!("expected a PUT");
+        };
+        assert_eq!(data.len(), 3000);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(Command::Help));
+    }
+
+    #[test]
+    fn put_reserves_as_it_arrives() {
+        let mut decoder = CommandDecoder::new(16 << 20);
+        let mut buf = BytesMut::from(&b"PUT /a 16000000\n"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        let capacity = |decoder: &CommandDecoder| match &decoder.put.as_ref().unwrap().data {
+            Ok(data) => data.capacity(),
+            Err(e) => panic!("{e}"),
+        };
+        assert_eq!(capacity(&decoder), MAX_PUT_RESERVE);
+        buf.extend_from_slice(&vec![b'x'; 3 * MAX_PUT_RESERVE]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert!(capacity(&decoder) >= 3 * MAX_PUT_RESERVE);
+    }
+
+    #[test]
+    fn put_too_large() {
+        let mut decoder = CommandDecoder::new(4);
+        let mut buf = BytesMut::from(&b"PUT /a 4\nabcdPUT /b 5\nabc"[..]);
+        assert_eq!(
+            decoder.decode(&mut buf).unwrap(),
+            Some(Command::Put {
+                path: "/a".to_string(),
+                data: "abcd".into()
+            })
+        );
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+        buf.extend_from_slice(b"deHELP\n");
+        assert_eq!(
+            decoder.decode(&mut buf).unwrap(),
+            Some(Command::PutRejected {
+                path: "/b".to_string(),
+                error: "file too large"
+            })
+        );
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(Command::Help));
+    }
+
+    #[test]
+    fn put_binary_not_buffered() {
+        let mut decoder = CommandDecoder::new(usize::MAX);
+        let mut buf = BytesMut::from(&b"PUT /a 1000000\nhello\x07"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+        for _ in 0..999 {
+            buf.extend_from_slice(&[b'x'; 1000]);
+            assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+            assert!(buf.is_empty());
+        }
+        buf.extend_from_slice(&[b'x'; 994]);
+        buf.extend_from_slice(b"HELP\n");
+        assert_eq!(
+            decoder.decode(&mut buf).unwrap(),
+            Some(Command::PutRejected {
+                path: "/a".to_string(),
+                error: "text files only"
+            })
+        );
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(Command::Help));
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut decoder = CommandDecoder {
+            max_line: 9,
+            ..CommandDecoder::new(usize::MAX)
+        };
+        let mut buf = BytesMut::new();
+        let mut decoded = vec![];
+        let stream = b"LIST /ab\nLIST /abcd\nPUT /a 10\nLIST /abcdHELP\nGET /a/b/c/d\nHELP\n";
+        // A byte at a time, and all at once.
+        for chunk in stream.chunks(1).chain([&stream[..]]) {
+            buf.extend_from_slice(chunk);
+            while let Some(command) = decoder.decode(&mut buf).unwrap() {
+                decoded.push(command);
+            }
+            // Never more than a line's worth buffered.
+            assert!(buf.len() <= 10, "{} bytes buffered", buf.len());
+        }
+        let expected = [
+            Command::List {
+                dir: "/ab".to_string(),
+            },
+            Command::TooLong,
+            // PUT data isn't a line, so it may be longer.
+            put("/a", b"LIST /abcd"),
+            Command::Help,
+            Command::TooLong,
+            Command::Help,
+        ];
+        assert_eq!(decoded[..6], expected);
+        assert_eq!(decoded[6..], expected);
+    }
+
+    fn command() -> impl Strategy<Value = Command> {
+        let path = || "(/[a-z0-9._-]{1,8}){1,3}";
+        prop_oneof![
+            Just(()).prop_map(|()| Command::Help),
+            (path(), proptest::option::of("r[0-9]{1,3}"))
+                .prop_map(|(path, revision)| Command::Get { path, revision }),
+            (path(), "[ -~\n\t]{0,100}").prop_map(|(path, data)| Command::Put {
+                path,
+                data: data.into(),
+            }),
+            path().prop_map(|dir| Command::List { dir }),
+        ]
+    }
+
+    fn encode(command: &Command) -> Vec<u8> {
+        match command {
+            Command::Help => b"HELP\n".to_vec(),
+            Command::Get {
+                path,
+                revision: Some(revision),
+            } => format!("GET {path} {revision}\n").into_bytes(),
+            Command::Get { path, .. } => format!("GET {path}\n").into_bytes(),
+            Command::Put { path, data } => {
+                let mut encoded = format!("PUT {path} {}\n", data.len()).into_bytes();
+                encoded.extend_from_slice(data);
+                encoded
+            }
+            Command::List { dir } => format!("LIST {dir}\n").into_bytes(),
+            _ => unreachable!(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(
+            commands in prop::collection::vec(command(), 0..8),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+        ) {
+            let stream: Vec<_> = commands.iter().flat_map(encode).collect();
+            let decoder = CommandDecoder::new(usize::MAX);
+            let decoded = decode_chunks(decoder, &split_at(&stream, &cuts)).unwrap();
+            prop_assert_eq!(decoded, commands);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tokio_util::bytes::Bytes;
+
+use super::store::Hash;
+
+/// A directory backing a store: `blobs/<hash>` holds each blob, and `index` is
+/// an append-only log of revisions, one `<hash> <path>` line each.
+///
+/// A blob is written and synced before the revision that refers to it, so a
+/// crash can leave an unused blob behind but never a revision without its
+/// contents.
+///
+/// Revisions are written in order on a thread of their own, so a PUT never
+/// waits on the disk, let alone holds up every other connection while it
+/// does. The flip side is that a PUT is answered before its revision is on
+/// disk: the last few before a crash may be lost, and a failed write is only
+/// logged. Either way the revision is still served until the server stops.
+pub(super) struct Disk {
+    /// Taken on drop, so the writer sees the end of the revisions.
+    revisions: Option<mpsc::Sender<Revision>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+/// A revision on its way to disk, with its blob if that's new.
+struct Revision {
+    path: String,
+    hash: Hash,
+    data: Option<Bytes>,
+}
+
+/// How long the writer waits for more revisions before writing what it has.
+const LINGER: Duration = Duration::from_millis(1);
+
+/// What loading a directory leaves behind.
+#[derive(Default)]
+pub(super) struct Loaded {
+    pub(super) blobs: HashMap<Hash, Bytes>,
+    /// Every revision, in the order they were made.
+    pub(super) revisions: Vec<(String, Hash)>,
+}
+
+impl Disk {
+    /// Opens the store in `dir`, creating it if needed, and loads it.
+    pub(super) fn open(dir: impl AsRef<Path>) -> Result<(Disk, Loaded)> {
+        let dir = dir.as_ref();
+        let blobs = dir.join("blobs");
+        fs::create_dir_all(&blobs)?;
+        let path = dir.join("index");
+        let contents = if path.exists() {
+            fs::read(&path)?
+        } else {
+            vec![]
+        };
+
+        let mut loaded = Loaded::default();
+        for (n, line) in contents.split(|&b| b == b'\n').enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let revision = std::str::from_utf8(line)
+                .ok()
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(hash, file)| Some((decode_hex(hash)?, file)));
+            let Some((hash, file)) = revision else {
+                // Most likely a write cut short by a crash.
+                println!("{}:{}: skipping revision", path.display(), n + 1);
+                continue;
+            };
+            if let Entry::Vacant(entry) = loaded.blobs.entry(hash) {
+                let blob = blobs.join(encode_hex(&hash));
+                match fs::read(&blob) {
+                    Ok(data) => entry.insert(data.into()),
+                    // A blob whose write failed, which later revisions with
+                    // the same contents didn't try again.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        println!("{}:{}: skipping revision: no blob", path.display(), n + 1);
+                        continue;
+                    }
+                    Err(e) => return Err(e).with_context(|| format!("{}", blob.display())),
+                };
+            }
+            loaded.revisions.push((file.to_string(), hash));
+        }
+
+        let mut index = OpenOptions::new().create(true).append(true).open(&path)?;
+        if contents.last().is_some_and(|&b| b != b'\n') {
+            // Don't glue the next revision onto a torn one.
+            index.write_all(b"\n")?;
+        }
+        let (revisions, rx) = mpsc::channel();
+        let disk = Disk {
+            revisions: Some(revisions),
+            writer: Some(thread::spawn(move || write(blobs, index, rx))),
+        };
+        Ok((disk, loaded))
+    }
+
+    /// Records a new revision. `data` is written out unless the blob is
+    /// already on disk.
+    pub(super) fn put(&mut self, path: &str, hash: &Hash, data: Option<Bytes>) {
+        if let Some(revisions) = &self.revisions {
+            // Fails only if the writer panicked, and it's already said why.
+            let _ = revisions.send(Revision {
+                path: path.to_string(),
+                hash: *hash,
+                data,
+            });
+        }
+    }
+}
+
+impl Drop for Disk {
+    /// Waits for every revision recorded so far to be written.
+    fn drop(&mut self) {
+        drop(self.revisions.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes `revisions` until the store is dropped. Whatever arrives within
+/// `LINGER` of a revision goes out with it: the new blobs first, then one
+/// write and one sync of the index for the lot.
+fn write(blobs: PathBuf, mut index: File, revisions: mpsc::Receiver<Revision>) {
+    while let Ok(revision) = revisions.recv() {
+        thread::sleep(LINGER);
+        let batch = std::iter::once(revision).chain(revisions.try_iter());
+        // Persistence is a convenience; failing to write shouldn't take the
+        // server down.
+        if let Err(e) = write_batch(&blobs, &mut index, batch) {
+            println!("VCS write failed: {e:?}");
+        }
+    }
+}
+
+fn write_batch(
+    blobs: &Path,
+    index: &mut File,
+    batch: impl Iterator<Item = Revision>,
+) -> Result<()> {
+    let mut lines = String::new();
+    for revision in batch {
+        let name = encode_hex(&revision.hash);
+        if let Some(data) = &revision.data {
+            write_blob(blobs, &name, data)?;
+        }
+        lines += &format!("{name} {}\n", revision.path);
+    }
+    index.write_all(lines.as_bytes())?;
+    index.sync_data()?;
+    Ok(())
+}
+
+/// Writes a blob under a temporary name and syncs it before renaming it, so
+/// a torn blob is never mistaken for a complete one.
+fn write_blob(blobs: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let tmp = blobs.join(format!("{name}.tmp"));
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_data()?;
+    fs::rename(&tmp, blobs.join(name))?;
+    Ok(())
+}
+
+fn encode_hex(hash: &Hash) -> String {
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Hash> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = Hash::default();
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
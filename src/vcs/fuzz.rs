@@ -0,0 +1,44 @@
+//! The entry point for the `vcs` fuzz target in `fuzz/`.
+
+use crate::fuzz::{decode_all, setup};
+
+use super::command::{Command, CommandDecoder};
+
+/// The largest PUT the fuzzed decoder accepts, small so that it's hit.
+const MAX_FILE_SIZE: usize = 64;
+
+/// Decodes `data` into commands, panicking if anything misbehaves. The first
+/// byte sets the read size.
+pub fn commands(data: &[u8]) {
+    let Some((size, _, stream)) = setup(data) else {
+        return;
+    };
+    decode_all(
+        CommandDecoder::new(MAX_FILE_SIZE),
+        stream,
+        size,
+        |command| {
+            if let Command::Put { data, .. } = command {
+                assert!(data.len() <= MAX_FILE_SIZE);
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::commands;
+
+    #[test]
+    fn seeds() {
+        let stream = b"HELP\nPUT /a 3\nabcGET /a r1\nLIST /\nPUT /b 100\n\
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxx\nPUT /c 1\n\x00jump\n";
+        commands(&[]);
+        for setup in 0..16 {
+            let mut data = vec![setup];
+            data.extend_from_slice(stream);
+            commands(&data);
+        }
+    }
+}
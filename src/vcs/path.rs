@@ -0,0 +1,58 @@
+/// A file name is an absolute path of non-empty segments, each made of
+/// letters, digits, `.`, `_` and `-`.
+pub(super) fn is_valid_file(path: &str) -> bool {
+    match path.strip_prefix('/') {
+        Some(rest) => rest.split('/').all(is_valid_segment),
+        None => false,
+    }
+}
+
+/// Like a file name, but a trailing slash is allowed and `/` is the root.
+pub(super) fn is_valid_dir(path: &str) -> bool {
+    path == "/" || is_valid_file(path.strip_suffix('/').unwrap_or(path))
+}
+
+fn is_valid_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_valid_dir, is_valid_file};
+
+    #[test]
+    fn names() {
+        // (path, valid file, valid dir)
+        let cases = [
+            ("/", false, true),
+            ("/a", true, true),
+            ("/a/", false, true),
+            ("/a/b.txt", true, true),
+            ("/A-Z_0-9.x", true, true),
+            ("/..", true, true),
+            ("/.a/.b/", false, true),
+            ("", false, false),
+            ("a", false, false),
+            ("a/b", false, false),
+            ("//", false, false),
+            ("//a", false, false),
+            ("/a//b", false, false),
+            ("/a//", false, false),
+            ("/a b", false, false),
+            ("/a\tb", false, false),
+            ("/a*", false, false),
+            ("/a~", false, false),
+            ("/a:b", false, false),
+            ("/a\\b", false, false),
+            ("/caf\u{e9}", false, false),
+            ("/a\0", false, false),
+        ];
+        for (path, file, dir) in cases {
+            assert_eq!(is_valid_file(path), file, "file {path:?}");
+            assert_eq!(is_valid_dir(path), dir, "dir {path:?}");
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use tokio_util::bytes::Bytes;
+
+use super::disk::Disk;
+
+/// The SHA-256 of a blob's contents.
+pub(super) type Hash = [u8; 32];
+
+/// Every revision of every file. Revisions are numbered from 1 per file.
+///
+/// Contents are stored once per distinct blob, and revisions refer to blobs
+/// by hash, so many files or revisions with the same contents cost one copy.
+pub(super) struct Store {
+    blobs: HashMap<Hash, Bytes>,
+    files: HashMap<String, Vec<Hash>>,
+    disk: Option<Disk>,
+    /// The largest PUT accepted, in bytes.
+    pub(super) max_file_size: usize,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store {
+            blobs: HashMap::new(),
+            files: HashMap::new(),
+            disk: None,
+            max_file_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Store {
+    /// Opens a store kept in `dir`, loading whatever is already there.
+    pub(super) fn open(dir: impl AsRef<Path>) -> Result<Store> {
+        let (disk, loaded) = Disk::open(dir)?;
+        let mut store = Store {
+            blobs: loaded.blobs,
+            disk: Some(disk),
+            ..Store::default()
+        };
+        println!(
+            "Loaded {} revisions of {} blobs",
+            loaded.revisions.len(),
+            store.blobs.len()
+        );
+        for (path, hash) in loaded.revisions {
+            store.files.entry(path).or_default().push(hash);
+        }
+        Ok(store)
+    }
+
+    /// Stores a new revision and returns its number. If the data is the same
+    /// as the latest revision, that revision's number is returned instead.
+    pub(super) fn put(&mut self, path: &str, data: Bytes) -> usize {
+        let hash: Hash = Sha256::digest(&data).into();
+        let revisions = self.files.entry(path.to_string()).or_default();
+        if revisions.last() == Some(&hash) {
+            return revisions.len();
+        }
+        revisions.push(hash);
+        let new_blob = !self.blobs.contains_key(&hash);
+        if new_blob {
+            self.blobs.insert(hash, data.clone());
+        }
+        if let Some(disk) = &mut self.disk {
+            disk.put(path, &hash, new_blob.then(|| data.clone()));
+        }
+        revisions.len()
+    }
+
+    /// Returns the given revision, or the latest one.
+    pub(super) fn get(&self, path: &str, revision: Option<usize>) -> Result<Bytes, &'static str> {
+        let revisions = self.files.get(path).ok_or("no such file")?;
+        let revision = revision.unwrap_or(revisions.len());
+        let hash = revision
+            .checked_sub(1)
+            .and_then(|idx| revisions.get(idx))
+            .ok_or("no such revision")?;
+        Ok(self.blobs[hash].clone())
+    }
+    /// Lists the files and subdirectories directly inside `dir`, sorted by
+    /// name. Directories only exist because of the files below them. A name
+    /// may be both a file and a directory, in which case both are listed.
+    pub(super) fn list(&self, dir: &str) -> Vec<(String, Entry)> {
+        let prefix = match dir.ends_with('/') {
+            true => dir.to_string(),
+            false => format!("{dir}/"),
+        };
+        let mut entries = BTreeMap::new();
+        for (path, revisions) in &self.files {
+            let Some(rest) = path.strip_prefix(&prefix) else {
+                continue;
+            };
+            match rest.split_once('/') {
+                Some((name, _)) => entries.insert(format!("{name}/"), Entry::Dir),
+                None => entries.insert(rest.to_string(), Entry::File(revisions.len())),
+            };
+        }
+        entries.into_iter().collect()
+    }
+}
+
+/// An entry in a directory listing.
+#[derive(Debug, PartialEq)]
+pub(super) enum Entry {
+    /// A file, with its latest revision.
+    File(usize),
+    Dir,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, Store};
+
+    #[test]
+    fn revisions() {
+        let mut store = Store::default();
+        assert_eq!(store.get("/a", None), Err("no such file"));
+        assert_eq!(store.put("/a", "one".into()), 1);
+        assert_eq!(store.put("/a", "two".into()), 2);
+        assert_eq!(store.put("/b", "one".into()), 1);
+
+        assert_eq!(store.get("/a", None), Ok("two".into()));
+        assert_eq!(store.get("/a", Some(1)), Ok("one".into()));
+        assert_eq!(store.get("/a", Some(3)), Err("no such revision"));
+        assert_eq!(store.get("/a", Some(0)), Err("no such revision"));
+        assert_eq!(store.get("/b", None), Ok("one".into()));
+    }
+
+    #[test]
+    fn dedup() {
+        let mut store = Store::default();
+        assert_eq!(store.put("/a", "one".into()), 1);
+        assert_eq!(store.put("/a", "one".into()), 1);
+        assert_eq!(store.put("/a", "two".into()), 2);
+        assert_eq!(store.put("/a", "one".into()), 3);
+        assert_eq!(store.put("/a", "one".into()), 3);
+
+        // Only the same path's latest revision counts.
+        assert_eq!(store.put("/b", "one".into()), 1);
+        assert_eq!(store.put("/c/a", "one".into()), 1);
+        assert_eq!(store.put("/b", "one".into()), 1);
+        assert_eq!(store.get("/a", Some(2)), Ok("two".into()));
+    }
+
+    #[test]
+    fn list() {
+        let mut store = Store::default();
+        for path in ["/a", "/b/c", "/b/d/e", "/b/d/f", "/bc", "/a/x"] {
+            store.put(path, "one".into());
+        }
+        store.put("/a", "two".into());
+
+        assert_eq!(
+            store.list("/"),
+            [
+                ("a".to_string(), Entry::File(2)),
+                ("a/".to_string(), Entry::Dir),
+                ("b/".to_string(), Entry::Dir),
+                ("bc".to_string(), Entry::File(1)),
+            ]
+        );
+        let b = [
+            ("c".to_string(), Entry::File(1)),
+            ("d/".to_string(), Entry::Dir),
+        ];
+        assert_eq!(store.list("/b"), b);
+        assert_eq!(store.list("/b/"), b);
+        assert_eq!(store.list("/b/d/e"), []);
+        assert_eq!(store.list("/nope"), []);
+    }
+
+    #[test]
+    fn blobs_shared() {
+        let mut store = Store::default();
+        store.put("/a", "one".into());
+        store.put("/a", "two".into());
+        store.put("/a", "one".into());
+        store.put("/b", "two".into());
+        store.put("/c/d", "three".into());
+        assert_eq!(store.blobs.len(), 3);
+        assert_eq!(store.get("/a", Some(3)), Ok("one".into()));
+        assert_eq!(store.get("/b", None), Ok("two".into()));
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = std::env::temp_dir().join(format!("vcs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut store = Store::open(&dir).unwrap();
+        store.put("/a", "one".into());
+        store.put("/a", "two".into());
+        store.put("/b/c", "one".into());
+        drop(store);
+
+        let mut store = Store::open(&dir).unwrap();
+        assert_eq!(store.blobs.len(), 2);
+        assert_eq!(store.get("/a", Some(1)), Ok("one".into()));
+        assert_eq!(store.get("/a", None), Ok("two".into()));
+        assert_eq!(store.get("/b/c", None), Ok("one".into()));
+        assert_eq!(store.put("/a", "two".into()), 2);
+        assert_eq!(store.put("/a", "three".into()), 3);
+        drop(store);
+
+        // A torn final revision is skipped.
+        let index = dir.join("index");
+        let contents = std::fs::read_to_string(&index).unwrap();
+        std::fs::write(&index, contents + "0123").unwrap();
+        let store = Store::open(&dir).unwrap();
+        assert_eq!(store.get("/a", None), Ok("three".into()));
+        assert_eq!(store.files.len(), 2);
+        drop(store);
+
+        // So is a revision whose blob was never written.
+        for blob in std::fs::read_dir(dir.join("blobs")).unwrap() {
+            let blob = blob.unwrap().path();
+            if std::fs::read(&blob).unwrap() == b"three" {
+                std::fs::remove_file(blob).unwrap();
+            }
+        }
+        let store = Store::open(&dir).unwrap();
+        assert_eq!(store.get("/a", None), Ok("two".into()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}